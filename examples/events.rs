@@ -0,0 +1,112 @@
+//! drives a run of remotes from your own code, showing its progress from the run's events instead of pupdate's progress
+//! bars while also writing every event as json lines, like `--events` does
+//!
+//! the remotes are pupdated by a mock runner, so this runs without any real hosts:
+//!
+//! ```sh
+//! cargo run --example events
+//! ```
+
+use std::{
+	collections::BTreeMap,
+	os::unix::process::ExitStatusExt,
+	process::{ExitStatus, Output},
+	sync::Arc,
+	time::Duration,
+};
+
+use pupdate::{
+	diagnostics::DiagnosticCommands,
+	events::{tee, Event, NdjsonSink},
+	logs::{LogFormats, LogLayout, DEFAULT_LOG_FLUSH_INTERVAL},
+	output::OutputLimits,
+	remote::{pupdate_remotes, ConcurrencyGroups, RemoteOptions, RemotesRun, Runner},
+	retry::RetryPolicy,
+	shell::RemoteCommand,
+};
+
+/// a runner which pretends to pupdate remotes, failing for any remote named `broken`
+struct MockRunner;
+
+impl Runner for MockRunner {
+	async fn run(&self, remote: &str, _command: &str) -> std::io::Result<Output> {
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		let (code, stdout) = if remote == "broken" {
+			(100, "E: Unable to locate package pupdate\n")
+		} else {
+			(0, "Reading package lists...\nUnpacking pupdate (1.0) ...\nSetting up pupdate (1.0) ...\n")
+		};
+		Ok(Output {
+			status: ExitStatus::from_raw(code << 8),
+			stdout: stdout.as_bytes().to_vec(),
+			stderr: Vec::new(),
+		})
+	}
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+	let options = RemoteOptions {
+		command: RemoteCommand::Shell("sudo pupdate".to_string()),
+		local_command: None,
+		log_dir: None,
+		log_layout: LogLayout::default(),
+		log_formats: LogFormats::default(),
+		log_names: BTreeMap::new(),
+		log_template: None,
+		log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+		stream_log: None,
+		report_sources: false,
+		fetch_logs: false,
+		fetch_compressed: false,
+		capture_stdout: false,
+		retry: RetryPolicy {
+			retries: 0,
+			base: Duration::ZERO,
+			max: Duration::ZERO,
+		},
+		offline: None,
+		skew_check: None,
+		services: None,
+		output_limits: OutputLimits::default(),
+		merge_output: false,
+		timeout: None,
+		verify_binary: None,
+		webhook: None,
+		silencer: None,
+		forward: None,
+		state: None,
+		change_id: None,
+		jobs: None,
+		concurrency_groups: ConcurrencyGroups::default(),
+		fail_on_repo_warnings: false,
+		fail_on_kept_back: false,
+		diagnostics: DiagnosticCommands::default(),
+		events: None,
+	};
+	let remotes = ["web1", "web2", "broken"].map(str::to_string).to_vec();
+	let RemotesRun {
+		events, results, ..
+	} = pupdate_remotes(Arc::new(MockRunner), remotes, Arc::new(options));
+
+	// every event goes to stderr as json on its way to the display below, so both see exactly the same events
+	let mut events = tee(events, NdjsonSink::new(std::io::stderr()));
+	while let Some(event) = events.recv().await {
+		match event {
+			Event::RemoteStarted { remote } => println!("[{remote}] started"),
+			Event::RemoteOutputLine { remote, line, .. } => println!("[{remote}] {line}"),
+			Event::RemoteFinished {
+				remote, success, ..
+			} => println!(
+				"[{remote}] {}",
+				if success { "succeeded" } else { "failed" }
+			),
+			_ => {}
+		}
+	}
+
+	for (remote, result) in results.await?? {
+		println!("{remote}: success = {}", result.success);
+	}
+	Ok(())
+}
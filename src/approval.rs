@@ -0,0 +1,40 @@
+//! approving remotes one by one, so sensitive remotes are only pupdated once someone has looked over their pending
+//! updates while the rest of the fleet is pupdated as usual
+
+use serde::{Deserialize, Serialize};
+
+/// whether a remote needs approving before it's pupdated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Approval {
+	/// pupdate the remote without asking
+	#[default]
+	Auto,
+	/// show the remote's pending updates and only pupdate it once it's approved
+	Manual,
+}
+
+/// the decision made about a remote which needed approving
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalDecision {
+	/// whether the remote was approved
+	pub approved: bool,
+	/// who made the decision, or `pupdate` if nobody could be asked
+	pub by: String,
+	/// how the decision was made when it wasn't answered at a prompt, like `--approve-all`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub how: Option<String>,
+}
+
+/// who's running pupdate, to record who approved remotes, from the environment or else `whoami`
+pub fn approver() -> String {
+	std::env::var("USER")
+		.or_else(|_| std::env::var("LOGNAME"))
+		.ok()
+		.or_else(|| {
+			let output = std::process::Command::new("whoami").output().ok()?;
+			let name = String::from_utf8(output.stdout).ok()?;
+			Some(name.trim().to_string()).filter(|name| !name.is_empty())
+		})
+		.unwrap_or_else(|| "unknown".to_string())
+}
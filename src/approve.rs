@@ -0,0 +1,151 @@
+use std::{
+	collections::BTreeMap,
+	io::{IsTerminal, Write},
+	sync::Arc,
+};
+
+use pupdate::{
+	approval::{approver, ApprovalDecision},
+	offline::OfflinePackages,
+	progress::Progress,
+	remote::Runner,
+	retry::RetryPolicy,
+};
+
+use crate::plan::{simulate_remotes, PlannedUpgrade};
+
+/// who's recorded as deciding about remotes nobody could be asked about
+const NOBODY: &str = "pupdate";
+
+/// prints what pupdating a remote would change, as far as it's known
+fn print_pending(
+	remote: &str,
+	upgrades: Option<&Vec<PlannedUpgrade>>,
+	offline: Option<&OfflinePackages>,
+) {
+	if let Some(packages) = offline {
+		let names: Vec<_> = packages
+			.packages
+			.iter()
+			.map(|package| format!("{} {}", package.name, package.version))
+			.collect();
+		println!(
+			"{remote} would install the pushed packages: {}",
+			names.join(", ")
+		);
+		return;
+	}
+	match upgrades {
+		Some(upgrades) if upgrades.is_empty() => println!("{remote} has nothing to upgrade"),
+		Some(upgrades) => {
+			println!("{remote} would upgrade {} packages:", upgrades.len());
+			for upgrade in upgrades {
+				println!(
+					"  {} {} -> {}{}",
+					upgrade.package,
+					upgrade.current.as_deref().unwrap_or("(new)"),
+					upgrade.candidate,
+					if upgrade.security { " (security)" } else { "" }
+				);
+			}
+		}
+		None => println!("couldn't list {remote}'s pending updates"),
+	}
+}
+
+/// decides whether to pupdate each remote needing manual approval. unless every remote is approved up front, each
+/// remote's pending updates are shown and it's asked about one at a time, so prompts never interleave. without a
+/// terminal to ask in, every remote is turned down
+pub async fn approve_remotes<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	offline: Option<(&[String], &OfflinePackages)>,
+	approve_all: bool,
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<BTreeMap<String, ApprovalDecision>> {
+	if approve_all {
+		println!(
+			"approving {} remotes needing manual approval since --approve-all was given",
+			remotes.len()
+		);
+		let by = approver();
+		return Ok(remotes
+			.into_iter()
+			.map(|remote| {
+				let decision = ApprovalDecision {
+					approved: true,
+					by: by.clone(),
+					how: Some("--approve-all".to_string()),
+				};
+				(remote, decision)
+			})
+			.collect());
+	}
+	if !std::io::stdin().is_terminal() {
+		eprintln!(
+			"warning: {} remotes need manual approval, but there's no terminal to ask in, so they're skipped",
+			remotes.len()
+		);
+		return Ok(remotes
+			.into_iter()
+			.map(|remote| {
+				let decision = ApprovalDecision {
+					approved: false,
+					by: NOBODY.to_string(),
+					how: Some("no terminal to ask in".to_string()),
+				};
+				(remote, decision)
+			})
+			.collect());
+	}
+
+	let (offline_remotes, offline_packages) = match offline {
+		Some((remotes, packages)) => (remotes, Some(packages)),
+		None => (&[][..], None),
+	};
+	// offline remotes install the packages they're given rather than whatever's pending, so they aren't simulated
+	let online: Vec<_> = remotes
+		.iter()
+		.filter(|remote| !offline_remotes.contains(remote))
+		.cloned()
+		.collect();
+	let upgrades = if online.is_empty() {
+		BTreeMap::new()
+	} else {
+		println!(
+			"listing pending updates on {} remotes needing manual approval",
+			online.len()
+		);
+		simulate_remotes(runner, online, retry, progress, verbose)
+			.await?
+			.0
+	};
+	let by = approver();
+	let mut decisions = BTreeMap::new();
+	for remote in remotes {
+		let offline = offline_remotes
+			.contains(&remote)
+			.then_some(offline_packages)
+			.flatten();
+		print_pending(&remote, upgrades.get(&remote), offline);
+		print!("pupdate {remote}? [y/N] ");
+		std::io::stdout().flush()?;
+		let mut line = String::new();
+		std::io::stdin().read_line(&mut line)?;
+		let approved = matches!(line.trim().to_lowercase().as_str(), "y" | "yes");
+		if !approved {
+			println!("skipping {remote}");
+		}
+		decisions.insert(
+			remote,
+			ApprovalDecision {
+				approved,
+				by: by.clone(),
+				how: None,
+			},
+		);
+	}
+	Ok(decisions)
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use pupdate::{
+	diagnostics::DiagnosticCommands,
+	logs::{LogFormats, LogLayout, DEFAULT_LOG_FLUSH_INTERVAL},
+	output::OutputLimits,
+	progress::Progress,
+	remote::{pupdate_remotes, ConcurrencyGroups, RemoteOptions, Runner},
+	retry::RetryPolicy,
+	shell::RemoteCommand,
+};
+
+/// a package with an update pending on a host
+#[derive(Debug, PartialEq, Eq)]
+pub struct Upgradable {
+	/// the name of the package
+	pub package: String,
+	/// the version the package would be upgraded to
+	pub candidate: String,
+	/// the version currently installed, if apt reported it
+	pub installed: Option<String>,
+}
+
+/// parses the output of `apt list --upgradable`
+pub fn parse_apt_upgradable(output: &[u8]) -> Vec<Upgradable> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(|line| {
+			// lines look like `bash/stable 5.2.15-2+b7 amd64 [upgradable from: 5.2.15-2+b2]`
+			let (package, rest) = line.split_once('/')?;
+			let candidate = rest.split_whitespace().nth(1)?;
+			let installed = line
+				.split_once("[upgradable from: ")
+				.map(|(_, installed)| installed.trim_end_matches(']').to_string());
+			Some(Upgradable {
+				package: package.to_string(),
+				candidate: candidate.to_string(),
+				installed,
+			})
+		})
+		.collect()
+}
+
+/// checks every remote for pending updates without changing anything, printing which packages need updating on how many
+/// hosts, and returns whether every remote could be checked
+pub async fn audit<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<bool> {
+	println!("auditing {} remotes", remotes.len());
+	let len = remotes.len();
+	let run = pupdate_remotes(
+		runner,
+		remotes,
+		Arc::new(RemoteOptions {
+			command: RemoteCommand::Argv(vec![
+				"apt".to_string(),
+				"list".to_string(),
+				"--upgradable".to_string(),
+			]),
+			local_command: None,
+			log_dir: None,
+			log_layout: LogLayout::default(),
+			log_formats: LogFormats::default(),
+			log_names: BTreeMap::new(),
+			log_template: None,
+			log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+			stream_log: None,
+			report_sources: false,
+			fetch_logs: false,
+			fetch_compressed: false,
+			capture_stdout: true,
+			retry,
+			offline: None,
+			skew_check: None,
+			services: None,
+			output_limits: OutputLimits::default(),
+			merge_output: false,
+			timeout: None,
+			verify_binary: None,
+			webhook: None,
+			silencer: None,
+			forward: None,
+			state: None,
+			change_id: None,
+			jobs: None,
+			concurrency_groups: ConcurrencyGroups::default(),
+			fail_on_repo_warnings: false,
+			fail_on_kept_back: false,
+			diagnostics: DiagnosticCommands::default(),
+			events: None,
+		}),
+	);
+	let results = progress.show_remotes(run, len, verbose).await?;
+
+	let mut packages: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+	let mut up_to_date = Vec::new();
+	let mut failed = Vec::new();
+	for (remote, result) in results {
+		if !result.success {
+			failed.push(remote);
+			continue;
+		}
+		let upgradable = parse_apt_upgradable(result.stdout.as_deref().unwrap_or_default());
+		if upgradable.is_empty() {
+			up_to_date.push(remote);
+			continue;
+		}
+		for upgradable in upgradable {
+			packages
+				.entry(upgradable.package)
+				.or_default()
+				.push((remote.clone(), upgradable.candidate));
+		}
+	}
+
+	let mut packages: Vec<_> = packages.into_iter().collect();
+	packages.sort_by(|(a_name, a_hosts), (b_name, b_hosts)| {
+		b_hosts.len().cmp(&a_hosts.len()).then(a_name.cmp(b_name))
+	});
+	if packages.is_empty() {
+		println!("no pending updates found");
+	} else {
+		println!("packages with pending updates:");
+		for (package, hosts) in packages {
+			println!(
+				"{package}: {} host{}",
+				hosts.len(),
+				if hosts.len() == 1 { "" } else { "s" }
+			);
+			for (host, candidate) in hosts {
+				println!("    {host} -> {candidate}");
+			}
+		}
+	}
+	if !up_to_date.is_empty() {
+		println!("up to date: {}", up_to_date.join(", "));
+	}
+	if !failed.is_empty() {
+		println!("the following remotes couldn't be audited:");
+		for remote in &failed {
+			println!("{remote}");
+		}
+	}
+	Ok(failed.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_apt_upgradable() {
+		let output = b"Listing... Done\n\
+			bash/stable 5.2.15-2+b7 amd64 [upgradable from: 5.2.15-2+b2]\n\
+			tzdata/stable-updates 2024b-0+deb12u1 all\n";
+		assert_eq!(
+			parse_apt_upgradable(output),
+			vec![
+				Upgradable {
+					package: "bash".to_string(),
+					candidate: "5.2.15-2+b7".to_string(),
+					installed: Some("5.2.15-2+b2".to_string()),
+				},
+				Upgradable {
+					package: "tzdata".to_string(),
+					candidate: "2024b-0+deb12u1".to_string(),
+					installed: None,
+				},
+			]
+		);
+	}
+}
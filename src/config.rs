@@ -0,0 +1,850 @@
+use std::{
+	collections::BTreeMap,
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+use time::UtcOffset;
+
+use crate::{
+	approval::Approval,
+	logs::{LogFormatSetting, LogLayout},
+	managers::PackageManager,
+	progress::{ProgressPreset, ProgressSettings},
+	remote::{HostKeyPolicy, SkewPolicy, SshTarget},
+	resolve::ResolvePolicy,
+	risk::RiskyPolicy,
+	shell::RemoteCommand,
+	silence::SilenceSettings,
+};
+
+/// what a remote in a config's remotes starts with to remove it from the remotes of the configs merged before it
+pub const REMOVED_REMOTE_PREFIX: char = '!';
+/// the lists which a config setting them to an empty list doesn't override when merged
+const LISTS_KEPT_WHEN_EMPTY: &[&str] = &["steps", "on_failure_capture"];
+
+/// pupdate config
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+	/// the remotes to pupdate if none are provided
+	#[serde(default)]
+	pub remotes: Vec<RemoteEntry>,
+	/// the directory to log to, no logs if missing
+	#[serde(default)]
+	pub log_dir: Option<PathBuf>,
+	/// the steps to run in order when pupdating locally, defaults to apt-get update and upgrade
+	#[serde(default)]
+	pub steps: Vec<Step>,
+	/// package managers the local system can be pupdated with when no steps are configured, by name, tried in order
+	/// of name before the built-in apt, dnf, and pacman, which they replace if they share a name
+	#[serde(default)]
+	pub package_managers: BTreeMap<String, PackageManager>,
+	/// the ssh executable to use, defaults to `ssh` from the path
+	#[serde(default)]
+	pub ssh_binary: Option<PathBuf>,
+	/// the scp executable used to copy packages to offline remotes, defaults to `scp` from the path
+	#[serde(default)]
+	pub scp_binary: Option<PathBuf>,
+	/// the curl executable used to post to webhooks, defaults to `curl` from the path
+	#[serde(default)]
+	pub curl_binary: Option<PathBuf>,
+	/// the jump host remotes are reached through unless they have their own, like `bastion.example.com` or a chain like
+	/// `admin@bastion1,bastion2:2222`, defaults to ssh's own config
+	#[serde(default)]
+	pub jump_host: Option<String>,
+	/// how ssh verifies the host keys of remotes, defaults to ssh's own config
+	#[serde(default)]
+	pub host_key_policy: Option<HostKeyPolicy>,
+	/// the sudo executable used by the default local steps, defaults to `sudo` from the path
+	#[serde(default)]
+	pub sudo_binary: Option<PathBuf>,
+	/// the apt-get executable used by the default local steps, defaults to `apt-get` from the path
+	#[serde(default)]
+	pub apt_binary: Option<PathBuf>,
+	/// the unattended-upgrade executable used for security-only updates, defaults to `unattended-upgrade` from the path
+	#[serde(default)]
+	pub unattended_upgrade_binary: Option<PathBuf>,
+	/// checks each remote's clock against the controller's before pupdating it, warning about or skipping skewed remotes
+	#[serde(default)]
+	pub skew_policy: Option<SkewPolicy>,
+	/// how many seconds a remote's clock can be off before it's considered skewed, defaults to 30
+	#[serde(default)]
+	pub skew_threshold: Option<u64>,
+	/// whether to use utc or local time for timestamps, defaults to utc
+	#[serde(default)]
+	pub timestamps: Option<Timestamps>,
+	/// the command run on remotes to pupdate them, either a shell command line or a list of arguments which are quoted
+	/// individually, defaults to `sudo pupdate`
+	#[serde(default)]
+	pub remote_command: Option<RemoteCommand>,
+	/// whether to continue without logs when the log directory isn't usable instead of failing
+	#[serde(default)]
+	pub logs_optional: Option<bool>,
+	/// the preset for remotes' progress bars, defaults to `default`
+	#[serde(default)]
+	pub progress_style: Option<ProgressPreset>,
+	/// customizations for remotes' progress bars on top of the preset
+	#[serde(default)]
+	pub progress: ProgressSettings,
+	/// whether to print plain lines for each remote's status instead of showing progress bars
+	#[serde(default)]
+	pub no_progress: Option<bool>,
+	/// the sha256 hash the remote command's binary is expected to have, which is checked before it's run
+	#[serde(default)]
+	pub expected_binary_sha256: Option<String>,
+	/// the sha256 hashes particular remotes' binaries are expected to have, by remote, overriding
+	/// `expected_binary_sha256`
+	#[serde(default)]
+	pub remote_binary_sha256: BTreeMap<String, String>,
+	/// the most remotes pupdated at once, or all of them if unset
+	#[serde(default)]
+	pub jobs: Option<NonZeroUsize>,
+	/// the most remotes in each concurrency group pupdated at once, by group, where groups missing here are unlimited
+	#[serde(default)]
+	pub concurrency_groups: BTreeMap<String, NonZeroUsize>,
+	/// the concurrency group each remote is in, by remote
+	#[serde(default)]
+	pub remote_concurrency_groups: BTreeMap<String, String>,
+	/// whether particular remotes need approving one by one before they're pupdated, by remote, where remotes missing
+	/// here are pupdated without asking
+	#[serde(default)]
+	pub remote_approval: BTreeMap<String, Approval>,
+	/// whether particular remotes are often offline, like laptops, by remote. they're given a short time to connect,
+	/// and aren't counted as failed when they can't be reached
+	#[serde(default)]
+	pub remote_best_effort: BTreeMap<String, bool>,
+	/// how many seconds each remote's pupdate can run before it's killed and counted as failed, where 0 means no
+	/// timeout, which is the default
+	#[serde(default)]
+	pub timeout: Option<u64>,
+	/// how many seconds best effort remotes are given to connect, defaults to 10
+	#[serde(default)]
+	pub best_effort_connect_timeout: Option<u64>,
+	/// read-only diagnostic commands run on remotes which failed to pupdate, like `df -h`, whose output is written to
+	/// `diagnostics.log` in the remote's log directory
+	#[serde(default)]
+	pub on_failure_capture: Vec<String>,
+	/// the diagnostic commands run on particular remotes which failed to pupdate instead of `on_failure_capture`, by
+	/// remote
+	#[serde(default)]
+	pub remote_on_failure_capture: BTreeMap<String, Vec<String>>,
+	/// whether to log each remote's stdout and stderr together to a single file instead of to separate files
+	#[serde(default)]
+	pub merge_output: Option<bool>,
+	/// the names particular remotes' logs are written under, by remote, defaulting to the remote itself
+	#[serde(default)]
+	pub log_names: BTreeMap<String, String>,
+	/// how logs are laid out inside the run directory, defaults to a directory for each target
+	#[serde(default)]
+	pub log_layout: Option<LogLayout>,
+	/// where each remote's logs are written instead of the run directory, with `{host}`, `{date}`, and `{stream}`
+	/// replaced, like `/var/log/pupdate/{date}/{host}.{stream}.log`
+	#[serde(default)]
+	pub log_path_template: Option<String>,
+	/// at most how many milliseconds lines written to logs wait before they're flushed to disk, defaults to 250
+	#[serde(default)]
+	pub log_flush_interval_ms: Option<u64>,
+	/// the format logs are written in, or a list of formats to write side by side, defaults to raw
+	#[serde(default)]
+	pub log_format: Option<LogFormatSetting>,
+	/// where to remember each remote's last successful pupdate, defaults to `history.json` in the local data directory
+	#[serde(default)]
+	pub history_file: Option<PathBuf>,
+	/// how long what's detected about a remote is trusted before it's detected again, like `12h`, defaults to a day
+	#[serde(default)]
+	pub detection_ttl: Option<String>,
+	/// checks for risky updates before pupdating each target, asking about, skipping, or only recording targets which
+	/// have them pending
+	#[serde(default)]
+	pub risky_policy: Option<RiskyPolicy>,
+	/// checks that every remote's hostname resolves before pupdating anything, warning about, skipping, or stopping
+	/// over remotes whose hostnames don't
+	#[serde(default)]
+	pub resolve_policy: Option<ResolvePolicy>,
+	/// the packages whose updates are risky, where a trailing `*` matches any package starting with what comes before
+	/// it, defaults to the kernel, libc, openssl, and the init system
+	#[serde(default)]
+	pub risky_packages: Option<Vec<String>>,
+	/// the hours pupdate is allowed to pupdate in, like `22:00-04:00`, in the local time zone unless followed by a time
+	/// zone like `22:00-04:00 Europe/Berlin` or a utc offset like `22:00-04:00 +01:00`
+	#[serde(default)]
+	pub maintenance_window: Option<String>,
+	/// the hours particular remotes may be pupdated in, by remote, written like `maintenance_window`. remotes outside
+	/// theirs are skipped
+	#[serde(default)]
+	pub remote_maintenance_window: BTreeMap<String, String>,
+	/// the commands which silence monitoring for each remote while it's pupdated
+	#[serde(default)]
+	pub silence: SilenceSettings,
+	/// how long particular remotes are expected to be down for while they're pupdated, by remote, like `15m`, which is
+	/// how long their silences last
+	#[serde(default)]
+	pub remote_expected_downtime: BTreeMap<String, String>,
+}
+
+impl Config {
+	/// parses a config from toml or json, returning it along with the path of every key which isn't known and was
+	/// ignored, like `progress.tick_char`
+	fn parse(contents: &str, is_toml: bool) -> Result<(Self, Vec<String>), String> {
+		let mut unknown = Vec::new();
+		let track = |key: serde_ignored::Path| unknown.push(key.to_string());
+		let config = if is_toml {
+			serde_ignored::deserialize(toml::Deserializer::new(contents), track)
+				.map_err(|e| e.to_string())?
+		} else {
+			let mut deserializer = serde_json::Deserializer::from_str(contents);
+			let config =
+				serde_ignored::deserialize(&mut deserializer, track).map_err(|e| e.to_string())?;
+			deserializer.end().map_err(|e| e.to_string())?;
+			config
+		};
+		Ok((config, unknown))
+	}
+
+	/// loads a config from the given path, parsed as toml if the extension is `.toml` and json otherwise. unknown keys
+	/// are ignored so configs written for newer versions still load, unless `strict` is set, when they're an error
+	pub fn load(path: &Path, strict: bool) -> eyre::Result<Self> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| eyre::eyre!("failed to read config {}: {e}", path.display()))?;
+		let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+		let (config, unknown) = Self::parse(&contents, is_toml)
+			.map_err(|e| eyre::eyre!("failed to parse config {}: {e}", path.display()))?;
+		if strict && !unknown.is_empty() {
+			eyre::bail!(
+				"config {} has unknown keys: {}",
+				path.display(),
+				unknown.join(", ")
+			);
+		}
+		Ok(config)
+	}
+
+	/// reads a config's settings exactly as they were written, without checking them
+	pub fn load_raw(path: &Path) -> eyre::Result<Value> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| eyre::eyre!("failed to read config {}: {e}", path.display()))?;
+		if path.extension().is_some_and(|ext| ext == "toml") {
+			toml::from_str(&contents)
+				.map_err(|e| eyre::eyre!("failed to parse config {}: {e}", path.display()))
+		} else {
+			serde_json::from_str(&contents)
+				.map_err(|e| eyre::eyre!("failed to parse config {}: {e}", path.display()))
+		}
+	}
+
+	/// gets the path of every `*.json` and `*.toml` file in the given directory in sorted order
+	pub fn dir_paths(dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+		let mut paths = Vec::new();
+		for entry in std::fs::read_dir(dir)
+			.map_err(|e| eyre::eyre!("failed to read config directory {}: {e}", dir.display()))?
+		{
+			let path = entry?.path();
+			if path.is_file()
+				&& path
+					.extension()
+					.is_some_and(|ext| ext == "json" || ext == "toml")
+			{
+				paths.push(path);
+			}
+		}
+		paths.sort();
+		Ok(paths)
+	}
+
+	/// loads every config at the given paths in order, merging each on top of the ones before, with unknown keys being
+	/// an error if `strict` is set, and two configs giving the same remote different hosts being an error too
+	pub fn load_all(paths: &[PathBuf], strict: bool) -> eyre::Result<Self> {
+		let mut config = Self::default();
+		let mut hosts = RemoteHosts::default();
+		for path in paths {
+			let other = Self::load(path, strict)?;
+			let source = path.display().to_string();
+			for remote in &other.remotes {
+				let host = match remote {
+					RemoteEntry::Name(_) => None,
+					RemoteEntry::Target { target, .. } => Some(target.host.as_str()),
+				};
+				hosts.add(remote.name(), host, &source)?;
+			}
+			config.merge(other);
+		}
+		Ok(config)
+	}
+
+	/// the names of the config's remotes, in order
+	pub fn remote_names(&self) -> Vec<String> {
+		self.remotes
+			.iter()
+			.map(|remote| remote.name().to_string())
+			.collect()
+	}
+
+	/// how to reach the config's remotes which have their host, user, port, or identity file given separately, by name
+	pub fn remote_targets(&self) -> BTreeMap<String, SshTarget> {
+		self.remotes
+			.iter()
+			.filter_map(|remote| match remote {
+				RemoteEntry::Name(_) => None,
+				RemoteEntry::Target { target, .. } => {
+					Some((remote.name().to_string(), target.clone()))
+				}
+			})
+			.collect()
+	}
+
+	/// the hours particular remotes may be pupdated in, by remote, from `remote_maintenance_window` and the remotes
+	/// given with a `window`
+	pub fn remote_windows(&self) -> BTreeMap<String, String> {
+		let mut windows: BTreeMap<_, _> = self
+			.remotes
+			.iter()
+			.filter_map(|remote| match remote {
+				RemoteEntry::Target {
+					window: Some(window),
+					..
+				} => Some((remote.name().to_string(), window.clone())),
+				_ => None,
+			})
+			.collect();
+		windows.extend(self.remote_maintenance_window.clone());
+		windows
+	}
+
+	/// merges another config on top of this one, unioning remotes apart from removing the ones it lists with a `!`,
+	/// and letting the other config's values win otherwise, including how to reach remotes both configs list
+	pub fn merge(&mut self, other: Self) {
+		for remote in other.remotes {
+			if let Some(removed) = remote.name().strip_prefix(REMOVED_REMOTE_PREFIX) {
+				self.remotes.retain(|remote| remote.name() != removed);
+			} else if let Some(known) = self
+				.remotes
+				.iter_mut()
+				.find(|known| known.name() == remote.name())
+			{
+				known.merge(remote);
+			} else {
+				self.remotes.push(remote);
+			}
+		}
+		if other.log_dir.is_some() {
+			self.log_dir = other.log_dir;
+		}
+		if !other.steps.is_empty() {
+			self.steps = other.steps;
+		}
+		self.package_managers.extend(other.package_managers);
+		if other.ssh_binary.is_some() {
+			self.ssh_binary = other.ssh_binary;
+		}
+		if other.scp_binary.is_some() {
+			self.scp_binary = other.scp_binary;
+		}
+		if other.curl_binary.is_some() {
+			self.curl_binary = other.curl_binary;
+		}
+		if other.jump_host.is_some() {
+			self.jump_host = other.jump_host;
+		}
+		if other.host_key_policy.is_some() {
+			self.host_key_policy = other.host_key_policy;
+		}
+		if other.sudo_binary.is_some() {
+			self.sudo_binary = other.sudo_binary;
+		}
+		if other.apt_binary.is_some() {
+			self.apt_binary = other.apt_binary;
+		}
+		if other.unattended_upgrade_binary.is_some() {
+			self.unattended_upgrade_binary = other.unattended_upgrade_binary;
+		}
+		if other.skew_policy.is_some() {
+			self.skew_policy = other.skew_policy;
+		}
+		if other.skew_threshold.is_some() {
+			self.skew_threshold = other.skew_threshold;
+		}
+		if other.timestamps.is_some() {
+			self.timestamps = other.timestamps;
+		}
+		if other.remote_command.is_some() {
+			self.remote_command = other.remote_command;
+		}
+		if other.logs_optional.is_some() {
+			self.logs_optional = other.logs_optional;
+		}
+		if other.progress_style.is_some() {
+			self.progress_style = other.progress_style;
+		}
+		self.progress.merge(other.progress);
+		if other.no_progress.is_some() {
+			self.no_progress = other.no_progress;
+		}
+		if other.merge_output.is_some() {
+			self.merge_output = other.merge_output;
+		}
+		if other.expected_binary_sha256.is_some() {
+			self.expected_binary_sha256 = other.expected_binary_sha256;
+		}
+		self.remote_binary_sha256.extend(other.remote_binary_sha256);
+		if other.jobs.is_some() {
+			self.jobs = other.jobs;
+		}
+		self.concurrency_groups.extend(other.concurrency_groups);
+		self.remote_concurrency_groups
+			.extend(other.remote_concurrency_groups);
+		self.remote_approval.extend(other.remote_approval);
+		if !other.on_failure_capture.is_empty() {
+			self.on_failure_capture = other.on_failure_capture;
+		}
+		self.remote_on_failure_capture
+			.extend(other.remote_on_failure_capture);
+		self.remote_best_effort.extend(other.remote_best_effort);
+		self.remote_maintenance_window
+			.extend(other.remote_maintenance_window);
+		self.silence.merge(other.silence);
+		self.remote_expected_downtime
+			.extend(other.remote_expected_downtime);
+		if other.timeout.is_some() {
+			self.timeout = other.timeout;
+		}
+		if other.best_effort_connect_timeout.is_some() {
+			self.best_effort_connect_timeout = other.best_effort_connect_timeout;
+		}
+		self.log_names.extend(other.log_names);
+		if other.log_path_template.is_some() {
+			self.log_path_template = other.log_path_template;
+		}
+		if other.log_flush_interval_ms.is_some() {
+			self.log_flush_interval_ms = other.log_flush_interval_ms;
+		}
+		if other.log_layout.is_some() {
+			self.log_layout = other.log_layout;
+		}
+		if other.log_format.is_some() {
+			self.log_format = other.log_format;
+		}
+		if other.history_file.is_some() {
+			self.history_file = other.history_file;
+		}
+		if other.detection_ttl.is_some() {
+			self.detection_ttl = other.detection_ttl;
+		}
+		if other.risky_policy.is_some() {
+			self.risky_policy = other.risky_policy;
+		}
+		if other.resolve_policy.is_some() {
+			self.resolve_policy = other.resolve_policy;
+		}
+		if other.risky_packages.is_some() {
+			self.risky_packages = other.risky_packages;
+		}
+		if other.maintenance_window.is_some() {
+			self.maintenance_window = other.maintenance_window;
+		}
+	}
+}
+
+/// which time zone timestamps are in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timestamps {
+	/// timestamps are in utc
+	#[default]
+	Utc,
+	/// timestamps are in the local time zone
+	Local,
+}
+
+impl Timestamps {
+	/// gets the offset to use for timestamps given the local offset, if it could be determined
+	pub fn offset(self, local_offset: Option<UtcOffset>) -> UtcOffset {
+		match (self, local_offset) {
+			(Self::Utc, _) => UtcOffset::UTC,
+			(Self::Local, Some(offset)) => offset,
+			(Self::Local, None) => {
+				eprintln!("warning: couldn't determine the local time zone, using utc timestamps");
+				UtcOffset::UTC
+			}
+		}
+	}
+}
+
+/// the name of the default step which refreshes apt's package lists
+pub const APT_UPDATE_STEP: &str = "apt-update";
+
+/// a single step of a local pupdate
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Step {
+	/// the name of the step, used for logging
+	pub name: String,
+	/// the command to run, the program followed by its arguments
+	pub command: Vec<String>,
+}
+
+impl Step {
+	/// creates a new step from a name and command
+	fn new(name: &str, command: &[&str]) -> Self {
+		Self {
+			name: name.to_string(),
+			command: command.iter().map(|arg| arg.to_string()).collect(),
+		}
+	}
+
+	/// the steps used when none are configured, using the given sudo and apt-get executables
+	pub fn default_steps(sudo: &str, apt: &str) -> Vec<Self> {
+		vec![
+			Self::new(APT_UPDATE_STEP, &[sudo, apt, "update"]),
+			Self::new("apt-upgrade", &[sudo, apt, "upgrade", "-y"]),
+		]
+	}
+
+	/// the steps used to only refresh package indexes when none are configured, using the given sudo and apt-get
+	/// executables
+	pub fn index_steps(sudo: &str, apt: &str) -> Vec<Self> {
+		vec![Self::new(APT_UPDATE_STEP, &[sudo, apt, "update"])]
+	}
+
+	/// the steps used for security-only updates when none are configured, using the given sudo, apt-get, and
+	/// unattended-upgrade executables
+	pub fn security_steps(sudo: &str, apt: &str, unattended_upgrade: &str) -> Vec<Self> {
+		// unattended-upgrade only installs from the security origins unless it's been configured otherwise
+		vec![
+			Self::new(APT_UPDATE_STEP, &[sudo, apt, "update"]),
+			Self::new("security-upgrade", &[sudo, unattended_upgrade, "-v"]),
+		]
+	}
+}
+
+/// a remote in the config, either as it's given to ssh or with how to reach it spelled out
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum RemoteEntry {
+	/// a remote given to ssh as it is, like `admin@pup1.example.com` or a host from ssh's config
+	Name(String),
+	/// a remote with its host, user, port, and identity file given separately
+	Target {
+		/// the name the remote goes by in pupdate's output, logs, and per-remote settings, defaulting to its host
+		#[serde(default)]
+		name: Option<String>,
+		/// the hours the remote may be pupdated in, written like `maintenance_window`, unless
+		/// `remote_maintenance_window` gives it other hours
+		#[serde(default)]
+		window: Option<String>,
+		/// how to reach the remote
+		#[serde(flatten)]
+		target: SshTarget,
+	},
+}
+
+impl RemoteEntry {
+	/// merges a later config's entry for the same remote on top of this one, with each field it sets winning, while
+	/// listing the remote by name alone leaves it as it was
+	fn merge(&mut self, other: Self) {
+		match (self, other) {
+			(
+				Self::Target { window, target, .. },
+				Self::Target {
+					window: other_window,
+					target: other_target,
+					..
+				},
+			) => {
+				*window = other_window.or(window.take());
+				target.merge(other_target);
+			}
+			(known, other @ Self::Target { .. }) => *known = other,
+			(_, Self::Name(_)) => {}
+		}
+	}
+
+	/// the name the remote goes by
+	pub fn name(&self) -> &str {
+		match self {
+			Self::Name(name) => name,
+			Self::Target { name, target, .. } => name.as_deref().unwrap_or(&target.host),
+		}
+	}
+}
+
+/// the name of a remote in a config's remotes as it was written, following [`RemoteEntry::name`]
+fn entry_name(entry: &Value) -> Option<&str> {
+	match entry {
+		Value::String(name) => Some(name),
+		Value::Object(target) => target
+			.get("name")
+			.or_else(|| target.get("host"))
+			.and_then(Value::as_str),
+		_ => None,
+	}
+}
+
+/// the host each remote given with its host spelled out has, along with the config it came from, so a remote isn't
+/// quietly pointed somewhere else by a later config using the same name for a different host
+#[derive(Default)]
+struct RemoteHosts(BTreeMap<String, (String, String)>);
+
+impl RemoteHosts {
+	/// notes a remote from the given config, failing if an earlier config gave it a different host
+	fn add(&mut self, remote: &str, host: Option<&str>, source: &str) -> eyre::Result<()> {
+		if let Some(removed) = remote.strip_prefix(REMOVED_REMOTE_PREFIX) {
+			self.0.remove(removed);
+			return Ok(());
+		}
+		let Some(host) = host else {
+			return Ok(());
+		};
+		match self.0.get(remote) {
+			Some((known, known_source)) if known != host => eyre::bail!(
+				"remote {remote} has host {known} in {known_source} but host {host} in {source}, give one of them another name"
+			),
+			Some(_) => {}
+			None => {
+				self.0
+					.insert(remote.to_string(), (host.to_string(), source.to_string()));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// a setting of merged configs, along with the config it came from
+#[derive(Debug, PartialEq)]
+pub struct SettingSource {
+	/// the setting, with tables' entries as `table.entry`
+	pub key: String,
+	/// the setting's value, or the remote's entry for remotes
+	pub value: Value,
+	/// the config the setting came from, or each config a remote's entry was merged from
+	pub source: String,
+}
+
+/// works out which settings configs merged in the given order end up with and which config each one came from,
+/// following [`Config::merge`]: remotes are unioned, tables are merged entry by entry, and every other setting is taken
+/// from the last config setting it, apart from some empty lists, which don't override anything, and two
+/// configs giving the same remote different hosts is an error
+pub fn setting_sources(configs: &[(String, Value)]) -> eyre::Result<Vec<SettingSource>> {
+	let mut remotes: Vec<(&str, Value, Vec<&str>)> = Vec::new();
+	let mut hosts = RemoteHosts::default();
+	let mut settings = BTreeMap::new();
+	for (source, config) in configs {
+		let Value::Object(config) = config else {
+			continue;
+		};
+		for (key, value) in config {
+			match value {
+				Value::Array(list) if key == "remotes" => {
+					for (remote, entry) in list
+						.iter()
+						.filter_map(|entry| Some((entry_name(entry)?, entry)))
+					{
+						hosts.add(remote, entry.get("host").and_then(Value::as_str), source)?;
+						if let Some(removed) = remote.strip_prefix(REMOVED_REMOTE_PREFIX) {
+							remotes.retain(|(remote, _, _)| *remote != removed);
+						} else if let Some(known) =
+							remotes.iter_mut().find(|(known, _, _)| *known == remote)
+						{
+							// later entries override the fields they set, following `RemoteEntry::merge`
+							match (&mut known.1, entry) {
+								(Value::Object(known_fields), Value::Object(fields)) => {
+									known_fields.extend(
+										fields
+											.iter()
+											.filter(|(_, value)| !value.is_null())
+											.map(|(field, value)| (field.clone(), value.clone())),
+									);
+									if !known.2.contains(&source.as_str()) {
+										known.2.push(source);
+									}
+								}
+								(_, entry @ Value::Object(_)) => {
+									*known = (remote, entry.clone(), vec![source]);
+								}
+								_ => {}
+							}
+						} else {
+							remotes.push((remote, entry.clone(), vec![source]));
+						}
+					}
+				}
+				Value::Object(entries) => {
+					for (entry, value) in entries {
+						settings.insert(format!("{key}.{entry}"), (value, source));
+					}
+				}
+				Value::Null => {}
+				Value::Array(list)
+					if list.is_empty() && LISTS_KEPT_WHEN_EMPTY.contains(&key.as_str()) => {}
+				value => {
+					settings.insert(key.clone(), (value, source));
+				}
+			}
+		}
+	}
+	let remotes = remotes
+		.into_iter()
+		.map(|(_, entry, sources)| SettingSource {
+			key: "remotes".to_string(),
+			value: entry,
+			source: sources.join(", "),
+		});
+	let settings = settings
+		.into_iter()
+		.map(|(key, (value, source))| SettingSource {
+			key,
+			value: value.clone(),
+			source: source.to_string(),
+		});
+	Ok(remotes.chain(settings).collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn merged_settings_are_traced_to_their_config() {
+		let configs = [
+			(
+				"fleet.toml".to_string(),
+				serde_json::json!({
+					"remotes": ["web1", "web2", "db1"],
+					"log_dir": "/var/log/pupdate",
+					"merge_output": true,
+					"remote_best_effort": {"web2": true},
+				}),
+			),
+			(
+				"mine.json".to_string(),
+				serde_json::json!({
+					"remotes": ["!web2", "lab1"],
+					"log_dir": "/home/me/logs",
+					"merge_output": false,
+					"remote_best_effort": {"lab1": true},
+				}),
+			),
+		];
+		let settings: Vec<_> = setting_sources(&configs)
+			.unwrap()
+			.into_iter()
+			.map(|setting| (setting.key, setting.value.to_string(), setting.source))
+			.collect();
+		let setting = |key: &str, value: &str, source: &str| {
+			(key.to_string(), value.to_string(), source.to_string())
+		};
+		assert_eq!(
+			settings,
+			[
+				setting("remotes", "\"web1\"", "fleet.toml"),
+				setting("remotes", "\"db1\"", "fleet.toml"),
+				setting("remotes", "\"lab1\"", "mine.json"),
+				setting("log_dir", "\"/home/me/logs\"", "mine.json"),
+				setting("merge_output", "false", "mine.json"),
+				setting("remote_best_effort.lab1", "true", "mine.json"),
+				setting("remote_best_effort.web2", "true", "fleet.toml"),
+			]
+		);
+
+		let mut config = Config::default();
+		for (_, layer) in configs {
+			config.merge(serde_json::from_value(layer).unwrap());
+		}
+		assert_eq!(config.remote_names(), ["web1", "db1", "lab1"]);
+		assert_eq!(config.merge_output, Some(false));
+	}
+
+	#[test]
+	fn structured_remotes_are_named() {
+		let mut config: Config = serde_json::from_value(serde_json::json!({
+			"remotes": [
+				"admin@web1",
+				{"host": "pup1.example.com", "user": "admin", "port": 2222},
+				{"host": "10.0.0.5", "name": "db1", "identity_file": "~/.ssh/db", "window": "02:00-05:00 Europe/Berlin"},
+			],
+			"remote_maintenance_window": {"pup1.example.com": "01:00-03:00"},
+		}))
+		.unwrap();
+		config.merge(
+			serde_json::from_value(serde_json::json!({
+				"remotes": ["db1", {"host": "web1.example.com", "name": "admin@web1"}],
+			}))
+			.unwrap(),
+		);
+		assert_eq!(
+			config.remote_names(),
+			["admin@web1", "pup1.example.com", "db1"]
+		);
+		let targets = config.remote_targets();
+		assert_eq!(targets["admin@web1"].host, "web1.example.com");
+		assert_eq!(targets["pup1.example.com"].port, Some(2222));
+		assert_eq!(targets["db1"].host, "10.0.0.5");
+		assert_eq!(
+			config.remote_windows(),
+			BTreeMap::from([
+				("db1".to_string(), "02:00-05:00 Europe/Berlin".to_string()),
+				("pup1.example.com".to_string(), "01:00-03:00".to_string()),
+			])
+		);
+
+		let dir = std::env::temp_dir().join(format!("pupdate-config-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let (fleet, mine) = (dir.join("fleet.json"), dir.join("mine.json"));
+		std::fs::write(
+			&fleet,
+			r#"{"remotes": [{"host": "10.0.0.5", "name": "db1", "user": "admin", "window": "02:00-05:00 utc"}]}"#,
+		)
+		.unwrap();
+		std::fs::write(
+			&mine,
+			r#"{"remotes": [{"host": "10.0.0.5", "name": "db1", "port": 2222}]}"#,
+		)
+		.unwrap();
+		let config = Config::load_all(&[fleet.clone(), mine.clone()], false).unwrap();
+		// the later entry only overrides the fields it sets
+		let db1 = &config.remote_targets()["db1"];
+		assert_eq!(db1.port, Some(2222));
+		assert_eq!(db1.user.as_deref(), Some("admin"));
+		assert_eq!(config.remote_windows()["db1"], "02:00-05:00 utc");
+		let configs = [&fleet, &mine]
+			.map(|path| (path.display().to_string(), Config::load_raw(path).unwrap()));
+		let remotes = setting_sources(&configs).unwrap();
+		assert_eq!(
+			remotes[0].value,
+			serde_json::json!({"host": "10.0.0.5", "name": "db1", "user": "admin", "window": "02:00-05:00 utc", "port": 2222})
+		);
+		assert_eq!(
+			remotes[0].source,
+			format!("{}, {}", fleet.display(), mine.display())
+		);
+		std::fs::write(
+			&mine,
+			r#"{"remotes": [{"host": "10.0.0.6", "name": "db1"}]}"#,
+		)
+		.unwrap();
+		let error = Config::load_all(&[fleet.clone(), mine.clone()], false)
+			.unwrap_err()
+			.to_string();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(error.contains(&fleet.display().to_string()));
+		assert!(error.contains(&mine.display().to_string()));
+	}
+
+	#[test]
+	fn unknown_keys_are_found() {
+		let (config, unknown) = Config::parse(
+			r#"{"remotez": ["web1"], "remotes": ["web2"], "progress": {"tick_char": "-"}}"#,
+			false,
+		)
+		.unwrap();
+		assert_eq!(config.remote_names(), ["web2"]);
+		assert_eq!(unknown, ["remotez", "progress.tick_char"]);
+
+		let (_, unknown) = Config::parse(
+			"remotes = [\"web1\"]\n\n[[steps]]\nname = \"update\"\ncommand = [\"apt-get\", \"update\"]\nsudo = true\n",
+			true,
+		)
+		.unwrap();
+		assert_eq!(unknown, ["steps.0.sudo"]);
+	}
+}
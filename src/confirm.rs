@@ -0,0 +1,91 @@
+use std::{io::Write, sync::Arc};
+
+use pupdate::{
+	progress::Progress,
+	remote::{pupdate_remotes, RemoteOptions, Runner},
+	summary::TargetSummary,
+};
+
+/// the status given to remotes declined at the prompt
+const DECLINED: &str = "declined at the prompt";
+
+/// an answer to whether to pupdate a remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+	/// pupdate the remote
+	Yes,
+	/// skip the remote
+	No,
+	/// pupdate the remote and every remote after it without asking again
+	All,
+}
+
+impl Answer {
+	/// parses an answer, where anything but yes or all declines
+	pub fn parse(answer: &str) -> Self {
+		match answer.trim().to_lowercase().as_str() {
+			"y" | "yes" => Self::Yes,
+			"a" | "all" => Self::All,
+			_ => Self::No,
+		}
+	}
+}
+
+/// asks whether to pupdate a remote
+fn ask(remote: &str) -> eyre::Result<Answer> {
+	print!("pupdate {remote}? [y/N/a(ll)] ");
+	std::io::stdout().flush()?;
+	let mut line = String::new();
+	std::io::stdin().read_line(&mut line)?;
+	Ok(Answer::parse(&line))
+}
+
+/// pupdates remotes one at a time, asking before each whether to pupdate it, until every remote is done or the
+/// remaining ones are all approved, which are then pupdated together. declined remotes are skipped
+pub async fn pupdate_confirmed<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	options: Arc<RemoteOptions>,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<Vec<(String, TargetSummary)>> {
+	let mut results = Vec::with_capacity(remotes.len());
+	let mut remaining = remotes.into_iter();
+	while let Some(remote) = remaining.next() {
+		let remotes = match ask(&remote)? {
+			Answer::Yes => vec![remote],
+			Answer::All => std::iter::once(remote).chain(remaining.by_ref()).collect(),
+			Answer::No => {
+				println!("skipping {remote}");
+				let result = TargetSummary {
+					skipped: Some(DECLINED.to_string()),
+					declined: true,
+					..Default::default()
+				};
+				if let Some(state) = &options.state {
+					state.remote_finished(&remote, &result);
+				}
+				results.push((remote, result));
+				continue;
+			}
+		};
+		let len = remotes.len();
+		let run = pupdate_remotes(runner.clone(), remotes, options.clone());
+		results.extend(progress.show_remotes(run, len, verbose).await?);
+	}
+	Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn answers_are_parsed() {
+		assert_eq!(Answer::parse("y\n"), Answer::Yes);
+		assert_eq!(Answer::parse("All"), Answer::All);
+		assert_eq!(Answer::parse("a"), Answer::All);
+		assert_eq!(Answer::parse("\n"), Answer::No);
+		assert_eq!(Answer::parse("maybe"), Answer::No);
+	}
+}
@@ -0,0 +1,117 @@
+//! what's been detected about each remote, cached so it isn't detected again over ssh on every run
+//!
+//! the only thing detected about remotes so far is which version of pupdate they have, which decides the flags they're
+//! forwarded
+
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::forward::Version;
+
+/// the name of the detection cache file, kept next to the history file
+pub const DETECTION_FILENAME: &str = "detected.json";
+/// how long what's detected about a remote is trusted by default
+pub const DEFAULT_DETECTION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// what's been detected about a single remote
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detected {
+	/// the version of pupdate the remote has
+	pub pupdate_version: String,
+	/// when it was detected, as a unix timestamp
+	pub detected_at: i64,
+}
+
+/// what's been detected about remotes, keyed by remote
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DetectionCache {
+	/// what's been detected about each remote
+	#[serde(default)]
+	pub remotes: BTreeMap<String, Detected>,
+}
+
+impl DetectionCache {
+	/// gets the path of the detection cache kept next to the given history file
+	pub fn path_beside(history_file: &Path) -> PathBuf {
+		history_file.with_file_name(DETECTION_FILENAME)
+	}
+
+	/// loads the cache from the given path, which is empty if the file doesn't exist yet
+	pub fn load(path: &Path) -> eyre::Result<Self> {
+		match std::fs::read_to_string(path) {
+			Ok(contents) => serde_json::from_str(&contents).map_err(|e| {
+				eyre::eyre!("failed to parse detection cache {}: {e}", path.display())
+			}),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => eyre::bail!("failed to read detection cache {}: {e}", path.display()),
+		}
+	}
+
+	/// saves the cache to the given path, replacing the file at once so a crash can't leave it half written
+	pub fn save(&self, path: &Path) -> eyre::Result<()> {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(|e| {
+				eyre::eyre!(
+					"failed to create detection cache directory {}: {e}",
+					parent.display()
+				)
+			})?;
+		}
+		let temp = path.with_extension("json.tmp");
+		std::fs::write(&temp, serde_json::to_string_pretty(self)?)
+			.and_then(|()| std::fs::rename(&temp, path))
+			.map_err(|e| eyre::eyre!("failed to write detection cache {}: {e}", path.display()))?;
+		Ok(())
+	}
+
+	/// records the version of pupdate detected on a remote at the given time
+	pub fn record(&mut self, remote: &str, version: Version, at: OffsetDateTime) {
+		self.remotes.insert(
+			remote.to_string(),
+			Detected {
+				pupdate_version: version.to_string(),
+				detected_at: at.unix_timestamp(),
+			},
+		);
+	}
+
+	/// gets the pupdate version of each remote which was detected less than the ttl ago
+	pub fn fresh_versions(&self, now: OffsetDateTime, ttl: Duration) -> BTreeMap<String, Version> {
+		self.remotes
+			.iter()
+			.filter(|(_, detected)| {
+				// a detection in the future (from the clock going backwards) can't be trusted
+				let age = now.unix_timestamp() - detected.detected_at;
+				age >= 0 && (age as u64) < ttl.as_secs()
+			})
+			.filter_map(|(remote, detected)| {
+				Some((remote.clone(), Version::parse(&detected.pupdate_version)?))
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stale_detections_are_ignored() {
+		let now = OffsetDateTime::from_unix_timestamp(1_000_000).unwrap();
+		let version = Version::parse("0.1.0").unwrap();
+		let mut cache = DetectionCache::default();
+		cache.record("fresh", version, now - Duration::from_secs(60));
+		cache.record("stale", version, now - Duration::from_secs(2 * 60 * 60));
+		cache.record("future", version, now + Duration::from_secs(60));
+		assert_eq!(
+			cache.fresh_versions(now, Duration::from_secs(60 * 60)),
+			BTreeMap::from([("fresh".to_string(), version)])
+		);
+	}
+}
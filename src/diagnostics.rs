@@ -0,0 +1,125 @@
+//! read-only diagnostics captured from remotes which failed to pupdate, like an excerpt of the journal or how full
+//! their disks are, since apt's stderr rarely tells the whole story of a failed upgrade
+
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	process::Output,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::remote::Runner;
+
+/// the name of the file diagnostics are written to in a remote's log directory
+pub const DIAGNOSTICS_FILENAME: &str = "diagnostics.log";
+
+/// the commands run on remotes which failed to pupdate
+#[derive(Debug, Default, Clone)]
+pub struct DiagnosticCommands {
+	/// the commands run on every remote, each a command line for the remote shell
+	pub commands: Vec<String>,
+	/// the commands run on particular remotes instead, by remote
+	pub remotes: BTreeMap<String, Vec<String>>,
+}
+
+impl DiagnosticCommands {
+	/// gets the commands to run on the given remote
+	pub fn for_remote(&self, remote: &str) -> &[String] {
+		self.remotes.get(remote).unwrap_or(&self.commands)
+	}
+}
+
+/// the diagnostics captured from a remote which failed to pupdate
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Diagnostics {
+	/// the file the diagnostics were written to, if they could be
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub log: Option<PathBuf>,
+	/// the diagnostic commands which failed or couldn't be run, with why
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub failed: Vec<String>,
+}
+
+/// renders a diagnostic command's output for the diagnostics log, headed by the command like other logs are
+fn render_output(command: &str, output: &Output) -> String {
+	let mut rendered = format!("# command: {command}\n");
+	rendered.push_str(&String::from_utf8_lossy(&output.stdout));
+	if !output.stderr.is_empty() {
+		rendered.push_str("# stderr:\n");
+		rendered.push_str(&String::from_utf8_lossy(&output.stderr));
+	}
+	if !rendered.ends_with('\n') {
+		rendered.push('\n');
+	}
+	rendered.push_str(&format!("# {}\n\n", output.status));
+	rendered
+}
+
+/// runs each diagnostic command on a remote in order and writes their output to the given path. a command failing is
+/// recorded and the rest are still run
+pub async fn capture_diagnostics<R: Runner>(
+	runner: &R,
+	remote: &str,
+	commands: &[String],
+	path: &Path,
+) -> Diagnostics {
+	let mut log = String::new();
+	let mut failed = Vec::new();
+	for command in commands {
+		match runner.run(remote, command).await {
+			Ok(output) => {
+				if !output.status.success() {
+					failed.push(format!("`{command}` failed ({})", output.status));
+				}
+				log.push_str(&render_output(command, &output));
+			}
+			Err(e) => {
+				failed.push(format!("`{command}` couldn't be run ({e})"));
+				log.push_str(&format!("# command: {command}\n# couldn't be run: {e}\n\n"));
+			}
+		}
+	}
+	let written = async {
+		if let Some(parent) = path.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		tokio::fs::write(path, log).await
+	};
+	match written.await {
+		Ok(()) => Diagnostics {
+			log: Some(path.to_path_buf()),
+			failed,
+		},
+		Err(e) => {
+			failed.push(format!("couldn't write {}: {e}", path.display()));
+			Diagnostics { log: None, failed }
+		}
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::os::unix::process::ExitStatusExt;
+
+	use super::*;
+
+	#[test]
+	fn diagnostics_are_rendered_with_their_commands() {
+		let output = Output {
+			status: std::process::ExitStatus::from_raw(127 << 8),
+			stdout: Vec::new(),
+			stderr: b"sh: 1: journalctl: not found\n".to_vec(),
+		};
+		assert_eq!(
+			render_output("journalctl -xe", &output),
+			"# command: journalctl -xe\n# stderr:\nsh: 1: journalctl: not found\n# exit status: 127\n\n"
+		);
+		let commands = DiagnosticCommands {
+			commands: vec!["df -h".to_string()],
+			remotes: BTreeMap::from([("db1".to_string(), Vec::new())]),
+		};
+		assert_eq!(commands.for_remote("web1"), ["df -h"]);
+		assert!(commands.for_remote("db1").is_empty());
+	}
+}
@@ -0,0 +1,446 @@
+use std::{
+	path::{Path, PathBuf},
+	process::{ExitCode, Output},
+	sync::Arc,
+};
+
+use pupdate::{
+	config::Config,
+	logs::{prepare_run_dir, LogLayout},
+	output::output_tail,
+	progress::ProgressStyles,
+	remote::{ssh_failure, HostKeyPolicy, Runner, SshRunner},
+	shell::{command_line, RemoteCommand},
+	summary::FailureKind,
+};
+use serde::Serialize;
+
+use crate::{
+	exit_code, find_executable, load_config, local_package_manager, resolve_remotes,
+	sources::RemoteSource, Args,
+};
+
+/// how a check turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+	/// nothing to fix
+	Pass,
+	/// pupdate can run, but something may not work as expected
+	Warn,
+	/// pupdate can't run until this is fixed
+	Fail,
+}
+
+/// the result of a single check
+#[derive(Debug, Serialize)]
+pub struct Check {
+	/// what was checked
+	pub name: String,
+	/// how the check turned out
+	pub status: Status,
+	/// what was found
+	pub message: String,
+	/// how to fix the problem, if there is one
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub hint: Option<String>,
+}
+
+impl Check {
+	/// a check which passed
+	fn pass(name: impl Into<String>, message: impl Into<String>) -> Self {
+		Self {
+			name: name.into(),
+			status: Status::Pass,
+			message: message.into(),
+			hint: None,
+		}
+	}
+
+	/// a check which didn't pass, with a hint on how to fix it
+	fn problem(
+		status: Status,
+		name: impl Into<String>,
+		message: impl Into<String>,
+		hint: impl Into<String>,
+	) -> Self {
+		Self {
+			name: name.into(),
+			status,
+			message: message.into(),
+			hint: Some(hint.into()),
+		}
+	}
+}
+
+/// checks that an executable can be found, failing if it's needed and warning otherwise
+fn executable_check(program: &Path, needed: bool, hint: &str) -> Check {
+	let name = format!("executable {}", program.display());
+	match find_executable(program) {
+		Some(path) => Check::pass(name, format!("found at {}", path.display())),
+		None => Check::problem(
+			if needed { Status::Fail } else { Status::Warn },
+			name,
+			"not found",
+			hint,
+		),
+	}
+}
+
+/// checks that the config makes sense beyond parsing
+fn validate_config(config: &Config) -> Vec<Check> {
+	let mut checks = Vec::new();
+	checks.push(
+		match ProgressStyles::new(
+			config.progress_style.unwrap_or_default(),
+			config.progress.clone(),
+		) {
+			Ok(_) => Check::pass("progress settings", "valid"),
+			Err(e) => Check::problem(
+				Status::Fail,
+				"progress settings",
+				e.to_string(),
+				"fix progress in config, see the readme for the supported template keys",
+			),
+		},
+	);
+	for (name, manager) in &config.package_managers {
+		if manager.upgrade.is_empty() {
+			checks.push(Check::problem(
+				Status::Fail,
+				format!("package manager {name}"),
+				"the upgrade command is empty",
+				"give the package manager an upgrade command or remove it",
+			));
+		}
+	}
+	for step in &config.steps {
+		if step.command.is_empty() {
+			checks.push(Check::problem(
+				Status::Fail,
+				format!("step {}", step.name),
+				"the command is empty",
+				"give the step a command or remove it",
+			));
+		}
+	}
+	if matches!(&config.remote_command, Some(RemoteCommand::Argv(argv)) if argv.is_empty()) {
+		checks.push(Check::problem(
+			Status::Fail,
+			"remote command",
+			"the command is empty",
+			"give remote_command a command or remove it to use `sudo pupdate`",
+		));
+	}
+	if config.log_layout == Some(LogLayout::Flat) {
+		checks.push(Check::problem(
+			Status::Warn,
+			"log layout",
+			"the flat log layout is deprecated and will be removed in the next release",
+			"move scripts reading the logs over to the per-target directories and remove log_layout from config",
+		));
+	}
+	if config.host_key_policy == Some(HostKeyPolicy::Insecure) {
+		checks.push(Check::problem(
+			Status::Warn,
+			"host key policy",
+			"host keys of remotes aren't verified",
+			"use accept-new or strict unless the network between here and the remotes is trusted",
+		));
+	}
+	checks
+}
+
+/// checks that the local system can be pupdated
+fn local_checks(config: &Config, configured_log_dir: Option<&Path>) -> Vec<Check> {
+	let mut checks = Vec::new();
+	let sudo = config
+		.sudo_binary
+		.clone()
+		.unwrap_or_else(|| PathBuf::from("sudo"));
+	let steps = if config.steps.is_empty() {
+		local_package_manager(
+			&config.steps,
+			config.apt_binary.as_deref(),
+			&config.package_managers,
+		)
+		.map(|(name, manager)| manager.steps(&name, &sudo.to_string_lossy()))
+	} else {
+		Some(config.steps.clone())
+	};
+	if let Some(steps) = steps {
+		for step in &steps {
+			if let Some(program) = step.command.first() {
+				checks.push(executable_check(
+					Path::new(program),
+					true,
+					&format!("install it or fix the command for step {}", step.name),
+				));
+			}
+		}
+	} else {
+		let apt = config
+			.apt_binary
+			.clone()
+			.unwrap_or_else(|| PathBuf::from("apt-get"));
+		checks.push(executable_check(
+			&sudo,
+			true,
+			"install sudo or set sudo_binary in config",
+		));
+		checks.push(executable_check(
+			&apt,
+			true,
+			"install apt or set apt_binary in config",
+		));
+		checks.push(executable_check(
+			&config
+				.unattended_upgrade_binary
+				.clone()
+				.unwrap_or_else(|| PathBuf::from("unattended-upgrade")),
+			false,
+			"install unattended-upgrades or set unattended_upgrade_binary in config to use --only-security",
+		));
+	}
+
+	if let Some(log_dir) = configured_log_dir {
+		checks.push(match prepare_run_dir(log_dir) {
+			Ok(()) => Check::pass(
+				"log directory",
+				format!("{} is writable", log_dir.display()),
+			),
+			Err(e) => Check::problem(
+				if config.logs_optional.unwrap_or_default() {
+					Status::Warn
+				} else {
+					Status::Fail
+				},
+				"log directory",
+				e.to_string(),
+				"create the directory and make it writable, or change log_dir in config",
+			),
+		});
+	}
+
+	let sudo = config
+		.sudo_binary
+		.clone()
+		.unwrap_or_else(|| PathBuf::from("sudo"));
+	if find_executable(&sudo).is_some() {
+		checks.push(
+			match std::process::Command::new(&sudo)
+				.args(["-n", "true"])
+				.stdin(std::process::Stdio::null())
+				.output()
+			{
+				Ok(output) if output.status.success() => {
+					Check::pass("local sudo", "passwordless sudo works")
+				}
+				Ok(output) => Check::problem(
+					Status::Warn,
+					"local sudo",
+					format!("sudo needs a password: {}", output_tail(&output.stderr)),
+					"local pupdates will prompt for a password, allow the update commands without one in sudoers to run unattended",
+				),
+				Err(e) => Check::problem(
+					Status::Warn,
+					"local sudo",
+					format!("failed to run sudo: {e}"),
+					"check that sudo_binary points to sudo",
+				),
+			},
+		);
+	}
+	checks
+}
+
+/// checks that ssh keys are available to connect to remotes with
+fn ssh_agent_check() -> Check {
+	match std::process::Command::new("ssh-add")
+		.arg("-l")
+		.stdin(std::process::Stdio::null())
+		.output()
+	{
+		Ok(output) if output.status.success() => {
+			let keys = String::from_utf8_lossy(&output.stdout).lines().count();
+			Check::pass("ssh agent", format!("{keys} keys loaded"))
+		}
+		Ok(output) if output.status.code() == Some(1) => Check::problem(
+			Status::Warn,
+			"ssh agent",
+			"the agent has no keys loaded",
+			"add a key with ssh-add unless remotes are reached with keys from ssh's own config",
+		),
+		Ok(_) => Check::problem(
+			Status::Warn,
+			"ssh agent",
+			"no agent is running",
+			"start ssh-agent and add a key unless remotes are reached with keys from ssh's own config",
+		),
+		Err(e) => Check::problem(
+			Status::Warn,
+			"ssh agent",
+			format!("failed to run ssh-add: {e}"),
+			"install openssh-client to check for loaded keys",
+		),
+	}
+}
+
+/// turns the result of running `sudo -n true` on a remote into a check
+fn remote_check(remote: &str, result: std::io::Result<Output>) -> Check {
+	let name = format!("remote {remote}");
+	match result {
+		Ok(output) => match ssh_failure(output.status).map(|failure| failure.kind) {
+			None => Check::pass(name, "reachable with passwordless sudo"),
+			Some(FailureKind::Connection) => Check::problem(
+				Status::Fail,
+				name,
+				format!("couldn't connect: {}", output_tail(&output.stderr)),
+				"check that the remote is reachable and that your key is loaded in ssh-agent or set in ssh's config",
+			),
+			Some(FailureKind::Signal | FailureKind::OutputLimit | FailureKind::Timeout) => Check::problem(
+				Status::Fail,
+				name,
+				"ssh was killed before the check finished",
+				"run the check again",
+			),
+			// anything else means `sudo -n true` ran and failed
+			Some(_) => Check::problem(
+				Status::Fail,
+				name,
+				format!("sudo needs a password: {}", output_tail(&output.stderr)),
+				"allow pupdate to run without a password in the remote's sudoers",
+			),
+		},
+		Err(e) => Check::problem(
+			Status::Fail,
+			name,
+			format!("failed to run ssh: {e}"),
+			"check that ssh_binary points to ssh",
+		),
+	}
+}
+
+/// checks every remote concurrently, in the order they were given
+async fn remote_checks(runner: Arc<SshRunner>, remotes: Vec<String>) -> Vec<Check> {
+	let tasks: Vec<_> = remotes
+		.into_iter()
+		.map(|remote| {
+			let runner = runner.clone();
+			tokio::spawn(async move {
+				let result = runner
+					.run(&remote, &command_line(&["sudo", "-n", "true"]))
+					.await;
+				remote_check(&remote, result)
+			})
+		})
+		.collect();
+	let mut checks = Vec::with_capacity(tasks.len());
+	for task in tasks {
+		match task.await {
+			Ok(check) => checks.push(check),
+			Err(e) => checks.push(Check::problem(
+				Status::Fail,
+				"remote",
+				format!("the check couldn't run to completion: {e}"),
+				"this is a bug in pupdate",
+			)),
+		}
+	}
+	checks
+}
+
+/// checks that this machine is set up to run pupdate, printing the result of each check and returning whether none
+/// of them failed
+pub async fn doctor(args: &Args, check_remotes: bool, json: bool) -> eyre::Result<ExitCode> {
+	let mut checks = Vec::new();
+	let config = match load_config(args) {
+		Ok(config) => {
+			checks.push(Check::pass("config", "loaded"));
+			Some(config)
+		}
+		Err(e) => {
+			checks.push(Check::problem(
+				Status::Fail,
+				"config",
+				e.to_string(),
+				"fix the config, the remaining checks are skipped until it loads",
+			));
+			None
+		}
+	};
+
+	if let Some(config) = config {
+		checks.extend(validate_config(&config));
+		let remotes = resolve_remotes(args, config.remote_names(), RemoteSource::Config)
+			.await?
+			.remotes;
+		let runner = SshRunner::from_config(&config, args.host_key_policy);
+		checks.push(executable_check(
+			&runner.ssh,
+			!remotes.is_empty(),
+			"install openssh-client or set ssh_binary in config",
+		));
+		if !remotes.is_empty() {
+			checks.push(ssh_agent_check());
+		}
+		if !args.skip_local {
+			checks.extend(local_checks(
+				&config,
+				args.log_dir.as_deref().or(config.log_dir.as_deref()),
+			));
+		}
+		if check_remotes && find_executable(&runner.ssh).is_some() {
+			checks.extend(remote_checks(Arc::new(runner), remotes).await);
+		}
+	}
+
+	if json {
+		println!("{}", serde_json::to_string_pretty(&checks)?);
+	} else {
+		for check in &checks {
+			let status = match check.status {
+				Status::Pass => "pass",
+				Status::Warn => "warn",
+				Status::Fail => "fail",
+			};
+			println!("[{status}] {}: {}", check.name, check.message);
+			if let Some(hint) = &check.hint {
+				println!("       hint: {hint}");
+			}
+		}
+	}
+	Ok(ExitCode::from(
+		if checks.iter().any(|check| check.status == Status::Fail) {
+			exit_code::CHECKS_FAILED
+		} else {
+			exit_code::SUCCESS
+		},
+	))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::os::unix::process::ExitStatusExt;
+
+	use super::*;
+
+	fn output(code: i32, stderr: &str) -> std::io::Result<Output> {
+		Ok(Output {
+			status: std::process::ExitStatus::from_raw(code << 8),
+			stdout: Vec::new(),
+			stderr: stderr.as_bytes().to_vec(),
+		})
+	}
+
+	#[test]
+	fn remote_checks_are_classified() {
+		assert_eq!(remote_check("a", output(0, "")).status, Status::Pass);
+		let connection = remote_check("a", output(255, "ssh: connect to host a port 22: refused"));
+		assert_eq!(connection.status, Status::Fail);
+		assert!(connection.message.starts_with("couldn't connect"));
+		let sudo = remote_check("a", output(1, "sudo: a password is required"));
+		assert_eq!(sudo.status, Status::Fail);
+		assert!(sudo.message.starts_with("sudo needs a password"));
+	}
+}
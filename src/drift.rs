@@ -0,0 +1,262 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::Path,
+	sync::Arc,
+};
+
+use pupdate::{
+	diagnostics::DiagnosticCommands,
+	logs::{LogFormats, LogLayout, DEFAULT_LOG_FLUSH_INTERVAL},
+	output::OutputLimits,
+	progress::Progress,
+	remote::{pupdate_remotes, ConcurrencyGroups, RemoteOptions, Runner},
+	retry::RetryPolicy,
+	shell::RemoteCommand,
+};
+use serde::Serialize;
+
+/// the name of the file drift between remotes is written to in the run directory
+pub const DRIFT_FILENAME: &str = "drift.json";
+/// what `dpkg-query` prints for each package: its status, so packages which were removed but left their config behind
+/// can be told apart, its name, qualified by its architecture when several can be installed, and its version
+const DPKG_QUERY_FORMAT: &str = "${db:Status-Abbrev} ${binary:Package} ${Version}\\n";
+
+/// parses the output of `dpkg-query` with [`DPKG_QUERY_FORMAT`] into the version of each installed package
+pub fn parse_dpkg_query(output: &[u8]) -> BTreeMap<String, String> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(|line| {
+			// lines look like `ii  libc6:amd64 2.36-9+deb12u4`, where the second letter of the status is `i` for
+			// installed packages
+			let mut fields = line.split_whitespace();
+			let status = fields.next()?;
+			if status.chars().nth(1) != Some('i') {
+				return None;
+			}
+			let package = fields.next()?;
+			let version = fields.next()?;
+			Some((package.to_string(), version.to_string()))
+		})
+		.collect()
+}
+
+/// a package which isn't installed at the same version on every remote compared
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PackageDrift {
+	/// the version installed on each remote which has the package
+	pub versions: BTreeMap<String, String>,
+	/// the remotes which don't have the package installed
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub missing: Vec<String>,
+}
+
+/// finds the packages which aren't installed at the same version on every remote
+pub fn find_drift(
+	installed: &BTreeMap<String, BTreeMap<String, String>>,
+) -> BTreeMap<String, PackageDrift> {
+	let packages: BTreeSet<_> = installed
+		.values()
+		.flat_map(|packages| packages.keys())
+		.collect();
+	let mut drift = BTreeMap::new();
+	for package in packages {
+		let mut versions = BTreeMap::new();
+		let mut missing = Vec::new();
+		for (remote, packages) in installed {
+			match packages.get(package) {
+				Some(version) => {
+					versions.insert(remote.clone(), version.clone());
+				}
+				None => missing.push(remote.clone()),
+			}
+		}
+		let first = versions.values().next();
+		if !missing.is_empty() || versions.values().any(|version| Some(version) != first) {
+			drift.insert(package.clone(), PackageDrift { versions, missing });
+		}
+	}
+	drift
+}
+
+/// how the packages installed on remotes differ
+#[derive(Debug, Serialize)]
+pub struct Drift {
+	/// the remote the others are compared against, the first one which could be queried
+	pub reference: Option<String>,
+	/// the remotes compared, starting with the reference
+	pub compared: Vec<String>,
+	/// the remotes whose packages couldn't be queried
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub failed: Vec<String>,
+	/// the packages which aren't installed at the same version everywhere
+	pub packages: BTreeMap<String, PackageDrift>,
+}
+
+impl Drift {
+	/// prints each package which differs with its version on every remote compared, starting with the reference
+	pub fn print(&self) {
+		if self.compared.len() < 2 {
+			println!("not enough remotes could be queried to compare their packages");
+		} else if self.packages.is_empty() {
+			println!(
+				"no drift: all {} remotes have the same packages at the same versions",
+				self.compared.len()
+			);
+		} else {
+			println!(
+				"{} packages differ across {} remotes, compared against {}:",
+				self.packages.len(),
+				self.compared.len(),
+				self.reference.as_deref().unwrap_or_default()
+			);
+			for (package, drift) in &self.packages {
+				println!("{package}:");
+				for remote in &self.compared {
+					match drift.versions.get(remote) {
+						Some(version) => println!("    {remote} {version}"),
+						None => println!("    {remote} (not installed)"),
+					}
+				}
+			}
+		}
+		if !self.failed.is_empty() {
+			println!("the following remotes couldn't be queried:");
+			for remote in &self.failed {
+				println!("{remote}");
+			}
+		}
+	}
+
+	/// writes the drift into the given run directory
+	pub fn write(&self, dir: &Path) -> eyre::Result<()> {
+		let path = dir.join(DRIFT_FILENAME);
+		std::fs::write(&path, serde_json::to_string_pretty(self)?)
+			.map_err(|e| eyre::eyre!("failed to write {}: {e}", path.display()))?;
+		Ok(())
+	}
+}
+
+/// queries the packages installed on every remote without changing anything and compares them against the first
+/// remote which could be queried
+pub async fn drift<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<Drift> {
+	println!("comparing packages across {} remotes", remotes.len());
+	let len = remotes.len();
+	let run = pupdate_remotes(
+		runner,
+		remotes.clone(),
+		Arc::new(RemoteOptions {
+			command: RemoteCommand::Argv(vec![
+				"dpkg-query".to_string(),
+				"-W".to_string(),
+				"-f".to_string(),
+				DPKG_QUERY_FORMAT.to_string(),
+			]),
+			local_command: None,
+			log_dir: None,
+			log_layout: LogLayout::default(),
+			log_formats: LogFormats::default(),
+			log_names: BTreeMap::new(),
+			log_template: None,
+			log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+			stream_log: None,
+			report_sources: false,
+			fetch_logs: false,
+			fetch_compressed: false,
+			capture_stdout: true,
+			retry,
+			offline: None,
+			skew_check: None,
+			services: None,
+			output_limits: OutputLimits::default(),
+			merge_output: false,
+			timeout: None,
+			verify_binary: None,
+			webhook: None,
+			silencer: None,
+			forward: None,
+			state: None,
+			change_id: None,
+			jobs: None,
+			concurrency_groups: ConcurrencyGroups::default(),
+			fail_on_repo_warnings: false,
+			fail_on_kept_back: false,
+			diagnostics: DiagnosticCommands::default(),
+			events: None,
+		}),
+	);
+	let mut results: BTreeMap<_, _> = progress
+		.show_remotes(run, len, verbose)
+		.await?
+		.into_iter()
+		.collect();
+
+	let mut installed = BTreeMap::new();
+	let mut compared = Vec::new();
+	let mut failed = Vec::new();
+	// the remotes are gone through in the order they were given so the first one is the reference
+	for remote in remotes {
+		match results.remove(&remote) {
+			Some(result) if result.success => {
+				let packages = parse_dpkg_query(result.stdout.as_deref().unwrap_or_default());
+				installed.insert(remote.clone(), packages);
+				compared.push(remote);
+			}
+			_ => failed.push(remote),
+		}
+	}
+	Ok(Drift {
+		reference: compared.first().cloned(),
+		packages: find_drift(&installed),
+		compared,
+		failed,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn drift_is_found() {
+		let web1 = parse_dpkg_query(
+			b"ii  libc6:amd64 2.36-9+deb12u4\n\
+			ii  nginx 1.22.1-9\n\
+			ii  openssl 3.0.11-1~deb12u2\n",
+		);
+		let web2 = parse_dpkg_query(
+			b"ii  libc6:amd64 2.36-9+deb12u3\n\
+			rc  nginx 1.22.1-9\n\
+			ii  openssl 3.0.11-1~deb12u2\n",
+		);
+		assert_eq!(web2.len(), 2);
+		let installed = BTreeMap::from([("web1".to_string(), web1), ("web2".to_string(), web2)]);
+		assert_eq!(
+			find_drift(&installed),
+			BTreeMap::from([
+				(
+					"libc6:amd64".to_string(),
+					PackageDrift {
+						versions: BTreeMap::from([
+							("web1".to_string(), "2.36-9+deb12u4".to_string()),
+							("web2".to_string(), "2.36-9+deb12u3".to_string()),
+						]),
+						missing: Vec::new(),
+					}
+				),
+				(
+					"nginx".to_string(),
+					PackageDrift {
+						versions: BTreeMap::from([("web1".to_string(), "1.22.1-9".to_string())]),
+						missing: vec!["web2".to_string()],
+					}
+				),
+			])
+		);
+	}
+}
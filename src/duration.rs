@@ -0,0 +1,85 @@
+//! parsing and formatting human-friendly durations like `1h30m`
+
+use std::time::Duration;
+
+/// parses a duration made of whole numbers followed by a unit, like `90s`, `45m`, `1h30m`, or `2d`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+	let mut seconds = 0u64;
+	let mut digits = String::new();
+	for c in s.trim().chars() {
+		if c.is_ascii_digit() {
+			digits.push(c);
+			continue;
+		}
+		let unit = match c {
+			's' => 1,
+			'm' => 60,
+			'h' => 60 * 60,
+			'd' => 24 * 60 * 60,
+			_ => {
+				return Err(format!(
+					"unknown unit `{c}` in `{s}`, expected s, m, h, or d"
+				))
+			}
+		};
+		let value: u64 = digits
+			.parse()
+			.map_err(|_| format!("expected a number before `{c}` in `{s}`"))?;
+		seconds = value
+			.checked_mul(unit)
+			.and_then(|value| seconds.checked_add(value))
+			.ok_or_else(|| format!("`{s}` is too long"))?;
+		digits.clear();
+	}
+	if !digits.is_empty() {
+		return Err(format!(
+			"missing a unit after `{digits}` in `{s}`, expected s, m, h, or d"
+		));
+	}
+	if s.trim().is_empty() {
+		return Err("expected a duration like 90s, 45m, or 1h30m".to_string());
+	}
+	Ok(Duration::from_secs(seconds))
+}
+
+/// formats how long ago something happened in its two largest units, like `47m` or `3h12m`
+pub fn format_age(age: Duration) -> String {
+	let seconds = age.as_secs();
+	let (days, hours, minutes) = (
+		seconds / (24 * 60 * 60),
+		seconds / (60 * 60) % 24,
+		seconds / 60 % 60,
+	);
+	match (days, hours, minutes) {
+		(0, 0, 0) => format!("{seconds}s"),
+		(0, 0, minutes) => format!("{minutes}m"),
+		(0, hours, 0) => format!("{hours}h"),
+		(0, hours, minutes) => format!("{hours}h{minutes}m"),
+		(days, 0, _) => format!("{days}d"),
+		(days, hours, _) => format!("{days}d{hours}h"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn durations_are_parsed() {
+		assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+		assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+		assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+		assert!(parse_duration("").is_err());
+		assert!(parse_duration("30").is_err());
+		assert!(parse_duration("5w").is_err());
+		assert!(parse_duration("h").is_err());
+	}
+
+	#[test]
+	fn ages_are_formatted() {
+		assert_eq!(format_age(Duration::from_secs(42)), "42s");
+		assert_eq!(format_age(Duration::from_secs(47 * 60 + 5)), "47m");
+		assert_eq!(format_age(Duration::from_secs(3 * 3600 + 12 * 60)), "3h12m");
+		assert_eq!(format_age(Duration::from_secs(26 * 3600)), "1d2h");
+	}
+}
@@ -0,0 +1,285 @@
+//! events sent while pupdating, so callers can drive their own display of a run's progress
+//!
+//! every consumer of a run's events, like the progress bars and `--events`, reads the same stream, with [`tee`]
+//! passing each event to an [`EventSink`] on its way
+
+use std::{io::Write, time::Duration};
+
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{logs::LogPaths, summary::Failure};
+
+/// which output stream a line was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+	/// standard output
+	Stdout,
+	/// standard error
+	Stderr,
+}
+
+/// something which happened while pupdating, serialized with its kind under `event`, like
+/// `{"event":"remote_started","remote":"web1"}`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	/// a remote is waiting for its turn under the run's concurrency limits
+	RemoteQueued {
+		/// the remote
+		remote: String,
+		/// what the remote is waiting for, like `concurrency group hv1`
+		waiting_for: String,
+	},
+	/// a remote started being pupdated
+	RemoteStarted {
+		/// the remote
+		remote: String,
+	},
+	/// a remote wrote a line of output while being pupdated
+	RemoteOutputLine {
+		/// the remote
+		remote: String,
+		/// the stream the line was written to
+		stream: OutputStream,
+		/// the line, without its line ending
+		line: String,
+	},
+	/// a remote's pupdate failed and will be retried after a delay
+	RemoteRetrying {
+		/// the remote
+		remote: String,
+		/// how long until the retry
+		#[serde(serialize_with = "as_seconds")]
+		delay: Duration,
+		/// which attempt the retry is, counting the first as 1
+		attempt: u32,
+		/// how many attempts there can be in all
+		attempts: u32,
+	},
+	/// a remote's pupdate was killed for running longer than the timeout
+	RemoteTimedOut {
+		/// the remote
+		remote: String,
+		/// the timeout it ran longer than
+		#[serde(serialize_with = "as_seconds")]
+		timeout: Duration,
+	},
+	/// a remote's clock is further off from the controller's than the skew threshold, but it's being pupdated anyway
+	RemoteClockSkewed {
+		/// the remote
+		remote: String,
+		/// how many seconds the remote's clock is ahead of the controller's, or behind if negative
+		skew_seconds: i64,
+	},
+	/// some flags weren't forwarded to a remote because its pupdate wouldn't know them, but it's being pupdated anyway
+	RemoteFlagsOmitted {
+		/// the remote
+		remote: String,
+		/// the flags left out
+		flags: Vec<String>,
+		/// why they were left out
+		reason: String,
+	},
+	/// a remote was skipped without being pupdated
+	RemoteSkipped {
+		/// the remote
+		remote: String,
+		/// why the remote was skipped
+		reason: String,
+	},
+	/// monitoring is being silenced for a remote before it's pupdated
+	RemoteSilencing {
+		/// the remote
+		remote: String,
+	},
+	/// monitoring couldn't be silenced for a remote
+	RemoteSilenceFailed {
+		/// the remote
+		remote: String,
+		/// why the silence command failed
+		error: String,
+	},
+	/// a remote's silence couldn't be expired once it finished
+	RemoteUnsilenceFailed {
+		/// the remote
+		remote: String,
+		/// why the unsilence command failed
+		error: String,
+	},
+	/// a remote's own logs are being fetched
+	RemoteFetchingLogs {
+		/// the remote
+		remote: String,
+	},
+	/// diagnostics are being captured from a remote which failed to pupdate
+	RemoteCapturingDiagnostics {
+		/// the remote
+		remote: String,
+	},
+	/// a remote is being checked for services which need restarting
+	RemoteCheckingServices {
+		/// the remote
+		remote: String,
+	},
+	/// a remote's services which need restarting are being restarted
+	RemoteRestartingServices {
+		/// the remote
+		remote: String,
+		/// the services being restarted
+		services: Vec<String>,
+	},
+	/// a remote finished being pupdated
+	RemoteFinished {
+		/// the remote
+		remote: String,
+		/// whether the pupdate succeeded
+		success: bool,
+		/// whether the pupdate succeeded without anything needing an upgrade
+		up_to_date: bool,
+		/// how many packages apt upgraded, if it said
+		upgraded: Option<u64>,
+		/// the packages apt kept back instead of upgrading
+		kept_back: Vec<String>,
+		/// how the pupdate failed, if it did
+		failure: Option<Failure>,
+		/// how long the pupdate took, including retries
+		#[serde(serialize_with = "as_seconds")]
+		duration: Duration,
+		/// how many bytes of output were kept
+		output_bytes: u64,
+		/// the logs written for the remote, if any
+		logs: Option<LogPaths>,
+	},
+	/// a remote's result couldn't be posted to the per-remote webhook
+	RemoteWebhookFailed {
+		/// the remote
+		remote: String,
+		/// why the post failed
+		error: String,
+	},
+	/// a remote's pupdate couldn't run to completion
+	RemoteErrored {
+		/// the remote
+		remote: String,
+		/// what went wrong
+		error: String,
+	},
+	/// a remote's pupdate was cancelled
+	RemoteCancelled {
+		/// the remote
+		remote: String,
+	},
+	/// a step started running on the local system
+	LocalStepStarted {
+		/// the step's name
+		step: String,
+	},
+	/// the local system finished being pupdated
+	LocalFinished {
+		/// whether every step succeeded
+		success: bool,
+		/// how long the pupdate took
+		#[serde(serialize_with = "as_seconds")]
+		duration: Duration,
+	},
+}
+
+/// serializes a duration as a number of seconds
+fn as_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// something told about each event of a run as it happens, besides whoever reads the run's events
+pub trait EventSink: Send + 'static {
+	/// takes an event which just happened
+	fn event(&mut self, event: &Event);
+}
+
+impl EventSink for UnboundedSender<Event> {
+	fn event(&mut self, event: &Event) {
+		// a sink which stopped listening doesn't stop the run
+		let _ = self.send(event.clone());
+	}
+}
+
+/// writes each event as a line of json, flushing after each one so the events can be followed as they're written
+#[derive(Debug)]
+pub struct NdjsonSink<W> {
+	/// where the events are written
+	writer: W,
+}
+
+impl<W: Write + Send + 'static> NdjsonSink<W> {
+	/// writes events to the given writer
+	pub fn new(writer: W) -> Self {
+		Self { writer }
+	}
+}
+
+impl<W: Write + Send + 'static> EventSink for NdjsonSink<W> {
+	fn event(&mut self, event: &Event) {
+		// an event which can't be written is left out rather than stopping the run
+		if let Ok(line) = serde_json::to_string(event) {
+			let _ = writeln!(self.writer, "{line}").and_then(|()| self.writer.flush());
+		}
+	}
+}
+
+/// passes each event to the given sink before passing it on to the returned events, so both see the same events in
+/// the same order
+pub fn tee(
+	mut events: UnboundedReceiver<Event>,
+	mut sink: impl EventSink,
+) -> UnboundedReceiver<Event> {
+	let (sender, receiver) = mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(event) = events.recv().await {
+			sink.event(&event);
+			let _ = sender.send(event);
+		}
+	});
+	receiver
+}
+
+/// describes how far off a remote's clock is, like `clock is 90 seconds ahead`
+pub fn describe_skew(skew_seconds: i64) -> String {
+	format!(
+		"clock is {} seconds {}",
+		skew_seconds.unsigned_abs(),
+		if skew_seconds > 0 { "ahead" } else { "behind" }
+	)
+}
+
+/// sends events to whoever is listening, if anyone still is
+pub(crate) fn emit(events: &UnboundedSender<Event>, event: Event) {
+	// a caller which stopped listening doesn't stop the pupdate
+	let _ = events.send(event);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn events_are_written_as_json_lines() {
+		let mut sink = NdjsonSink::new(Vec::new());
+		sink.event(&Event::RemoteRetrying {
+			remote: "web1".to_string(),
+			delay: Duration::from_millis(1500),
+			attempt: 2,
+			attempts: 3,
+		});
+		sink.event(&Event::RemoteOutputLine {
+			remote: "web1".to_string(),
+			stream: OutputStream::Stderr,
+			line: "W: a warning".to_string(),
+		});
+		assert_eq!(
+			String::from_utf8(sink.writer).unwrap(),
+			"{\"event\":\"remote_retrying\",\"remote\":\"web1\",\"delay\":1.5,\"attempt\":2,\"attempts\":3}\n\
+			{\"event\":\"remote_output_line\",\"remote\":\"web1\",\"stream\":\"stderr\",\"line\":\"W: a warning\"}\n"
+		);
+	}
+}
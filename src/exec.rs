@@ -0,0 +1,144 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use pupdate::{
+	manifest::Manifest,
+	progress::Progress,
+	remote::{pupdate_remotes, RemoteOptions, RemotesRun, Runner},
+	summary::{Failure, Summary},
+};
+use serde::Serialize;
+
+use crate::failures::print_failed;
+
+/// the most of each remote's stdout included in json output
+const JSON_STDOUT_LIMIT: usize = 64 * 1024;
+
+/// a remote's result in json output
+#[derive(Debug, Serialize)]
+struct ExecResult {
+	/// whether the command succeeded
+	success: bool,
+	/// what the command wrote to stdout, up to the limit
+	stdout: String,
+	/// whether stdout was cut off at the limit
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	stdout_truncated: bool,
+	/// how the command failed, if it ran to completion and failed
+	#[serde(flatten)]
+	failure: Option<Failure>,
+	/// the error which stopped the command from running to completion, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>,
+	/// whether the command was cancelled before it finished
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	cancelled: bool,
+}
+
+/// prints a remote's output, on the same line as the remote if it's a single line
+fn print_output(remote: &str, stdout: &[u8]) {
+	let stdout = String::from_utf8_lossy(stdout);
+	let lines: Vec<_> = stdout.lines().collect();
+	match lines.as_slice() {
+		[] => println!("{remote}: (no output)"),
+		[line] => println!("{remote}: {line}"),
+		lines => {
+			println!("{remote}:");
+			for line in lines {
+				println!("    {line}");
+			}
+		}
+	}
+}
+
+/// runs a command on every remote the same way remotes are pupdated, printing each remote's output (as json if asked
+/// to) and returning the run's summary
+pub async fn exec<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	options: RemoteOptions,
+	progress: &Progress,
+	verbose: bool,
+	json: bool,
+) -> eyre::Result<Summary> {
+	if let Some(log_dir) = &options.log_dir {
+		Manifest::new(&remotes, &options)?.write(log_dir)?;
+	}
+	let log_format =
+		(options.log_dir.is_some() && !options.merge_output).then_some(options.log_formats);
+	let change_id = options.change_id.clone();
+	let len = remotes.len();
+	let command = options.command.to_command_line();
+	let run = pupdate_remotes(
+		runner,
+		remotes,
+		Arc::new(RemoteOptions {
+			capture_stdout: true,
+			..options
+		}),
+	);
+	let results = if json {
+		// progress would get in the way of the json, so the events go unused
+		let RemotesRun {
+			events, results, ..
+		} = run;
+		drop(events);
+		results.await??
+	} else {
+		println!("running `{command}` on {len} remotes");
+		progress.show_remotes(run, len, verbose).await?
+	};
+
+	let mut summary = Summary {
+		log_format,
+		change_id,
+		..Default::default()
+	};
+	let mut outputs = BTreeMap::new();
+	let mut failed = Vec::new();
+	for (remote, mut result) in results {
+		if !result.success {
+			failed.push(remote.clone());
+		}
+		outputs.insert(remote.clone(), result.stdout.take().unwrap_or_default());
+		summary.remotes.insert(remote, result);
+	}
+
+	if json {
+		let results: BTreeMap<_, _> = summary
+			.remotes
+			.iter()
+			.map(|(remote, result)| {
+				let stdout = &outputs[remote];
+				let kept = &stdout[..stdout.len().min(JSON_STDOUT_LIMIT)];
+				(
+					remote,
+					ExecResult {
+						success: result.success,
+						stdout: String::from_utf8_lossy(kept).into_owned(),
+						stdout_truncated: kept.len() < stdout.len(),
+						failure: result.failure,
+						error: result.error.clone(),
+						cancelled: result.cancelled,
+					},
+				)
+			})
+			.collect();
+		println!("{}", serde_json::to_string_pretty(&results)?);
+		return Ok(summary);
+	}
+
+	for (remote, result) in &summary.remotes {
+		if result.success {
+			print_output(remote, &outputs[remote]);
+		}
+	}
+	println!(
+		"{}/{len} remotes ran the command successfully",
+		len - failed.len()
+	);
+	if !failed.is_empty() {
+		println!("the following remotes failed to run the command:");
+		print_failed(&summary.remotes, &failed);
+	}
+	Ok(summary)
+}
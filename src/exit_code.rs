@@ -0,0 +1,16 @@
+//! the exit codes pupdate uses so scripts can tell what happened during a run
+//!
+//! errors which stop pupdate before anything is pupdated (bad arguments or config, missing executables) exit with 1
+
+/// everything was pupdated successfully
+pub const SUCCESS: u8 = 0;
+/// some remotes failed to pupdate, but the local system was pupdated successfully or skipped
+pub const REMOTES_FAILED: u8 = 10;
+/// the local system failed to pupdate, but every remote was pupdated successfully
+pub const LOCAL_FAILED: u8 = 20;
+/// both the local system and some remotes failed to pupdate
+pub const BOTH_FAILED: u8 = 30;
+/// `pupdate doctor` found problems which stop pupdate from running
+pub const CHECKS_FAILED: u8 = 1;
+/// the run was aborted before everything could be pupdated, by `--resolve-policy abort` or a playbook halting at a failed phase
+pub const ABORTED: u8 = 40;
@@ -0,0 +1,351 @@
+//! the report of failed remotes printed at the end of a run, grouped by what went wrong so a run with many failures
+//! reads as the handful of problems behind them rather than a list of hostnames
+
+use std::{
+	collections::BTreeMap,
+	hash::{DefaultHasher, Hash, Hasher},
+};
+
+use pupdate::{
+	output::format_size,
+	summary::{FailureKind, TargetSummary},
+};
+
+/// what ssh says when it reached a remote but couldn't log in
+const AUTHENTICATION_ERRORS: &[&str] = &[
+	"Permission denied",
+	"Host key verification failed",
+	"Too many authentication failures",
+];
+/// what ssh says when a remote never answered
+const TIMEOUT_ERRORS: &[&str] = &["timed out"];
+/// what apt and dpkg say when a remote is out of disk space
+const LOW_DISK_ERRORS: &[&str] = &[
+	"No space left on device",
+	"You don't have enough free space",
+];
+
+/// what went wrong with a failed remote, in the order they're reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Category {
+	/// ssh couldn't connect through the jump host to the remote
+	JumpHost,
+	/// ssh couldn't connect to the remote
+	Connection,
+	/// ssh connected but couldn't log in
+	Authentication,
+	/// the remote never answered, or ran longer than the timeout
+	Timeout,
+	/// sudo needed a password but had no way to ask for one
+	SudoPassword,
+	/// the remote ran out of disk space
+	LowDisk,
+	/// apt warned about a repository's signature and repository warnings are failures
+	RepoSignatures,
+	/// apt kept back packages and kept back packages are failures
+	KeptBack,
+	/// the update command failed for any other reason
+	UpdateFailed,
+	/// the remote was killed for writing too much output
+	OutputLimit,
+	/// the remote was killed by a signal
+	Killed,
+	/// the pupdate errored before it could finish
+	Errored,
+	/// the remote was skipped without being pupdated
+	Skipped,
+	/// the remote was cancelled
+	Cancelled,
+}
+
+impl Category {
+	/// how the category is headed in the report
+	fn describe(self) -> &'static str {
+		match self {
+			Self::JumpHost => "couldn't connect through the jump host",
+			Self::Connection => "couldn't connect",
+			Self::Authentication => "couldn't authenticate",
+			Self::Timeout => "timed out",
+			Self::SudoPassword => "sudo needed a password",
+			Self::LowDisk => "low on disk space",
+			Self::RepoSignatures => "repository signatures broken",
+			Self::KeptBack => "packages kept back",
+			Self::UpdateFailed => "update failed",
+			Self::OutputLimit => "output limit exceeded",
+			Self::Killed => "killed",
+			Self::Errored => "errored",
+			Self::Skipped => "skipped",
+			Self::Cancelled => "cancelled",
+		}
+	}
+
+	/// gets the category of a failed remote from its result
+	fn of(result: &TargetSummary) -> Self {
+		let tail = result.stderr_tail.as_deref().unwrap_or_default();
+		let mentions = |errors: &[&str]| errors.iter().any(|error| tail.contains(error));
+		if result.cancelled {
+			return Self::Cancelled;
+		}
+		if result.skipped.is_some() {
+			return Self::Skipped;
+		}
+		if result.error.is_some() {
+			return Self::Errored;
+		}
+		match result.failure.map(|failure| failure.kind) {
+			Some(FailureKind::Connection) if mentions(AUTHENTICATION_ERRORS) => {
+				Self::Authentication
+			}
+			Some(FailureKind::Connection) if mentions(TIMEOUT_ERRORS) => Self::Timeout,
+			Some(FailureKind::Connection) => Self::Connection,
+			Some(FailureKind::JumpHost) => Self::JumpHost,
+			Some(FailureKind::SudoPassword) => Self::SudoPassword,
+			Some(FailureKind::Timeout) => Self::Timeout,
+			Some(FailureKind::OutputLimit) => Self::OutputLimit,
+			Some(FailureKind::Signal) => Self::Killed,
+			Some(FailureKind::RepoWarnings) => Self::RepoSignatures,
+			Some(FailureKind::KeptBack) => Self::KeptBack,
+			_ if mentions(LOW_DISK_ERRORS) => Self::LowDisk,
+			_ => Self::UpdateFailed,
+		}
+	}
+}
+
+/// why a failed remote failed, beyond its category
+fn reason(result: &TargetSummary) -> Option<String> {
+	if result.cancelled {
+		None
+	} else if let Some(error) = &result.error {
+		Some(error.clone())
+	} else if let Some(skipped) = &result.skipped {
+		Some(skipped.clone())
+	} else {
+		result.failure.map(|failure| failure.to_string())
+	}
+}
+
+/// hashes what a remote's error says once anything which varies between remotes is taken out, so remotes which failed
+/// the same way hash the same: the remote's own name, numbers like pids, sizes, and times, and case and spacing
+fn normalized_hash(text: &str, remote: &str) -> u64 {
+	let host = remote.split_once('@').map_or(remote, |(_, host)| host);
+	let mut normalized = String::with_capacity(text.len());
+	for word in text.split_whitespace() {
+		if !normalized.is_empty() {
+			normalized.push(' ');
+		}
+		// the remote's name is only taken out as a whole word, since a short name could be part of anything
+		let name = word.trim_matches(|c: char| !c.is_alphanumeric());
+		if name == remote || name == host {
+			normalized.push_str("<remote>");
+			continue;
+		}
+		let mut digits = false;
+		for c in word.chars() {
+			if c.is_ascii_digit() {
+				if !digits {
+					normalized.push('#');
+				}
+				digits = true;
+			} else {
+				normalized.extend(c.to_lowercase());
+				digits = false;
+			}
+		}
+	}
+	let mut hasher = DefaultHasher::new();
+	normalized.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// remotes which failed the same way
+#[derive(Debug)]
+struct Cluster<'a> {
+	/// the remotes, in the order they were given
+	remotes: Vec<&'a str>,
+	/// the result of the first remote, which stands in for the rest
+	result: &'a TargetSummary,
+}
+
+/// groups failed remotes by category, clustering those within a category which failed the same way
+fn group<'a>(
+	results: &'a BTreeMap<String, TargetSummary>,
+	failed: &'a [String],
+) -> BTreeMap<Category, Vec<Cluster<'a>>> {
+	let mut groups: BTreeMap<_, Vec<(u64, Cluster)>> = BTreeMap::new();
+	// failed remotes are always taken from the results, so none are missing
+	for (remote, result) in failed
+		.iter()
+		.filter_map(|remote| Some((remote, results.get(remote)?)))
+	{
+		let reason = reason(result).unwrap_or_default();
+		let tail = result.stderr_tail.as_deref().unwrap_or_default();
+		let hash = normalized_hash(&format!("{reason}\n{tail}"), remote);
+		let clusters = groups.entry(Category::of(result)).or_default();
+		match clusters.iter_mut().find(|(other, _)| *other == hash) {
+			Some((_, cluster)) => cluster.remotes.push(remote),
+			None => clusters.push((
+				hash,
+				Cluster {
+					remotes: vec![remote],
+					result,
+				},
+			)),
+		}
+	}
+	groups
+		.into_iter()
+		.map(|(category, clusters)| {
+			let clusters = clusters.into_iter().map(|(_, cluster)| cluster).collect();
+			(category, clusters)
+		})
+		.collect()
+}
+
+/// prints the failed remotes grouped by what went wrong, with the remotes which failed the same way together and their
+/// stderr's tail printed once. a remote which failed on its own also gets how much output it wrote and where it was
+/// logged, and every remote gets where its diagnostics were captured, if they were
+pub fn print_failed(results: &BTreeMap<String, TargetSummary>, failed: &[String]) {
+	for (category, clusters) in group(results, failed) {
+		let count: usize = clusters.iter().map(|cluster| cluster.remotes.len()).sum();
+		println!("{} ({count}):", category.describe());
+		for Cluster { remotes, result } in clusters {
+			let single = remotes.len() == 1;
+			let size = result
+				.output_bytes()
+				.filter(|_| single)
+				.map(|bytes| format!("{} output", format_size(bytes)));
+			let details: Vec<_> = reason(result).into_iter().chain(size).collect();
+			if details.is_empty() {
+				println!("  {}", remotes.join(", "));
+			} else {
+				println!("  {} ({})", remotes.join(", "), details.join(", "));
+			}
+			if single {
+				for file in result.logs.iter().flat_map(|logs| logs.files()) {
+					println!("    log {}", file.display());
+				}
+			}
+			// diagnostics differ between remotes even when they failed the same way
+			for remote in &remotes {
+				let Some(diagnostics) = results
+					.get(*remote)
+					.and_then(|result| result.diagnostics.as_ref())
+				else {
+					continue;
+				};
+				let whose = if single {
+					String::new()
+				} else {
+					format!(" for {remote}")
+				};
+				if let Some(log) = &diagnostics.log {
+					println!("    diagnostics{whose} captured in {}", log.display());
+				}
+				for failed in &diagnostics.failed {
+					println!("    diagnostics{whose}: {failed}");
+				}
+			}
+			if let Some(tail) = result.stderr_tail.as_deref() {
+				for line in tail.lines() {
+					println!("      {line}");
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use pupdate::summary::Failure;
+
+	use super::*;
+
+	/// the result of a remote which failed the given way with the given stderr
+	fn failed(kind: FailureKind, exit_code: Option<i32>, stderr: &str) -> TargetSummary {
+		TargetSummary {
+			failure: Some(Failure {
+				kind,
+				exit_code,
+				signal: None,
+			}),
+			stderr_tail: Some(stderr.to_string()),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn failures_are_grouped() {
+		let mirror = |remote: &str, pid: u32| {
+			failed(
+				FailureKind::Command,
+				Some(100),
+				&format!("E: Failed to fetch http://mirror.example/pool/libc6.deb  404  Not Found\nE: {remote} apt-get[{pid}] gave up"),
+			)
+		};
+		let results = BTreeMap::from([
+			(
+				"web1".to_string(),
+				failed(
+					FailureKind::Connection,
+					Some(255),
+					"ssh: connect to host web1 port 22: Connection refused",
+				),
+			),
+			(
+				"web2".to_string(),
+				failed(
+					FailureKind::Connection,
+					Some(255),
+					"ssh: connect to host web2 port 22: Connection refused",
+				),
+			),
+			("root@web3".to_string(), mirror("web3", 4112)),
+			("web4".to_string(), mirror("web4", 977)),
+			(
+				"web5".to_string(),
+				failed(
+					FailureKind::Command,
+					Some(100),
+					"dpkg: error processing archive linux-image.deb: No space left on device",
+				),
+			),
+			(
+				"web6".to_string(),
+				failed(FailureKind::Command, Some(1), "E: dpkg was interrupted"),
+			),
+			(
+				"web7".to_string(),
+				failed(
+					FailureKind::Connection,
+					Some(255),
+					"root@web7: Permission denied (publickey).",
+				),
+			),
+			("web8".to_string(), TargetSummary::cancelled()),
+		]);
+		let failed: Vec<_> = results.keys().cloned().collect();
+		let groups: Vec<_> = group(&results, &failed)
+			.into_iter()
+			.map(|(category, clusters)| {
+				let remotes: Vec<_> = clusters
+					.into_iter()
+					.map(|cluster| cluster.remotes)
+					.collect();
+				(category, remotes)
+			})
+			.collect();
+		assert_eq!(
+			groups,
+			[
+				(Category::Connection, vec![vec!["web1", "web2"]]),
+				(Category::Authentication, vec![vec!["web7"]]),
+				(Category::LowDisk, vec![vec!["web5"]]),
+				(
+					Category::UpdateFailed,
+					vec![vec!["root@web3", "web4"], vec!["web6"]]
+				),
+				(Category::Cancelled, vec![vec!["web8"]]),
+			]
+		);
+	}
+}
@@ -0,0 +1,72 @@
+use std::{
+	io::Read,
+	path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{output::output_tail, remote::Runner, shell::command_line};
+
+/// fetches every file in a remote pupdate's run directory into the given local directory, returning the local paths written
+pub async fn fetch_remote_logs<R: Runner>(
+	runner: &R,
+	remote: &str,
+	remote_dir: &str,
+	local_dir: &Path,
+	compressed: bool,
+) -> eyre::Result<Vec<PathBuf>> {
+	let list = runner
+		.run(
+			remote,
+			&command_line(&["sudo", "find", remote_dir, "-type", "f"]),
+		)
+		.await?;
+	if !list.status.success() {
+		eyre::bail!(
+			"failed to list remote logs in {remote_dir}: {}",
+			output_tail(&list.stderr)
+		);
+	}
+
+	let mut fetched = Vec::new();
+	for path in String::from_utf8_lossy(&list.stdout).lines() {
+		let Some(relative) = path.strip_prefix(remote_dir) else {
+			continue;
+		};
+		let relative = Path::new(relative.trim_start_matches('/'));
+		if !relative
+			.components()
+			.all(|component| matches!(component, Component::Normal(_)))
+		{
+			continue;
+		}
+
+		let command = if compressed {
+			command_line(&["sudo", "gzip", "-c", path])
+		} else {
+			command_line(&["sudo", "cat", path])
+		};
+		let output = runner.run(remote, &command).await?;
+		if !output.status.success() {
+			eyre::bail!(
+				"failed to fetch remote log {path}: {}",
+				output_tail(&output.stderr)
+			);
+		}
+		let contents = if compressed {
+			let mut contents = Vec::new();
+			GzDecoder::new(output.stdout.as_slice()).read_to_end(&mut contents)?;
+			contents
+		} else {
+			output.stdout
+		};
+
+		let local = local_dir.join(relative);
+		if let Some(parent) = local.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		tokio::fs::write(&local, contents).await?;
+		fetched.push(local);
+	}
+	Ok(fetched)
+}
@@ -0,0 +1,144 @@
+//! forwarding the controller's flags to pupdate on remotes, as long as the remote's pupdate is new enough to know them
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{remote::Runner, shell::command_line};
+
+/// a pupdate version, compared part by part
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+	/// the major version
+	pub major: u64,
+	/// the minor version
+	pub minor: u64,
+	/// the patch version
+	pub patch: u64,
+}
+
+impl Version {
+	/// parses a version like `0.1.0`, ignoring any pre-release or build suffix
+	pub fn parse(version: &str) -> Option<Self> {
+		let version = version.split(['-', '+']).next()?;
+		let mut parts = version.split('.').map(|part| part.parse().ok());
+		let version = Self {
+			major: parts.next()??,
+			minor: parts.next()??,
+			patch: parts.next()??,
+		};
+		parts.next().is_none().then_some(version)
+	}
+}
+
+impl fmt::Display for Version {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+	}
+}
+
+/// parses the output of `pupdate --version`, like `pupdate 0.1.0`
+fn parse_version_output(output: &[u8]) -> Option<Version> {
+	let output = String::from_utf8_lossy(output);
+	let version = output.lines().next()?.trim().strip_prefix("pupdate ")?;
+	Version::parse(version)
+}
+
+/// a flag forwarded to pupdate on remotes
+#[derive(Debug, Clone)]
+pub struct ForwardedFlag {
+	/// the flag followed by its values, if it has any
+	pub args: Vec<String>,
+	/// the first pupdate version which knows the flag
+	pub since: Version,
+}
+
+/// the flags forwarded to a remote and the ones left out because the remote's pupdate wouldn't know them
+#[derive(Debug, Default)]
+pub struct ForwardedArgs {
+	/// the arguments to add to the remote command
+	pub args: Vec<String>,
+	/// the flags left out
+	pub omitted: Vec<String>,
+	/// why the flags were left out, if any were
+	pub reason: Option<String>,
+	/// the remote's pupdate version, if it's known
+	pub version: Option<Version>,
+}
+
+/// the flags forwarded to pupdate on remotes
+#[derive(Debug, Clone)]
+pub struct Forwarding {
+	/// the program the remote command runs, which is asked for its version
+	pub program: String,
+	/// the flags to forward
+	pub flags: Vec<ForwardedFlag>,
+	/// the pupdate versions already known for remotes, which aren't asked for their version again
+	pub known_versions: BTreeMap<String, Version>,
+}
+
+impl Forwarding {
+	/// gives every flag to this same pupdate, which knows all of them
+	pub fn to_own_pupdate(&self) -> ForwardedArgs {
+		ForwardedArgs {
+			args: self
+				.flags
+				.iter()
+				.flat_map(|flag| flag.args.clone())
+				.collect(),
+			version: Version::parse(env!("CARGO_PKG_VERSION")),
+			..Default::default()
+		}
+	}
+
+	/// finds out which pupdate version a remote has, unless it's already known, and which of the flags it can be given
+	pub async fn for_remote<R: Runner>(&self, runner: &R, remote: &str) -> ForwardedArgs {
+		let version = match self.known_versions.get(remote) {
+			Some(version) => Some(*version),
+			None => runner
+				.run(remote, &command_line(&[self.program.as_str(), "--version"]))
+				.await
+				.ok()
+				.filter(|output| output.status.success())
+				.and_then(|output| parse_version_output(&output.stdout)),
+		};
+		let mut forwarded = ForwardedArgs {
+			version,
+			..Default::default()
+		};
+		for flag in &self.flags {
+			if version.is_some_and(|version| version >= flag.since) {
+				forwarded.args.extend(flag.args.iter().cloned());
+			} else if let Some(name) = flag.args.first() {
+				forwarded.omitted.push(name.clone());
+			}
+		}
+		if !forwarded.omitted.is_empty() {
+			forwarded.reason = Some(match version {
+				Some(version) => format!("the remote's pupdate {version} is too old"),
+				None => "the remote's pupdate version couldn't be found".to_string(),
+			});
+		}
+		forwarded
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn versions_are_parsed() {
+		let version = Version {
+			major: 0,
+			minor: 1,
+			patch: 0,
+		};
+		assert_eq!(parse_version_output(b"pupdate 0.1.0\n"), Some(version));
+		assert_eq!(Version::parse("0.1.0-rc.1"), Some(version));
+		assert_eq!(
+			parse_version_output(b"sudo: pupdate: command not found\n"),
+			None
+		);
+		assert_eq!(Version::parse("0.1"), None);
+		assert!(Version::parse("0.10.0") > Version::parse("0.9.3"));
+	}
+}
@@ -0,0 +1,101 @@
+use std::{collections::BTreeSet, path::Path};
+
+use crate::sources::parse_remote_lines;
+
+/// a list of remotes read from a file, guarding which remotes a run may act on
+#[derive(Debug)]
+pub struct RemoteList {
+	/// the remotes in the list
+	remotes: BTreeSet<String>,
+	/// the file the list was read from, for pointing at it in errors
+	path: String,
+}
+
+impl RemoteList {
+	/// parses a list of remotes, one per line, skipping blank lines and comments starting with `#`
+	pub fn parse(contents: &str, path: &str) -> Self {
+		Self {
+			remotes: parse_remote_lines(contents).into_iter().collect(),
+			path: path.to_string(),
+		}
+	}
+
+	/// loads a list of remotes from the given file, which has to exist so a misplaced list can't quietly stop
+	/// guarding anything
+	pub fn load(path: &Path) -> eyre::Result<Self> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| eyre::eyre!("failed to read remote list {}: {e}", path.display()))?;
+		Ok(Self::parse(&contents, &path.display().to_string()))
+	}
+
+	/// whether the list has the given remote
+	fn contains(&self, remote: &str) -> bool {
+		self.remotes.contains(remote)
+	}
+}
+
+/// drops the remotes denied by `deny` and the ones `allow` doesn't have from the resolved remotes, erroring instead if
+/// any of them were explicitly requested, since those were clearly meant to be acted on
+pub fn guard_remotes(
+	remotes: Vec<String>,
+	requested: &[String],
+	deny: Option<&RemoteList>,
+	allow: Option<&RemoteList>,
+) -> eyre::Result<Vec<String>> {
+	for remote in requested {
+		if let Some(deny) = deny.filter(|deny| deny.contains(remote)) {
+			eyre::bail!(
+				"refusing to act on {remote}, which is denied by {}",
+				deny.path
+			);
+		}
+		if let Some(allow) = allow.filter(|allow| !allow.contains(remote)) {
+			eyre::bail!(
+				"refusing to act on {remote}, which isn't allowed by {}",
+				allow.path
+			);
+		}
+	}
+	let (kept, dropped): (Vec<_>, Vec<_>) = remotes.into_iter().partition(|remote| {
+		!deny.is_some_and(|deny| deny.contains(remote))
+			&& allow.is_none_or(|allow| allow.contains(remote))
+	});
+	if !dropped.is_empty() {
+		eprintln!(
+			"leaving out {} remotes guarded by the deny or allow file: {}",
+			dropped.len(),
+			dropped.join(", ")
+		);
+	}
+	Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn guarded_remotes_are_dropped_unless_requested() {
+		let deny = RemoteList::parse("# never touch\ndb1 # primary\n\n", "deny");
+		let allow = RemoteList::parse("db1\nweb1\nweb2\n", "allow");
+		let remotes = ["db1", "web1", "web3"].map(str::to_string).to_vec();
+		assert_eq!(
+			guard_remotes(remotes.clone(), &[], Some(&deny), None).unwrap(),
+			["web1", "web3"]
+		);
+		assert_eq!(
+			guard_remotes(remotes.clone(), &[], Some(&deny), Some(&allow)).unwrap(),
+			["web1"]
+		);
+		let error = guard_remotes(remotes.clone(), &remotes, Some(&deny), None).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"refusing to act on db1, which is denied by deny"
+		);
+		let error = guard_remotes(remotes.clone(), &remotes[1..], None, Some(&allow)).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"refusing to act on web3, which isn't allowed by allow"
+		);
+	}
+}
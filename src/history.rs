@@ -0,0 +1,288 @@
+//! what this controller remembers about each remote across runs
+
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use directories::BaseDirs;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// the name of the history file kept in the data directory
+pub const HISTORY_FILENAME: &str = "history.json";
+/// how many runs are remembered, dropping the oldest once there are more, so the history can't grow without bound
+const MAX_RUNS: usize = 1000;
+
+/// what's remembered about a single remote
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RemoteHistory {
+	/// when the remote was last pupdated successfully, as a unix timestamp
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub last_success: Option<i64>,
+	/// how many runs in a row have failed to pupdate the remote, up to the latest one
+	#[serde(default, skip_serializing_if = "is_zero")]
+	pub consecutive_failures: u32,
+	/// the change ticket the latest run to pupdate the remote was tagged with, if it was
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub last_change_id: Option<String>,
+	/// how many runs in a row have had apt keep back packages on the remote, up to the latest one
+	#[serde(default, skip_serializing_if = "is_zero")]
+	pub consecutive_kept_back: u32,
+	/// the packages apt kept back in the latest run, if it kept any back
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub kept_back: Vec<String>,
+}
+
+/// whether a count is zero, so it can be left out of the history
+fn is_zero(count: &u32) -> bool {
+	*count == 0
+}
+
+/// what's remembered about a single run which pupdated remotes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+	/// when the run finished pupdating remotes, as a unix timestamp
+	pub at: i64,
+	/// how many remotes were pupdated, leaving out skipped and cancelled remotes
+	pub remotes: usize,
+	/// how many of the pupdated remotes failed
+	pub failed: usize,
+	/// how long pupdating every remote took added together
+	pub sequential_seconds: f64,
+	/// how long pupdating the remotes took from start to finish
+	pub wall_seconds: f64,
+}
+
+/// the runs of a single iso week added together, for spotting trends across weeks
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WeekStats {
+	/// the week, like `2025-W02`
+	pub week: String,
+	/// how many runs there were
+	pub runs: usize,
+	/// how many remotes a run pupdated on average
+	pub average_remotes: f64,
+	/// how long a remote took to pupdate on average
+	pub average_remote_seconds: f64,
+	/// the share of pupdated remotes which failed, from 0 to 1
+	pub failure_rate: f64,
+	/// how long pupdating every remote took added together
+	pub sequential_seconds: f64,
+	/// how long the runs took from start to finish added together
+	pub wall_seconds: f64,
+	/// how many times faster pupdating remotes concurrently was than pupdating them one after another
+	pub speedup: f64,
+}
+
+/// what this controller remembers about remotes across runs, keyed by remote
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+	/// each remote's history
+	#[serde(default)]
+	pub remotes: BTreeMap<String, RemoteHistory>,
+	/// the runs which pupdated remotes, oldest first
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub runs: Vec<RunRecord>,
+}
+
+impl History {
+	/// gets the default path of the history file, in the local data directory (or temp directory)
+	pub fn default_path() -> PathBuf {
+		BaseDirs::new()
+			.map(|bd| bd.data_local_dir().to_path_buf())
+			.unwrap_or_else(std::env::temp_dir)
+			.join("pupdate")
+			.join(HISTORY_FILENAME)
+	}
+
+	/// loads the history from the given path, which is empty if the file doesn't exist yet
+	pub fn load(path: &Path) -> eyre::Result<Self> {
+		match std::fs::read_to_string(path) {
+			Ok(contents) => serde_json::from_str(&contents)
+				.map_err(|e| eyre::eyre!("failed to parse history {}: {e}", path.display())),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => eyre::bail!("failed to read history {}: {e}", path.display()),
+		}
+	}
+
+	/// saves the history to the given path, replacing the file at once so a crash can't leave it half written
+	pub fn save(&self, path: &Path) -> eyre::Result<()> {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(|e| {
+				eyre::eyre!(
+					"failed to create history directory {}: {e}",
+					parent.display()
+				)
+			})?;
+		}
+		let temp = path.with_extension("json.tmp");
+		std::fs::write(&temp, serde_json::to_string_pretty(self)?)
+			.and_then(|()| std::fs::rename(&temp, path))
+			.map_err(|e| eyre::eyre!("failed to write history {}: {e}", path.display()))?;
+		Ok(())
+	}
+
+	/// records whether a run pupdated a remote successfully, finishing at the given time, along with the change ticket
+	/// the run was tagged with
+	pub fn record(
+		&mut self,
+		remote: &str,
+		success: bool,
+		at: OffsetDateTime,
+		change_id: Option<&str>,
+	) {
+		let history = self.remotes.entry(remote.to_string()).or_default();
+		history.last_change_id = change_id.map(str::to_string);
+		if success {
+			history.last_success = Some(at.unix_timestamp());
+			history.consecutive_failures = 0;
+		} else {
+			history.consecutive_failures += 1;
+		}
+	}
+
+	/// records the packages apt kept back on a remote in a run which got as far as upgrading it, if any
+	pub fn record_kept_back(&mut self, remote: &str, kept_back: &[String]) {
+		let history = self.remotes.entry(remote.to_string()).or_default();
+		if kept_back.is_empty() {
+			history.consecutive_kept_back = 0;
+		} else {
+			history.consecutive_kept_back += 1;
+		}
+		history.kept_back = kept_back.to_vec();
+	}
+
+	/// records a run which pupdated remotes, forgetting the oldest runs once there are too many
+	pub fn record_run(&mut self, run: RunRecord) {
+		self.runs.push(run);
+		if self.runs.len() > MAX_RUNS {
+			self.runs.drain(..self.runs.len() - MAX_RUNS);
+		}
+	}
+
+	/// adds up the remembered runs by the iso week they finished in, oldest week first
+	pub fn weekly_stats(&self) -> Vec<WeekStats> {
+		let mut weeks: BTreeMap<(i32, u8), Vec<&RunRecord>> = BTreeMap::new();
+		for run in &self.runs {
+			let Ok(at) = OffsetDateTime::from_unix_timestamp(run.at) else {
+				continue;
+			};
+			let (year, week, _) = at.to_iso_week_date();
+			weeks.entry((year, week)).or_default().push(run);
+		}
+		weeks
+			.into_iter()
+			.map(|((year, week), runs)| {
+				let remotes: usize = runs.iter().map(|run| run.remotes).sum();
+				let failed: usize = runs.iter().map(|run| run.failed).sum();
+				let sequential_seconds: f64 = runs.iter().map(|run| run.sequential_seconds).sum();
+				let wall_seconds: f64 = runs.iter().map(|run| run.wall_seconds).sum();
+				let ratio = |a: f64, b: f64| if b > 0.0 { a / b } else { 0.0 };
+				WeekStats {
+					week: format!("{year}-W{week:02}"),
+					runs: runs.len(),
+					average_remotes: ratio(remotes as f64, runs.len() as f64),
+					average_remote_seconds: ratio(sequential_seconds, remotes as f64),
+					failure_rate: ratio(failed as f64, remotes as f64),
+					sequential_seconds,
+					wall_seconds,
+					speedup: ratio(sequential_seconds, wall_seconds),
+				}
+			})
+			.collect()
+	}
+
+	/// gets how many runs in a row have failed to pupdate a remote
+	pub fn consecutive_failures(&self, remote: &str) -> u32 {
+		self.remotes
+			.get(remote)
+			.map_or(0, |history| history.consecutive_failures)
+	}
+
+	/// gets how long ago a remote was last pupdated successfully, if it ever was
+	pub fn since_last_success(&self, remote: &str, now: OffsetDateTime) -> Option<Duration> {
+		let last_success = self.remotes.get(remote)?.last_success?;
+		// a last success in the future (from the clock going backwards) counts as just now
+		Some(Duration::from_secs(
+			(now.unix_timestamp() - last_success).max(0) as u64,
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn last_success_is_tracked_per_remote() {
+		let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+		let mut history = History::default();
+		history.record("a", true, now - Duration::from_secs(47 * 60), None);
+		assert_eq!(
+			history.since_last_success("a", now),
+			Some(Duration::from_secs(47 * 60))
+		);
+		assert_eq!(history.since_last_success("b", now), None);
+		history.record("a", true, now, None);
+		assert_eq!(history.since_last_success("a", now), Some(Duration::ZERO));
+	}
+
+	#[test]
+	fn runs_are_added_up_by_week() {
+		let monday = 1_736_121_600; // 2025-01-06, the start of 2025-W02
+		let run = |at, remotes, failed, sequential_seconds, wall_seconds| RunRecord {
+			at,
+			remotes,
+			failed,
+			sequential_seconds,
+			wall_seconds,
+		};
+		let mut history = History::default();
+		history.record_run(run(monday - 60, 2, 0, 100.0, 50.0));
+		history.record_run(run(monday + 60, 4, 1, 400.0, 100.0));
+		history.record_run(run(monday + 3 * 24 * 60 * 60, 6, 2, 200.0, 100.0));
+		let weeks = history.weekly_stats();
+		assert_eq!(weeks.len(), 2);
+		assert_eq!(weeks[0].week, "2025-W01");
+		assert_eq!(
+			weeks[1],
+			WeekStats {
+				week: "2025-W02".to_string(),
+				runs: 2,
+				average_remotes: 5.0,
+				average_remote_seconds: 60.0,
+				failure_rate: 0.3,
+				sequential_seconds: 600.0,
+				wall_seconds: 200.0,
+				speedup: 3.0,
+			}
+		);
+	}
+
+	#[test]
+	fn failure_streaks_reset_on_success() {
+		let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+		let mut history = History::default();
+		history.record("a", false, now, None);
+		history.record("a", false, now, None);
+		assert_eq!(history.consecutive_failures("a"), 2);
+		history.record("a", true, now, None);
+		history.record("a", false, now, None);
+		assert_eq!(history.consecutive_failures("a"), 1);
+		assert_eq!(history.consecutive_failures("b"), 0);
+	}
+
+	#[test]
+	fn kept_back_streaks_reset_once_upgraded() {
+		let mut history = History::default();
+		let kept_back = ["systemd".to_string()];
+		history.record_kept_back("a", &kept_back);
+		history.record_kept_back("a", &kept_back);
+		assert_eq!(history.remotes["a"].consecutive_kept_back, 2);
+		history.record_kept_back("a", &[]);
+		assert_eq!(history.remotes["a"].consecutive_kept_back, 0);
+		assert!(history.remotes["a"].kept_back.is_empty());
+	}
+}
@@ -0,0 +1,40 @@
+//! pupdate updates remote systems alongside the local system
+//!
+//! the pupdate binary is one consumer of this library: [`remote::pupdate_remotes`] starts a run of remotes and hands
+//! back a stream of [`events::Event`]s, so other frontends can drive their own display of a run instead of using
+//! [`progress::Progress`]
+
+// the schema of `summary.json` is one large `json!` literal
+#![recursion_limit = "256"]
+
+pub mod approval;
+pub mod config;
+pub mod detection;
+pub mod diagnostics;
+pub mod duration;
+pub mod events;
+pub mod fetch;
+pub mod forward;
+pub mod history;
+pub mod local;
+pub mod logs;
+pub mod managers;
+pub mod manifest;
+pub mod offline;
+pub mod output;
+pub mod phase;
+pub mod playbook;
+pub mod progress;
+pub mod remote;
+pub mod resolve;
+pub mod retry;
+pub mod risk;
+pub mod schema;
+pub mod services;
+pub mod shell;
+pub mod silence;
+pub mod state;
+pub mod summary;
+pub mod verify;
+pub mod webhook;
+pub mod window;
@@ -0,0 +1,330 @@
+use std::{
+	path::PathBuf,
+	process::{Output, Stdio},
+	time::{Duration, Instant},
+};
+
+use tokio::{
+	io::{AsyncBufReadExt, AsyncRead, BufReader},
+	process::Command,
+	sync::mpsc::UnboundedSender,
+};
+
+use crate::{
+	config::Step,
+	events::{emit, Event, OutputStream},
+	logs::{
+		sanitize_file_name, LogFormats, LogHeader, LogLayout, LogPaths, LogWriter, StreamLog,
+		LOCAL_LOG_DIR,
+	},
+	output::{
+		apt_changed, kept_back, mirror_hosts, repo_warnings, sudo_needs_password,
+		DEFAULT_OUTPUT_LIMIT,
+	},
+	retry::RetryPolicy,
+	shell::command_line,
+	summary::{Failure, FailureKind, LocalSummary, StepSummary, TargetSummary},
+};
+
+/// what the local system is called in the stream log
+const LOCAL_TARGET: &str = "local";
+
+/// the io scheduling class local steps can be run with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IoniceClass {
+	/// only use the disk when nothing else wants it
+	Idle,
+	/// share the disk with everything else, at the lowest priority
+	BestEffort,
+}
+
+impl IoniceClass {
+	/// the ionice arguments selecting this class
+	fn args(self) -> &'static [&'static str] {
+		match self {
+			Self::Idle => &["-c", "3"],
+			Self::BestEffort => &["-c", "2", "-n", "7"],
+		}
+	}
+}
+
+/// how much local steps yield to everything else running on the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Priority {
+	/// the niceness to run steps with, if it's changed
+	pub nice: Option<u8>,
+	/// the io scheduling class to run steps with, if it's changed
+	pub ionice: Option<IoniceClass>,
+}
+
+impl Priority {
+	/// gets the command each step is wrapped in, which is empty when the priority isn't changed
+	pub fn wrapper(self) -> Vec<String> {
+		let mut wrapper = Vec::new();
+		if let Some(nice) = self.nice {
+			wrapper.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+		}
+		if let Some(class) = self.ionice {
+			wrapper.push("ionice".to_string());
+			wrapper.extend(class.args().iter().map(|arg| arg.to_string()));
+		}
+		wrapper
+	}
+}
+
+/// options for pupdating the local system
+#[derive(Debug)]
+pub struct LocalOptions {
+	/// the run directory to log to, if any
+	pub log_dir: Option<PathBuf>,
+	/// how logs are laid out inside the run directory
+	pub log_layout: LogLayout,
+	/// which files steps' logs are written to and how
+	pub log_formats: LogFormats,
+	/// at most how long lines written to the logs wait before they're flushed
+	pub log_flush_interval: Duration,
+	/// the log every target's lines are appended to as they arrive, if there is one
+	pub stream_log: Option<StreamLog>,
+	/// whether to report the package mirrors contacted
+	pub report_sources: bool,
+	/// how failed steps are retried
+	pub retry: RetryPolicy,
+	/// the priority steps are run with
+	pub priority: Priority,
+	/// whether to print extra details, such as retry delays
+	pub verbose: bool,
+	/// where to send events, if anywhere
+	pub events: Option<UnboundedSender<Event>>,
+	/// the change ticket the run was tagged with, recorded in logs, if any
+	pub change_id: Option<String>,
+	/// whether apt warning about a repository's signature fails the step instead of only being recorded
+	pub fail_on_repo_warnings: bool,
+	/// whether apt keeping back packages fails the step instead of only being recorded
+	pub fail_on_kept_back: bool,
+}
+
+/// reads everything from a step's output stream, sending each line to the log writer and the stream log as it arrives
+async fn record_lines(
+	stream: impl AsyncRead + Unpin,
+	kind: OutputStream,
+	writer: Option<&LogWriter>,
+	stream_log: Option<&StreamLog>,
+) -> std::io::Result<Vec<u8>> {
+	let mut reader = BufReader::new(stream);
+	let mut output = Vec::new();
+	loop {
+		let start = output.len();
+		if reader.read_until(b'\n', &mut output).await? == 0 {
+			return Ok(output);
+		}
+		let line = String::from_utf8_lossy(&output[start..]);
+		let line = line.trim_end_matches(['\r', '\n']);
+		if let Some(log) = stream_log {
+			log.line(LOCAL_TARGET, kind, line);
+		}
+		if let Some(writer) = writer {
+			writer.line(kind, line.to_string());
+		}
+	}
+}
+
+/// runs a step's command, sending each line of its output to the log writer and the stream log as it arrives if there
+/// are either
+async fn run_step(
+	program: &str,
+	args: &[&String],
+	writer: Option<&LogWriter>,
+	stream_log: Option<&StreamLog>,
+) -> std::io::Result<Output> {
+	if writer.is_none() && stream_log.is_none() {
+		return Command::new(program).args(args).output().await;
+	}
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()?;
+	let stdout = child.stdout.take().expect("stdout is piped");
+	let stderr = child.stderr.take().expect("stderr is piped");
+	let (stdout, stderr) = tokio::try_join!(
+		record_lines(stdout, OutputStream::Stdout, writer, stream_log),
+		record_lines(stderr, OutputStream::Stderr, writer, stream_log),
+	)?;
+	Ok(Output {
+		status: child.wait().await?,
+		stdout,
+		stderr,
+	})
+}
+
+/// pupdates the local system by running each step in order, stopping at the first failure
+pub async fn pupdate_local(steps: &[Step], options: &LocalOptions) -> eyre::Result<LocalSummary> {
+	let start = Instant::now();
+	let mut summaries = Vec::with_capacity(steps.len());
+	let mut changed = None;
+	let wrapper = options.priority.wrapper();
+	for step in steps {
+		if step.command.is_empty() {
+			eyre::bail!("step {} has an empty command", step.name);
+		}
+		// niceness and the io class are inherited through sudo, so wrapping the whole command lowers the package
+		// manager's priority too
+		let command: Vec<_> = wrapper.iter().chain(&step.command).collect();
+		let (program, args) = (command[0], &command[1..]);
+		if let Some(events) = &options.events {
+			emit(
+				events,
+				Event::LocalStepStarted {
+					step: step.name.clone(),
+				},
+			);
+		}
+		let logged = command_line(&command);
+		let header = LogHeader {
+			command: Some(&logged),
+			change_id: options.change_id.as_deref(),
+			invocation: None,
+		};
+		let dir = options
+			.log_dir
+			.as_ref()
+			.map(|log_dir| log_dir.join(LOCAL_LOG_DIR));
+		let name = sanitize_file_name(&step.name);
+		let logs = match &dir {
+			Some(dir) => Some(
+				LogPaths::create_formatted_in(dir, &name, options.log_layout, options.log_formats)
+					.await?,
+			),
+			None => None,
+		};
+		let writer = match &logs {
+			Some(logs) if options.log_formats.streams_lines() => Some(
+				LogWriter::start(
+					logs,
+					options.log_formats,
+					header,
+					DEFAULT_OUTPUT_LIMIT,
+					options.log_flush_interval,
+				)
+				.await?,
+			),
+			_ => None,
+		};
+		let mut retry = 0;
+		let output = loop {
+			if retry > 0 {
+				// a retry starts the logs over, like the output
+				if let Some(writer) = &writer {
+					writer.restart();
+				}
+			}
+			let output =
+				run_step(program, args, writer.as_ref(), options.stream_log.as_ref()).await?;
+			if output.status.success() || retry >= options.retry.retries {
+				break output;
+			}
+			let delay = options.retry.delay(retry);
+			if options.verbose {
+				println!(
+					"step {} failed, retrying in {:.1} seconds",
+					step.name,
+					delay.as_secs_f64()
+				);
+			}
+			tokio::time::sleep(delay).await;
+			retry += 1;
+		};
+		if let Some(writer) = writer {
+			writer.finish().await?;
+		}
+		if let Some(logs) = &logs {
+			logs.write(header, &output.stdout, &output.stderr, options.log_formats)
+				.await?;
+		}
+		// apt only warns about repositories it can't verify, so a step can succeed without them having been updated
+		let repo_warnings = repo_warnings(&[&output.stdout, &output.stderr]);
+		let failed_on_warnings =
+			output.status.success() && options.fail_on_repo_warnings && !repo_warnings.is_empty();
+		let kept_back = kept_back(&output.stdout);
+		let failed_on_kept_back = output.status.success()
+			&& !failed_on_warnings
+			&& options.fail_on_kept_back
+			&& !kept_back.is_empty();
+		let success = output.status.success() && !failed_on_warnings && !failed_on_kept_back;
+		if let Some(step_changed) = apt_changed(&output.stdout) {
+			changed = Some(changed.unwrap_or(false) || step_changed);
+		}
+		let sources = options.report_sources.then(|| mirror_hosts(&output.stdout));
+		// only sudo needing a password is worth telling apart, since it needs fixing before any step can succeed
+		let kind = if failed_on_warnings {
+			Some(FailureKind::RepoWarnings)
+		} else if failed_on_kept_back {
+			Some(FailureKind::KeptBack)
+		} else {
+			(!success && sudo_needs_password(&output.stderr)).then_some(FailureKind::SudoPassword)
+		};
+		let failure = kind.map(|kind| Failure {
+			kind,
+			exit_code: output.status.code(),
+			signal: None,
+		});
+		let result = TargetSummary {
+			success,
+			logs,
+			sources,
+			failure,
+			stdout_bytes: Some(output.stdout.len() as u64),
+			stderr_bytes: Some(output.stderr.len() as u64),
+			repo_warnings,
+			kept_back,
+			..Default::default()
+		};
+		if let Some(dir) = &dir {
+			options.log_layout.write_result(dir, &name, &result).await?;
+		}
+		summaries.push(StepSummary {
+			name: step.name.clone(),
+			result,
+		});
+		if !success {
+			break;
+		}
+	}
+	let sources = options.report_sources.then(|| {
+		let mut sources: Vec<String> = Vec::new();
+		for source in summaries
+			.iter()
+			.filter_map(|step| step.result.sources.as_ref())
+			.flatten()
+		{
+			if !sources.contains(source) {
+				sources.push(source.clone());
+			}
+		}
+		sources
+	});
+	let success = summaries.iter().all(|step| step.result.success);
+	let kept_back = summaries
+		.iter()
+		.any(|step| !step.result.kept_back.is_empty());
+	if let Some(events) = &options.events {
+		emit(
+			events,
+			Event::LocalFinished {
+				success,
+				duration: start.elapsed(),
+			},
+		);
+	}
+	Ok(LocalSummary {
+		success,
+		// steps which don't run apt can't be told apart from ones which changed something, so at least one step has to
+		// report that apt changed nothing
+		// packages kept back still need upgrading, so they're not up to date either
+		up_to_date: success && changed == Some(false) && !kept_back,
+		sources,
+		steps: summaries,
+		..Default::default()
+	})
+}
@@ -0,0 +1,947 @@
+use std::{
+	io::SeekFrom,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use time::{format_description::FormatItem, OffsetDateTime};
+use tokio::{
+	fs::File,
+	io::{AsyncSeekExt, AsyncWriteExt, BufWriter},
+	sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+	task::JoinHandle,
+	time::Instant,
+};
+
+use crate::{events::OutputStream, phase::Phase, remote::Invocation, summary::TargetSummary};
+
+/// the directory inside the run directory where remote logs are written
+pub const REMOTE_LOG_DIR: &str = "remotes";
+/// the directory inside the run directory where local logs are written
+pub const LOCAL_LOG_DIR: &str = "local";
+/// the file in each target's log directory recording its result
+pub const RESULT_FILENAME: &str = "result.json";
+/// how long lines written to streamed logs wait to be flushed by default, so following a log with `tail -f` shows
+/// output as it arrives without flushing after every line
+pub const DEFAULT_LOG_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+/// the file written to check that the run directory is writable
+const PROBE_FILENAME: &str = ".pupdate-probe";
+/// the format used for run directory names in utc, avoiding characters which aren't valid in paths on windows
+const RUN_DIR_FORMAT_UTC: &[FormatItem] =
+	time::macros::format_description!("[year]-[month]-[day]T[hour]-[minute]-[second]Z");
+/// the format used for run directory names in local time, including the offset from utc
+const RUN_DIR_FORMAT_LOCAL: &[FormatItem] = time::macros::format_description!(
+	"[year]-[month]-[day]T[hour]-[minute]-[second][offset_hour sign:mandatory][offset_minute]"
+);
+/// the format of the date a log path template's `{date}` is replaced by
+const TEMPLATE_DATE_FORMAT: &[FormatItem] =
+	time::macros::format_description!("[year]-[month]-[day]");
+/// what's replaced by the remote's log name in a log path template
+pub const HOST_PLACEHOLDER: &str = "{host}";
+/// what's replaced by the run's date in a log path template
+pub const DATE_PLACEHOLDER: &str = "{date}";
+/// what's replaced by the stream a log holds in a log path template, like `stdout` or `combined`
+pub const STREAM_PLACEHOLDER: &str = "{stream}";
+/// the format of the timestamp each line of timestamped logs starts with, always in utc
+const LINE_TIMESTAMP_FORMAT: &[FormatItem] = time::macros::format_description!(
+	"[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+);
+
+/// a format target's logs can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	/// stdout and stderr logged to separate files exactly as the command wrote them
+	Raw,
+	/// stdout and stderr logged to separate files with each line starting with when it arrived
+	Timestamped,
+	/// stdout and stderr interleaved into a single file in the order their lines arrived
+	Combined,
+}
+
+/// the formats a run's logs are written in, which can be a single format or a list of them in the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LogFormatSetting {
+	/// a single format
+	One(LogFormat),
+	/// several formats, each written alongside the others
+	Many(Vec<LogFormat>),
+}
+
+impl LogFormatSetting {
+	/// gets the formats as a list
+	pub fn formats(&self) -> &[LogFormat] {
+		match self {
+			Self::One(format) => std::slice::from_ref(format),
+			Self::Many(formats) => formats,
+		}
+	}
+}
+
+/// which files a target's logs are written to and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(into = "Vec<LogFormat>")]
+pub struct LogFormats {
+	/// whether stdout and stderr are logged to separate files
+	pub split: bool,
+	/// whether stdout and stderr are interleaved into a single file
+	pub combined: bool,
+	/// whether each line starts with when it arrived
+	pub timestamped: bool,
+}
+
+impl Default for LogFormats {
+	fn default() -> Self {
+		Self {
+			split: true,
+			combined: false,
+			timestamped: false,
+		}
+	}
+}
+
+impl LogFormats {
+	/// works out which files to write from the chosen formats, which default to raw when none are chosen
+	pub fn new(formats: &[LogFormat]) -> eyre::Result<Self> {
+		if formats.is_empty() {
+			return Ok(Self::default());
+		}
+		let raw = formats.contains(&LogFormat::Raw);
+		let timestamped = formats.contains(&LogFormat::Timestamped);
+		if raw && timestamped {
+			eyre::bail!("the raw and timestamped log formats can't be used together, since both write stdout.log and stderr.log");
+		}
+		Ok(Self {
+			split: raw || timestamped,
+			combined: formats.contains(&LogFormat::Combined),
+			timestamped,
+		})
+	}
+
+	/// whether any of a target's logs are written from its lines as they arrive, with a [`LogWriter`]
+	pub fn streams_lines(self) -> bool {
+		self.combined || self.timestamped
+	}
+}
+
+impl From<LogFormats> for Vec<LogFormat> {
+	fn from(formats: LogFormats) -> Self {
+		let mut list = Vec::new();
+		if formats.split {
+			list.push(if formats.timestamped {
+				LogFormat::Timestamped
+			} else {
+				LogFormat::Raw
+			});
+		}
+		if formats.combined {
+			list.push(LogFormat::Combined);
+		}
+		list
+	}
+}
+
+/// formats a line for a timestamped or combined log, starting it with the utc time it arrived if it's timestamped
+fn format_line(at: OffsetDateTime, line: &str, timestamped: bool) -> eyre::Result<String> {
+	if timestamped {
+		Ok(format!("{} {line}\n", at.format(LINE_TIMESTAMP_FORMAT)?))
+	} else {
+		Ok(format!("{line}\n"))
+	}
+}
+
+/// the header lines a target's logs start with
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogHeader<'a> {
+	/// the command which was run, if a single one was, recorded at the top of stdout and combined logs
+	pub command: Option<&'a str>,
+	/// the change ticket the run was tagged with, if any, recorded at the top of every log
+	pub change_id: Option<&'a str>,
+	/// exactly what was spawned to run the command on a remote, if it's known, recorded at the top of every log
+	pub invocation: Option<&'a Invocation>,
+}
+
+impl LogHeader<'_> {
+	/// gets the header lines for a log, which only records the command if the log has stdout in it
+	fn lines(self, has_stdout: bool) -> String {
+		let mut lines = String::new();
+		if let Some(command) = self.command.filter(|_| has_stdout) {
+			lines.push_str(&format!("# command: {command}\n"));
+		}
+		if let Some(change_id) = self.change_id {
+			lines.push_str(&format!("# change: {change_id}\n"));
+		}
+		for line in self
+			.invocation
+			.iter()
+			.flat_map(|invocation| invocation.lines())
+		{
+			lines.push_str(&format!("# {line}\n"));
+		}
+		lines
+	}
+}
+
+/// something sent to a [`LogWriter`]'s task
+#[derive(Debug)]
+enum LogMessage {
+	/// a line which arrived at the given time on the given stream
+	Line(OffsetDateTime, OutputStream, String),
+	/// the target is being retried from the start, so everything written so far is dropped
+	Restart,
+	/// the given attempt out of however many there can be is starting, which is marked in every log
+	Attempt(u32, u32),
+}
+
+/// the line marking where an attempt at pupdating a target starts in its logs, like `# attempt 2/3`
+pub fn attempt_marker(attempt: u32, attempts: u32) -> String {
+	format!("# attempt {attempt}/{attempts}\n")
+}
+
+/// a log file written by a [`LogWriter`], along with which streams' lines go in it
+struct StreamedLog {
+	/// the log file
+	file: BufWriter<File>,
+	/// the streams whose lines are written to the file
+	streams: &'static [OutputStream],
+}
+
+/// writes a target's timestamped and combined logs as its lines arrive, on a task of its own so reading a target's
+/// output only ever sends the line over a channel instead of waiting on the disk
+#[derive(Debug)]
+pub struct LogWriter {
+	/// sends lines to the task
+	sender: UnboundedSender<LogMessage>,
+	/// the task writing the logs
+	task: JoinHandle<eyre::Result<()>>,
+}
+
+impl LogWriter {
+	/// starts writing the logs in the given formats which need lines as they arrive, starting each with its header
+	/// lines, and writing up to the given number of bytes of lines before dropping the rest. lines are flushed to the
+	/// files at most the given interval after they arrive
+	pub async fn start(
+		paths: &LogPaths,
+		formats: LogFormats,
+		header: LogHeader<'_>,
+		limit: u64,
+		flush_interval: Duration,
+	) -> eyre::Result<Self> {
+		use OutputStream::{Stderr, Stdout};
+
+		let mut logs = Vec::new();
+		let (split, combined) = match paths {
+			LogPaths::SplitAndCombined {
+				stdout,
+				stderr,
+				combined,
+			} => (Some((stdout, stderr)), Some(combined)),
+			LogPaths::Split { stdout, stderr } => (Some((stdout, stderr)), None),
+			LogPaths::Merged { output } => (None, Some(output)),
+		};
+		if let Some((stdout, stderr)) = split.filter(|_| formats.timestamped) {
+			logs.push((stdout, &[Stdout][..]));
+			logs.push((stderr, &[Stderr][..]));
+		}
+		if let Some(combined) = combined.filter(|_| formats.combined) {
+			logs.push((combined, &[Stdout, Stderr][..]));
+		}
+		let mut streamed = Vec::with_capacity(logs.len());
+		for (path, streams) in logs {
+			let file = File::create(path)
+				.await
+				.map_err(|e| eyre::eyre!("failed to create {}: {e}", path.display()))?;
+			streamed.push(StreamedLog {
+				file: BufWriter::new(file),
+				streams,
+			});
+		}
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let task = tokio::spawn(write_streamed(
+			streamed,
+			receiver,
+			[header.lines(true), header.lines(false)],
+			formats.timestamped,
+			limit,
+			flush_interval,
+		));
+		Ok(Self { sender, task })
+	}
+
+	/// sends a line which just arrived to be written
+	pub fn line(&self, stream: OutputStream, line: String) {
+		// the task only stops early if writing failed, which finishing reports
+		let _ = self
+			.sender
+			.send(LogMessage::Line(OffsetDateTime::now_utc(), stream, line));
+	}
+
+	/// drops everything written so far, for when the target is retried from the start
+	pub fn restart(&self) {
+		let _ = self.sender.send(LogMessage::Restart);
+	}
+
+	/// marks the start of the given attempt out of however many there can be, keeping what earlier attempts wrote
+	pub fn attempt(&self, attempt: u32, attempts: u32) {
+		let _ = self.sender.send(LogMessage::Attempt(attempt, attempts));
+	}
+
+	/// waits for every line sent to be written
+	pub async fn finish(self) -> eyre::Result<()> {
+		drop(self.sender);
+		self.task.await?
+	}
+}
+
+/// formats a line for the stream log, like `2025-01-10T14:30:05.123Z web1 stdout: Reading package lists...`
+fn format_stream_line(
+	at: OffsetDateTime,
+	target: &str,
+	stream: OutputStream,
+	line: &str,
+) -> eyre::Result<String> {
+	let stream = match stream {
+		OutputStream::Stdout => "stdout",
+		OutputStream::Stderr => "stderr",
+	};
+	Ok(format!(
+		"{} {target} {stream}: {line}\n",
+		at.format(LINE_TIMESTAMP_FORMAT)?
+	))
+}
+
+/// sends lines to the stream log, which every target's output is appended to as it arrives
+#[derive(Debug, Clone)]
+pub struct StreamLog {
+	/// sends formatted lines to the task writing the stream log, or nothing once it's finishing
+	sender: UnboundedSender<Option<String>>,
+}
+
+impl StreamLog {
+	/// sends a line which just arrived from the given target
+	pub fn line(&self, target: &str, stream: OutputStream, line: &str) {
+		// lines which can't be formatted are left out rather than stopping the target
+		if let Ok(line) = format_stream_line(OffsetDateTime::now_utc(), target, stream, line) {
+			let _ = self.sender.send(Some(line));
+		}
+	}
+}
+
+/// writes the stream log on a task of its own, appending every line sent to any [`StreamLog`] and flushing whenever
+/// there's nothing else waiting to be written, so it can be followed with `tail -f`
+#[derive(Debug)]
+pub struct StreamLogWriter {
+	/// sends lines to the task
+	log: StreamLog,
+	/// the task writing the stream log
+	task: JoinHandle<eyre::Result<()>>,
+}
+
+impl StreamLogWriter {
+	/// opens the stream log at the given path for appending, creating it if needed
+	pub async fn start(path: &Path) -> eyre::Result<Self> {
+		let file = tokio::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.await
+			.map_err(|e| eyre::eyre!("failed to open the stream log {}: {e}", path.display()))?;
+		let (sender, mut receiver) = mpsc::unbounded_channel::<Option<String>>();
+		let task = tokio::spawn(async move {
+			let mut file = BufWriter::new(file);
+			while let Some(Some(line)) = receiver.recv().await {
+				file.write_all(line.as_bytes()).await?;
+				if receiver.is_empty() {
+					file.flush().await?;
+				}
+			}
+			file.flush().await?;
+			Ok(())
+		});
+		Ok(Self {
+			log: StreamLog { sender },
+			task,
+		})
+	}
+
+	/// gets a handle lines can be sent to the stream log through
+	pub fn log(&self) -> StreamLog {
+		self.log.clone()
+	}
+
+	/// waits for every line sent before now to be written
+	pub async fn finish(self) -> eyre::Result<()> {
+		// handles to the stream log may outlive the run, so the task is told to stop rather than waiting for them
+		let _ = self.log.sender.send(None);
+		self.task.await?
+	}
+}
+
+/// flushes every log, syncing them to disk too if `sync` is set
+async fn flush_logs(logs: &mut [StreamedLog], sync: bool) -> std::io::Result<()> {
+	for log in logs.iter_mut() {
+		log.file.flush().await?;
+		if sync {
+			log.file.get_ref().sync_all().await?;
+		}
+	}
+	Ok(())
+}
+
+/// writes lines to each log as they're received until the sender is dropped. lines are flushed once the oldest
+/// unflushed one has waited the flush interval, and the logs are synced to disk whenever apt moves on to another phase
+/// and once they're finished, so a crash loses little of what was written
+async fn write_streamed(
+	mut logs: Vec<StreamedLog>,
+	mut receiver: UnboundedReceiver<LogMessage>,
+	headers: [String; 2],
+	timestamped: bool,
+	limit: u64,
+	flush_interval: Duration,
+) -> eyre::Result<()> {
+	/// starts each log over with just its header, which is the first one given for logs with stdout in them and the
+	/// second otherwise
+	async fn start_over(logs: &mut [StreamedLog], headers: &[String; 2]) -> std::io::Result<()> {
+		for log in logs.iter_mut() {
+			log.file.flush().await?;
+			let file = log.file.get_mut();
+			file.set_len(0).await?;
+			file.seek(SeekFrom::Start(0)).await?;
+			let header = if log.streams.contains(&OutputStream::Stdout) {
+				&headers[0]
+			} else {
+				&headers[1]
+			};
+			log.file.write_all(header.as_bytes()).await?;
+		}
+		flush_logs(logs, false).await
+	}
+
+	start_over(&mut logs, &headers).await?;
+	let mut written = 0u64;
+	let mut dropped = 0u64;
+	// when the oldest line which hasn't been flushed yet was written, if there is one
+	let mut unflushed: Option<Instant> = None;
+	let mut phase = None;
+	loop {
+		let message = match unflushed {
+			Some(since) => {
+				match tokio::time::timeout_at(since + flush_interval, receiver.recv()).await {
+					Ok(message) => message,
+					Err(_) => {
+						flush_logs(&mut logs, false).await?;
+						unflushed = None;
+						continue;
+					}
+				}
+			}
+			None => receiver.recv().await,
+		};
+		let Some(message) = message else {
+			break;
+		};
+		match message {
+			LogMessage::Line(at, stream, line) => {
+				written += line.len() as u64 + 1;
+				if written > limit {
+					dropped += 1;
+					continue;
+				}
+				let formatted = format_line(at, &line, timestamped)?;
+				for log in logs.iter_mut().filter(|log| log.streams.contains(&stream)) {
+					log.file.write_all(formatted.as_bytes()).await?;
+				}
+				let line_phase = Phase::of_line(&line).map(|(phase, _)| phase);
+				if line_phase.is_some() && line_phase != phase {
+					phase = line_phase;
+					flush_logs(&mut logs, true).await?;
+					unflushed = None;
+				} else {
+					// lines arriving steadily would keep the timeout from ever firing, so they're checked here too
+					let since = *unflushed.get_or_insert_with(Instant::now);
+					if since.elapsed() >= flush_interval {
+						flush_logs(&mut logs, false).await?;
+						unflushed = None;
+					}
+				}
+			}
+			LogMessage::Attempt(attempt, attempts) => {
+				// each attempt gets the whole output limit, like its output
+				write_dropped(&mut logs, dropped).await?;
+				for log in logs.iter_mut() {
+					log.file
+						.write_all(attempt_marker(attempt, attempts).as_bytes())
+						.await?;
+				}
+				written = 0;
+				dropped = 0;
+			}
+			LogMessage::Restart => {
+				start_over(&mut logs, &headers).await?;
+				written = 0;
+				dropped = 0;
+				unflushed = None;
+				phase = None;
+			}
+		}
+	}
+	write_dropped(&mut logs, dropped).await?;
+	flush_logs(&mut logs, true).await?;
+	Ok(())
+}
+
+/// notes in each log how many lines were dropped for going over the output limit, if any were
+async fn write_dropped(logs: &mut [StreamedLog], dropped: u64) -> std::io::Result<()> {
+	if dropped != 0 {
+		for log in logs.iter_mut() {
+			log.file
+				.write_all(
+					format!("# {dropped} lines weren't logged for going over the output limit\n")
+						.as_bytes(),
+				)
+				.await?;
+		}
+	}
+	Ok(())
+}
+
+/// how logs are laid out inside the run directory
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLayout {
+	/// each target gets its own directory holding all of its logs and its `result.json`
+	#[default]
+	Directories,
+	/// every target's logs are written side by side as `<target>.stdout.log` and so on, without a `result.json`
+	// kept for one release so scripts reading the old layout have time to move over
+	Flat,
+}
+
+impl LogLayout {
+	/// gets the path of one of a target's files inside the given directory, like `<target>/stdout.log` or
+	/// `<target>.stdout.log`
+	pub fn path(self, dir: &Path, name: &str, file: &str) -> PathBuf {
+		match self {
+			Self::Directories => dir.join(name).join(file),
+			Self::Flat => dir.join(format!("{name}.{file}")),
+		}
+	}
+
+	/// writes a target's result into its directory, if targets have their own directories
+	pub async fn write_result(
+		self,
+		dir: &Path,
+		name: &str,
+		result: &TargetSummary,
+	) -> eyre::Result<()> {
+		if self == Self::Directories {
+			let path = self.path(dir, name, RESULT_FILENAME);
+			if let Some(parent) = path.parent() {
+				tokio::fs::create_dir_all(parent).await?;
+			}
+			tokio::fs::write(&path, serde_json::to_vec_pretty(result)?)
+				.await
+				.map_err(|e| eyre::eyre!("failed to write {}: {e}", path.display()))?;
+		}
+		Ok(())
+	}
+}
+
+/// where each remote's logs are written instead of its directory in the run directory, like
+/// `/var/log/pupdate/{date}/{host}.{stream}.log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogPathTemplate {
+	/// the template, with `{date}` already replaced
+	template: String,
+}
+
+impl LogPathTemplate {
+	/// checks a template for a run started at the given time. templates need `{host}` and `{stream}` so remotes and
+	/// their streams don't overwrite each other's logs
+	pub fn new(template: &str, start: OffsetDateTime) -> eyre::Result<Self> {
+		for placeholder in [HOST_PLACEHOLDER, STREAM_PLACEHOLDER] {
+			if !template.contains(placeholder) {
+				eyre::bail!("the log path template `{template}` needs {placeholder} in it");
+			}
+		}
+		Ok(Self {
+			template: template.replace(DATE_PLACEHOLDER, &start.format(TEMPLATE_DATE_FORMAT)?),
+		})
+	}
+
+	/// gets the path of the log holding the given stream for the given log name
+	pub fn path(&self, name: &str, stream: &str) -> PathBuf {
+		PathBuf::from(
+			self.template
+				.replace(HOST_PLACEHOLDER, name)
+				.replace(STREAM_PLACEHOLDER, stream),
+		)
+	}
+}
+
+impl std::fmt::Display for LogPathTemplate {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.template)
+	}
+}
+
+/// paths to the logs written for a single pupdate target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogPaths {
+	/// stdout and stderr logged to separate files, and interleaved into a single file too
+	// this has to come first so the other variants don't match it when it's read back
+	SplitAndCombined {
+		/// where stdout was logged
+		stdout: PathBuf,
+		/// where stderr was logged
+		stderr: PathBuf,
+		/// where stdout and stderr were interleaved
+		combined: PathBuf,
+	},
+	/// stdout and stderr logged to separate files
+	Split {
+		/// where stdout was logged
+		stdout: PathBuf,
+		/// where stderr was logged
+		stderr: PathBuf,
+	},
+	/// stdout and stderr logged together to a single file
+	Merged {
+		/// where the output was logged
+		output: PathBuf,
+	},
+}
+
+impl LogPaths {
+	/// gets the path of every log file
+	pub fn files(&self) -> Vec<&Path> {
+		match self {
+			Self::SplitAndCombined {
+				stdout,
+				stderr,
+				combined,
+			} => vec![stdout, stderr, combined],
+			Self::Split { stdout, stderr } => vec![stdout, stderr],
+			Self::Merged { output } => vec![output],
+		}
+	}
+
+	/// gets the log paths for the given name inside the given directory, creating the directories if needed
+	pub async fn create_in(dir: &Path, name: &str, layout: LogLayout) -> eyre::Result<Self> {
+		let stdout = layout.path(dir, name, "stdout.log");
+		if let Some(parent) = stdout.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		Ok(Self::Split {
+			stdout,
+			stderr: layout.path(dir, name, "stderr.log"),
+		})
+	}
+
+	/// gets the path of a single log for the given name inside the given directory, creating the directories if needed
+	pub async fn create_merged_in(dir: &Path, name: &str, layout: LogLayout) -> eyre::Result<Self> {
+		let output = match layout {
+			LogLayout::Directories => layout.path(dir, name, "output.log"),
+			LogLayout::Flat => dir.join(format!("{name}.log")),
+		};
+		if let Some(parent) = output.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		Ok(Self::Merged { output })
+	}
+
+	/// gets the log paths for the given name inside the given directory in the given formats, creating the directories
+	/// if needed
+	pub async fn create_formatted_in(
+		dir: &Path,
+		name: &str,
+		layout: LogLayout,
+		formats: LogFormats,
+	) -> eyre::Result<Self> {
+		let combined = layout.path(dir, name, "combined.log");
+		let logs = match Self::create_in(dir, name, layout).await? {
+			Self::Split { stdout, stderr } if formats.combined && formats.split => {
+				Self::SplitAndCombined {
+					stdout,
+					stderr,
+					combined,
+				}
+			}
+			// a combined log on its own is written just like merged output
+			_ if formats.combined => Self::Merged { output: combined },
+			logs => logs,
+		};
+		Ok(logs)
+	}
+
+	/// gets the log paths for the given name from a template, for output merged into a single log or else in the given
+	/// formats, creating the directories if needed
+	pub async fn create_from_template(
+		template: &LogPathTemplate,
+		name: &str,
+		merged: bool,
+		formats: LogFormats,
+	) -> eyre::Result<Self> {
+		let logs = if merged {
+			Self::Merged {
+				output: template.path(name, "output"),
+			}
+		} else {
+			let (stdout, stderr, combined) = (
+				template.path(name, "stdout"),
+				template.path(name, "stderr"),
+				template.path(name, "combined"),
+			);
+			match (formats.split, formats.combined) {
+				(true, true) => Self::SplitAndCombined {
+					stdout,
+					stderr,
+					combined,
+				},
+				(false, true) => Self::Merged { output: combined },
+				_ => Self::Split { stdout, stderr },
+			}
+		};
+		for file in logs.files() {
+			if let Some(parent) = file.parent() {
+				tokio::fs::create_dir_all(parent).await.map_err(|e| {
+					eyre::eyre!("failed to create log directory {}: {e}", parent.display())
+				})?;
+			}
+		}
+		Ok(logs)
+	}
+
+	/// writes the logs which aren't written by a [`LogWriter`] as lines arrive, starting each with its header lines and
+	/// putting stderr after stdout when they're merged
+	pub async fn write(
+		&self,
+		header: LogHeader<'_>,
+		stdout: &[u8],
+		stderr: &[u8],
+		formats: LogFormats,
+	) -> eyre::Result<()> {
+		let (stdout_header, stderr_header) = (header.lines(true), header.lines(false));
+		let header = stdout_header.as_bytes();
+		match self {
+			Self::SplitAndCombined {
+				stdout: stdout_path,
+				stderr: stderr_path,
+				..
+			}
+			| Self::Split {
+				stdout: stdout_path,
+				stderr: stderr_path,
+			} => {
+				if !formats.timestamped {
+					tokio::fs::write(stdout_path, [header, stdout].concat()).await?;
+					tokio::fs::write(stderr_path, [stderr_header.as_bytes(), stderr].concat())
+						.await?;
+				}
+			}
+			// a combined log is always written as lines arrive, so this is only merged output
+			Self::Merged { output } => {
+				if !formats.combined {
+					tokio::fs::write(output, [header, stdout, stderr].concat()).await?
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// gets the name of the run directory for a run started at the given time
+pub fn run_dir_name(start: OffsetDateTime) -> eyre::Result<String> {
+	if start.offset().is_utc() {
+		Ok(start.format(RUN_DIR_FORMAT_UTC)?)
+	} else {
+		Ok(start.format(RUN_DIR_FORMAT_LOCAL)?)
+	}
+}
+
+/// creates the run directory and makes sure files can be written to it
+pub fn prepare_run_dir(dir: &Path) -> eyre::Result<()> {
+	std::fs::create_dir_all(dir)
+		.map_err(|e| eyre::eyre!("failed to create logs directory {}: {e}", dir.display()))?;
+	let probe = dir.join(PROBE_FILENAME);
+	std::fs::write(&probe, b"")
+		.and_then(|()| std::fs::remove_file(&probe))
+		.map_err(|e| eyre::eyre!("logs directory {} isn't writable: {e}", dir.display()))?;
+	Ok(())
+}
+
+/// replaces characters which aren't valid in file names on any supported platform
+pub fn sanitize_file_name(name: &str) -> String {
+	name.chars()
+		.map(|c| match c {
+			'<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+			c if c.is_control() => '_',
+			c => c,
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use super::*;
+
+	#[test]
+	fn log_formats_are_combined() {
+		assert_eq!(LogFormats::new(&[]).unwrap(), LogFormats::default());
+		let formats = LogFormats::new(&[LogFormat::Combined, LogFormat::Raw]).unwrap();
+		assert!(formats.split && formats.combined && !formats.timestamped);
+		assert_eq!(Vec::from(formats), [LogFormat::Raw, LogFormat::Combined]);
+		let formats = LogFormats::new(&[LogFormat::Combined]).unwrap();
+		assert!(!formats.split && formats.streams_lines());
+		assert!(LogFormats::new(&[LogFormat::Raw, LogFormat::Timestamped]).is_err());
+	}
+
+	#[test]
+	fn lines_are_timestamped() {
+		let at = OffsetDateTime::from_unix_timestamp_nanos(1_736_519_405_123_456_789).unwrap();
+		assert_eq!(
+			format_line(at, "Setting up libc6", true).unwrap(),
+			"2025-01-10T14:30:05.123Z Setting up libc6\n"
+		);
+		assert_eq!(
+			format_line(at, "Setting up libc6", false).unwrap(),
+			"Setting up libc6\n"
+		);
+	}
+
+	#[test]
+	fn headers_record_the_change() {
+		let header = LogHeader {
+			command: Some("sudo pupdate"),
+			change_id: Some("CHG-1234"),
+			invocation: None,
+		};
+		assert_eq!(
+			header.lines(true),
+			"# command: sudo pupdate\n# change: CHG-1234\n"
+		);
+		assert_eq!(header.lines(false), "# change: CHG-1234\n");
+		assert_eq!(LogHeader::default().lines(true), "");
+
+		let invocation = Invocation {
+			transport: "subprocess".to_string(),
+			argv: vec!["ssh", "--", "web1", "sudo pupdate"]
+				.into_iter()
+				.map(str::to_string)
+				.collect(),
+			env: BTreeMap::from([
+				(
+					"SSH_AUTH_SOCK".to_string(),
+					Some("/tmp/agent.sock".to_string()),
+				),
+				("SSH_ASKPASS".to_string(), None),
+			]),
+		};
+		let header = LogHeader {
+			invocation: Some(&invocation),
+			..header
+		};
+		assert_eq!(
+			header.lines(false),
+			"# change: CHG-1234\n# subprocess: ssh -- web1 'sudo pupdate'\n# env: SSH_ASKPASS cleared\n# env: SSH_AUTH_SOCK=/tmp/agent.sock\n"
+		);
+	}
+
+	#[test]
+	fn log_path_templates_are_filled_in() {
+		let start = OffsetDateTime::from_unix_timestamp(1_736_519_405).unwrap();
+		let template =
+			LogPathTemplate::new("/var/log/pupdate/{date}/{host}.{stream}.log", start).unwrap();
+		assert_eq!(
+			template.path("web1", "stderr"),
+			Path::new("/var/log/pupdate/2025-01-10/web1.stderr.log")
+		);
+		assert!(LogPathTemplate::new("/var/log/pupdate/{date}/{host}.log", start).is_err());
+		assert!(LogPathTemplate::new("/var/log/pupdate/{stream}.log", start).is_err());
+	}
+
+	#[tokio::test]
+	async fn streamed_logs_are_flushed_while_running() {
+		let dir = std::env::temp_dir().join(format!("pupdate-flush-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let output = dir.join("combined.log");
+		let writer = LogWriter::start(
+			&LogPaths::Merged {
+				output: output.clone(),
+			},
+			LogFormats::new(&[LogFormat::Combined]).unwrap(),
+			LogHeader::default(),
+			u64::MAX,
+			Duration::from_millis(50),
+		)
+		.await
+		.unwrap();
+		writer.line(OutputStream::Stdout, "Reading package lists...".to_string());
+		tokio::time::sleep(Duration::from_millis(300)).await;
+		// the writer hasn't finished, like while the command is still running
+		let written = std::fs::read_to_string(&output).unwrap();
+		assert_eq!(written, "Reading package lists...\n");
+		writer.line(OutputStream::Stderr, "W: a warning".to_string());
+		writer.finish().await.unwrap();
+		let written = std::fs::read_to_string(&output).unwrap();
+		assert_eq!(written, "Reading package lists...\nW: a warning\n");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn attempts_are_kept() {
+		let dir = std::env::temp_dir().join(format!("pupdate-attempt-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let output = dir.join("combined.log");
+		let writer = LogWriter::start(
+			&LogPaths::Merged {
+				output: output.clone(),
+			},
+			LogFormats::new(&[LogFormat::Combined]).unwrap(),
+			LogHeader::default(),
+			20,
+			DEFAULT_LOG_FLUSH_INTERVAL,
+		)
+		.await
+		.unwrap();
+		writer.attempt(1, 2);
+		writer.line(OutputStream::Stderr, "Connection reset".to_string());
+		writer.line(OutputStream::Stderr, "by peer".to_string());
+		writer.attempt(2, 2);
+		writer.line(OutputStream::Stdout, "Reading".to_string());
+		writer.finish().await.unwrap();
+		assert_eq!(
+			std::fs::read_to_string(&output).unwrap(),
+			"# attempt 1/2\nConnection reset\n# 1 lines weren't logged for going over the output limit\n\
+			# attempt 2/2\nReading\n"
+		);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn stream_lines_are_tagged() {
+		let at = OffsetDateTime::from_unix_timestamp(1_736_519_405).unwrap();
+		assert_eq!(
+			format_stream_line(at, "web1", OutputStream::Stderr, "W: a warning").unwrap(),
+			"2025-01-10T14:30:05.000Z web1 stderr: W: a warning\n"
+		);
+	}
+
+	#[test]
+	fn layouts_place_files() {
+		let dir = Path::new("remotes");
+		assert_eq!(
+			LogLayout::Directories.path(dir, "a", "stdout.log"),
+			Path::new("remotes/a/stdout.log")
+		);
+		assert_eq!(
+			LogLayout::Flat.path(dir, "a", "stdout.log"),
+			Path::new("remotes/a.stdout.log")
+		);
+	}
+}
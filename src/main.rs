@@ -1,217 +1,2698 @@
+use approve::approve_remotes;
+use audit::audit;
+use confirm::pupdate_confirmed;
 use directories::BaseDirs;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::{path::PathBuf, time::Duration};
-use time::OffsetDateTime;
-use tokio::{fs::File, io::AsyncWriteExt, process::Command};
+use doctor::doctor;
+use drift::drift;
+use exec::exec;
+use failures::print_failed;
+use guard::{guard_remotes, RemoteList};
+use pick::pick_remotes;
+use plan::{plan, PLAN_FILENAME};
+use pupdate::{
+	approval::Approval,
+	config::{setting_sources, Config, Step, APT_UPDATE_STEP},
+	detection::{DetectionCache, DEFAULT_DETECTION_TTL},
+	diagnostics::DiagnosticCommands,
+	duration::{format_age, parse_duration},
+	events::{Event, EventSink, NdjsonSink},
+	forward::{ForwardedFlag, Forwarding, Version},
+	history::{History, RunRecord},
+	local::{pupdate_local, IoniceClass, LocalOptions, Priority},
+	logs::{
+		prepare_run_dir, run_dir_name, LogFormat, LogFormats, LogPathTemplate, StreamLogWriter,
+		DEFAULT_LOG_FLUSH_INTERVAL,
+	},
+	managers::{self, PackageManager, APT},
+	manifest::Manifest,
+	offline::OfflinePackages,
+	output::{parse_size, OutputLimits, LOGS_WRITTEN_PREFIX, REPO_WARNING_PREFIX, UP_TO_DATE_LINE},
+	playbook::{run_playbook, Playbook},
+	progress::{Progress, ProgressMessages, ProgressPreset, ProgressSettings, ProgressStyles},
+	remote::{
+		pupdate_remotes, ConcurrencyGroups, HostKeyPolicy, RemoteOptions, Runner, SkewCheck,
+		SkewPolicy, SshRunner, LOCAL_REMOTE,
+	},
+	resolve::{unresolved_remotes, ResolvePolicy},
+	retry::RetryPolicy,
+	risk::{describe_risky, RiskyPolicy, DEFAULT_RISKY_PACKAGES},
+	schema::summary_schema,
+	services::ServicesPolicy,
+	shell::{merge_stderr, RemoteCommand},
+	silence::Silencer,
+	state::{RunState, StateFile, Status, STATE_FILENAME},
+	summary::{
+		FailureKind, LocalSummary, Parallelism, Resumed, Summary, TargetSummary, SUMMARY_FILENAME,
+	},
+	verify::{parse_sha256, BinaryCheck},
+	webhook::Webhook,
+	window::{format_time, outside_windows, MaintenanceWindow},
+};
+use risky::{allow_risky, check_risky_local, check_risky_remotes};
+use script::{render_script, write_script, ScriptedRemote};
+use sources::{read_remotes, RemoteSource, ResolvedRemotes, STDIN};
+use stats::{print_history, print_kept_back, print_stale};
+use std::{
+	collections::BTreeMap,
+	io::IsTerminal,
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
+	process::ExitCode,
+	sync::Arc,
+	time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
 
 use clap::Parser;
-use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, sync::mpsc::UnboundedSender, task::JoinHandle};
+
+mod approve;
+mod audit;
+mod confirm;
+mod doctor;
+mod drift;
+mod exec;
+mod exit_code;
+mod failures;
+mod guard;
+mod pick;
+mod plan;
+mod risky;
+mod script;
+mod sources;
+mod stats;
 
 const PUPDATE_CONFIG_FILENAME: &str = ".pupdate";
-const SPINNER_STYLE: &str = "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏";
-const SPINNER_TIME_MILLIS: u64 = 80;
+/// the first pupdate version which knows `--only-security`, so older remotes aren't given it
+const ONLY_SECURITY_SINCE: Version = Version {
+	major: 0,
+	minor: 2,
+	patch: 0,
+};
+/// the first pupdate version which knows `--index-only`
+const INDEX_ONLY_SINCE: Version = Version {
+	major: 0,
+	minor: 2,
+	patch: 0,
+};
+/// the first pupdate version which knows `--skip-refresh-if-recent`
+const SKIP_REFRESH_IF_RECENT_SINCE: Version = Version {
+	major: 0,
+	minor: 2,
+	patch: 0,
+};
+/// where apt keeps its package lists, which are modified whenever they're refreshed
+const APT_LISTS_DIR: &str = "/var/lib/apt/lists";
+/// how many seconds a remote's clock can be off by default before it's considered skewed
+const DEFAULT_SKEW_THRESHOLD_SECS: u64 = 30;
+const LONG_VERSION: &str = concat!(
+	env!("CARGO_PKG_VERSION"),
+	"\n\n",
+	"changes:\n",
+	"- stdout logs now start with a `# command: ...` line recording the exact command run\n",
+	"- logs are now namespaced inside the run directory, with each target getting its own\n",
+	"  log directory holding its logs and a result.json, like remotes/<remote>/stdout.log\n",
+	"  for remotes and local/<step>/stdout.log for local steps, see summary.json for the\n",
+	"  exact paths used by a run, and set log_layout to \"flat\" in config to keep the old\n",
+	"  layout until the next release\n",
+	"- run directories are now named like 2025-01-10T14-30-05Z instead of using rfc3339\n",
+	"  timestamps, and characters which aren't valid in file names are replaced in log names"
+);
 
 /// arguments pupdate has received
 #[derive(Debug, Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, long_version = LONG_VERSION, about, long_about = None)]
 struct Args {
+	/// what to do instead of pupdating
+	#[command(subcommand)]
+	command: Option<Subcommand>,
 	/// list of remotes to run pupdates on
 	remotes: Option<Vec<String>>,
 	/// whether to only run pupdates locally
-	#[arg(long)]
+	#[arg(long, conflicts_with_all = ["remotes", "skip_local", "remotes_from_command"])]
 	local_only: bool,
 	/// whether to skip local pupdates
 	#[arg(long)]
 	skip_local: bool,
+	/// a file listing remotes to use along with the configured ones, one per line, or `-` to read them from stdin.
+	/// remotes given as arguments take priority over it
+	#[arg(long, visible_alias = "remotes-file", value_name = "PATH", conflicts_with_all = ["local_only", "remotes_from_command"])]
+	remotes_from: Option<PathBuf>,
+	/// whether remotes read with `--remotes-from` replace the configured ones instead of being merged with them
+	#[arg(long, requires = "remotes_from")]
+	replace_remotes: bool,
+	/// whether remotes read from stdin with `-` are added to the configured ones instead of replacing them
+	#[arg(long, conflicts_with = "replace_remotes")]
+	append_remotes: bool,
+	/// a shell command whose output is a newline-delimited list of remotes to use instead of the configured ones
+	#[arg(long)]
+	remotes_from_command: Option<String>,
+	/// a playbook of phases to pupdate one after another, each with its own remotes, concurrency, and command, instead
+	/// of the configured remotes
+	#[arg(long, conflicts_with_all = ["remotes", "local_only", "remotes_from", "remotes_from_command", "offline_for", "pick"])]
+	playbook: Option<PathBuf>,
 	/// the directory to log to
 	#[arg(short, long)]
 	log_dir: Option<PathBuf>,
-	/// the config to use as a base
+	/// where to write each remote's logs instead of the run directory, with {host}, {date}, and {stream} replaced,
+	/// like /var/log/pupdate/{date}/{host}.{stream}.log
+	#[arg(long, value_name = "TEMPLATE")]
+	log_path_template: Option<String>,
+	/// at most how many milliseconds lines written to logs wait before they're flushed to disk, so logs can be
+	/// followed as they're written, defaults to 250
+	#[arg(long, value_name = "MS")]
+	log_flush_interval_ms: Option<u64>,
+	/// a file every target's output is appended to as it arrives, with each line tagged with when it arrived, the
+	/// target, and its stream, for following a run with `tail -f`
+	#[arg(long, value_name = "PATH")]
+	stream_log: Option<PathBuf>,
+	/// a file every event of the run is written to as a line of json as it happens, like each remote starting, writing
+	/// a line of output, and finishing
+	#[arg(long, value_name = "PATH")]
+	events: Option<PathBuf>,
+	/// the config to use as a base, which can be given more than once to merge each config on top of the ones before
+	#[arg(short, long)]
+	config: Vec<PathBuf>,
+	/// a directory of `*.json`/`*.toml` configs to merge on top of the base config in sorted order
+	#[arg(long)]
+	config_dir: Option<PathBuf>,
+	/// whether keys in the config which pupdate doesn't know are errors instead of being ignored, to catch typos
+	#[arg(long)]
+	strict_config: bool,
+	/// whether to print every setting of the merged configs along with the config it came from, without running
+	#[arg(long)]
+	show_config: bool,
+	/// whether to log to a directory in the cache (or temp) directory when no log directory is configured
+	#[arg(long)]
+	auto_log: bool,
+	/// whether to fetch each remote's own pupdate logs into the local run directory
+	#[arg(long)]
+	fetch_logs: bool,
+	/// whether to have remotes gzip their logs before fetching them
+	#[arg(long, requires = "fetch_logs")]
+	fetch_compressed: bool,
+	/// how many times to retry a failed remote pupdate or local step
+	#[arg(long, default_value_t = 0)]
+	retries: u32,
+	/// the delay in milliseconds before the first retry, doubled for each retry after it and jittered
+	#[arg(long, value_name = "BASE_MS", default_value_t = 1000)]
+	retry_backoff: u64,
+	/// the longest delay in milliseconds between retries
+	#[arg(long, value_name = "MS", default_value_t = 60000)]
+	retry_max_backoff: u64,
+	/// whether to print extra details, such as retry delays
 	#[arg(short, long)]
-	config: Option<PathBuf>,
+	verbose: bool,
+	/// whether to report the package mirrors contacted by each pupdate
+	#[arg(long)]
+	report_sources: bool,
+	/// whether to only apply security updates, locally and on remotes
+	#[arg(long)]
+	only_security: bool,
+	/// whether to only refresh package indexes without upgrading anything, locally and on remotes
+	#[arg(long, conflicts_with_all = ["only_security", "offline_for"])]
+	index_only: bool,
+	/// skip refreshing package indexes when they were refreshed more recently than this, like 30m or 2h, locally and
+	/// on remotes
+	#[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "index_only")]
+	skip_refresh_if_recent: Option<Duration>,
+	/// the niceness to run local steps with so they yield to interactive work, from 0 to 19
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=19))]
+	nice: Option<u8>,
+	/// the io scheduling class to run local steps with so they yield to interactive work
+	#[arg(long, value_name = "CLASS")]
+	ionice: Option<IoniceClass>,
+	/// skip remotes last pupdated successfully less recently than this, like 30m or 2h, unless they're given as arguments
+	#[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+	min_interval: Option<Duration>,
+	/// whether to check each remote for services still running code replaced by the update, using `needs-restarting -s`
+	#[arg(long)]
+	check_services: bool,
+	/// whether to restart the services found by `--check-services`, implying it
+	#[arg(long)]
+	restart_services: bool,
+	/// how much of each of a remote's output streams is kept before the rest is dropped apart from its tail, like 64M
+	#[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "64M")]
+	output_limit: u64,
+	/// how much a remote can write to one of its output streams before it's killed, like 1G, unlimited by default
+	#[arg(long, value_name = "SIZE", value_parser = parse_size)]
+	output_kill_limit: Option<u64>,
+	/// how many seconds each remote's pupdate can run before it's killed and counted as failed, where 0 means no
+	/// timeout, which is the default
+	#[arg(long, value_name = "SECONDS")]
+	timeout: Option<u64>,
+	/// whether to log each remote's stdout and stderr together to `<remote>.log`, in the order they were written
+	#[arg(long)]
+	merge_output: bool,
+	/// whether to print exactly what's spawned to pupdate each remote, including the ssh command line and the
+	/// environment it inherits, before pupdating them
+	#[arg(long)]
+	print_commands: bool,
+	/// the formats to write logs in, any of raw, timestamped, and combined separated by commas, overriding the config
+	#[arg(long, value_enum, value_name = "FORMAT", value_delimiter = ',')]
+	log_format: Vec<LogFormat>,
+	/// whether to skip checking remotes' binaries against their expected hashes, for emergencies
+	#[arg(long)]
+	no_verify: bool,
+	/// the sha256 every remote's pupdate binary is expected to have for this run, overriding the config's hashes.
+	/// remotes whose binary has another hash aren't pupdated
+	#[arg(long, value_name = "SHA256", value_parser = parse_sha256, conflicts_with = "no_verify")]
+	verify_remote_checksum: Option<String>,
+	/// a shell command to run once the run is complete, which gets the run's summary as json on its stdin
+	#[arg(long, value_name = "COMMAND")]
+	on_complete: Option<String>,
+	/// whether to compare the packages installed on the remotes which pupdated successfully once the run is done,
+	/// reporting packages which are missing or at different versions on some of them
+	#[arg(long, conflicts_with = "local_only")]
+	diff_remotes: bool,
+	/// a url each remote's result is posted to as json as soon as the remote finishes
+	#[arg(long, value_name = "URL")]
+	per_remote_webhook: Option<String>,
+	/// the most remotes pupdated at once, like 8, so a large fleet doesn't open a connection to every remote at the same
+	/// time, defaults to all of them
+	#[arg(short, long, value_name = "N")]
+	jobs: Option<NonZeroUsize>,
+	/// how many threads the async runtime uses, defaults to one per cpu core
+	#[arg(long, value_name = "N")]
+	worker_threads: Option<NonZeroUsize>,
+	/// whether to detect the pupdate version of remotes again instead of trusting what earlier runs detected
+	#[arg(long)]
+	redetect: bool,
+	/// whether to only pupdate remotes which failed to pupdate at least `--threshold` runs in a row, for troubleshooting
+	#[arg(long)]
+	flaky_only: bool,
+	/// how many runs in a row a remote has to have failed for `--flaky-only` to select it
+	#[arg(
+		long,
+		value_name = "RUNS",
+		default_value_t = 3,
+		requires = "flaky_only"
+	)]
+	threshold: u32,
+	/// how ssh verifies the host keys of remotes, defaults to ssh's own config
+	#[arg(long, value_enum)]
+	host_key_policy: Option<HostKeyPolicy>,
+	/// remotes without internet access to pupdate by pushing the packages in `--package-dir` to them and installing them
+	#[arg(long, value_name = "REMOTE", num_args = 1.., requires = "package_dir", conflicts_with = "local_only")]
+	offline_for: Vec<String>,
+	/// checks each remote's clock against the controller's before pupdating it, warning about or skipping skewed remotes
+	#[arg(long, value_enum)]
+	skew_policy: Option<SkewPolicy>,
+	/// checks for risky updates like a new kernel or libc before pupdating each target, asking about, skipping, or only
+	/// recording targets which have them pending
+	#[arg(long, value_enum, conflicts_with = "index_only")]
+	risky_policy: Option<RiskyPolicy>,
+	/// checks that every remote's hostname resolves before pupdating anything, warning about, skipping, or stopping
+	/// over remotes whose hostnames don't
+	#[arg(long, value_enum)]
+	resolve_policy: Option<ResolvePolicy>,
+	/// whether apt warning about a repository's signature, like a missing or expired key, fails the target instead of
+	/// only being recorded
+	#[arg(long)]
+	fail_on_repo_warnings: bool,
+	/// whether apt keeping back packages instead of upgrading them fails the target instead of only being recorded
+	#[arg(long)]
+	fail_on_kept_back: bool,
+	/// whether to approve every remote configured to need manual approval without asking, for emergencies
+	#[arg(long)]
+	approve_all: bool,
+	/// whether to ask before pupdating each remote, pupdating them one at a time until `a` is answered to pupdate the
+	/// rest without asking
+	#[arg(long, conflicts_with = "playbook")]
+	confirm_per_host: bool,
+	/// flag remotes which wrote no output, or went longer than this without writing any before they finished, like 5m,
+	/// as suspicious in the summary since they may have stalled
+	#[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+	max_output_age: Option<Duration>,
+	/// how many seconds a remote's clock can be off before it's considered skewed, defaults to 30
+	#[arg(long, value_name = "SECS")]
+	skew_threshold: Option<u64>,
+	/// the directory of `.deb` packages to install on the remotes given to `--offline-for`
+	#[arg(long, requires = "offline_for")]
+	package_dir: Option<PathBuf>,
+	/// the preset for remotes' progress bars, ignoring any progress customizations in the config
+	#[arg(long, value_enum)]
+	progress_style: Option<ProgressPreset>,
+	/// what's shown as each remote's status on its progress bar: only that it's being pupdated, what apt is doing, or
+	/// the latest line of its output
+	#[arg(long, value_enum, value_name = "MODE")]
+	progress_messages: Option<ProgressMessages>,
+	/// whether to print plain lines for each remote's status instead of showing progress bars
+	#[arg(long)]
+	no_progress: bool,
+	/// a file of remotes which must never be acted on, one per line, which are dropped from the resolved remotes, erroring
+	/// if any of them were given explicitly
+	#[arg(long, value_name = "PATH")]
+	deny_file: Option<PathBuf>,
+	/// a file of the only remotes which may be acted on, one per line, which the resolved remotes are narrowed down to,
+	/// erroring if any others were given explicitly
+	#[arg(long, value_name = "PATH")]
+	allow_file: Option<PathBuf>,
+	/// write a shell script of every command the run would execute to this path instead of running anything, for
+	/// reviewing or running by hand
+	#[arg(long, value_name = "PATH", conflicts_with = "playbook")]
+	emit_script: Option<PathBuf>,
+	/// whether to pick which of the resolved remotes to act on from an interactive menu
+	#[arg(long, conflicts_with_all = ["local_only", "flaky_only"])]
+	pick: bool,
+	/// whether to pupdate even outside the configured maintenance window
+	#[arg(long)]
+	force: bool,
+	/// whether to pupdate remotes even outside their own maintenance windows
+	#[arg(long)]
+	ignore_windows: bool,
+	/// the change ticket to tag the run with, recorded in the summary, history, logs, and webhook posts
+	#[arg(long, value_name = "ID", value_parser = parse_change_id)]
+	change_id: Option<String>,
 }
 
-/// pupdate config
-#[derive(Debug, Default, Deserialize)]
-struct Config {
-	/// the remotes to pupdate if none are provided
-	#[serde(default)]
-	remotes: Vec<String>,
-	/// the directory to log to, no logs if missing
-	#[serde(default)]
-	log_dir: Option<PathBuf>,
+/// the files `pupdate schema` prints the schema of
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum SchemaOf {
+	/// summary.json, written to the run directory
+	Summary,
 }
 
-/// pupdates a remote target through ssh
-/// TODO: build pupdate daemon and pupdate through that instead
-async fn pupdate_remote(
-	remote: String,
-	log_dir: Option<PathBuf>,
-	pb: ProgressBar,
-	finished_style: ProgressStyle,
-	overall: ProgressBar,
-) -> eyre::Result<(String, bool)> {
-	pb.set_message("pupdating...");
-	let start = OffsetDateTime::now_utc();
-	let output = Command::new("ssh")
-		.arg(&remote)
-		.arg("sudo pupdate")
+/// things pupdate can do besides pupdating
+#[derive(Debug, clap::Subcommand)]
+enum Subcommand {
+	/// report which packages have pending updates across every remote without changing anything
+	Audit {
+		/// the remotes to audit instead of the configured ones
+		remotes: Option<Vec<String>>,
+	},
+	/// print the remotes pupdate would act on, after resolving them from the arguments and config
+	ListRemotes {
+		/// whether to print the remotes as a json array
+		#[arg(long)]
+		json: bool,
+		/// whether to print the remotes as a toml document with a `remotes` array
+		#[arg(long, conflicts_with = "json")]
+		toml: bool,
+		/// whether to print where each remote came from, like `config` or `stdin`, beside it, or with `--json` as an
+		/// array of objects with a `remote` and its `source`
+		#[arg(long, conflicts_with = "toml")]
+		sources: bool,
+	},
+	/// run a command on every remote with the same progress, logs, and summary as pupdating them
+	Exec {
+		/// whether to run the command through sudo
+		#[arg(long)]
+		sudo: bool,
+		/// whether to print each remote's result and stdout as json
+		#[arg(long)]
+		json: bool,
+		/// the remotes to run the command on instead of the configured ones
+		remotes: Option<Vec<String>>,
+		/// the command to run, after `--`
+		#[arg(last = true, required = true)]
+		command: Vec<String>,
+	},
+	/// compare the packages installed across remotes without changing anything, reporting packages which are missing
+	/// or at different versions on some of them
+	Drift {
+		/// the remotes to compare instead of the configured ones, the first of which the others are compared against
+		remotes: Option<Vec<String>>,
+	},
+	/// simulate an upgrade locally and on every remote without changing anything, printing a table of the packages
+	/// which would be upgraded and writing it to plan.json in the run directory
+	Plan {
+		/// the remotes to plan for instead of the configured ones
+		remotes: Option<Vec<String>>,
+		/// a path to write the plan's json to besides the run directory
+		#[arg(long)]
+		output: Option<PathBuf>,
+	},
+	/// print what's remembered about each remote across runs, or with --stats the remembered runs added up by week
+	History {
+		/// whether to print each week's runs, remotes, per-remote duration, failure rate, and parallel speedup
+		#[arg(long)]
+		stats: bool,
+		/// list the best effort remotes which haven't been pupdated successfully for this long, like 14d, instead
+		#[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "stats")]
+		stale: Option<Duration>,
+		/// list the remotes which have had packages kept back by apt in more than one run in a row instead
+		#[arg(long, conflicts_with_all = ["stats", "stale"])]
+		kept_back: bool,
+		/// whether to print the history as json
+		#[arg(long)]
+		json: bool,
+	},
+	/// pupdate whatever an interrupted run didn't finish successfully, writing to the same run directory
+	Resume {
+		/// the run to resume, either its run directory or the directory's name inside the log directory
+		#[arg(required_unless_present = "last")]
+		run: Option<String>,
+		/// whether to resume the most recent run in the log directory
+		#[arg(long, conflicts_with = "run")]
+		last: bool,
+	},
+	/// print the json schema of one of the files pupdate writes
+	Schema {
+		/// the file to print the schema of
+		#[arg(value_enum)]
+		of: SchemaOf,
+	},
+	/// check that this machine is set up to run pupdate, printing a line for each check
+	Doctor {
+		/// whether to also check that each remote can be reached and has passwordless sudo
+		#[arg(long)]
+		remotes: bool,
+		/// whether to print the checks as a json array
+		#[arg(long)]
+		json: bool,
+	},
+}
+
+/// finds the package manager to pupdate the local system with when no steps are configured, or `None` if it's pupdated
+/// with the default apt steps
+fn local_package_manager(
+	steps: &[Step],
+	apt_binary: Option<&Path>,
+	package_managers: &BTreeMap<String, PackageManager>,
+) -> Option<(String, PackageManager)> {
+	// a configured apt-get means apt is wanted, and the built-in apt is left to the default steps, which know more about
+	// it than its commands
+	if !steps.is_empty() || apt_binary.is_some() {
+		return None;
+	}
+	managers::detect(package_managers, |binary| {
+		find_executable(Path::new(binary)).is_some()
+	})
+	.filter(|(name, _)| name != APT || package_managers.contains_key(APT))
+}
+
+/// the flags forwarded to remotes whose pupdate is new enough for them
+fn forwarded_flags(
+	only_security: bool,
+	index_only: bool,
+	skip_refresh_if_recent: Option<Duration>,
+) -> Vec<ForwardedFlag> {
+	let mut flags = Vec::new();
+	if only_security {
+		flags.push(ForwardedFlag {
+			args: vec!["--only-security".to_string()],
+			since: ONLY_SECURITY_SINCE,
+		});
+	}
+	if index_only {
+		flags.push(ForwardedFlag {
+			args: vec!["--index-only".to_string()],
+			since: INDEX_ONLY_SINCE,
+		});
+	}
+	if let Some(recent) = skip_refresh_if_recent {
+		flags.push(ForwardedFlag {
+			args: vec![
+				"--skip-refresh-if-recent".to_string(),
+				format!("{}s", recent.as_secs()),
+			],
+			since: SKIP_REFRESH_IF_RECENT_SINCE,
+		});
+	}
+	flags
+}
+
+/// how long each remote's pupdate can run before it's killed, with the flag winning over the config and 0 meaning no
+/// timeout
+fn remote_timeout(flag: Option<u64>, config: Option<u64>) -> Option<Duration> {
+	flag.or(config)
+		.filter(|&seconds| seconds > 0)
+		.map(Duration::from_secs)
+}
+
+/// finds an executable, either by its path or by searching the `PATH` environment variable
+fn find_executable(program: &Path) -> Option<PathBuf> {
+	if program.components().count() > 1 {
+		return program.is_file().then(|| program.to_path_buf());
+	}
+	std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+		let path = dir.join(program);
+		if path.is_file() {
+			return Some(path);
+		}
+		let path = path.with_extension(std::env::consts::EXE_EXTENSION);
+		path.is_file().then_some(path)
+	})
+}
+
+/// errors with an actionable message if the given executable can't be found
+fn require_executable(program: &Path, hint: &str) -> eyre::Result<()> {
+	if find_executable(program).is_none() {
+		if program.components().count() > 1 {
+			eyre::bail!("{} executable not found — {hint}", program.display());
+		}
+		eyre::bail!(
+			"{} executable not found in PATH — {hint}",
+			program.display()
+		);
+	}
+	Ok(())
+}
+
+/// builds a command running the given command line through the local shell
+fn local_shell(command: &str) -> tokio::process::Command {
+	let mut shell = if cfg!(windows) {
+		let mut shell = tokio::process::Command::new("cmd");
+		shell.arg("/C");
+		shell
+	} else {
+		let mut shell = tokio::process::Command::new("sh");
+		shell.arg("-c");
+		shell
+	};
+	shell.arg(command);
+	shell
+}
+
+/// runs a shell command and reads its output as a newline-delimited list of remotes
+async fn remotes_from_command(command: &str) -> eyre::Result<Vec<String>> {
+	let output = local_shell(command)
+		.stderr(std::process::Stdio::inherit())
 		.output()
-		.await?;
-	let end = OffsetDateTime::now_utc();
-	let success = output.status.success();
-	if let Some(log_dir) = log_dir {
-		let mut stdout = File::create(log_dir.join(format!("{remote}.stdout.log"))).await?;
-		stdout.write_all(&output.stdout).await?;
-		let mut stderr = File::create(log_dir.join(format!("{remote}.stderr.log"))).await?;
-		stderr.write_all(&output.stderr).await?;
-	}
-	let duration = end - start;
-	pb.set_style(finished_style);
-	pb.finish_with_message(format!(
-		"finished in {} seconds: {}",
-		duration.whole_seconds(),
-		if success { "succeeded" } else { "failed" }
-	));
-	overall.inc(1);
-	Ok((remote, success))
-}
-
-/// pupdates the local system using apt-get
-async fn pupdate_apt(log_dir: Option<PathBuf>) -> eyre::Result<bool> {
-	async fn log(outputs: &[std::process::Output], log_dir: Option<PathBuf>) -> eyre::Result<bool> {
-		if let Some(log_dir) = log_dir {
-			let mut stdout = File::create(log_dir.join("local.stdout.log")).await?;
-			let mut stderr = File::create(log_dir.join("local.stderr.log")).await?;
-			for output in outputs {
-				stdout.write_all(&output.stdout).await?;
-				stderr.write_all(&output.stderr).await?;
+		.await
+		.map_err(|e| eyre::eyre!("failed to run remotes command `{command}`: {e}"))?;
+	if !output.status.success() {
+		eyre::bail!("remotes command `{command}` failed: {}", output.status);
+	}
+	Ok(String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty())
+		.map(str::to_string)
+		.collect())
+}
+
+/// runs the command given to `--on-complete` with the run's summary as json on its stdin, returning its exit status
+async fn on_complete(command: &str, summary: &Summary) -> eyre::Result<std::process::ExitStatus> {
+	let mut child = local_shell(command)
+		.stdin(std::process::Stdio::piped())
+		.spawn()
+		.map_err(|e| eyre::eyre!("failed to run on-complete command `{command}`: {e}"))?;
+	let mut stdin = child.stdin.take().expect("stdin is piped");
+	// a command which doesn't read the summary closes its stdin early, which isn't an error
+	if let Err(e) = stdin
+		.write_all(serde_json::to_string_pretty(summary)?.as_bytes())
+		.await
+	{
+		if e.kind() != std::io::ErrorKind::BrokenPipe {
+			return Err(e.into());
+		}
+	}
+	drop(stdin);
+	Ok(child.wait().await?)
+}
+
+/// parses a change ticket's id, which has to fit on the single header line it gets in each log
+fn parse_change_id(s: &str) -> Result<String, String> {
+	if s.trim().is_empty() {
+		Err("the change id can't be empty".to_string())
+	} else if s.contains(['\n', '\r']) {
+		Err("the change id has to be a single line".to_string())
+	} else {
+		Ok(s.to_string())
+	}
+}
+
+/// formats a list of mirror hostnames for printing
+fn format_sources(sources: Option<&[String]>) -> String {
+	match sources {
+		Some(sources) if !sources.is_empty() => sources.join(", "),
+		_ => "none found".to_string(),
+	}
+}
+
+fn main() -> eyre::Result<ExitCode> {
+	let args = Args::parse();
+	let (args, resume) = match &args.command {
+		Some(Subcommand::Resume { run, last }) => {
+			let dir = find_run_dir(&args, run.as_deref(), *last)?;
+			let Some((args, resume)) = prepare_resume(&args, dir)? else {
+				return Ok(ExitCode::from(exit_code::SUCCESS));
+			};
+			(args, Some(resume))
+		}
+		_ => (args, None),
+	};
+	// the local offset has to be determined before the runtime starts any threads
+	let local_offset = UtcOffset::current_local_offset().ok();
+	let mut runtime = tokio::runtime::Builder::new_multi_thread();
+	runtime.enable_all();
+	if let Some(worker_threads) = args.worker_threads {
+		runtime.worker_threads(worker_threads.get());
+	}
+	let runtime = runtime.build()?;
+	let result = runtime.block_on(run(args, resume, local_offset));
+	// a prompt left unanswered is still reading stdin, which would otherwise hold up exiting until enter is pressed
+	runtime.shutdown_background();
+	result
+}
+
+/// an interrupted run being resumed
+struct Resume {
+	/// the run directory of the interrupted run, which the resumed run writes to
+	dir: PathBuf,
+	/// the interrupted run's state, which the resumed run carries on updating
+	state: Arc<StateFile>,
+	/// the interrupted run's state before it was resumed
+	previous: RunState,
+	/// the remotes pupdated again
+	remotes: Vec<String>,
+	/// the remotes which were still running when the run was interrupted
+	mid_flight: Vec<String>,
+}
+
+/// finds the run directory of the run to resume, either the one given or the most recent one with a state file
+fn find_run_dir(args: &Args, run: Option<&str>, last: bool) -> eyre::Result<PathBuf> {
+	if let Some(run) = run.map(PathBuf::from).filter(|run| run.is_dir()) {
+		return Ok(run);
+	}
+	let config = load_config(args)?;
+	// runs logged with --auto-log can be resumed too, so the cache directory is looked in without a log directory
+	let Some(base) = log_base_dir(args.log_dir.clone().or(config.log_dir), true) else {
+		eyre::bail!("no log directory to look for runs in, pass the run's directory instead");
+	};
+	if let Some(run) = run {
+		let dir = base.join(run);
+		if !dir.is_dir() {
+			eyre::bail!("no run {run} in {}", base.display());
+		}
+		return Ok(dir);
+	}
+	debug_assert!(last);
+	let entries = std::fs::read_dir(&base)
+		.map_err(|e| eyre::eyre!("failed to read log directory {}: {e}", base.display()))?;
+	// run directories are named after when they started, so the most recent one sorts last
+	entries
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|dir| dir.join(STATE_FILENAME).is_file())
+		.max()
+		.ok_or_else(|| eyre::eyre!("no runs which can be resumed in {}", base.display()))
+}
+
+/// works out how to resume the run in the given run directory from its state, returning its arguments changed to
+/// only pupdate what didn't finish successfully, or `None` if there's nothing left to pupdate
+fn prepare_resume(args: &Args, dir: PathBuf) -> eyre::Result<Option<(Args, Resume)>> {
+	let state = StateFile::open(&dir)?;
+	let previous = state.state();
+	if previous.arguments.is_empty() {
+		eyre::bail!(
+			"{} doesn't record the arguments it was run with, so it can't be resumed",
+			dir.display()
+		);
+	}
+	let mut resumed = Args::try_parse_from(&previous.arguments).map_err(|e| {
+		eyre::eyre!(
+			"the arguments {} was run with can't be parsed anymore: {e}",
+			dir.display()
+		)
+	})?;
+	if resumed.command.is_some() {
+		eyre::bail!("only runs pupdating remotes or the local system can be resumed");
+	}
+
+	let unfinished: Vec<_> = previous
+		.remotes
+		.iter()
+		.filter(|(_, target)| target.status != Status::Succeeded)
+		.map(|(remote, _)| remote.clone())
+		.collect();
+	let mid_flight: Vec<_> = previous
+		.remotes
+		.iter()
+		.filter(|(_, target)| target.status == Status::Running)
+		.map(|(remote, _)| remote.clone())
+		.collect();
+	let local = previous
+		.local
+		.as_ref()
+		.is_some_and(|local| local.status != Status::Succeeded);
+	if unfinished.is_empty() && !local {
+		println!(
+			"nothing to resume, everything in {} finished successfully",
+			dir.display()
+		);
+		return Ok(None);
+	}
+
+	// remotes are given explicitly so nothing else is picked up from the config or skipped since the run
+	if !resumed.local_only {
+		resumed
+			.offline_for
+			.retain(|remote| unfinished.contains(remote));
+		resumed.remotes = Some(
+			unfinished
+				.iter()
+				.filter(|remote| !resumed.offline_for.contains(remote))
+				.cloned()
+				.collect(),
+		);
+	}
+	resumed.remotes_from = None;
+	resumed.replace_remotes = false;
+	resumed.append_remotes = false;
+	resumed.remotes_from_command = None;
+	resumed.flaky_only = false;
+	resumed.min_interval = None;
+	resumed.pick = false;
+	resumed.skip_local |= !local;
+	// how the run is shown can be changed when resuming it
+	resumed.verbose |= args.verbose;
+	resumed.no_progress |= args.no_progress;
+	resumed.force |= args.force;
+	resumed.ignore_windows |= args.ignore_windows;
+
+	if local {
+		println!(
+			"resuming {} with {} remotes and the local system",
+			dir.display(),
+			unfinished.len()
+		);
+	} else {
+		println!(
+			"resuming {} with {} remotes",
+			dir.display(),
+			unfinished.len()
+		);
+	}
+	if !mid_flight.is_empty() {
+		println!("the following remotes were still pupdating when the run was interrupted, so they may be partly pupdated:");
+		for remote in &mid_flight {
+			println!("{remote}");
+		}
+	}
+	Ok(Some((
+		resumed,
+		Resume {
+			dir,
+			state: Arc::new(state),
+			previous,
+			remotes: unfinished,
+			mid_flight,
+		},
+	)))
+}
+
+/// gets the path of every config to merge in order: the base configs, either the ones given or `~/.pupdate` if it
+/// exists, followed by the configs in the config directory, if one was given
+fn config_paths(args: &Args) -> eyre::Result<Vec<PathBuf>> {
+	let mut paths = args.config.clone();
+	if paths.is_empty() {
+		paths.extend(
+			BaseDirs::new()
+				.map(|bd| bd.home_dir().join(PUPDATE_CONFIG_FILENAME))
+				.filter(|p| std::fs::exists(p).unwrap_or_default()),
+		);
+	}
+	if let Some(config_dir) = &args.config_dir {
+		paths.extend(Config::dir_paths(config_dir)?);
+	}
+	Ok(paths)
+}
+
+/// loads the base configs and merges the config directory on top of them, if one was given
+fn load_config(args: &Args) -> eyre::Result<Config> {
+	Config::load_all(&config_paths(args)?, args.strict_config)
+}
+
+/// the command pupdating the local system when it's listed among the remotes as [`LOCAL_REMOTE`], which is this same
+/// pupdate binary given the same configs, so it pupdates the local system just like this run would
+fn local_command(config_files: &[PathBuf]) -> eyre::Result<RemoteCommand> {
+	let binary = std::env::current_exe().map_err(|e| {
+		eyre::eyre!("couldn't find pupdate's own binary to pupdate {LOCAL_REMOTE} with: {e}")
+	})?;
+	let mut argv = vec![binary.display().to_string()];
+	for path in config_files {
+		argv.push("--config".to_string());
+		argv.push(path.display().to_string());
+	}
+	Ok(RemoteCommand::Argv(argv))
+}
+
+/// prints every setting of the merged configs along with the config it came from
+fn show_config(args: &Args) -> eyre::Result<()> {
+	let mut configs = Vec::new();
+	for path in config_paths(args)? {
+		configs.push((path.display().to_string(), Config::load_raw(&path)?));
+	}
+	if configs.is_empty() {
+		println!("no configs were given or found, so every setting is its default");
+	}
+	for setting in setting_sources(&configs)? {
+		println!("{} = {}  # {}", setting.key, setting.value, setting.source);
+	}
+	Ok(())
+}
+
+/// resolves the remotes to act on: the ones given as arguments, with `-` reading more from stdin, then the ones read
+/// with `--remotes-from` merged with the configured ones, then the ones from `--remotes-from-command`, then the
+/// configured ones, guarded by `--deny-file` and `--allow-file`, and narrowed down to the ones picked with `--pick`
+async fn resolve_remotes(
+	args: &Args,
+	configured: Vec<String>,
+	configured_source: RemoteSource,
+) -> eyre::Result<ResolvedRemotes> {
+	let given = args.remotes.clone().or_else(|| match &args.command {
+		Some(
+			Subcommand::Audit { remotes }
+			| Subcommand::Exec { remotes, .. }
+			| Subcommand::Drift { remotes }
+			| Subcommand::Plan { remotes, .. },
+		) => remotes.clone(),
+		Some(
+			Subcommand::ListRemotes { .. }
+			| Subcommand::Doctor { .. }
+			| Subcommand::Schema { .. }
+			| Subcommand::History { .. }
+			| Subcommand::Resume { .. },
+		)
+		| None => None,
+	});
+	let requested: Vec<_> = given
+		.iter()
+		.flatten()
+		.chain(&args.offline_for)
+		.filter(|remote| *remote != STDIN)
+		.cloned()
+		.collect();
+	let mut resolved = ResolvedRemotes::default();
+	let mut read = false;
+	let mut merge = args.append_remotes;
+	if args.local_only {
+	} else if let Some(remotes) = given {
+		for remote in remotes {
+			if remote == STDIN {
+				let (remotes, source) = read_remotes(Path::new(STDIN))?;
+				resolved.add(remotes, source);
+				read = true;
+			} else {
+				resolved.add([remote], RemoteSource::Cli);
 			}
 		}
-		for output in outputs {
-			if !output.status.success() {
-				return Ok(false);
+	} else if let Some(path) = &args.remotes_from {
+		let (remotes, source) = read_remotes(path)?;
+		resolved.add(remotes, source);
+		read = true;
+		merge |= !args.replace_remotes;
+	} else if let Some(command) = &args.remotes_from_command {
+		resolved.add(remotes_from_command(command).await?, RemoteSource::Command);
+	} else {
+		resolved.add(configured.iter().cloned(), configured_source.clone());
+	}
+	if args.append_remotes && !read {
+		eyre::bail!("--append-remotes only applies to remotes read from stdin with `-` or with --remotes-from");
+	}
+	// remotes already read keep their place, so the configured ones only add whatever wasn't listed
+	if read && merge {
+		resolved.add(configured, configured_source);
+	}
+	let deny = args
+		.deny_file
+		.as_deref()
+		.map(RemoteList::load)
+		.transpose()?;
+	let allow = args
+		.allow_file
+		.as_deref()
+		.map(RemoteList::load)
+		.transpose()?;
+	let remotes = guard_remotes(
+		std::mem::take(&mut resolved.remotes),
+		&requested,
+		deny.as_ref(),
+		allow.as_ref(),
+	)?;
+	resolved.keep(if args.pick {
+		pick_remotes(remotes)?
+	} else {
+		remotes
+	});
+	Ok(resolved)
+}
+
+/// gets the log directory runs' directories are created in, which is inside the cache directory with `--auto-log` when
+/// no log directory is configured
+fn log_base_dir(configured: Option<PathBuf>, auto_log: bool) -> Option<PathBuf> {
+	match configured {
+		// logs' paths are printed for pasting into other commands, so they shouldn't depend on where pupdate was run
+		Some(log_dir) => Some(std::path::absolute(&log_dir).unwrap_or(log_dir)),
+		None if auto_log => Some(
+			BaseDirs::new()
+				.map(|bd| bd.cache_dir().to_path_buf())
+				.unwrap_or_else(std::env::temp_dir)
+				.join("pupdate")
+				.join("logs"),
+		),
+		None => None,
+	}
+}
+
+/// creates the directory for this run's logs inside the log directory, or inside the cache directory with `--auto-log`
+/// when no log directory is configured, returning it if there is one
+fn run_log_dir(
+	configured: Option<PathBuf>,
+	auto_log: bool,
+	logs_optional: bool,
+	offset: UtcOffset,
+) -> eyre::Result<Option<PathBuf>> {
+	let Some(log_dir) = log_base_dir(configured, auto_log) else {
+		return Ok(None);
+	};
+	let log_dir = log_dir.join(run_dir_name(OffsetDateTime::now_utc().to_offset(offset))?);
+	match prepare_run_dir(&log_dir) {
+		Ok(()) => Ok(Some(log_dir)),
+		Err(e) if logs_optional => {
+			eprintln!("warning: {e}, continuing without logs");
+			Ok(None)
+		}
+		Err(e) => Err(e),
+	}
+}
+
+/// gets the run's id, which is the name of its run directory, or what it would be called for a run without one
+fn run_id(log_dir: Option<&Path>, offset: UtcOffset) -> eyre::Result<String> {
+	match log_dir.and_then(Path::file_name) {
+		Some(name) => Ok(name.to_string_lossy().into_owned()),
+		None => run_dir_name(OffsetDateTime::now_utc().to_offset(offset)),
+	}
+}
+
+/// fills in the template remotes' logs are written to for a run starting now, if there is one
+fn log_template(
+	template: Option<String>,
+	offset: UtcOffset,
+) -> eyre::Result<Option<LogPathTemplate>> {
+	template
+		.map(|template| {
+			LogPathTemplate::new(&template, OffsetDateTime::now_utc().to_offset(offset))
+		})
+		.transpose()
+}
+
+/// starts writing every event sent to the returned sender to the given file as json lines, on a thread of its own
+fn start_event_log(path: &Path) -> eyre::Result<(UnboundedSender<Event>, JoinHandle<()>)> {
+	let file = std::fs::File::create(path)
+		.map_err(|e| eyre::eyre!("failed to create the event log {}: {e}", path.display()))?;
+	let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+	let task = tokio::task::spawn_blocking(move || {
+		let mut sink = NdjsonSink::new(std::io::BufWriter::new(file));
+		while let Some(event) = receiver.blocking_recv() {
+			sink.event(&event);
+		}
+	});
+	Ok((sender, task))
+}
+
+/// waits for every event sent to the event log to be written, once nothing else can send any
+async fn finish_event_log(
+	log: Option<(UnboundedSender<Event>, JoinHandle<()>)>,
+) -> eyre::Result<()> {
+	if let Some((sender, task)) = log {
+		drop(sender);
+		task.await?;
+	}
+	Ok(())
+}
+
+/// runs pupdate with the given arguments, returning the exit code to use
+async fn run(
+	mut args: Args,
+	resume: Option<Resume>,
+	local_offset: Option<UtcOffset>,
+) -> eyre::Result<ExitCode> {
+	if let Some(Subcommand::Doctor { remotes, json }) = args.command {
+		return doctor(&args, remotes, json).await;
+	}
+	if let Some(Subcommand::Schema { of }) = args.command {
+		let schema = match of {
+			SchemaOf::Summary => summary_schema(),
+		};
+		println!("{}", serde_json::to_string_pretty(&schema)?);
+		return Ok(ExitCode::from(exit_code::SUCCESS));
+	}
+
+	let config_files = config_paths(&args)?;
+	let config = Config::load_all(&config_files, args.strict_config)?;
+	if args.show_config {
+		show_config(&args)?;
+		return Ok(ExitCode::from(exit_code::SUCCESS));
+	}
+	if let Some(Subcommand::History {
+		stats,
+		stale,
+		kept_back,
+		json,
+	}) = args.command
+	{
+		let path = config
+			.history_file
+			.clone()
+			.unwrap_or_else(History::default_path);
+		match stale {
+			Some(stale) => {
+				let best_effort: Vec<_> = config
+					.remote_best_effort
+					.iter()
+					.filter(|(_, best_effort)| **best_effort)
+					.map(|(remote, _)| remote.as_str())
+					.collect();
+				print_stale(&path, &best_effort, stale, json)?;
 			}
+			None if kept_back => print_kept_back(&path, json)?,
+			None => print_history(&path, stats, json)?,
 		}
-		Ok(true)
+		return Ok(ExitCode::from(exit_code::SUCCESS));
+	}
+	let playbook = args.playbook.as_deref().map(Playbook::load).transpose()?;
+	// remotes are reached the same way whichever of them end up being acted on
+	let runner = SshRunner::from_config(&config, args.host_key_policy);
+	let remote_windows = config.remote_windows();
+	let (configured, configured_source) = match &playbook {
+		Some(playbook) => (playbook.remotes(), RemoteSource::Playbook),
+		None => (config.remote_names(), RemoteSource::Config),
+	};
+	let ResolvedRemotes {
+		remotes,
+		sources: remote_sources,
+	} = resolve_remotes(&args, configured, configured_source).await?;
+	// progress settings are checked up front so a typo in a template doesn't surface halfway through a run
+	let progress = if args.no_progress || config.no_progress.unwrap_or_default() {
+		Progress::Lines
+	} else {
+		// what's shown as remotes' statuses isn't part of how they look, so it's kept with a preset from the flag
+		let messages = args.progress_messages.or(config.progress.messages);
+		let (preset, settings) = match args.progress_style {
+			Some(preset) => (preset, ProgressSettings::default()),
+			None => (config.progress_style.unwrap_or_default(), config.progress),
+		};
+		Progress::bars(ProgressStyles::new(
+			preset,
+			ProgressSettings {
+				messages,
+				..settings
+			},
+		)?)
+	};
+
+	if runner.host_key_policy == Some(HostKeyPolicy::Insecure) && !remotes.is_empty() {
+		eprintln!(
+			"WARNING: host keys of remotes aren't being verified, so anyone able to intercept the connection can \
+			 impersonate a remote and receive what's sent to it"
+		);
 	}
 
-	let update_output = Command::new("sudo")
-		.arg("apt-get")
-		.arg("update")
-		.output()
+	let merge_output = args.merge_output || config.merge_output.unwrap_or_default();
+	let log_formats = if args.log_format.is_empty() {
+		LogFormats::new(
+			config
+				.log_format
+				.as_ref()
+				.map_or(&[], |setting| setting.formats()),
+		)?
+	} else {
+		LogFormats::new(&args.log_format)?
+	};
+	if merge_output && log_formats != LogFormats::default() {
+		eyre::bail!("merged output is always logged to a single file, use the combined log format instead of --merge-output to have it alongside other formats");
+	}
+
+	let retry = RetryPolicy {
+		retries: args.retries,
+		base: Duration::from_millis(args.retry_backoff),
+		max: Duration::from_millis(args.retry_max_backoff),
+	};
+
+	if let Some(Subcommand::Audit { .. }) = args.command {
+		if remotes.is_empty() {
+			eyre::bail!("nothing to audit: no remotes were given or configured");
+		}
+		require_executable(
+			&runner.ssh,
+			"install openssh-client or set ssh_binary in config",
+		)?;
+		let success = audit(Arc::new(runner), remotes, retry, &progress, args.verbose).await?;
+		return Ok(ExitCode::from(if success {
+			exit_code::SUCCESS
+		} else {
+			exit_code::REMOTES_FAILED
+		}));
+	}
+
+	if let Some(Subcommand::Exec {
+		sudo,
+		json,
+		command,
+		..
+	}) = &args.command
+	{
+		if remotes.is_empty() {
+			eyre::bail!("nothing to run the command on: no remotes were given or configured");
+		}
+		require_executable(
+			&runner.ssh,
+			"install openssh-client or set ssh_binary in config",
+		)?;
+		let offset = config.timestamps.unwrap_or_default().offset(local_offset);
+		let log_template =
+			log_template(args.log_path_template.or(config.log_path_template), offset)?;
+		let log_flush_interval = args
+			.log_flush_interval_ms
+			.or(config.log_flush_interval_ms)
+			.map_or(DEFAULT_LOG_FLUSH_INTERVAL, Duration::from_millis);
+		let stream_log = match &args.stream_log {
+			Some(path) => Some(StreamLogWriter::start(path).await?),
+			None => None,
+		};
+		let event_log = args.events.as_deref().map(start_event_log).transpose()?;
+		let log_dir = run_log_dir(
+			args.log_dir.or(config.log_dir),
+			args.auto_log,
+			config.logs_optional.unwrap_or_default(),
+			offset,
+		)?;
+		let mut argv = Vec::with_capacity(command.len() + 1);
+		if *sudo {
+			argv.push("sudo".to_string());
+		}
+		argv.extend(command.iter().cloned());
+		let summary = exec(
+			Arc::new(runner),
+			remotes,
+			RemoteOptions {
+				command: RemoteCommand::Argv(argv),
+				local_command: None,
+				log_dir: log_dir.clone(),
+				log_layout: config.log_layout.unwrap_or_default(),
+				log_formats,
+				log_names: config.log_names,
+				log_template,
+				log_flush_interval,
+				stream_log: stream_log.as_ref().map(StreamLogWriter::log),
+				report_sources: false,
+				fetch_logs: false,
+				fetch_compressed: false,
+				capture_stdout: true,
+				retry,
+				offline: None,
+				skew_check: None,
+				services: None,
+				output_limits: OutputLimits {
+					limit: args.output_limit,
+					kill_limit: args.output_kill_limit,
+				},
+				merge_output,
+				timeout: remote_timeout(args.timeout, config.timeout),
+				verify_binary: None,
+				webhook: None,
+				silencer: None,
+				forward: None,
+				state: None,
+				change_id: args.change_id.clone(),
+				jobs: args.jobs.or(config.jobs),
+				concurrency_groups: ConcurrencyGroups::default(),
+				fail_on_repo_warnings: false,
+				fail_on_kept_back: false,
+				diagnostics: DiagnosticCommands::default(),
+				events: event_log.as_ref().map(|(sender, _)| sender.clone()),
+			},
+			&progress,
+			args.verbose,
+			*json,
+		)
 		.await?;
-	if !update_output.status.success() {
-		return log(&[update_output], log_dir).await;
+		if let Some(writer) = stream_log {
+			writer.finish().await?;
+		}
+		finish_event_log(event_log).await?;
+		if let Some(log_dir) = log_dir {
+			std::fs::write(
+				log_dir.join(SUMMARY_FILENAME),
+				serde_json::to_string_pretty(&summary)?,
+			)?;
+			if !json {
+				println!("{LOGS_WRITTEN_PREFIX}{}", log_dir.display());
+			}
+		}
+		return Ok(ExitCode::from(
+			if summary.remotes.values().all(|result| result.success) {
+				exit_code::SUCCESS
+			} else {
+				exit_code::REMOTES_FAILED
+			},
+		));
 	}
-	let upgrade_output = Command::new("sudo")
-		.arg("apt-get")
-		.arg("upgrade")
-		.arg("-y")
-		.output()
+
+	if let Some(Subcommand::Drift { .. }) = args.command {
+		if remotes.len() < 2 {
+			eyre::bail!("nothing to compare: at least two remotes have to be given or configured");
+		}
+		require_executable(
+			&runner.ssh,
+			"install openssh-client or set ssh_binary in config",
+		)?;
+		let log_dir = run_log_dir(
+			args.log_dir.or(config.log_dir),
+			args.auto_log,
+			config.logs_optional.unwrap_or_default(),
+			config.timestamps.unwrap_or_default().offset(local_offset),
+		)?;
+		let drift = drift(Arc::new(runner), remotes, retry, &progress, args.verbose).await?;
+		drift.print();
+		if let Some(log_dir) = log_dir {
+			drift.write(&log_dir)?;
+			println!("{LOGS_WRITTEN_PREFIX}{}", log_dir.display());
+		}
+		return Ok(ExitCode::from(if drift.failed.is_empty() {
+			exit_code::SUCCESS
+		} else {
+			exit_code::REMOTES_FAILED
+		}));
+	}
+
+	if let Some(Subcommand::Plan { output, .. }) = &args.command {
+		if remotes.is_empty() && args.skip_local {
+			eyre::bail!("nothing to plan for: no remotes are given or configured and the local system is skipped");
+		}
+		if !remotes.is_empty() {
+			require_executable(
+				&runner.ssh,
+				"install openssh-client or set ssh_binary in config",
+			)?;
+		}
+		let local_apt = if args.skip_local {
+			None
+		} else {
+			let apt = config
+				.apt_binary
+				.unwrap_or_else(|| PathBuf::from("apt-get"));
+			require_executable(&apt, "install apt or set apt_binary in config")?;
+			Some(apt)
+		};
+		let log_dir = run_log_dir(
+			args.log_dir.or(config.log_dir),
+			args.auto_log,
+			config.logs_optional.unwrap_or_default(),
+			config.timestamps.unwrap_or_default().offset(local_offset),
+		)?;
+		let plan = plan(
+			Arc::new(runner),
+			remotes,
+			local_apt,
+			retry,
+			&progress,
+			args.verbose,
+		)
 		.await?;
-	log(&[update_output, upgrade_output], log_dir).await
-}
+		plan.print();
+		if let Some(output) = output {
+			plan.write(output)?;
+		}
+		if let Some(log_dir) = log_dir {
+			plan.write(&log_dir.join(PLAN_FILENAME))?;
+			println!("{LOGS_WRITTEN_PREFIX}{}", log_dir.display());
+		}
+		return Ok(ExitCode::from(if plan.failed.is_empty() {
+			exit_code::SUCCESS
+		} else {
+			exit_code::REMOTES_FAILED
+		}));
+	}
 
-#[tokio::main]
-async fn main() -> eyre::Result<()> {
-	let args = Args::parse();
-	let base_config_path = {
-		BaseDirs::new()
-			.map(|bd| bd.home_dir().join(PUPDATE_CONFIG_FILENAME))
-			.and_then(|p| std::fs::exists(&p).ok().and(Some(p)))
-	};
-	let config_path = args.config.or(base_config_path);
-	let config: Config = if let Some(config) = config_path {
-		serde_json::from_str(&std::fs::read_to_string(config)?)?
+	let history_file = config
+		.history_file
+		.clone()
+		.unwrap_or_else(History::default_path);
+	let detection_file = DetectionCache::path_beside(&history_file);
+	let detection_ttl = match &config.detection_ttl {
+		Some(ttl) => {
+			parse_duration(ttl).map_err(|e| eyre::eyre!("invalid detection_ttl in config: {e}"))?
+		}
+		None => DEFAULT_DETECTION_TTL,
+	};
+
+	// remotes pupdated offline are pupdated alongside the rest instead of running the remote command
+	let mut offline_remotes = Vec::new();
+	for remote in args.offline_for {
+		if !offline_remotes.contains(&remote) {
+			offline_remotes.push(remote);
+		}
+	}
+	let remotes: Vec<_> = remotes
+		.into_iter()
+		.filter(|remote| !offline_remotes.contains(remote))
+		.collect();
+	let remotes = if args.flaky_only {
+		let history = History::load(&history_file)?;
+		let flaky: Vec<_> = remotes
+			.into_iter()
+			.filter(|remote| history.consecutive_failures(remote) >= args.threshold)
+			.collect();
+		if args.command.is_none() {
+			println!(
+				"selected {} remotes which failed to pupdate at least {} runs in a row",
+				flaky.len(),
+				args.threshold
+			);
+		}
+		flaky
 	} else {
-		Config::default()
+		remotes
 	};
+	// the local system listed among the remotes is pupdated like one of them rather than on its own, unless it's
+	// skipped
+	let remotes: Vec<_> = if args.skip_local {
+		remotes
+			.into_iter()
+			.filter(|remote| remote != LOCAL_REMOTE)
+			.collect()
+	} else {
+		args.skip_local = remotes.iter().any(|remote| remote == LOCAL_REMOTE);
+		remotes
+	};
+	if let Some(Subcommand::ListRemotes {
+		json,
+		toml,
+		sources,
+	}) = args.command
+	{
+		let remotes: Vec<_> = remotes.iter().chain(&offline_remotes).collect();
+		// remotes given to `--offline-for` are always given as arguments
+		let source = |remote: &String| {
+			remote_sources
+				.get(remote)
+				.cloned()
+				.unwrap_or(RemoteSource::Cli)
+		};
+		#[derive(serde::Serialize)]
+		struct SourcedRemote<'a> {
+			remote: &'a String,
+			source: RemoteSource,
+		}
+		if json && sources {
+			let remotes: Vec<_> = remotes
+				.into_iter()
+				.map(|remote| SourcedRemote {
+					remote,
+					source: source(remote),
+				})
+				.collect();
+			println!("{}", serde_json::to_string_pretty(&remotes)?);
+		} else if json {
+			println!("{}", serde_json::to_string_pretty(&remotes)?);
+		} else if toml {
+			#[derive(serde::Serialize)]
+			struct RemoteList<'a> {
+				remotes: Vec<&'a String>,
+			}
+			print!("{}", toml::to_string(&RemoteList { remotes })?);
+		} else if sources {
+			let width = remotes.iter().map(|remote| remote.chars().count()).max();
+			for remote in remotes {
+				println!(
+					"{remote:width$}  {}",
+					source(remote),
+					width = width.unwrap_or_default()
+				);
+			}
+		} else {
+			for remote in remotes {
+				println!("{remote}");
+			}
+		}
+		return Ok(ExitCode::from(exit_code::SUCCESS));
+	}
 
-	let log_dir = args.log_dir.or(config.log_dir).map(|log_dir| {
-		let log_dir = log_dir.join(
-			OffsetDateTime::now_utc()
-				.format(&time::format_description::well_known::Rfc3339)
-				.expect("should never fail, surely"),
+	// emitting a script doesn't pupdate anything, so it's allowed outside the maintenance window
+	if let Some(window) = config
+		.maintenance_window
+		.as_ref()
+		.filter(|_| args.emit_script.is_none())
+	{
+		let window = MaintenanceWindow::parse(window)
+			.map_err(|e| eyre::eyre!("invalid maintenance_window in config: {e}"))?;
+		let problem = match window.time_at(OffsetDateTime::now_utc(), local_offset) {
+			Some(now) => {
+				(!window.contains(now)).then(|| {
+					format!(
+						"it's {}, outside the maintenance window {window}",
+						format_time(now)
+					)
+				})
+			}
+			None => Some(format!(
+				"couldn't determine the local time zone to check the maintenance window {window} against, give it a \
+				 time zone like `{window} Europe/Berlin`"
+			)),
+		};
+		if let Some(problem) = problem {
+			if !args.force {
+				eyre::bail!("{problem}, pass --force to pupdate anyway");
+			}
+			eprintln!("warning: {problem}, pupdating anyway since --force was given");
+		}
+	}
+
+	// packages are checked up front so a badly named one doesn't surface after other remotes were pupdated
+	let offline_packages = args
+		.package_dir
+		.as_deref()
+		.map(OfflinePackages::load)
+		.transpose()?;
+
+	if remotes.is_empty() && offline_remotes.is_empty() && args.skip_local {
+		eyre::bail!(
+			"nothing to pupdate: local pupdates are skipped and no remotes were given or configured"
 		);
-		std::fs::create_dir_all(&log_dir).expect("failed to create logs directory");
-		log_dir
+	}
+
+	if !remotes.is_empty() || !offline_remotes.is_empty() {
+		require_executable(
+			&runner.ssh,
+			"install openssh-client or set ssh_binary in config",
+		)?;
+	}
+	if !offline_remotes.is_empty() {
+		require_executable(
+			&runner.scp,
+			"install openssh-client or set scp_binary in config",
+		)?;
+	}
+	let curl = config.curl_binary.unwrap_or_else(|| PathBuf::from("curl"));
+	if args.per_remote_webhook.is_some() && !args.local_only {
+		require_executable(&curl, "install curl or set curl_binary in config")?;
+	}
+	// refreshing indexes doesn't upgrade anything, so there's nothing risky to check for
+	let risky_policy = args
+		.risky_policy
+		.or(config.risky_policy)
+		.filter(|_| !args.index_only);
+	let risky_packages = config.risky_packages.unwrap_or_else(|| {
+		DEFAULT_RISKY_PACKAGES
+			.iter()
+			.map(|package| package.to_string())
+			.collect()
 	});
+	if risky_policy == Some(RiskyPolicy::Prompt) && !std::io::stdin().is_terminal() {
+		eyre::bail!("--risky-policy prompt needs a terminal to ask in, use skip or proceed for unattended runs");
+	}
+	if args.confirm_per_host && !std::io::stdin().is_terminal() {
+		eyre::bail!("--confirm-per-host needs a terminal to ask in");
+	}
+	let manager = if args.skip_local {
+		None
+	} else {
+		local_package_manager(
+			&config.steps,
+			config.apt_binary.as_deref(),
+			&config.package_managers,
+		)
+	};
+	let sudo = config.sudo_binary.unwrap_or_else(|| PathBuf::from("sudo"));
+	let custom_steps = if config.steps.is_empty() {
+		manager.map(|(name, manager)| manager.steps(&name, &sudo.to_string_lossy()))
+	} else {
+		Some(config.steps)
+	};
+	let apt = config
+		.apt_binary
+		.unwrap_or_else(|| PathBuf::from("apt-get"));
+	let mut steps = if let Some(custom_steps) = custom_steps {
+		if args.only_security && !args.skip_local {
+			// there's no telling which of a custom step's updates are security updates
+			eyre::bail!("--only-security can't be used with custom steps or package managers, pass --skip-local to only pupdate remotes");
+		}
+		if args.index_only && !args.skip_local {
+			// there's no telling which custom steps or package managers only refresh indexes
+			eyre::bail!("--index-only can't be used with custom steps or package managers, pass --skip-local to only pupdate remotes");
+		}
+		if args.skip_refresh_if_recent.is_some() && !args.skip_local {
+			eyre::bail!("--skip-refresh-if-recent can't be used with custom steps or package managers, pass --skip-local to only pupdate remotes");
+		}
+		if risky_policy.is_some() && !args.skip_local {
+			// there's no telling what custom steps or package managers will update
+			eyre::bail!("risky updates can't be checked for with custom steps or package managers, pass --skip-local to only pupdate remotes");
+		}
+		if !args.skip_local {
+			for step in &custom_steps {
+				if let Some(program) = step.command.first() {
+					require_executable(
+						Path::new(program),
+						&format!("install it or fix the command for step {}", step.name),
+					)?;
+				}
+			}
+		}
+		custom_steps
+	} else {
+		if !args.skip_local {
+			require_executable(&sudo, "install sudo or set sudo_binary in config")?;
+			require_executable(&apt, "install apt or set apt_binary in config")?;
+		}
+		if args.index_only {
+			Step::index_steps(&sudo.to_string_lossy(), &apt.to_string_lossy())
+		} else if args.only_security {
+			let unattended_upgrade = config
+				.unattended_upgrade_binary
+				.unwrap_or_else(|| PathBuf::from("unattended-upgrade"));
+			if !args.skip_local {
+				require_executable(
+					&unattended_upgrade,
+					"install unattended-upgrades or set unattended_upgrade_binary in config",
+				)?;
+			}
+			Step::security_steps(
+				&sudo.to_string_lossy(),
+				&apt.to_string_lossy(),
+				&unattended_upgrade.to_string_lossy(),
+			)
+		} else {
+			Step::default_steps(&sudo.to_string_lossy(), &apt.to_string_lossy())
+		}
+	};
+	if let Some(recent) = args.skip_refresh_if_recent.filter(|_| !args.skip_local) {
+		let age = std::fs::metadata(APT_LISTS_DIR)
+			.and_then(|metadata| metadata.modified())
+			.ok()
+			.and_then(|modified| modified.elapsed().ok());
+		if let Some(age) = age.filter(|age| *age < recent) {
+			println!(
+				"skipping apt-get update, the package lists were refreshed {} ago",
+				format_age(age)
+			);
+			steps.retain(|step| step.name != APT_UPDATE_STEP);
+		}
+	}
+	let priority = Priority {
+		nice: args.nice,
+		ionice: args.ionice,
+	};
+	if !args.skip_local {
+		if priority.nice.is_some() {
+			require_executable(Path::new("nice"), "install coreutils to use --nice")?;
+		}
+		if priority.ionice.is_some() {
+			require_executable(Path::new("ionice"), "install util-linux to use --ionice")?;
+		}
+	}
+
+	if let Some(path) = &args.emit_script {
+		let command = config.remote_command.clone().unwrap_or_default();
+		let command = if merge_output {
+			merge_stderr(&command.to_command_line())
+		} else {
+			command.to_command_line()
+		};
+		let scripted: Vec<_> = remotes
+			.iter()
+			.map(|remote| {
+				let scripted = match runner.invocation(remote, &command) {
+					Some(invocation) => ScriptedRemote::Invocation(invocation),
+					None => ScriptedRemote::Unknown,
+				};
+				(remote.clone(), scripted)
+			})
+			.chain(
+				offline_remotes
+					.iter()
+					.map(|remote| (remote.clone(), ScriptedRemote::Offline)),
+			)
+			.collect();
+		let forwarded: Vec<_> = forwarded_flags(
+			args.only_security,
+			args.index_only,
+			args.skip_refresh_if_recent,
+		)
+		.into_iter()
+		.flat_map(|flag| flag.args)
+		.collect();
+		let local: Vec<_> = if args.skip_local {
+			Vec::new()
+		} else {
+			let wrapper = priority.wrapper();
+			steps
+				.iter()
+				.map(|step| wrapper.iter().chain(&step.command).cloned().collect())
+				.collect()
+		};
+		write_script(path, &render_script(&scripted, &forwarded, &local))?;
+		println!(
+			"wrote the commands for {} remotes and {} local steps to {} without running them",
+			scripted.len(),
+			local.len(),
+			path.display()
+		);
+		return Ok(ExitCode::from(exit_code::SUCCESS));
+	}
+
+	let log_template = log_template(
+		args.log_path_template.clone().or(config.log_path_template),
+		config.timestamps.unwrap_or_default().offset(local_offset),
+	)?;
+	let log_flush_interval = args
+		.log_flush_interval_ms
+		.or(config.log_flush_interval_ms)
+		.map_or(DEFAULT_LOG_FLUSH_INTERVAL, Duration::from_millis);
+	let stream_log = match &args.stream_log {
+		Some(path) => Some(StreamLogWriter::start(path).await?),
+		None => None,
+	};
+	let event_log = args.events.as_deref().map(start_event_log).transpose()?;
+	// a resumed run carries on in the interrupted run's directory
+	let log_dir = match &resume {
+		Some(resume) => Some(resume.dir.clone()),
+		None => run_log_dir(
+			args.log_dir.or(config.log_dir),
+			args.auto_log,
+			config.logs_optional.unwrap_or_default(),
+			config.timestamps.unwrap_or_default().offset(local_offset),
+		)?,
+	};
+
+	if args.fetch_logs && log_dir.is_none() {
+		eyre::bail!("--fetch-logs needs a log directory to fetch remote logs into");
+	}
+
+	let state = match (&resume, &log_dir) {
+		(Some(resume), _) => Some(resume.state.clone()),
+		(None, Some(log_dir)) => {
+			match StateFile::create(
+				log_dir,
+				remotes.iter().chain(&offline_remotes),
+				!args.skip_local,
+			) {
+				Ok(state) => Some(Arc::new(state)),
+				Err(e) => {
+					eprintln!("warning: {e}, this run can't be resumed if it's interrupted");
+					None
+				}
+			}
+		}
+		(None, None) => None,
+	};
+	let silencer = Silencer::new(
+		config.silence,
+		&config.remote_expected_downtime,
+		run_id(
+			log_dir.as_deref(),
+			config.timestamps.unwrap_or_default().offset(local_offset),
+		)?,
+	)?;
+
+	let mut summary = Summary::default();
 
 	if args.local_only {
 		println!("running in local mode, no remotes will be pupdated");
 	} else {
-		let remotes = args.remotes.unwrap_or(config.remotes);
-		let len = remotes.len();
+		// remotes given as arguments are always pupdated, as are offline remotes since they're named explicitly too
+		let remotes = match args.min_interval.filter(|_| args.remotes.is_none()) {
+			Some(min_interval) => {
+				let history = History::load(&history_file)?;
+				let now = OffsetDateTime::now_utc();
+				let mut kept = Vec::with_capacity(remotes.len());
+				for remote in remotes {
+					match history.since_last_success(&remote, now) {
+						Some(age) if age < min_interval => {
+							let result = TargetSummary {
+								success: true,
+								skipped: Some(format!("updated {} ago", format_age(age))),
+								..Default::default()
+							};
+							if let Some(state) = &state {
+								state.remote_finished(&remote, &result);
+							}
+							summary.remotes.insert(remote, result);
+						}
+						_ => kept.push(remote),
+					}
+				}
+				if !summary.remotes.is_empty() {
+					println!(
+						"skipping {} remotes pupdated in the last {}:",
+						summary.remotes.len(),
+						format_age(min_interval)
+					);
+					for (remote, result) in &summary.remotes {
+						if let Some(skipped) = &result.skipped {
+							println!("{remote} (skipped: {skipped})");
+						}
+					}
+				}
+				kept
+			}
+			None => remotes,
+		};
+		let (remotes, offline_remotes) = if args.ignore_windows {
+			(remotes, offline_remotes)
+		} else {
+			let outside = outside_windows(
+				remotes.iter().chain(&offline_remotes),
+				&remote_windows,
+				OffsetDateTime::now_utc(),
+				local_offset,
+			)
+			.map_err(|e| eyre::eyre!(e))?;
+			if !outside.is_empty() {
+				println!(
+					"skipping {} remotes outside their maintenance windows:",
+					outside.len()
+				);
+			}
+			for (remote, window) in &outside {
+				let result = TargetSummary {
+					skipped: Some(format!(
+						"outside maintenance window ({})",
+						window.describe_opening()
+					)),
+					outside_window: Some(window.to_string()),
+					..Default::default()
+				};
+				println!(
+					"{remote} (skipped: {})",
+					result.skipped.as_deref().unwrap_or_default()
+				);
+				if let Some(state) = &state {
+					state.remote_finished(remote, &result);
+				}
+				summary.remotes.insert(remote.clone(), result);
+			}
+			let inside = |remote: &String| !outside.contains_key(remote);
+			(
+				remotes.into_iter().filter(inside).collect(),
+				offline_remotes.into_iter().filter(inside).collect(),
+			)
+		};
+		let runner = Arc::new(runner);
+		let (remotes, offline_remotes) = match args.resolve_policy.or(config.resolve_policy) {
+			Some(policy) if !remotes.is_empty() || !offline_remotes.is_empty() => {
+				let all: Vec<_> = remotes.iter().chain(&offline_remotes).cloned().collect();
+				let unresolved = unresolved_remotes(runner.clone(), &all).await;
+				if !unresolved.is_empty() {
+					println!("the following remotes' hostnames don't resolve:");
+					for (remote, problem) in &unresolved {
+						println!("{remote} ({problem})");
+					}
+				}
+				match policy {
+					ResolvePolicy::Abort if !unresolved.is_empty() => {
+						eprintln!(
+							"error: {} remotes' hostnames don't resolve, fix them or pass --resolve-policy warn or skip",
+							unresolved.len()
+						);
+						return Ok(ExitCode::from(exit_code::ABORTED));
+					}
+					ResolvePolicy::Skip => {
+						for (remote, problem) in &unresolved {
+							let result = TargetSummary {
+								skipped: Some(problem.clone()),
+								..Default::default()
+							};
+							if let Some(state) = &state {
+								state.remote_finished(remote, &result);
+							}
+							summary.remotes.insert(remote.clone(), result);
+						}
+						let resolves = |remote: &String| !unresolved.contains_key(remote);
+						(
+							remotes.into_iter().filter(resolves).collect(),
+							offline_remotes.into_iter().filter(resolves).collect(),
+						)
+					}
+					_ => (remotes, offline_remotes),
+				}
+			}
+			_ => (remotes, offline_remotes),
+		};
+		// offline remotes install the packages they're given rather than whatever's pending, so they aren't checked
+		let mut risky = BTreeMap::new();
+		let remotes = match risky_policy.filter(|_| !remotes.is_empty()) {
+			Some(policy) => {
+				risky = check_risky_remotes(
+					runner.clone(),
+					remotes.clone(),
+					&risky_packages,
+					retry,
+					&progress,
+					args.verbose,
+				)
+				.await?;
+				let mut kept = Vec::with_capacity(remotes.len());
+				for remote in remotes {
+					match risky.get(&remote) {
+						Some(packages) if !allow_risky(policy, &remote, packages)? => {
+							let result = TargetSummary {
+								skipped: Some(describe_risky(packages)),
+								risky_updates: packages.clone(),
+								..Default::default()
+							};
+							if let Some(state) = &state {
+								state.remote_finished(&remote, &result);
+							}
+							summary.remotes.insert(remote, result);
+						}
+						_ => kept.push(remote),
+					}
+				}
+				kept
+			}
+			None => remotes,
+		};
+		let manual: Vec<_> = remotes
+			.iter()
+			.chain(&offline_remotes)
+			.filter(|remote| config.remote_approval.get(*remote) == Some(&Approval::Manual))
+			.cloned()
+			.collect();
+		let mut approvals = BTreeMap::new();
+		let (remotes, offline_remotes) = if manual.is_empty() {
+			(remotes, offline_remotes)
+		} else {
+			approvals = approve_remotes(
+				runner.clone(),
+				manual,
+				offline_packages
+					.as_ref()
+					.map(|packages| (&offline_remotes[..], packages)),
+				args.approve_all,
+				retry,
+				&progress,
+				args.verbose,
+			)
+			.await?;
+			let mut approved = |remote: &String| match approvals.get(remote) {
+				Some(decision) if !decision.approved => {
+					let result = TargetSummary {
+						skipped: Some(match &decision.how {
+							Some(how) => format!("needs manual approval, {how}"),
+							None => format!("not approved by {}", decision.by),
+						}),
+						approval: Some(decision.clone()),
+						declined: true,
+						..Default::default()
+					};
+					if let Some(state) = &state {
+						state.remote_finished(remote, &result);
+					}
+					summary.remotes.insert(remote.clone(), result);
+					false
+				}
+				_ => true,
+			};
+			(
+				remotes.into_iter().filter(&mut approved).collect(),
+				offline_remotes.into_iter().filter(&mut approved).collect(),
+			)
+		};
+		let len = remotes.len() + offline_remotes.len();
 		let mut failed = Vec::new();
 
 		if len != 0 {
-			println!("pupdating {} remotes", len);
-			let progress = MultiProgress::new();
-			let overall = progress.add(ProgressBar::new(len as u64));
-			let spinner_style =
-				ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")?
-					.tick_chars(SPINNER_STYLE);
-			let finished_style = ProgressStyle::with_template("{prefix:.bold.dim} {wide_msg}")?;
 			let start = OffsetDateTime::now_utc();
-
-			let mut tasks = Vec::with_capacity(len);
-			for remote in remotes {
-				let pb = progress.insert_before(&overall, ProgressBar::new_spinner());
-				pb.set_prefix(remote.clone());
-				pb.set_style(spinner_style.clone());
-				pb.enable_steady_tick(Duration::from_millis(SPINNER_TIME_MILLIS));
-				tasks.push(tokio::spawn(pupdate_remote(
-					remote,
-					log_dir.clone(),
-					pb,
-					finished_style.clone(),
-					overall.clone(),
-				)));
+			let output_limits = OutputLimits {
+				limit: args.output_limit,
+				kill_limit: args.output_kill_limit,
+			};
+			summary.output_limits = Some(output_limits);
+			let command = config.remote_command.unwrap_or_default();
+			// a hash given for the run applies to every remote, replacing any particular remotes' hashes
+			let (expected_sha256, remote_sha256) = match args.verify_remote_checksum {
+				Some(sha256) => (Some(sha256), BTreeMap::new()),
+				None => (config.expected_binary_sha256, config.remote_binary_sha256),
+			};
+			let verify_binary = if expected_sha256.is_some() || !remote_sha256.is_empty() {
+				let Some(program) = command.program() else {
+					eyre::bail!(
+						"the remote command's binary can't be verified since it has no program"
+					);
+				};
+				Some(BinaryCheck {
+					program: program.to_string(),
+					expected_sha256,
+					remote_sha256,
+					skip: args.no_verify,
+				})
+			} else {
+				None
+			};
+			let flags = forwarded_flags(
+				args.only_security,
+				args.index_only,
+				args.skip_refresh_if_recent,
+			);
+			let forward = if flags.is_empty() {
+				None
+			} else {
+				let Some(program) = command.program() else {
+					eyre::bail!(
+						"flags can't be forwarded to remotes since the remote command has no program to check the version of"
+					);
+				};
+				// versions detected on earlier runs save asking every remote again
+				let known_versions = if args.redetect {
+					BTreeMap::new()
+				} else {
+					match DetectionCache::load(&detection_file) {
+						Ok(cache) => cache.fresh_versions(OffsetDateTime::now_utc(), detection_ttl),
+						Err(e) => {
+							eprintln!("warning: {e}, detecting remotes' pupdate versions again");
+							BTreeMap::new()
+						}
+					}
+				};
+				Some(Forwarding {
+					program: program.to_string(),
+					flags,
+					known_versions,
+				})
+			};
+			let cached_versions: Option<Vec<_>> = forward
+				.as_ref()
+				.map(|forward| forward.known_versions.keys().cloned().collect());
+			let local_command = remotes
+				.iter()
+				.any(|remote| remote == LOCAL_REMOTE)
+				.then(|| local_command(&config_files))
+				.transpose()?;
+			let options = RemoteOptions {
+				command,
+				local_command,
+				log_dir: log_dir.clone(),
+				log_layout: config.log_layout.unwrap_or_default(),
+				log_formats,
+				log_names: config.log_names,
+				log_template,
+				log_flush_interval,
+				stream_log: stream_log.as_ref().map(StreamLogWriter::log),
+				report_sources: args.report_sources,
+				fetch_logs: args.fetch_logs,
+				fetch_compressed: args.fetch_compressed,
+				capture_stdout: false,
+				retry,
+				offline: None,
+				skew_check: args
+					.skew_policy
+					.or(config.skew_policy)
+					.map(|policy| SkewCheck {
+						policy,
+						threshold: Duration::from_secs(
+							args.skew_threshold
+								.or(config.skew_threshold)
+								.unwrap_or(DEFAULT_SKEW_THRESHOLD_SECS),
+						),
+					}),
+				services: if args.restart_services {
+					Some(ServicesPolicy::Restart)
+				} else {
+					args.check_services.then_some(ServicesPolicy::Report)
+				},
+				output_limits,
+				merge_output,
+				timeout: remote_timeout(args.timeout, config.timeout),
+				verify_binary,
+				forward,
+				webhook: args.per_remote_webhook.map(|url| Webhook { curl, url }),
+				silencer,
+				state: state.clone(),
+				change_id: args.change_id.clone(),
+				jobs: args.jobs.or(config.jobs),
+				concurrency_groups: ConcurrencyGroups {
+					limits: config.concurrency_groups,
+					remotes: config.remote_concurrency_groups,
+				},
+				fail_on_repo_warnings: args.fail_on_repo_warnings,
+				fail_on_kept_back: args.fail_on_kept_back,
+				diagnostics: DiagnosticCommands {
+					commands: config.on_failure_capture,
+					remotes: config.remote_on_failure_capture,
+				},
+				events: event_log.as_ref().map(|(sender, _)| sender.clone()),
+			};
+			// a resumed run keeps the manifest of the run it resumes, which covers every remote
+			if let Some(log_dir) = log_dir.as_ref().filter(|_| resume.is_none()) {
+				Manifest::new(remotes.iter().chain(&offline_remotes), &options)?.write(log_dir)?;
 			}
-			overall.tick();
-
-			for task in tasks {
-				let (remote, success) = task.await??;
-				if !success {
-					failed.push(remote);
+			if args.print_commands {
+				// flags forwarded to a remote's pupdate depend on its version, so they're only in its log and summary
+				let command = options.command.to_command_line();
+				let command = if merge_output {
+					merge_stderr(&command)
+				} else {
+					command
+				};
+				for remote in &remotes {
+					for line in runner
+						.invocation(remote, &command)
+						.iter()
+						.flat_map(|invocation| invocation.lines())
+					{
+						println!("{remote}: {line}");
+					}
+				}
+			}
+			let mut results = Vec::with_capacity(len);
+			if !remotes.is_empty() {
+				let len = remotes.len();
+				if let Some(playbook) = &playbook {
+					println!(
+						"pupdating {len} remotes in {} phases",
+						playbook.phases.len()
+					);
+					let (playbook_results, phases) = run_playbook(
+						runner.clone(),
+						playbook,
+						&remotes,
+						&options,
+						&progress,
+						args.verbose,
+					)
+					.await?;
+					results.extend(playbook_results);
+					summary.phases = phases;
+				} else {
+					let at_a_time = options
+						.jobs
+						.map(|jobs| format!(", {jobs} at a time"))
+						.unwrap_or_default();
+					if args.only_security {
+						println!("pupdating {len} remotes with security updates only{at_a_time}");
+					} else if args.index_only {
+						println!("refreshing package indexes on {len} remotes{at_a_time}");
+					} else {
+						println!("pupdating {len} remotes{at_a_time}");
+					}
+					let options = Arc::new(options.clone());
+					if args.confirm_per_host {
+						results.extend(
+							pupdate_confirmed(
+								runner.clone(),
+								remotes,
+								options,
+								&progress,
+								args.verbose,
+							)
+							.await?,
+						);
+					} else {
+						let run = pupdate_remotes(runner.clone(), remotes, options);
+						results.extend(progress.show_remotes(run, len, args.verbose).await?);
+					}
+				}
+			}
+			if let Some(packages) = offline_packages {
+				let len = offline_remotes.len();
+				println!(
+					"installing {} packages on {len} offline remotes",
+					packages.packages.len()
+				);
+				let options = Arc::new(RemoteOptions {
+					// offline remotes don't run pupdate themselves, so they have no logs of their own to fetch
+					fetch_logs: false,
+					offline: Some(packages),
+					..options
+				});
+				if args.confirm_per_host {
+					results.extend(
+						pupdate_confirmed(
+							runner.clone(),
+							offline_remotes,
+							options,
+							&progress,
+							args.verbose,
+						)
+						.await?,
+					);
+				} else {
+					let run = pupdate_remotes(runner.clone(), offline_remotes, options);
+					results.extend(progress.show_remotes(run, len, args.verbose).await?);
 				}
 			}
+			let mut offline = Vec::new();
+			// remotes declined at the prompt are listed with the other skipped remotes rather than as failures
+			let mut declined = 0;
+			for (remote, mut result) in results {
+				// best effort remotes are expected to be unreachable now and then, which isn't worth failing over
+				result.offline = !result.success
+					&& result
+						.failure
+						.is_some_and(|failure| failure.kind == FailureKind::Connection)
+					&& config.remote_best_effort.get(&remote) == Some(&true);
+				if result.offline {
+					offline.push(remote.clone());
+				} else if result.declined {
+					declined += 1;
+				} else if !result.success {
+					failed.push(remote.clone());
+				}
+				result.risky_updates = risky.remove(&remote).unwrap_or_default();
+				result.approval = approvals.remove(&remote);
+				result.suspicious = args
+					.max_output_age
+					.and_then(|max_output_age| result.stalled(max_output_age));
+				summary.remotes.insert(remote, result);
+			}
 
 			let end = OffsetDateTime::now_utc();
 			let duration = end - start;
 
-			overall.finish_and_clear();
-
+			let len = len - declined;
 			println!(
 				"{}/{len} remotes pupdated successfully in {} seconds",
-				len - failed.len(),
+				len - failed.len() - offline.len(),
 				duration.whole_seconds()
 			);
+			let up_to_date = summary
+				.remotes
+				.values()
+				.filter(|result| result.up_to_date)
+				.count();
+			if up_to_date != 0 {
+				println!(
+					"{} remotes updated, {up_to_date} already up to date",
+					len - failed.len() - offline.len() - up_to_date
+				);
+			}
+			if !summary.phases.is_empty() {
+				println!("phases:");
+				for phase in &summary.phases {
+					let len = phase.remotes.len();
+					match (&phase.skipped, phase.duration_seconds) {
+						(Some(skipped), _) => println!("{}: skipped since {skipped}", phase.name),
+						(None, duration) => println!(
+							"{}: {}/{len} remotes pupdated successfully in {} seconds",
+							phase.name,
+							len - phase.failed.len(),
+							duration.unwrap_or_default().round()
+						),
+					}
+				}
+			}
+			let parallelism = Parallelism {
+				sequential_seconds: summary
+					.remotes
+					.values()
+					.filter_map(|result| result.duration_seconds)
+					.sum(),
+				wall_seconds: duration.as_seconds_f64(),
+			};
+			if let Some(speedup) = parallelism.speedup().filter(|_| len > 1) {
+				println!(
+					"{} of updates completed in {} wall time, {speedup:.1}x parallel speedup",
+					format_age(Duration::from_secs_f64(parallelism.sequential_seconds)),
+					format_age(Duration::from_secs_f64(parallelism.wall_seconds))
+				);
+			}
+			summary.parallelism = Some(parallelism);
 			if !failed.is_empty() {
 				println!("the following remotes failed to pupdate:");
-				for failed in failed {
-					println!("{failed}");
+				print_failed(&summary.remotes, &failed);
+			}
+			if !offline.is_empty() {
+				println!("offline (best effort):");
+				for remote in &offline {
+					println!("{remote}");
+				}
+			}
+			let suspicious: Vec<_> = summary
+				.remotes
+				.iter()
+				.filter_map(|(remote, result)| Some((remote, result.suspicious.as_ref()?)))
+				.collect();
+			if !suspicious.is_empty() {
+				println!(
+					"the following remotes look like they may have stalled and are worth checking:"
+				);
+				for (remote, suspicious) in suspicious {
+					println!("{remote}: {suspicious}");
+				}
+			}
+			if args.check_services || args.restart_services {
+				let mut needing_restart = Vec::new();
+				for (remote, result) in &summary.remotes {
+					match (&result.services_needing_restart, &result.services_error) {
+						(Some(services), None) if !services.is_empty() => {
+							needing_restart.push((remote, services.join(", ")))
+						}
+						(_, Some(error)) => {
+							needing_restart.push((remote, format!("couldn't be handled ({error})")))
+						}
+						_ => {}
+					}
+				}
+				if needing_restart.is_empty() {
+					println!("no services need restarting");
+				} else if args.restart_services {
+					println!("services restarted on each remote:");
+				} else {
+					println!("services needing a restart on each remote:");
+				}
+				for (remote, services) in needing_restart {
+					println!("{remote}: {services}");
+				}
+			}
+			let repo_warnings: Vec<_> = summary
+				.remotes
+				.iter()
+				.filter(|(_, result)| !result.repo_warnings.is_empty())
+				.collect();
+			if !repo_warnings.is_empty() {
+				println!("apt warned about repository signatures on these remotes:");
+				for (remote, result) in repo_warnings {
+					for warning in &result.repo_warnings {
+						println!("{remote}: {warning}");
+					}
+				}
+			}
+			let kept_back: Vec<_> = summary
+				.remotes
+				.iter()
+				.filter(|(_, result)| !result.kept_back.is_empty())
+				.collect();
+			if !kept_back.is_empty() {
+				println!("apt kept back packages on these remotes:");
+				for (remote, result) in kept_back {
+					println!("{remote}: {}", result.kept_back.join(", "));
 				}
 			}
+			if args.report_sources {
+				println!("package mirrors contacted by each remote:");
+				for (remote, result) in summary
+					.remotes
+					.iter()
+					.filter(|(_, result)| result.skipped.is_none())
+				{
+					println!("{remote}: {}", format_sources(result.sources.as_deref()));
+				}
+			}
+
+			if args.diff_remotes {
+				let pupdated: Vec<_> = summary
+					.remotes
+					.iter()
+					.filter(|(_, result)| result.success && result.skipped.is_none())
+					.map(|(remote, _)| remote.clone())
+					.collect();
+				if pupdated.len() < 2 {
+					println!("not enough remotes pupdated successfully to compare their packages");
+				} else {
+					// drift only reports on the run, so failing to query a remote doesn't change pupdate's exit code
+					let drift = drift(runner, pupdated, retry, &progress, args.verbose).await?;
+					drift.print();
+					if let Some(log_dir) = &log_dir {
+						drift.write(log_dir)?;
+					}
+				}
+			}
+
+			if let Some(cached_versions) = cached_versions {
+				let detected: Vec<_> = summary
+					.remotes
+					.iter()
+					.filter(|(remote, _)| !cached_versions.contains(remote))
+					.filter_map(|(remote, result)| {
+						Some((remote, Version::parse(result.pupdate_version.as_deref()?)?))
+					})
+					.collect();
+				if !detected.is_empty() {
+					let saved = DetectionCache::load(&detection_file).and_then(|mut cache| {
+						for (remote, version) in detected {
+							cache.record(remote, version, end);
+						}
+						cache.save(&detection_file)
+					});
+					if let Err(e) = saved {
+						eprintln!("warning: {e}, remotes' pupdate versions will be detected again next run");
+					}
+				}
+			}
+
+			// refreshing indexes doesn't upgrade anything, so it doesn't count as a pupdate for the history
+			if !args.index_only {
+				// the history is loaded again so runs which overlapped this one keep what they recorded
+				let recorded = History::load(&history_file).and_then(|mut history| {
+					// cancelled remotes weren't given the chance to succeed, so they don't count either way
+					let mut run = RunRecord {
+						at: end.unix_timestamp(),
+						remotes: 0,
+						failed: 0,
+						sequential_seconds: parallelism.sequential_seconds,
+						wall_seconds: parallelism.wall_seconds,
+					};
+					for (remote, result) in &summary.remotes {
+						if result.skipped.is_none() && !result.cancelled && !result.offline {
+							history.record(remote, result.success, end, args.change_id.as_deref());
+							// remotes which failed before apt finished can't say whether it would still keep anything
+							// back, so they leave their streak as it was
+							if result.success || !result.kept_back.is_empty() {
+								history.record_kept_back(remote, &result.kept_back);
+							}
+							run.remotes += 1;
+							run.failed += usize::from(!result.success);
+						}
+					}
+					history.record_run(run);
+					history.save(&history_file)
+				});
+				if let Err(e) = recorded {
+					eprintln!(
+						"warning: {e}, this run won't be remembered for --min-interval or --flaky-only"
+					);
+				}
+			}
+		}
+		// remotes skipped for risky updates or hostnames which don't resolve still need seeing to, so they're listed
+		// again once the run is over
+		let skipped: Vec<_> = summary
+			.remotes
+			.iter()
+			.filter(|(_, result)| !result.success)
+			.filter_map(|(remote, result)| Some((remote, result.skipped.as_ref()?)))
+			.collect();
+		if !skipped.is_empty() {
+			println!("the following remotes weren't pupdated:");
+			for (remote, skipped) in skipped {
+				println!("{remote} (skipped: {skipped})");
+			}
 		}
 	}
 
-	if !args.skip_local {
-		println!("running local pupdates, you may be pawmpted for your password");
+	let mut local_risky = Vec::new();
+	if let Some(policy) = risky_policy.filter(|_| !args.skip_local) {
+		local_risky = check_risky_local(&apt, &risky_packages).await;
+		if !local_risky.is_empty() && !allow_risky(policy, "the local system", &local_risky)? {
+			let result = LocalSummary {
+				skipped: Some(describe_risky(&local_risky)),
+				risky_updates: std::mem::take(&mut local_risky),
+				..Default::default()
+			};
+			if let Some(state) = &state {
+				state.local_finished(&result);
+			}
+			summary.local = Some(result);
+		}
+	}
+
+	if !args.skip_local && summary.local.is_none() {
+		if args.only_security {
+			println!("running local security pupdates, you may be pawmpted for your password");
+		} else if args.index_only {
+			println!("refreshing local package indexes, you may be pawmpted for your password");
+		} else {
+			println!("running local pupdates, you may be pawmpted for your password");
+		}
 		let start = OffsetDateTime::now_utc();
-		if pupdate_apt(log_dir).await? {
+		if let Some(state) = &state {
+			state.local_started();
+		}
+		let result = pupdate_local(
+			&steps,
+			&LocalOptions {
+				log_dir: log_dir.clone(),
+				log_layout: config.log_layout.unwrap_or_default(),
+				log_formats,
+				log_flush_interval,
+				stream_log: stream_log.as_ref().map(StreamLogWriter::log),
+				report_sources: args.report_sources,
+				retry,
+				priority,
+				verbose: args.verbose,
+				events: event_log.as_ref().map(|(sender, _)| sender.clone()),
+				change_id: args.change_id.clone(),
+				fail_on_repo_warnings: args.fail_on_repo_warnings,
+				fail_on_kept_back: args.fail_on_kept_back,
+			},
+		)
+		.await?;
+		let result = LocalSummary {
+			risky_updates: local_risky,
+			..result
+		};
+		if let Some(state) = &state {
+			state.local_finished(&result);
+		}
+		if result.success {
 			let end = OffsetDateTime::now_utc();
 			let duration = end - start;
 
+			// controllers look for this line in remotes' output to tell which remotes were already up to date
+			if result.up_to_date {
+				println!(
+					"{UP_TO_DATE_LINE}, checked in {} seconds",
+					duration.whole_seconds()
+				);
+			} else {
+				println!(
+					"successfully pupdated the local system in {} seconds",
+					duration.whole_seconds()
+				);
+			}
+		} else {
+			match result.steps.last() {
+				Some(step) => match step.result.failure {
+					Some(failure) => println!(
+						"failed to pupdate the local system at step {}: {failure}",
+						step.name
+					),
+					None => println!("failed to pupdate the local system at step {}", step.name),
+				},
+				None => println!("failed to pupdate the local system"),
+			}
+		}
+		let kept_back: Vec<_> = result
+			.steps
+			.iter()
+			.flat_map(|step| &step.result.kept_back)
+			.map(String::as_str)
+			.collect();
+		if !kept_back.is_empty() {
 			println!(
-				"successfully pupdated the local system in {} seconds",
-				duration.whole_seconds()
+				"apt kept back {} packages: {}",
+				kept_back.len(),
+				kept_back.join(", ")
+			);
+		}
+		// these go to stderr with a prefix a controller recognizes, so it records them for this system too
+		for step in &result.steps {
+			for warning in &step.result.repo_warnings {
+				eprintln!("{REPO_WARNING_PREFIX}{warning}");
+			}
+		}
+		if args.report_sources {
+			println!(
+				"package mirrors contacted locally: {}",
+				format_sources(result.sources.as_deref())
 			);
-		} else {
-			println!("failed to pupdate the local system");
 		}
+		summary.local = Some(result);
+	}
+	if let Some(writer) = stream_log {
+		writer.finish().await?;
 	}
+	finish_event_log(event_log).await?;
 
-	Ok(())
+	if let Some(resume) = resume {
+		// what finished successfully before the run was interrupted is kept in the summary alongside what's just run
+		for (remote, target) in resume.previous.remotes {
+			if let (Status::Succeeded, Some(result)) = (target.status, target.result) {
+				summary.remotes.entry(remote).or_insert(result);
+			}
+		}
+		if summary.local.is_none() {
+			summary.local = resume
+				.previous
+				.local
+				.filter(|local| local.status == Status::Succeeded)
+				.and_then(|local| local.result);
+		}
+		summary.resumed = Some(Resumed {
+			resumed_at: OffsetDateTime::now_utc().format(&Rfc3339)?,
+			remotes: resume.remotes,
+			mid_flight: resume.mid_flight,
+			local: !args.skip_local,
+		});
+	}
+	if let Some(e) = state.as_ref().and_then(|state| state.error()) {
+		eprintln!("warning: {e}, this run may not be resumable if it was interrupted");
+	}
+
+	if let Some(log_dir) = log_dir {
+		summary.log_format = (!merge_output).then_some(log_formats);
+		summary.change_id = args.change_id;
+		std::fs::write(
+			log_dir.join(SUMMARY_FILENAME),
+			serde_json::to_string_pretty(&summary)?,
+		)?;
+		println!("{LOGS_WRITTEN_PREFIX}{}", log_dir.display());
+	}
+
+	if let Some(command) = &args.on_complete {
+		// the hook only reports on the run, so its failure doesn't change pupdate's exit code
+		match on_complete(command, &summary).await {
+			Ok(status) if status.success() => println!("on-complete command succeeded"),
+			Ok(status) => eprintln!("warning: on-complete command failed: {status}"),
+			Err(e) => eprintln!("warning: {e}"),
+		}
+	}
+
+	let remotes_failed = summary.remotes.values().any(TargetSummary::failed);
+	let local_failed = summary.local.as_ref().is_some_and(|local| !local.success);
+	// a playbook which halted at a failed phase never got to pupdate the phases after it
+	if summary.phases.iter().any(|phase| phase.skipped.is_some()) {
+		return Ok(ExitCode::from(exit_code::ABORTED));
+	}
+	Ok(ExitCode::from(match (remotes_failed, local_failed) {
+		(false, false) => exit_code::SUCCESS,
+		(true, false) => exit_code::REMOTES_FAILED,
+		(false, true) => exit_code::LOCAL_FAILED,
+		(true, true) => exit_code::BOTH_FAILED,
+	}))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::{
+		os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+		process::{ExitStatus, Output},
+	};
+
+	use pupdate::schema::validate;
+	use serde_json::json;
+
+	use super::*;
+
+	/// a runner whose remotes all have pupdate 0.1.0, which came before any flags were forwarded
+	struct OldRunner;
+
+	impl Runner for OldRunner {
+		async fn run(&self, _remote: &str, _command: &str) -> std::io::Result<Output> {
+			Ok(Output {
+				status: ExitStatus::from_raw(0),
+				stdout: b"pupdate 0.1.0\n".to_vec(),
+				stderr: Vec::new(),
+			})
+		}
+	}
+
+	#[tokio::test]
+	async fn old_remotes_are_not_given_new_flags() {
+		let forwarding = Forwarding {
+			program: "pupdate".to_string(),
+			flags: forwarded_flags(true, true, Some(Duration::from_secs(3600))),
+			known_versions: BTreeMap::new(),
+		};
+		let forwarded = forwarding.for_remote(&OldRunner, "web1").await;
+		assert!(forwarded.args.is_empty());
+		assert_eq!(
+			forwarded.omitted,
+			[
+				"--only-security",
+				"--index-only",
+				"--skip-refresh-if-recent"
+			]
+		);
+		// the controller's own version knows every flag it forwards
+		let own = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+		assert!(forwarding.flags.iter().all(|flag| own >= flag.since));
+	}
+
+	#[tokio::test]
+	async fn given_remotes_take_priority_over_a_remotes_file() {
+		let args =
+			Args::try_parse_from(["pupdate", "--remotes-file", "/nonexistent", "web1"]).unwrap();
+		let resolved = resolve_remotes(&args, Vec::new(), RemoteSource::Config)
+			.await
+			.unwrap();
+		assert_eq!(resolved.remotes, ["web1"]);
+	}
+
+	/// runs pupdate with the given arguments against a fake ssh, under which remotes with `bad` in their names fail,
+	/// and checks the summary it writes against the schema, returning its exit code
+	async fn check_written_summary(dir: &Path, argv: &[&str]) -> ExitCode {
+		std::fs::create_dir_all(dir).unwrap();
+		let ssh = dir.join("ssh");
+		std::fs::write(&ssh, "#!/bin/sh\ncase \"$*\" in *bad*) exit 1 ;; esac\n").unwrap();
+		std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+		let config = dir.join("config.json");
+		std::fs::write(
+			&config,
+			json!({
+				"ssh_binary": ssh,
+				"remote_command": ["pupdate"],
+				"history_file": dir.join("history.json"),
+				"remotes": ["good1", "bad1"],
+			})
+			.to_string(),
+		)
+		.unwrap();
+		let log_dir = dir.join("logs");
+		let mut args = vec!["pupdate", "--no-progress", "--skip-local"];
+		args.extend(["--config", config.to_str().unwrap()]);
+		args.extend(["--log-dir", log_dir.to_str().unwrap()]);
+		args.extend(argv);
+		let code = run(Args::try_parse_from(args).unwrap(), None, None)
+			.await
+			.unwrap();
+		let runs: Vec<_> = std::fs::read_dir(&log_dir).unwrap().collect();
+		let [Ok(run_dir)] = &runs[..] else {
+			panic!("expected one run directory, found {runs:?}");
+		};
+		let summary = std::fs::read_to_string(run_dir.path().join(SUMMARY_FILENAME)).unwrap();
+		let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+		assert_eq!(validate(&summary_schema(), &summary), Ok(()));
+		code
+	}
+
+	#[tokio::test]
+	async fn written_summaries_match_the_schema() {
+		let dir = std::env::temp_dir().join(format!("pupdate-summaries-{}", std::process::id()));
+		let code = check_written_summary(&dir.join("run"), &[]).await;
+		assert_eq!(code, ExitCode::from(exit_code::REMOTES_FAILED));
+		let code = check_written_summary(&dir.join("exec"), &["exec", "--", "uptime"]).await;
+		assert_eq!(code, ExitCode::from(exit_code::REMOTES_FAILED));
+
+		// a playbook whose first phase fails aborts the run before its second phase
+		let playbook = dir.join("playbook.json");
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(
+			&playbook,
+			json!({
+				"phases": [
+					{ "name": "first", "remotes": ["bad1"] },
+					{ "name": "second", "remotes": ["good1"] },
+				],
+			})
+			.to_string(),
+		)
+		.unwrap();
+		let code = check_written_summary(
+			&dir.join("playbook"),
+			&["--playbook", playbook.to_str().unwrap()],
+		)
+		.await;
+		assert_eq!(code, ExitCode::from(exit_code::ABORTED));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn remotes_files_are_merged_with_the_configured_remotes() {
+		let path =
+			std::env::temp_dir().join(format!("pupdate-remotes-file-{}", std::process::id()));
+		std::fs::write(&path, "web2\n# canary\nweb3\nweb2\n").unwrap();
+		let configured = ["web1", "web2"].map(str::to_string).to_vec();
+		let resolve = |extra: &[&str]| {
+			let mut argv = vec!["pupdate", "--remotes-file", path.to_str().unwrap()];
+			argv.extend(extra);
+			let args = Args::try_parse_from(argv).unwrap();
+			let configured = configured.clone();
+			async move {
+				resolve_remotes(&args, configured, RemoteSource::Config)
+					.await
+					.unwrap()
+			}
+		};
+		let merged = resolve(&[]).await;
+		assert_eq!(merged.remotes, ["web2", "web3", "web1"]);
+		assert_eq!(merged.sources["web1"], RemoteSource::Config);
+		let replaced = resolve(&["--replace-remotes"]).await;
+		assert_eq!(replaced.remotes, ["web2", "web3"]);
+		std::fs::remove_file(&path).unwrap();
+	}
 }
@@ -0,0 +1,161 @@
+//! package managers the local system can be pupdated with, defined by the binary which shows they're installed and the
+//! commands which update their indexes, upgrade packages, and clean up after themselves, so systems without apt can be
+//! pupdated without writing out every step
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::config::Step;
+
+/// what's replaced by the sudo executable in a package manager's commands
+pub const SUDO_PLACEHOLDER: &str = "{sudo}";
+
+/// a package manager's commands, each the program followed by its arguments, where `{sudo}` is replaced by the sudo
+/// executable
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManager {
+	/// the binary whose presence shows the package manager is installed, either a path or a name to find on the path
+	pub detect: String,
+	/// the command which refreshes package indexes, if it's separate from upgrading
+	#[serde(default)]
+	pub update: Option<Vec<String>>,
+	/// the command which upgrades packages
+	pub upgrade: Vec<String>,
+	/// the command which cleans up once packages are upgraded, if any
+	#[serde(default)]
+	pub clean: Option<Vec<String>>,
+}
+
+impl PackageManager {
+	/// creates a package manager from its commands
+	fn new(
+		detect: &str,
+		update: Option<&[&str]>,
+		upgrade: &[&str],
+		clean: Option<&[&str]>,
+	) -> Self {
+		let command = |command: &[&str]| command.iter().map(|arg| arg.to_string()).collect();
+		Self {
+			detect: detect.to_string(),
+			update: update.map(command),
+			upgrade: command(upgrade),
+			clean: clean.map(command),
+		}
+	}
+
+	/// the steps pupdating with the package manager, named after it, with `{sudo}` replaced by the given executable
+	pub fn steps(&self, name: &str, sudo: &str) -> Vec<Step> {
+		[
+			("update", self.update.as_ref()),
+			("upgrade", Some(&self.upgrade)),
+			("clean", self.clean.as_ref()),
+		]
+		.into_iter()
+		.filter_map(|(step, command)| {
+			Some(Step {
+				name: format!("{name}-{step}"),
+				command: command?
+					.iter()
+					.map(|arg| arg.replace(SUDO_PLACEHOLDER, sudo))
+					.collect(),
+			})
+		})
+		.collect()
+	}
+}
+
+/// the name of the built-in package manager the default steps already pupdate with
+pub const APT: &str = "apt";
+
+/// the package managers pupdate knows without being configured, in the order they're detected
+pub fn builtin() -> Vec<(String, PackageManager)> {
+	vec![
+		(
+			APT.to_string(),
+			PackageManager::new(
+				"apt-get",
+				Some(&["{sudo}", "apt-get", "update"]),
+				&["{sudo}", "apt-get", "upgrade", "-y"],
+				None,
+			),
+		),
+		(
+			"dnf".to_string(),
+			PackageManager::new(
+				"dnf",
+				Some(&["{sudo}", "dnf", "makecache"]),
+				&["{sudo}", "dnf", "upgrade", "-y"],
+				Some(&["{sudo}", "dnf", "clean", "packages"]),
+			),
+		),
+		(
+			"pacman".to_string(),
+			PackageManager::new(
+				"pacman",
+				Some(&["{sudo}", "pacman", "-Sy"]),
+				&["{sudo}", "pacman", "-Su", "--noconfirm"],
+				Some(&["{sudo}", "pacman", "-Sc", "--noconfirm"]),
+			),
+		),
+	]
+}
+
+/// finds the package manager to pupdate with: the first configured one, by name, whose detection binary `is_installed`
+/// says is installed, or else the first such built-in one. configured package managers replace built-in ones of the
+/// same name
+pub fn detect(
+	configured: &BTreeMap<String, PackageManager>,
+	is_installed: impl Fn(&str) -> bool,
+) -> Option<(String, PackageManager)> {
+	let builtin = builtin()
+		.into_iter()
+		.filter(|(name, _)| !configured.contains_key(name));
+	configured
+		.iter()
+		.map(|(name, manager)| (name.clone(), manager.clone()))
+		.chain(builtin)
+		.find(|(_, manager)| is_installed(&manager.detect))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn package_managers_are_detected() {
+		let (name, manager) = detect(&BTreeMap::new(), |binary| binary == "dnf").unwrap();
+		assert_eq!(name, "dnf");
+		assert_eq!(
+			manager.steps(&name, "sudo")[1],
+			Step {
+				name: "dnf-upgrade".to_string(),
+				command: vec!["sudo", "dnf", "upgrade", "-y"]
+					.into_iter()
+					.map(str::to_string)
+					.collect(),
+			}
+		);
+		assert_eq!(manager.steps(&name, "sudo").len(), 3);
+
+		let configured: BTreeMap<String, PackageManager> = toml::from_str(
+			r#"
+			[nixos]
+			detect = "nixos-rebuild"
+			upgrade = ["{sudo}", "nixos-rebuild", "switch", "--upgrade"]
+			"#,
+		)
+		.unwrap();
+		let detected = detect(&configured, |binary| {
+			matches!(binary, "nixos-rebuild" | "apt-get")
+		});
+		let (name, manager) = detected.unwrap();
+		assert_eq!(name, "nixos");
+		assert_eq!(
+			manager.steps(&name, "/usr/bin/sudo")[0].command,
+			["/usr/bin/sudo", "nixos-rebuild", "switch", "--upgrade"]
+		);
+		assert_eq!(detect(&configured, |_| false), None);
+	}
+}
@@ -0,0 +1,241 @@
+//! the manifest written to the run directory, so old run directories describe what produced them
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+	logs::{LogFormats, LogLayout},
+	output::OutputLimits,
+	remote::{RemoteOptions, SkewPolicy, LOCAL_REMOTE},
+	services::ServicesPolicy,
+};
+
+/// the name of the manifest file written to the run directory
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// where ssh connects to for a remote, as far as can be told from the remote itself
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Destination {
+	/// the user logged in as, if the remote names one instead of leaving it to ssh's config
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub user: Option<String>,
+	/// the host connected to, which may be an alias from ssh's config
+	pub host: String,
+	/// the port connected to, if the remote names one instead of leaving it to ssh's config
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub port: Option<u16>,
+}
+
+impl Destination {
+	/// splits a remote given to ssh, like `user@host` or `ssh://user@host:2222`, into its parts
+	pub fn parse(remote: &str) -> Self {
+		let (uri, rest) = match remote.strip_prefix("ssh://") {
+			Some(rest) => (true, rest),
+			None => (false, remote),
+		};
+		let (user, host) = match rest.rsplit_once('@') {
+			Some((user, host)) => (Some(user.to_string()), host),
+			None => (None, rest),
+		};
+		// only ssh uris can have a port, anything else after a colon is part of the host as far as ssh is concerned
+		let (host, port) = match host.rsplit_once(':') {
+			Some((address, port)) if uri && !address.is_empty() => match port.parse() {
+				Ok(port) => (address, Some(port)),
+				Err(_) => (host, None),
+			},
+			_ => (host, None),
+		};
+		let host = host
+			.strip_prefix('[')
+			.and_then(|host| host.strip_suffix(']'))
+			.unwrap_or(host);
+		Self {
+			user,
+			host: host.to_string(),
+			port,
+		}
+	}
+}
+
+/// a remote pupdated by the run
+#[derive(Debug, Serialize)]
+pub struct ManifestRemote {
+	/// the remote as it was given to ssh
+	pub remote: String,
+	/// where ssh connected to for the remote
+	#[serde(flatten)]
+	pub destination: Destination,
+}
+
+impl ManifestRemote {
+	/// describes a remote as it was given to ssh
+	pub fn new(remote: &str) -> Self {
+		let destination = if remote == LOCAL_REMOTE {
+			Destination {
+				user: None,
+				host: "localhost".to_string(),
+				port: None,
+			}
+		} else {
+			Destination::parse(remote)
+		};
+		Self {
+			remote: remote.to_string(),
+			destination,
+		}
+	}
+}
+
+/// the options the run's remotes were pupdated with, after the config and arguments were combined
+#[derive(Debug, Serialize)]
+pub struct RunOptions {
+	/// the command run on remotes, before any flags are forwarded
+	pub remote_command: String,
+	/// the flags forwarded to remotes whose pupdate knows them
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub forwarded_flags: Vec<String>,
+	/// whether remotes logged stdout and stderr to a single file
+	pub merge_output: bool,
+	/// how logs are laid out inside the run directory
+	pub log_layout: LogLayout,
+	/// the formats remotes' logs were written in, unless their output was merged
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub log_format: Option<LogFormats>,
+	/// where remotes' logs were written instead of the run directory, with the run's date filled in, if anywhere
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub log_path_template: Option<String>,
+	/// how many times failed pupdates were retried
+	pub retries: u32,
+	/// how much of each remote's output was kept
+	#[serde(flatten)]
+	pub output_limits: OutputLimits,
+	/// how many seconds each remote was given before it was killed, if there was a timeout
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub timeout_seconds: Option<u64>,
+	/// what was done with skewed remotes, if clocks were checked
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub skew_policy: Option<SkewPolicy>,
+	/// what was done about services needing a restart, if they were checked
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub services: Option<ServicesPolicy>,
+	/// whether remotes' binaries were verified before they were run
+	pub verify_binary: bool,
+	/// whether remotes' own logs were fetched
+	pub fetch_logs: bool,
+	/// the change ticket the run was tagged with, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change_id: Option<String>,
+}
+
+impl RunOptions {
+	/// describes the options remotes are pupdated with
+	pub fn new(options: &RemoteOptions) -> Self {
+		Self {
+			remote_command: options.command.to_command_line(),
+			forwarded_flags: options
+				.forward
+				.iter()
+				.flat_map(|forwarding| &forwarding.flags)
+				.flat_map(|flag| flag.args.iter().cloned())
+				.collect(),
+			merge_output: options.merge_output,
+			log_layout: options.log_layout,
+			log_format: (!options.merge_output).then_some(options.log_formats),
+			log_path_template: options.log_template.as_ref().map(ToString::to_string),
+			retries: options.retry.retries,
+			output_limits: options.output_limits,
+			timeout_seconds: options.timeout.map(|timeout| timeout.as_secs()),
+			skew_policy: options.skew_check.as_ref().map(|check| check.policy),
+			services: options.services,
+			verify_binary: options
+				.verify_binary
+				.as_ref()
+				.is_some_and(|check| !check.skip),
+			fetch_logs: options.fetch_logs,
+			change_id: options.change_id.clone(),
+		}
+	}
+}
+
+/// describes a run and the remotes it pupdated
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+	/// the version of pupdate which made the run
+	pub version: String,
+	/// the arguments pupdate was run with
+	pub arguments: Vec<String>,
+	/// the options remotes were pupdated with
+	pub options: RunOptions,
+	/// the remotes pupdated, by the name their logs were written under
+	pub remotes: BTreeMap<String, ManifestRemote>,
+}
+
+impl Manifest {
+	/// describes a run of the given remotes with the given options, failing if two remotes would write their logs
+	/// under the same name
+	pub fn new<'a>(
+		remotes: impl IntoIterator<Item = &'a String>,
+		options: &RemoteOptions,
+	) -> eyre::Result<Self> {
+		let mut by_log_name = BTreeMap::new();
+		for remote in remotes {
+			let log_name = options.log_name(remote);
+			if let Some(other) = by_log_name.insert(log_name.clone(), ManifestRemote::new(remote)) {
+				eyre::bail!(
+					"remotes {} and {remote} would both write their logs as {log_name}, set log_names in config to tell them apart",
+					other.remote
+				);
+			}
+		}
+		Ok(Self {
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			arguments: std::env::args_os()
+				.map(|arg| arg.to_string_lossy().into_owned())
+				.collect(),
+			options: RunOptions::new(options),
+			remotes: by_log_name,
+		})
+	}
+
+	/// writes the manifest into the given run directory
+	pub fn write(&self, dir: &Path) -> eyre::Result<()> {
+		let path = dir.join(MANIFEST_FILENAME);
+		std::fs::write(&path, serde_json::to_string_pretty(self)?)
+			.map_err(|e| eyre::eyre!("failed to write {}: {e}", path.display()))?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn destinations_are_parsed() {
+		assert_eq!(
+			Destination::parse("10.0.3.7"),
+			Destination {
+				user: None,
+				host: "10.0.3.7".to_string(),
+				port: None,
+			}
+		);
+		assert_eq!(
+			Destination::parse("admin@web"),
+			Destination {
+				user: Some("admin".to_string()),
+				host: "web".to_string(),
+				port: None,
+			}
+		);
+		assert_eq!(
+			Destination::parse("ssh://admin@[::1]:2222"),
+			Destination {
+				user: Some("admin".to_string()),
+				host: "::1".to_string(),
+				port: Some(2222),
+			}
+		);
+	}
+}
@@ -0,0 +1,244 @@
+//! pupdating remotes without internet access by pushing a directory of packages to them and installing it
+
+use std::{
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use crate::{
+	events::OutputStream,
+	output::{output_tail, LimitedOutput, OutputLimits},
+	remote::Runner,
+	shell::{command_line, merge_stderr, quote},
+};
+
+/// a `.deb` package to push to offline remotes, described by its file name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebPackage {
+	/// the path of the package on the controller
+	pub path: PathBuf,
+	/// the name of the package
+	pub name: String,
+	/// the version of the package
+	pub version: String,
+	/// the architecture the package was built for, or `all`
+	pub arch: String,
+}
+
+impl DebPackage {
+	/// describes a package from a file name following debian's `<name>_<version>_<arch>.deb` convention
+	fn from_path(path: PathBuf) -> Option<Self> {
+		let stem = path.file_name()?.to_str()?.strip_suffix(".deb")?;
+		let mut parts = stem.split('_');
+		let (name, version, arch) = (parts.next()?, parts.next()?, parts.next()?);
+		if parts.next().is_some() || name.is_empty() || version.is_empty() || arch.is_empty() {
+			return None;
+		}
+		Some(Self {
+			name: name.to_string(),
+			// versions with epochs have their colon url encoded in file names
+			version: version.replace("%3a", ":"),
+			arch: arch.to_string(),
+			path,
+		})
+	}
+}
+
+/// packages to push to offline remotes
+#[derive(Debug, Clone)]
+pub struct OfflinePackages {
+	/// the packages, sorted by path
+	pub packages: Vec<DebPackage>,
+}
+
+impl OfflinePackages {
+	/// loads every `.deb` in the given directory, failing if any of them aren't named like debian packages
+	pub fn load(dir: &Path) -> eyre::Result<Self> {
+		let mut packages = Vec::new();
+		for entry in std::fs::read_dir(dir)
+			.map_err(|e| eyre::eyre!("failed to read package directory {}: {e}", dir.display()))?
+		{
+			let path = entry?.path();
+			if !path.is_file() || path.extension().is_none_or(|ext| ext != "deb") {
+				continue;
+			}
+			packages.push(DebPackage::from_path(path.clone()).ok_or_else(|| {
+				eyre::eyre!(
+					"{} isn't named like a debian package (<name>_<version>_<arch>.deb)",
+					path.display()
+				)
+			})?);
+		}
+		if packages.is_empty() {
+			eyre::bail!("no packages found in {}", dir.display());
+		}
+		packages.sort_by(|a, b| a.path.cmp(&b.path));
+		Ok(Self { packages })
+	}
+
+	/// finds the packages which can't be installed on a remote of the given architecture
+	fn mismatched_arch(&self, arch: &str) -> Vec<&DebPackage> {
+		self.packages
+			.iter()
+			.filter(|package| package.arch != "all" && package.arch != arch)
+			.collect()
+	}
+
+	/// builds a command listing the packages which the remote already has a newer version of
+	fn downgrades_command(&self) -> String {
+		let packages = self
+			.packages
+			.iter()
+			.map(|package| quote(&format!("{}={}", package.name, package.version)).into_owned())
+			.collect::<Vec<_>>()
+			.join(" ");
+		format!(
+			"for package in {packages}; do \
+				name=\"${{package%%=*}}\"; version=\"${{package#*=}}\"; \
+				installed=\"$(dpkg-query -W -f='${{Version}}' \"$name\" 2>/dev/null)\" && \
+				dpkg --compare-versions \"$installed\" gt \"$version\" && echo \"$name $installed\"; \
+			done; true"
+		)
+	}
+}
+
+/// pushes the packages to a remote and installs them, checking that they suit the remote before anything is copied and
+/// cleaning up afterwards, returning the output of the install
+pub async fn install_offline<R: Runner>(
+	runner: &R,
+	remote: &str,
+	packages: &OfflinePackages,
+	limits: OutputLimits,
+	timeout: Option<Duration>,
+	merge_output: bool,
+	on_line: impl Fn(OutputStream, String) + Send + Sync,
+) -> eyre::Result<LimitedOutput> {
+	let arch = runner
+		.run(remote, &command_line(&["dpkg", "--print-architecture"]))
+		.await?;
+	if !arch.status.success() {
+		eyre::bail!(
+			"failed to get the remote's architecture: {}",
+			output_tail(&arch.stderr)
+		);
+	}
+	let arch = String::from_utf8_lossy(&arch.stdout).trim().to_string();
+	let mismatched = packages.mismatched_arch(&arch);
+	if !mismatched.is_empty() {
+		eyre::bail!(
+			"packages built for another architecture than the remote's {arch}: {}",
+			mismatched
+				.iter()
+				.map(|package| format!("{} ({})", package.name, package.arch))
+				.collect::<Vec<_>>()
+				.join(", ")
+		);
+	}
+
+	let downgrades = runner.run(remote, &packages.downgrades_command()).await?;
+	let downgrades = String::from_utf8_lossy(&downgrades.stdout);
+	if !downgrades.trim().is_empty() {
+		eyre::bail!(
+			"the remote already has newer versions of: {}",
+			downgrades.trim().lines().collect::<Vec<_>>().join(", ")
+		);
+	}
+
+	let temp_dir = runner
+		.run(
+			remote,
+			&command_line(&["mktemp", "-d", "/tmp/pupdate-offline.XXXXXX"]),
+		)
+		.await?;
+	if !temp_dir.status.success() {
+		eyre::bail!(
+			"failed to create a directory on the remote: {}",
+			output_tail(&temp_dir.stderr)
+		);
+	}
+	let temp_dir = String::from_utf8_lossy(&temp_dir.stdout).trim().to_string();
+
+	let result = match push_packages(runner, remote, packages, &temp_dir, merge_output).await {
+		Ok(install) => runner
+			.run_streaming(remote, &install, limits, timeout, on_line)
+			.await
+			.map_err(Into::into),
+		Err(e) => Err(e),
+	};
+	let cleanup = runner
+		.run(remote, &command_line(&["rm", "-rf", &temp_dir]))
+		.await;
+	let output = result?;
+	match cleanup {
+		Ok(cleanup) if cleanup.status.success() => Ok(output),
+		Ok(cleanup) => eyre::bail!(
+			"failed to clean up {temp_dir} on the remote: {}",
+			output_tail(&cleanup.stderr)
+		),
+		Err(e) => eyre::bail!("failed to clean up {temp_dir} on the remote: {e}"),
+	}
+}
+
+/// pushes the packages into a directory on the remote, returning the command which installs them from there
+async fn push_packages<R: Runner>(
+	runner: &R,
+	remote: &str,
+	packages: &OfflinePackages,
+	remote_dir: &str,
+	merge_output: bool,
+) -> eyre::Result<String> {
+	let files: Vec<_> = packages
+		.packages
+		.iter()
+		.map(|package| package.path.clone())
+		.collect();
+	let push = runner.push(remote, &files, remote_dir).await?;
+	if !push.status.success() {
+		eyre::bail!(
+			"failed to copy packages to the remote: {}",
+			output_tail(&push.stderr)
+		);
+	}
+
+	let mut install = vec![
+		"sudo".to_string(),
+		"apt-get".to_string(),
+		"install".to_string(),
+		"-y".to_string(),
+	];
+	for file in &files {
+		let name = file.file_name().unwrap_or_default().to_string_lossy();
+		install.push(format!("{remote_dir}/{name}"));
+	}
+	let install = command_line(&install);
+	Ok(if merge_output {
+		merge_stderr(&install)
+	} else {
+		install
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn packages_are_described_by_file_name() {
+		assert_eq!(
+			DebPackage::from_path(PathBuf::from("/debs/bash_5.2.15-2+b7_amd64.deb")),
+			Some(DebPackage {
+				path: PathBuf::from("/debs/bash_5.2.15-2+b7_amd64.deb"),
+				name: "bash".to_string(),
+				version: "5.2.15-2+b7".to_string(),
+				arch: "amd64".to_string(),
+			})
+		);
+		assert_eq!(
+			DebPackage::from_path(PathBuf::from("tzdata_1%3a2024b-0_all.deb"))
+				.map(|package| package.version),
+			Some("1:2024b-0".to_string())
+		);
+		assert_eq!(DebPackage::from_path(PathBuf::from("bash.deb")), None);
+		assert_eq!(DebPackage::from_path(PathBuf::from("a_b_c_d.deb")), None);
+	}
+}
@@ -0,0 +1,476 @@
+use std::process::Output;
+
+use serde::Serialize;
+
+/// the maximum number of lines of stderr kept in memory for failed remotes
+const STDERR_TAIL_LINES: usize = 20;
+/// the maximum number of bytes of stderr kept in memory for failed remotes
+const STDERR_TAIL_BYTES: usize = 4096;
+/// how many bytes of each of a remote's output streams are kept by default
+pub const DEFAULT_OUTPUT_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// how much of each of a remote's output streams is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OutputLimits {
+	/// how many bytes of a stream are kept before the rest is dropped, apart from a bounded tail
+	#[serde(rename = "output_limit_bytes")]
+	pub limit: u64,
+	/// how many bytes a stream can reach before the command writing it is killed, if there's a limit
+	#[serde(
+		rename = "output_kill_limit_bytes",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub kill_limit: Option<u64>,
+}
+
+impl Default for OutputLimits {
+	fn default() -> Self {
+		Self {
+			limit: DEFAULT_OUTPUT_LIMIT,
+			kill_limit: None,
+		}
+	}
+}
+
+impl OutputLimits {
+	/// whether a stream of the given length went over the kill limit
+	pub fn exceeded(&self, len: u64) -> bool {
+		self.kill_limit.is_some_and(|kill_limit| len > kill_limit)
+	}
+}
+
+/// a stream's output kept within a limit: everything up to the limit and a bounded tail of what came after it
+#[derive(Debug)]
+pub struct CappedOutput {
+	/// how many bytes are kept from the start of the stream
+	limit: usize,
+	/// the start of the stream, up to the limit
+	head: Vec<u8>,
+	/// the most recent bytes after the limit, trimmed back to the tail size as it grows
+	tail: Vec<u8>,
+	/// how many bytes the stream has had in total
+	total: u64,
+}
+
+impl CappedOutput {
+	/// starts keeping a stream's output within the given limit
+	pub fn new(limit: u64) -> Self {
+		Self {
+			limit: usize::try_from(limit).unwrap_or(usize::MAX),
+			head: Vec::new(),
+			tail: Vec::new(),
+			total: 0,
+		}
+	}
+
+	/// adds the next bytes of the stream
+	pub fn push(&mut self, bytes: &[u8]) {
+		self.total += bytes.len() as u64;
+		let room = self.limit.saturating_sub(self.head.len()).min(bytes.len());
+		self.head.extend_from_slice(&bytes[..room]);
+		self.tail.extend_from_slice(&bytes[room..]);
+		if self.tail.len() > STDERR_TAIL_BYTES * 2 {
+			self.tail.drain(..self.tail.len() - STDERR_TAIL_BYTES);
+		}
+	}
+
+	/// how many bytes the stream has had in total
+	pub fn total(&self) -> u64 {
+		self.total
+	}
+
+	/// gets the kept output, with a marker between the start and the tail if anything was dropped, and whether anything
+	/// was
+	pub fn finish(mut self) -> (Vec<u8>, bool) {
+		let tail = &self.tail[self.tail.len().saturating_sub(STDERR_TAIL_BYTES)..];
+		let dropped = self.total - self.head.len() as u64 - tail.len() as u64;
+		if dropped == 0 {
+			self.head.extend_from_slice(tail);
+			return (self.head, false);
+		}
+		self.head.extend_from_slice(
+			format!(
+				"\n[pupdate: output truncated, {dropped} bytes dropped after the first {} bytes]\n",
+				self.limit
+			)
+			.as_bytes(),
+		);
+		self.head.extend_from_slice(tail);
+		(self.head, true)
+	}
+}
+
+/// the output of a command whose output streams were kept within [`OutputLimits`]
+#[derive(Debug)]
+pub struct LimitedOutput {
+	/// the command's output, with anything over the limit dropped
+	pub output: Output,
+	/// whether anything was dropped from either stream
+	pub truncated: bool,
+	/// whether the command was killed for going over the kill limit
+	pub limit_exceeded: bool,
+	/// whether the command was killed for running longer than its timeout
+	pub timed_out: bool,
+}
+
+impl LimitedOutput {
+	/// keeps the output of a command which has already finished within the limits
+	pub fn from_output(output: Output, limits: OutputLimits) -> Self {
+		let limit_exceeded = limits.exceeded(output.stdout.len() as u64)
+			|| limits.exceeded(output.stderr.len() as u64);
+		let mut stdout = CappedOutput::new(limits.limit);
+		stdout.push(&output.stdout);
+		let (stdout, stdout_truncated) = stdout.finish();
+		let mut stderr = CappedOutput::new(limits.limit);
+		stderr.push(&output.stderr);
+		let (stderr, stderr_truncated) = stderr.finish();
+		Self {
+			output: Output {
+				status: output.status,
+				stdout,
+				stderr,
+			},
+			truncated: stdout_truncated || stderr_truncated,
+			limit_exceeded,
+			timed_out: false,
+		}
+	}
+}
+
+/// parses a size in bytes, optionally followed by `K`, `M`, or `G` for kibibytes, mebibytes, or gibibytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+	let s = s.trim();
+	let (number, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+		Some((i, _)) => s.split_at(i),
+		None => (s, ""),
+	};
+	let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+		"" | "B" => 1,
+		"K" | "KB" | "KIB" => 1024,
+		"M" | "MB" | "MIB" => 1024 * 1024,
+		"G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+		_ => {
+			return Err(format!(
+				"unknown unit `{unit}` in `{s}`, expected K, M, or G"
+			))
+		}
+	};
+	let number: u64 = number
+		.parse()
+		.map_err(|_| format!("expected a size like 64M, got `{s}`"))?;
+	number
+		.checked_mul(multiplier)
+		.ok_or_else(|| format!("`{s}` is too large"))
+}
+
+/// formats a size in bytes to be read at a glance, in the units [`parse_size`] takes
+pub fn format_size(bytes: u64) -> String {
+	const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+	if bytes < 1024 {
+		return format!("{bytes}B");
+	}
+	let mut size = bytes as f64 / 1024.0;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	// a decimal place only matters for small sizes
+	if size < 10.0 {
+		format!("{size:.1}{}", UNITS[unit])
+	} else {
+		format!("{size:.0}{}", UNITS[unit])
+	}
+}
+
+/// finds the distinct mirror hostnames contacted according to apt-get update's `Hit`/`Get`/`Ign` lines
+pub fn mirror_hosts(output: &[u8]) -> Vec<String> {
+	let mut hosts = Vec::new();
+	for line in String::from_utf8_lossy(output).lines() {
+		let Some((kind, rest)) = line.split_once(':') else {
+			continue;
+		};
+		if !matches!(kind, "Hit" | "Get" | "Ign") {
+			continue;
+		}
+		let Some(url) = rest.split_whitespace().nth(1) else {
+			continue;
+		};
+		let Some((_, url)) = url.split_once("://") else {
+			continue;
+		};
+		let host = url.split('/').next().unwrap_or(url);
+		if !host.is_empty() && !hosts.iter().any(|h| h == host) {
+			hosts.push(host.to_string());
+		}
+	}
+	hosts
+}
+
+/// the line pupdate prints when the local system had nothing to upgrade, so controllers can tell from its output
+pub const UP_TO_DATE_LINE: &str = "the local system was already up to date";
+
+/// how many packages apt upgraded, newly installed, and removed according to one of its
+/// `0 upgraded, 0 newly installed, 0 to remove` lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AptCounts {
+	/// how many packages were upgraded
+	pub upgraded: u64,
+	/// how many packages were newly installed
+	pub installed: u64,
+	/// how many packages were removed
+	pub removed: u64,
+}
+
+impl AptCounts {
+	/// parses one of apt's `0 upgraded, 0 newly installed, 0 to remove` lines
+	pub fn parse(line: &str) -> Option<Self> {
+		let (upgraded, rest) = line.split_once(" upgraded, ")?;
+		let (installed, rest) = rest.split_once(" newly installed, ")?;
+		let (removed, _) = rest.split_once(" to remove")?;
+		Some(Self {
+			upgraded: upgraded.trim().parse().ok()?,
+			installed: installed.trim().parse().ok()?,
+			removed: removed.trim().parse().ok()?,
+		})
+	}
+}
+
+/// finds whether apt changed anything according to its `0 upgraded, 0 newly installed, 0 to remove` lines, if it
+/// printed any
+pub fn apt_changed(output: &[u8]) -> Option<bool> {
+	let mut changed = None;
+	for counts in String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(AptCounts::parse)
+	{
+		changed = Some(
+			changed.unwrap_or(false) || counts.upgraded + counts.installed + counts.removed > 0,
+		);
+	}
+	changed
+}
+
+/// finds how many packages apt upgraded according to its `0 upgraded, 0 newly installed, 0 to remove` lines, added
+/// up across every apt run in the output, if it printed any
+pub fn apt_upgraded(output: &[u8]) -> Option<u64> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(AptCounts::parse)
+		.map(|counts| counts.upgraded)
+		.reduce(|a, b| a + b)
+}
+
+/// what apt prints before the indented list of packages it held back instead of upgrading
+const KEPT_BACK_HEADER: &str = "The following packages have been kept back:";
+
+/// finds the distinct packages apt kept back instead of upgrading, which it doesn't count as a failure, so they'd
+/// otherwise go without upgrades unnoticed
+pub fn kept_back(output: &[u8]) -> Vec<String> {
+	let mut packages: Vec<String> = Vec::new();
+	let mut listing = false;
+	for line in String::from_utf8_lossy(output).lines() {
+		if line.trim() == KEPT_BACK_HEADER {
+			listing = true;
+			continue;
+		}
+		// the list is indented, so it ends at the first line which isn't
+		listing &= line.starts_with(char::is_whitespace) && !line.trim().is_empty();
+		if listing {
+			for package in line.split_whitespace() {
+				if !packages.iter().any(|kept| kept == package) {
+					packages.push(package.to_string());
+				}
+			}
+		}
+	}
+	packages
+}
+
+/// finds whether a remote had nothing to upgrade, either from pupdate saying so or from apt's own output
+pub fn reported_up_to_date(output: &[u8]) -> bool {
+	String::from_utf8_lossy(output)
+		.lines()
+		.any(|line| line.starts_with(UP_TO_DATE_LINE))
+		|| apt_changed(output) == Some(false)
+}
+
+/// what sudo prints when it needs a password but can't ask for one
+pub const SUDO_PASSWORD_REQUIRED: &str = "sudo: a password is required";
+/// what sudo prints instead of [`SUDO_PASSWORD_REQUIRED`] when it needs a password and there's no terminal to ask on,
+/// depending on its version
+const SUDO_NO_TERMINAL: [&str; 2] = ["sudo: no tty present", "sudo: a terminal is required"];
+
+/// finds whether a command failed because sudo needed a password it had no way to ask for
+pub fn sudo_needs_password(output: &[u8]) -> bool {
+	String::from_utf8_lossy(output).lines().any(|line| {
+		let line = line.trim_start();
+		line.starts_with(SUDO_PASSWORD_REQUIRED)
+			|| SUDO_NO_TERMINAL
+				.iter()
+				.any(|message| line.starts_with(message))
+	})
+}
+
+/// what apt says about a repository whose signature it couldn't verify, which it only warns about, so an update can
+/// exit successfully without anything from the repository having been updated
+const REPO_SIGNATURE_ERRORS: &[&str] = &["NO_PUBKEY", "EXPKEYSIG", "is not signed"];
+/// the prefix of the lines pupdate prints for each repository warning, which a controller strips again
+pub const REPO_WARNING_PREFIX: &str = "warning: repository problem: ";
+
+/// finds the distinct lines in which apt warned about a repository's signature, like a missing or expired key
+pub fn repo_warnings(outputs: &[&[u8]]) -> Vec<String> {
+	let mut warnings: Vec<String> = Vec::new();
+	for output in outputs {
+		for line in String::from_utf8_lossy(output).lines() {
+			if !REPO_SIGNATURE_ERRORS
+				.iter()
+				.any(|error| line.contains(error))
+			{
+				continue;
+			}
+			let line = line
+				.split_once(REPO_WARNING_PREFIX)
+				.map_or(line, |(_, warning)| warning)
+				.trim();
+			if !warnings.iter().any(|warning| warning == line) {
+				warnings.push(line.to_string());
+			}
+		}
+	}
+	warnings
+}
+
+/// the prefix of the line pupdate prints with the run directory it logged to
+pub const LOGS_WRITTEN_PREFIX: &str = "logs written to ";
+
+/// finds the run directory a pupdate reported logging to in its output
+pub fn reported_log_dir(output: &[u8]) -> Option<String> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.rev()
+		.find_map(|line| line.strip_prefix(LOGS_WRITTEN_PREFIX))
+		.map(|dir| dir.trim().to_string())
+}
+
+/// gets the bounded tail of some command output as text
+pub fn output_tail(output: &[u8]) -> String {
+	let output = &output[output.len().saturating_sub(STDERR_TAIL_BYTES)..];
+	let output = String::from_utf8_lossy(output);
+	let lines: Vec<_> = output.lines().collect();
+	lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn apt_changes_are_found() {
+		assert_eq!(
+			apt_changed(b"Reading package lists...\n0 upgraded, 0 newly installed, 0 to remove and 3 not upgraded.\n"),
+			Some(false)
+		);
+		assert_eq!(
+			apt_changed(b"12 upgraded, 1 newly installed, 0 to remove and 0 not upgraded.\n"),
+			Some(true)
+		);
+		assert_eq!(
+			apt_changed(b"Hit:1 http://deb.debian.org/debian bookworm InRelease\n"),
+			None
+		);
+		assert!(reported_up_to_date(
+			b"the local system was already up to date, checked in 3 seconds\n"
+		));
+	}
+
+	#[test]
+	fn kept_back_packages_are_found() {
+		let output = b"Calculating upgrade... Done\n\
+			The following packages have been kept back:\n  \
+			linux-generic linux-image-generic\n  \
+			systemd\n\
+			The following packages will be upgraded:\n  \
+			libc6 openssl\n\
+			2 upgraded, 0 newly installed, 0 to remove and 3 not upgraded.\n\
+			The following packages have been kept back:\n  \
+			systemd\n\
+			0 upgraded, 0 newly installed, 0 to remove and 1 not upgraded.\n";
+		assert_eq!(
+			kept_back(output),
+			["linux-generic", "linux-image-generic", "systemd"]
+		);
+		assert_eq!(apt_upgraded(output), Some(2));
+		assert!(
+			kept_back(b"0 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.\n")
+				.is_empty()
+		);
+	}
+
+	#[test]
+	fn sudo_passwords_are_spotted() {
+		assert!(sudo_needs_password(b"sudo: a password is required\n"));
+		assert!(sudo_needs_password(
+			b"sudo: no tty present and no askpass program specified\n"
+		));
+		assert!(sudo_needs_password(
+			b"failed to pupdate the local system at step apt-update\nsudo: a terminal is required to read the password\n"
+		));
+		assert!(!sudo_needs_password(
+			b"E: Could not get lock /var/lib/dpkg/lock-frontend\n"
+		));
+	}
+
+	#[test]
+	fn repo_warnings_are_found() {
+		let stderr = b"W: GPG error: http://repo.example stable InRelease: The following signatures couldn't be verified because the public key is not available: NO_PUBKEY 0123456789ABCDEF\n\
+			E: The repository 'http://repo.example stable InRelease' is not signed.\n\
+			W: Some index files failed to download. They have been ignored, or old ones used instead.\n";
+		let forwarded = b"warning: repository problem: E: The repository 'http://repo.example stable InRelease' is not signed.\n";
+		assert_eq!(
+			repo_warnings(&[b"Hit:1 http://deb.debian.org/debian bookworm InRelease\n", stderr, forwarded]),
+			[
+				"W: GPG error: http://repo.example stable InRelease: The following signatures couldn't be verified because the public key is not available: NO_PUBKEY 0123456789ABCDEF",
+				"E: The repository 'http://repo.example stable InRelease' is not signed.",
+			]
+		);
+		assert!(repo_warnings(&[
+			b"0 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.\n"
+		])
+		.is_empty());
+	}
+
+	#[test]
+	fn output_is_capped_with_a_tail() {
+		let mut output = CappedOutput::new(4);
+		output.push(b"ab");
+		output.push(b"cd");
+		assert_eq!(output.finish(), (b"abcd".to_vec(), false));
+
+		let mut output = CappedOutput::new(4);
+		output.push(b"abcdef");
+		for _ in 0..STDERR_TAIL_BYTES {
+			output.push(b"x");
+		}
+		output.push(b"end");
+		assert_eq!(output.total(), 6 + STDERR_TAIL_BYTES as u64 + 3);
+		let (kept, truncated) = output.finish();
+		assert!(truncated);
+		assert!(kept.starts_with(b"abcd\n[pupdate: output truncated, 5 bytes dropped"));
+		assert!(kept.ends_with(b"xxend"));
+	}
+
+	#[test]
+	fn sizes_are_parsed() {
+		assert_eq!(parse_size("512"), Ok(512));
+		assert_eq!(parse_size("64M"), Ok(64 * 1024 * 1024));
+		assert_eq!(parse_size("1g"), Ok(1024 * 1024 * 1024));
+		assert!(parse_size("").is_err());
+		assert_eq!(format_size(512), "512B");
+		assert_eq!(format_size(1536), "1.5KB");
+		assert_eq!(format_size(182 * 1024 + 300), "182KB");
+		assert_eq!(format_size(3 * 1024 * 1024 * 1024 * 1024), "3072GB");
+		assert!(parse_size("5T").is_err());
+		assert!(parse_size("M").is_err());
+	}
+}
@@ -0,0 +1,263 @@
+//! following a remote's upgrade through apt's output, so its progress can say whether it's downloading, unpacking,
+//! or setting up packages instead of just that it's pupdating
+//!
+//! apt's output isn't meant to be parsed, so anything which isn't recognized is ignored rather than treated as an
+//! error, leaving the remote's status as it was
+
+use crate::output::AptCounts;
+
+/// what a remote's upgrade is in the middle of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+	/// refreshing the package lists
+	Refreshing,
+	/// downloading packages
+	Downloading,
+	/// unpacking packages
+	Unpacking,
+	/// setting up unpacked packages
+	SettingUp,
+	/// running triggers for packages, such as rebuilding the man page index
+	ProcessingTriggers,
+}
+
+impl Phase {
+	/// how the phase is described in a remote's status
+	fn describe(self) -> &'static str {
+		match self {
+			Self::Refreshing => "refreshing package lists",
+			Self::Downloading => "downloading",
+			Self::Unpacking => "unpacking",
+			Self::SettingUp => "setting up",
+			Self::ProcessingTriggers => "processing triggers",
+		}
+	}
+
+	/// finds which phase a line of apt's output belongs to, along with the package it's about if it names one
+	pub fn of_line(line: &str) -> Option<(Self, Option<&str>)> {
+		let line = line.trim();
+		if let Some(rest) = line.strip_prefix("Get:") {
+			Some(match downloaded_package(rest) {
+				Some(package) => (Self::Downloading, Some(package)),
+				None => (Self::Refreshing, None),
+			})
+		} else if line.starts_with("Hit:") || line.starts_with("Ign:") {
+			Some((Self::Refreshing, None))
+		} else if let Some(rest) = line.strip_prefix("Unpacking ") {
+			Some((Self::Unpacking, Some(rest.split_whitespace().next()?)))
+		} else if let Some(rest) = line.strip_prefix("Setting up ") {
+			Some((Self::SettingUp, Some(rest.split_whitespace().next()?)))
+		} else if let Some(rest) = line.strip_prefix("Processing triggers for ") {
+			Some((
+				Self::ProcessingTriggers,
+				Some(rest.split_whitespace().next()?),
+			))
+		} else {
+			None
+		}
+	}
+}
+
+/// a package's name from apt's output, without the architecture apt sometimes adds like `libc6:amd64`
+fn package_name(package: &str) -> &str {
+	package.split(':').next().unwrap_or(package)
+}
+
+/// the package a `Get:` line downloads, like `Get:1 http://deb.debian.org/debian bookworm/main amd64 libc6 amd64
+/// 2.36-9 [2,757 kB]`, or `None` for package lists, which don't name a package and architecture before their size
+fn downloaded_package(rest: &str) -> Option<&str> {
+	let fields: Vec<_> = rest
+		.split_whitespace()
+		.take_while(|field| !field.starts_with('['))
+		.collect();
+	// the fetch's number, the url, the suite and component, the architecture, then the package, its architecture, and
+	// its version
+	match fields.as_slice() {
+		[_number, _url, _suite, _arch, package, _package_arch, _version] => Some(package),
+		_ => None,
+	}
+}
+
+/// follows a remote's upgrade through apt's output, counting packages through each phase
+#[derive(Debug, Default)]
+pub struct PhaseTracker {
+	/// how many packages apt said it would upgrade or install, once it's said
+	total: Option<u64>,
+	/// how many packages have been downloaded
+	downloaded: u64,
+	/// how many packages have been unpacked
+	unpacked: u64,
+	/// how many packages have been set up
+	set_up: u64,
+}
+
+impl PhaseTracker {
+	/// follows a line of a remote's output, returning the remote's new status if the line changed it
+	pub fn line(&mut self, line: &str) -> Option<String> {
+		let line = line.trim();
+		if let Some(counts) = AptCounts::parse(line) {
+			self.total = Some(counts.upgraded + counts.installed);
+			return None;
+		}
+		let (phase, package) = Phase::of_line(line)?;
+
+		let count = match phase {
+			Phase::Downloading => Some(&mut self.downloaded),
+			Phase::Unpacking => Some(&mut self.unpacked),
+			Phase::SettingUp => Some(&mut self.set_up),
+			Phase::Refreshing | Phase::ProcessingTriggers => None,
+		};
+		let mut status = phase.describe().to_string();
+		if let Some(count) = count {
+			*count += 1;
+			let count = *count;
+			// packages pulled in along the way can push the count past apt's total, which isn't worth showing
+			match self.total {
+				Some(total) if count <= total => status.push_str(&format!(" {count}/{total}")),
+				_ => status.push_str(&format!(" {count}")),
+			}
+		}
+		match package {
+			Some(package) => status.push_str(&format!(": {}", package_name(package))),
+			None => status.push_str("..."),
+		}
+		Some(status)
+	}
+
+	/// forgets what's been seen, for when a remote's pupdate is retried from the start
+	pub fn reset(&mut self) {
+		*self = Self::default();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// an `apt-get update && apt-get upgrade -y` on debian bookworm
+	const BOOKWORM: &str = "\
+Hit:1 http://deb.debian.org/debian bookworm InRelease
+Get:2 http://deb.debian.org/debian bookworm-updates InRelease [55.4 kB]
+Get:3 http://deb.debian.org/debian-security bookworm-security InRelease [48.0 kB]
+Get:4 http://deb.debian.org/debian-security bookworm-security/main amd64 Packages [187 kB]
+Fetched 290 kB in 1s (412 kB/s)
+Reading package lists...
+Reading package lists...
+Building dependency tree...
+Reading state information...
+Calculating upgrade...
+The following packages will be upgraded:
+  libc-bin libc6 openssh-client
+3 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.
+Need to get 4,721 kB of archives.
+After this operation, 12.3 kB of additional disk space will be used.
+Get:1 http://deb.debian.org/debian-security bookworm-security/main amd64 libc6 amd64 2.36-9+deb12u4 [2,757 kB]
+Get:2 http://deb.debian.org/debian-security bookworm-security/main amd64 libc-bin amd64 2.36-9+deb12u4 [607 kB]
+Get:3 http://deb.debian.org/debian-security bookworm-security/main amd64 openssh-client amd64 1:9.2p1-2+deb12u2 [991 kB]
+debconf: delaying package configuration, since apt-utils is not installed
+Fetched 4,721 kB in 0s (21.3 MB/s)
+(Reading database ... 9482 files and directories currently installed.)
+Preparing to unpack .../libc6_2.36-9+deb12u4_amd64.deb ...
+Unpacking libc6:amd64 (2.36-9+deb12u4) over (2.36-9+deb12u3) ...
+Setting up libc6:amd64 (2.36-9+deb12u4) ...
+(Reading database ... 9482 files and directories currently installed.)
+Preparing to unpack .../libc-bin_2.36-9+deb12u4_amd64.deb ...
+Unpacking libc-bin (2.36-9+deb12u4) over (2.36-9+deb12u3) ...
+Setting up libc-bin (2.36-9+deb12u4) ...
+(Reading database ... 9482 files and directories currently installed.)
+Preparing to unpack .../openssh-client_1%3a9.2p1-2+deb12u2_amd64.deb ...
+Unpacking openssh-client (1:9.2p1-2+deb12u2) over (1:9.2p1-2+deb12u1) ...
+Setting up openssh-client (1:9.2p1-2+deb12u2) ...
+Processing triggers for libc-bin (2.36-9+deb12u4) ...
+";
+
+	/// an `apt-get upgrade -y` on ubuntu noble pulling in a new dependency, with apt's own progress lines mixed in
+	const NOBLE: &str = "\
+Reading package lists... Done
+Building dependency tree... Done
+Reading state information... Done
+Calculating upgrade... Done
+The following NEW packages will be installed:
+  linux-image-6.8.0-45-generic
+The following packages will be upgraded:
+  linux-image-generic
+1 upgraded, 1 newly installed, 0 to remove and 0 not upgraded.
+Need to get 14.5 MB of archives.
+Get:1 http://archive.ubuntu.com/ubuntu noble-updates/main amd64 linux-image-6.8.0-45-generic amd64 6.8.0-45.45 [14.5 MB]
+Get:2 http://archive.ubuntu.com/ubuntu noble-updates/main amd64 linux-image-generic amd64 6.8.0-45.45 [10.2 kB]
+Fetched 14.5 MB in 2s (7,012 kB/s)
+Selecting previously unselected package linux-image-6.8.0-45-generic.
+Preparing to unpack .../linux-image-6.8.0-45-generic_6.8.0-45.45_amd64.deb ...
+Unpacking linux-image-6.8.0-45-generic (6.8.0-45.45) ...
+Unpacking linux-image-generic (6.8.0-45.45) over (6.8.0-44.44) ...
+Setting up linux-image-6.8.0-45-generic (6.8.0-45.45) ...
+Setting up linux-image-generic (6.8.0-45.45) ...
+Setting up some-trigger-dependency (1.0) ...
+Processing triggers for linux-image-6.8.0-45-generic (6.8.0-45.45) ...
+";
+
+	/// every status a transcript leads to, in order
+	fn statuses(transcript: &str) -> Vec<String> {
+		let mut tracker = PhaseTracker::default();
+		transcript
+			.lines()
+			.filter_map(|line| tracker.line(line))
+			.collect()
+	}
+
+	#[test]
+	fn bookworm_phases_are_followed() {
+		assert_eq!(
+			statuses(BOOKWORM),
+			[
+				"refreshing package lists...",
+				"refreshing package lists...",
+				"refreshing package lists...",
+				"refreshing package lists...",
+				"downloading 1/3: libc6",
+				"downloading 2/3: libc-bin",
+				"downloading 3/3: openssh-client",
+				"unpacking 1/3: libc6",
+				"setting up 1/3: libc6",
+				"unpacking 2/3: libc-bin",
+				"setting up 2/3: libc-bin",
+				"unpacking 3/3: openssh-client",
+				"setting up 3/3: openssh-client",
+				"processing triggers: libc-bin",
+			]
+		);
+	}
+
+	#[test]
+	fn noble_phases_are_followed() {
+		assert_eq!(
+			statuses(NOBLE),
+			[
+				"downloading 1/2: linux-image-6.8.0-45-generic",
+				"downloading 2/2: linux-image-generic",
+				"unpacking 1/2: linux-image-6.8.0-45-generic",
+				"unpacking 2/2: linux-image-generic",
+				"setting up 1/2: linux-image-6.8.0-45-generic",
+				"setting up 2/2: linux-image-generic",
+				"setting up 3: some-trigger-dependency",
+				"processing triggers: linux-image-6.8.0-45-generic",
+			]
+		);
+	}
+
+	#[test]
+	fn unknown_output_is_ignored() {
+		let mut tracker = PhaseTracker::default();
+		assert_eq!(tracker.line("pupdating..."), None);
+		assert_eq!(
+			tracker.line("Get:"),
+			Some("refreshing package lists...".to_string())
+		);
+		assert_eq!(tracker.line("Unpacking"), None);
+		assert_eq!(tracker.line("Setting up "), None);
+		assert_eq!(
+			tracker.line("Entpacken von libc6:amd64 (2.36-9+deb12u4) über (2.36-9+deb12u3) ..."),
+			None
+		);
+	}
+}
@@ -0,0 +1,63 @@
+use std::io::IsTerminal;
+
+/// lets the user pick which of the given remotes to act on from a fuzzy-searchable menu, choosing a remote again to
+/// drop it, returning the picked remotes in the order they were given
+pub fn pick_remotes(remotes: Vec<String>) -> eyre::Result<Vec<String>> {
+	// scripts without a terminal would otherwise hang waiting for a choice which can't be made
+	if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+		eyre::bail!("--pick needs a terminal to pick remotes in");
+	}
+	if remotes.is_empty() {
+		eyre::bail!("nothing to pick from: no remotes were given or configured");
+	}
+	pick(remotes)
+}
+
+#[cfg(feature = "pick")]
+fn pick(remotes: Vec<String>) -> eyre::Result<Vec<String>> {
+	use dialoguer::FuzzySelect;
+
+	let mut picked = vec![false; remotes.len()];
+	// the menu starts on the first remote rather than on finishing
+	let mut cursor = 1;
+	loop {
+		let count = picked.iter().filter(|picked| **picked).count();
+		let items: Vec<_> =
+			std::iter::once(format!("done, {count} remotes picked"))
+				.chain(remotes.iter().zip(&picked).map(|(remote, picked)| {
+					format!("[{}] {remote}", if *picked { 'x' } else { ' ' })
+				}))
+				.collect();
+		let Some(choice) = FuzzySelect::new()
+			.with_prompt("pick remotes, type to search and choose a picked remote again to drop it")
+			.items(&items)
+			.default(cursor)
+			.report(false)
+			.interact_opt()?
+		else {
+			eyre::bail!("picking remotes was cancelled");
+		};
+		if choice == 0 {
+			break;
+		}
+		picked[choice - 1] = !picked[choice - 1];
+		cursor = choice;
+	}
+
+	let remotes: Vec<_> = remotes
+		.into_iter()
+		.zip(picked)
+		.filter_map(|(remote, picked)| picked.then_some(remote))
+		.collect();
+	if remotes.is_empty() {
+		eyre::bail!("no remotes were picked");
+	}
+	Ok(remotes)
+}
+
+#[cfg(not(feature = "pick"))]
+fn pick(_remotes: Vec<String>) -> eyre::Result<Vec<String>> {
+	eyre::bail!(
+		"pupdate was built without the pick feature, so remotes can't be picked interactively"
+	)
+}
@@ -0,0 +1,339 @@
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use pupdate::{
+	diagnostics::DiagnosticCommands,
+	logs::{LogFormats, LogLayout, DEFAULT_LOG_FLUSH_INTERVAL},
+	output::OutputLimits,
+	progress::Progress,
+	remote::{pupdate_remotes, ConcurrencyGroups, RemoteOptions, Runner},
+	retry::RetryPolicy,
+	risk::SIMULATE_UPGRADE_ARGS,
+	shell::RemoteCommand,
+};
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// the name of the file the plan is written to in the run directory
+pub const PLAN_FILENAME: &str = "plan.json";
+/// what the local system is called in the plan's table
+const LOCAL_TARGET: &str = "local";
+
+/// a package an upgrade would change on a target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedUpgrade {
+	/// the package's name, qualified by its architecture when apt does
+	pub package: String,
+	/// the version installed now, unless the package would be newly installed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub current: Option<String>,
+	/// the version the package would be upgraded to
+	pub candidate: String,
+	/// whether the candidate comes from a security pocket
+	pub security: bool,
+}
+
+/// parses the output of `apt-get -s upgrade` into the packages it would upgrade or install
+pub fn parse_simulated_upgrades(output: &[u8]) -> Vec<PlannedUpgrade> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(|line| {
+			// lines look like `Inst libc6 [2.36-9+deb12u3] (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])`,
+			// without the installed version in brackets for packages which would be newly installed
+			let (package, rest) = line.strip_prefix("Inst ")?.split_once(' ')?;
+			let (current, rest) = match rest.strip_prefix('[') {
+				Some(rest) => {
+					let (current, rest) = rest.split_once(']')?;
+					(Some(current.to_string()), rest.trim_start())
+				}
+				None => (None, rest),
+			};
+			let details = rest.strip_prefix('(')?;
+			let candidate = details.split_whitespace().next()?;
+			// the release the candidate comes from follows it, like `Debian-Security:12/stable-security` or
+			// `Ubuntu:22.04/jammy-security`
+			let releases = details.split(" [").next().unwrap_or(details);
+			Some(PlannedUpgrade {
+				package: package.to_string(),
+				current,
+				candidate: candidate.to_string(),
+				security: releases.to_lowercase().contains("security"),
+			})
+		})
+		.collect()
+}
+
+/// a package upgraded to the same version on every target it's pending on
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PlannedPackage {
+	/// the package's name
+	pub package: String,
+	/// the version the package would be upgraded to
+	pub candidate: String,
+	/// the versions installed now across the targets, leaving out targets which would newly install it
+	pub current: Vec<String>,
+	/// whether the candidate comes from a security pocket
+	pub security: bool,
+	/// the targets the package would be upgraded or installed on
+	pub targets: Vec<String>,
+}
+
+/// gathers the upgrades pending on each target into one entry for each package and version it would be upgraded to
+pub fn gather_packages(targets: &BTreeMap<String, Vec<PlannedUpgrade>>) -> Vec<PlannedPackage> {
+	let mut packages: BTreeMap<(&str, &str), PlannedPackage> = BTreeMap::new();
+	for (target, upgrades) in targets {
+		for upgrade in upgrades {
+			let package = packages
+				.entry((&upgrade.package, &upgrade.candidate))
+				.or_insert_with(|| PlannedPackage {
+					package: upgrade.package.clone(),
+					candidate: upgrade.candidate.clone(),
+					current: Vec::new(),
+					security: upgrade.security,
+					targets: Vec::new(),
+				});
+			if let Some(current) = &upgrade.current {
+				if !package.current.contains(current) {
+					package.current.push(current.clone());
+				}
+			}
+			if !package.targets.contains(target) {
+				package.targets.push(target.clone());
+			}
+		}
+	}
+	packages.into_values().collect()
+}
+
+/// what an upgrade would change on each target, gathered without changing anything
+#[derive(Debug, Serialize)]
+pub struct Plan {
+	/// when the plan was made, in rfc3339
+	pub planned_at: String,
+	/// the upgrades pending on each target which could be checked, by target, with the local system as `local`
+	pub targets: BTreeMap<String, Vec<PlannedUpgrade>>,
+	/// the targets which couldn't be checked
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub failed: Vec<String>,
+	/// each package and version which would be upgraded to, with the targets it would be upgraded on
+	pub packages: Vec<PlannedPackage>,
+}
+
+impl Plan {
+	/// prints the plan as a table of packages, their current and candidate versions, the targets they're pending on,
+	/// and whether they're security updates
+	pub fn print(&self) {
+		if self.packages.is_empty() {
+			println!(
+				"nothing to upgrade on the {} targets checked",
+				self.targets.len()
+			);
+		} else {
+			let rows: Vec<_> = self
+				.packages
+				.iter()
+				.map(|package| {
+					[
+						package.package.clone(),
+						package.current.join(", "),
+						package.candidate.clone(),
+						package.targets.join(", "),
+						if package.security { "yes" } else { "" }.to_string(),
+					]
+				})
+				.collect();
+			let header =
+				["package", "current", "candidate", "targets", "security"].map(str::to_string);
+			let mut widths = [0; 5];
+			for row in std::iter::once(&header).chain(&rows) {
+				for (width, cell) in widths.iter_mut().zip(row) {
+					*width = (*width).max(cell.chars().count());
+				}
+			}
+			for row in std::iter::once(&header).chain(&rows) {
+				let line: Vec<_> = row
+					.iter()
+					.zip(widths)
+					.map(|(cell, width)| format!("{cell:width$}"))
+					.collect();
+				println!("{}", line.join("  ").trim_end());
+			}
+		}
+		if !self.failed.is_empty() {
+			println!("the following targets couldn't be checked:");
+			for target in &self.failed {
+				println!("{target}");
+			}
+		}
+	}
+
+	/// writes the plan to the given path
+	pub fn write(&self, path: &Path) -> eyre::Result<()> {
+		std::fs::write(path, serde_json::to_string_pretty(self)?)
+			.map_err(|e| eyre::eyre!("failed to write {}: {e}", path.display()))?;
+		Ok(())
+	}
+}
+
+/// simulates an upgrade on every remote without changing anything, returning the upgrades pending on each remote
+/// which could be checked, along with the remotes which couldn't
+pub async fn simulate_remotes<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<(BTreeMap<String, Vec<PlannedUpgrade>>, Vec<String>)> {
+	let len = remotes.len();
+	let run = pupdate_remotes(
+		runner,
+		remotes,
+		Arc::new(RemoteOptions {
+			command: RemoteCommand::Argv(
+				std::iter::once("apt-get")
+					.chain(SIMULATE_UPGRADE_ARGS.iter().copied())
+					.map(str::to_string)
+					.collect(),
+			),
+			local_command: None,
+			log_dir: None,
+			log_layout: LogLayout::default(),
+			log_formats: LogFormats::default(),
+			log_names: BTreeMap::new(),
+			log_template: None,
+			log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+			stream_log: None,
+			report_sources: false,
+			fetch_logs: false,
+			fetch_compressed: false,
+			capture_stdout: true,
+			retry,
+			offline: None,
+			skew_check: None,
+			services: None,
+			output_limits: OutputLimits::default(),
+			merge_output: false,
+			timeout: None,
+			verify_binary: None,
+			webhook: None,
+			silencer: None,
+			forward: None,
+			state: None,
+			change_id: None,
+			jobs: None,
+			concurrency_groups: ConcurrencyGroups::default(),
+			fail_on_repo_warnings: false,
+			fail_on_kept_back: false,
+			diagnostics: DiagnosticCommands::default(),
+			events: None,
+		}),
+	);
+	let mut upgrades = BTreeMap::new();
+	let mut failed = Vec::new();
+	for (remote, result) in progress.show_remotes(run, len, verbose).await? {
+		if result.success {
+			let pending = parse_simulated_upgrades(result.stdout.as_deref().unwrap_or_default());
+			upgrades.insert(remote, pending);
+		} else {
+			failed.push(remote);
+		}
+	}
+	Ok((upgrades, failed))
+}
+
+/// simulates an upgrade on every remote, and the local system with the given apt-get if it's given, gathering what
+/// each of them would upgrade without changing anything
+pub async fn plan<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	local_apt: Option<PathBuf>,
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<Plan> {
+	let planned_at = OffsetDateTime::now_utc().format(&Rfc3339)?;
+	let (mut targets, mut failed) = if remotes.is_empty() {
+		(BTreeMap::new(), Vec::new())
+	} else {
+		println!("simulating upgrades on {} remotes", remotes.len());
+		simulate_remotes(runner, remotes, retry, progress, verbose).await?
+	};
+	if let Some(apt) = local_apt {
+		println!("simulating an upgrade on the local system");
+		let output = tokio::process::Command::new(&apt)
+			.args(SIMULATE_UPGRADE_ARGS)
+			.stdin(std::process::Stdio::null())
+			.output()
+			.await;
+		match output {
+			Ok(output) if output.status.success() => {
+				targets.insert(
+					LOCAL_TARGET.to_string(),
+					parse_simulated_upgrades(&output.stdout),
+				);
+			}
+			Ok(output) => {
+				eprintln!(
+					"warning: couldn't simulate an upgrade on the local system ({})",
+					output.status
+				);
+				failed.push(LOCAL_TARGET.to_string());
+			}
+			Err(e) => {
+				eprintln!("warning: couldn't simulate an upgrade on the local system ({e})");
+				failed.push(LOCAL_TARGET.to_string());
+			}
+		}
+	}
+	Ok(Plan {
+		planned_at,
+		packages: gather_packages(&targets),
+		targets,
+		failed,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn plans_are_gathered() {
+		let web1 = parse_simulated_upgrades(
+			b"Reading package lists...\n\
+			Inst libc6 [2.36-9+deb12u3] (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])\n\
+			Inst nginx [1.22.1-9] (1.22.1-9+deb12u1 Debian:12.5/stable [amd64])\n\
+			Inst linux-image-6.1.0-18-amd64 (6.1.76-1 Debian-Security:12/stable-security [amd64])\n\
+			Conf libc6 (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])\n",
+		);
+		assert_eq!(
+			web1[2],
+			PlannedUpgrade {
+				package: "linux-image-6.1.0-18-amd64".to_string(),
+				current: None,
+				candidate: "6.1.76-1".to_string(),
+				security: true,
+			}
+		);
+		assert!(!web1[1].security);
+		let web2 = parse_simulated_upgrades(
+			b"Inst libc6 [2.36-9+deb12u2] (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])\n",
+		);
+		let targets = BTreeMap::from([("web1".to_string(), web1), ("web2".to_string(), web2)]);
+		let packages = gather_packages(&targets);
+		assert_eq!(packages.len(), 3);
+		assert_eq!(
+			packages[0],
+			PlannedPackage {
+				package: "libc6".to_string(),
+				candidate: "2.36-9+deb12u4".to_string(),
+				current: vec!["2.36-9+deb12u3".to_string(), "2.36-9+deb12u2".to_string()],
+				security: true,
+				targets: vec!["web1".to_string(), "web2".to_string()],
+			}
+		);
+	}
+}
@@ -0,0 +1,244 @@
+//! playbooks, which split a run into phases pupdated one after another, each with its own remotes, concurrency, and
+//! command, so a run can go through load balancers before app servers without being split into several runs
+
+use std::{collections::BTreeSet, num::NonZeroUsize, path::Path, sync::Arc, time::Instant};
+
+use serde::Deserialize;
+
+use crate::{
+	progress::Progress,
+	remote::{pupdate_remotes, RemoteOptions, Runner},
+	shell::RemoteCommand,
+	summary::{PhaseSummary, TargetSummary},
+};
+
+/// a phase of a playbook
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaybookPhase {
+	/// the phase's name, used in its status and summary
+	pub name: String,
+	/// the remotes pupdated in the phase
+	pub remotes: Vec<String>,
+	/// how many of the phase's remotes are pupdated at once, or all of them if unset
+	#[serde(default)]
+	pub jobs: Option<NonZeroUsize>,
+	/// the command run on the phase's remotes, or the configured remote command if unset
+	#[serde(default)]
+	pub command: Option<RemoteCommand>,
+	/// whether the phases after this one are still pupdated when any of its remotes fail
+	#[serde(default)]
+	pub continue_on_failure: bool,
+}
+
+/// phases pupdated one after another, stopping at the first phase with a failed remote unless it's allowed to fail
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Playbook {
+	/// the phases, in the order they're pupdated
+	pub phases: Vec<PlaybookPhase>,
+}
+
+impl Playbook {
+	/// parses a playbook as toml or json, checking that every phase has remotes and a name of its own and that no
+	/// remote is in more than one phase
+	pub fn parse(contents: &str, is_toml: bool) -> Result<Self, String> {
+		let playbook: Self = if is_toml {
+			toml::from_str(contents).map_err(|e| e.to_string())?
+		} else {
+			serde_json::from_str(contents).map_err(|e| e.to_string())?
+		};
+		if playbook.phases.is_empty() {
+			return Err("it has no phases".to_string());
+		}
+		let mut names = BTreeSet::new();
+		let mut remotes = BTreeSet::new();
+		for phase in &playbook.phases {
+			if !names.insert(&phase.name) {
+				return Err(format!("there's more than one phase named {}", phase.name));
+			}
+			if phase.remotes.is_empty() {
+				return Err(format!("phase {} has no remotes", phase.name));
+			}
+			for remote in &phase.remotes {
+				if !remotes.insert(remote) {
+					return Err(format!("{remote} is in more than one phase"));
+				}
+			}
+		}
+		Ok(playbook)
+	}
+
+	/// loads a playbook from the given path, parsed as toml if the extension is `.toml` and json otherwise
+	pub fn load(path: &Path) -> eyre::Result<Self> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| eyre::eyre!("failed to read playbook {}: {e}", path.display()))?;
+		let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+		Self::parse(&contents, is_toml)
+			.map_err(|e| eyre::eyre!("invalid playbook {}: {e}", path.display()))
+	}
+
+	/// gets every phase's remotes, in the order they're pupdated
+	pub fn remotes(&self) -> Vec<String> {
+		self.phases
+			.iter()
+			.flat_map(|phase| phase.remotes.iter().cloned())
+			.collect()
+	}
+}
+
+/// pupdates the given remotes phase by phase, leaving out any remotes of a phase which weren't given. once a phase
+/// has a failed remote, the phases after it are skipped unless it's allowed to fail, with their remotes counting as
+/// failed
+pub async fn run_playbook<R: Runner>(
+	runner: Arc<R>,
+	playbook: &Playbook,
+	remotes: &[String],
+	options: &RemoteOptions,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<(Vec<(String, TargetSummary)>, Vec<PhaseSummary>)> {
+	let mut results = Vec::with_capacity(remotes.len());
+	let mut phases = Vec::with_capacity(playbook.phases.len());
+	let mut halted_by = None;
+	for phase in &playbook.phases {
+		let phase_remotes: Vec<_> = phase
+			.remotes
+			.iter()
+			.filter(|remote| remotes.contains(remote))
+			.cloned()
+			.collect();
+		if phase_remotes.is_empty() {
+			continue;
+		}
+		if let Some(halted_by) = &halted_by {
+			let skipped = format!("phase {halted_by} failed");
+			for remote in &phase_remotes {
+				let result = TargetSummary {
+					skipped: Some(skipped.clone()),
+					..Default::default()
+				};
+				if let Some(state) = &options.state {
+					state.remote_finished(remote, &result);
+				}
+				results.push((remote.clone(), result));
+			}
+			println!("phase {}: skipped since {skipped}", phase.name);
+			phases.push(PhaseSummary {
+				name: phase.name.clone(),
+				remotes: phase_remotes,
+				failed: Vec::new(),
+				duration_seconds: None,
+				skipped: Some(skipped),
+			});
+			continue;
+		}
+
+		// phases without a limit of their own are limited like the rest of the run
+		let mut options = RemoteOptions {
+			jobs: phase.jobs.or(options.jobs),
+			..options.clone()
+		};
+		if let Some(command) = &phase.command {
+			match command.program() {
+				Some(program) => {
+					// the binary checked and asked for its version is whichever the phase's command runs
+					if let Some(verify) = &mut options.verify_binary {
+						verify.program = program.to_string();
+					}
+					if let Some(forward) = &mut options.forward {
+						forward.program = program.to_string();
+					}
+				}
+				None if options.verify_binary.is_some() || options.forward.is_some() => {
+					eyre::bail!(
+						"phase {}'s command has no program to verify or forward flags to",
+						phase.name
+					);
+				}
+				None => {}
+			}
+			options.command = command.clone();
+		}
+
+		let len = phase_remotes.len();
+		match options.jobs {
+			Some(jobs) => println!(
+				"phase {}: pupdating {len} remotes, {jobs} at a time",
+				phase.name
+			),
+			None => println!("phase {}: pupdating {len} remotes", phase.name),
+		}
+		let start = Instant::now();
+		let run = pupdate_remotes(runner.clone(), phase_remotes.clone(), Arc::new(options));
+		let phase_results = progress.show_remotes(run, len, verbose).await?;
+		let failed: Vec<_> = phase_results
+			.iter()
+			.filter(|(_, result)| !result.success)
+			.map(|(remote, _)| remote.clone())
+			.collect();
+		let duration = start.elapsed();
+		if !failed.is_empty() && !phase.continue_on_failure {
+			halted_by = Some(phase.name.clone());
+		}
+		results.extend(phase_results);
+		phases.push(PhaseSummary {
+			name: phase.name.clone(),
+			remotes: phase_remotes,
+			failed,
+			duration_seconds: Some(duration.as_secs_f64()),
+			skipped: None,
+		});
+	}
+	Ok((results, phases))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn playbooks_are_parsed() {
+		let playbook = Playbook::parse(
+			r#"
+			[[phases]]
+			name = "load balancers"
+			remotes = ["lb1", "lb2"]
+
+			[[phases]]
+			name = "app servers"
+			remotes = ["app1", "app2", "app3"]
+			jobs = 2
+			command = "sudo pupdate --only-security"
+			continue_on_failure = true
+			"#,
+			true,
+		)
+		.unwrap();
+		assert_eq!(playbook.remotes(), ["lb1", "lb2", "app1", "app2", "app3"]);
+		assert_eq!(playbook.phases[0].jobs, None);
+		assert_eq!(playbook.phases[1].jobs, NonZeroUsize::new(2));
+		assert_eq!(
+			playbook.phases[1].command,
+			Some(RemoteCommand::Shell(
+				"sudo pupdate --only-security".to_string()
+			))
+		);
+		assert!(!playbook.phases[0].continue_on_failure);
+
+		assert!(Playbook::parse(r#"{"phases": []}"#, false).is_err());
+		assert!(Playbook::parse(
+			r#"{"phases": [{"name": "a", "remotes": ["web1"]}, {"name": "b", "remotes": ["web1"]}]}"#,
+			false
+		)
+		.is_err());
+		assert!(Playbook::parse(
+			r#"{"phases": [{"name": "a", "remotes": ["web1"], "jobs": 0}]}"#,
+			false
+		)
+		.is_err());
+		assert!(
+			Playbook::parse(r#"{"phases": [{"name": "a", "remote": ["web1"]}]}"#, false).is_err()
+		);
+	}
+}
@@ -0,0 +1,1122 @@
+//! progress for remotes, shown either as progress bars styled from a named preset and optionally customized in the
+//! config, or as plain printed lines
+//!
+//! progress bars are drawn with indicatif, which is only pulled in with the `progress` feature. without it, progress is
+//! always shown as plain printed lines
+
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	io::Write,
+	num::NonZeroUsize,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+#[cfg(feature = "progress")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "progress")]
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Deserialize;
+use tokio::{io::AsyncBufReadExt, sync::mpsc};
+
+use crate::{
+	events::{describe_skew, Event, OutputStream},
+	output::format_size,
+	phase::PhaseTracker,
+	remote::{Canceller, JobLimit, RemotesRun},
+	summary::TargetSummary,
+};
+
+/// the keys indicatif understands in templates, which otherwise renders unknown keys as nothing
+const TEMPLATE_KEYS: &[&str] = &[
+	"bar",
+	"wide_bar",
+	"spinner",
+	"prefix",
+	"msg",
+	"wide_msg",
+	"pos",
+	"human_pos",
+	"len",
+	"human_len",
+	"percent",
+	"percent_precise",
+	"bytes",
+	"binary_bytes",
+	"decimal_bytes",
+	"total_bytes",
+	"binary_total_bytes",
+	"decimal_total_bytes",
+	"elapsed",
+	"elapsed_precise",
+	"eta",
+	"eta_precise",
+	"duration",
+	"duration_precise",
+	"per_sec",
+	"bytes_per_sec",
+	"binary_bytes_per_sec",
+	"decimal_bytes_per_sec",
+];
+
+/// checks a template for mistakes indicatif silently ignores: unbalanced braces, unknown keys, and more than one wide
+/// element
+fn check_template(template: &str) -> Result<(), String> {
+	let mut chars = template.chars().peekable();
+	let mut wide = 0;
+	while let Some(c) = chars.next() {
+		match c {
+			'{' if chars.peek() == Some(&'{') => {
+				chars.next();
+			}
+			'}' if chars.peek() == Some(&'}') => {
+				chars.next();
+			}
+			'{' => {
+				let mut key = String::new();
+				loop {
+					match chars.next() {
+						Some('}') => break,
+						Some('{') | None => return Err("a `{` is never closed".to_string()),
+						Some(c) => key.push(c),
+					}
+				}
+				let name = key.split(':').next().unwrap_or_default();
+				if !TEMPLATE_KEYS.contains(&name) {
+					return Err(format!(
+						"unknown key `{name}`, expected one of {}",
+						TEMPLATE_KEYS.join(", ")
+					));
+				}
+				if name.starts_with("wide_") {
+					wide += 1;
+				}
+			}
+			'}' => return Err("a `}` is never opened".to_string()),
+			_ => {}
+		}
+	}
+	if wide > 1 {
+		return Err("only one of `wide_bar` and `wide_msg` can be used".to_string());
+	}
+	Ok(())
+}
+
+/// a named set of progress settings
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressPreset {
+	/// the remote, a spinner, and as much of the status as fits
+	#[default]
+	Default,
+	/// a spinner and the remote followed by the full status, with ascii ticks for limited terminals
+	Compact,
+	/// the default layout with the time each remote has been running
+	Verbose,
+}
+
+impl ProgressPreset {
+	/// gets the running template, finished template, tick characters, and tick interval in milliseconds for this preset
+	fn settings(self) -> (&'static str, &'static str, &'static str, u64) {
+		match self {
+			Self::Default => (
+				"{prefix:.bold.dim} {spinner} {wide_msg}",
+				"{prefix:.bold.dim} {spinner} {wide_msg}",
+				"⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏",
+				80,
+			),
+			Self::Compact => (
+				"{spinner} {prefix:.bold.dim} {msg}",
+				"{spinner} {prefix:.bold.dim} {msg}",
+				"-\\|/",
+				120,
+			),
+			Self::Verbose => (
+				"{prefix:.bold.dim} {spinner} [{elapsed_precise}] {wide_msg}",
+				"{prefix:.bold.dim} {spinner} [{elapsed_precise}] {wide_msg}",
+				"⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏",
+				80,
+			),
+		}
+	}
+}
+
+/// the longest a line of output shown as a remote's status can be, in characters, so it fits on a line even in
+/// templates which don't fit the status to the terminal
+const MAX_LINE_STATUS: usize = 100;
+/// how wide the terminal has to be for the full progress layout, below which the narrow one is used
+#[cfg(feature = "progress")]
+const FULL_LAYOUT_WIDTH: u16 = 100;
+/// how wide the terminal has to be for progress bars at all, below which plain lines are printed instead
+#[cfg(feature = "progress")]
+const BARS_WIDTH: u16 = 50;
+/// the template for running remotes in narrow terminals, with a short prefix and how long the remote has been running
+#[cfg(feature = "progress")]
+const NARROW_TEMPLATE: &str = "{spinner} {prefix:12!.bold.dim} {elapsed}";
+/// the template for finished remotes in narrow terminals, with the finished message cut to fit
+#[cfg(feature = "progress")]
+const NARROW_FINISHED_TEMPLATE: &str = "{spinner} {prefix:12!.bold.dim} {wide_msg}";
+/// how often a remote's status is changed to its latest line of output at most
+const LINE_STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// what's shown as a remote's status while it's being pupdated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressMessages {
+	/// only that the remote is being pupdated
+	Status,
+	/// what apt is doing, like `unpacking (12/40) linux-image-6.8.0`
+	#[default]
+	Phase,
+	/// the latest line the remote wrote to stdout
+	LastLine,
+}
+
+/// gets the status a line of output is shown as, without escape sequences or other control characters and cut short
+/// if it's long, or nothing if it's blank
+pub fn line_status(line: &str) -> Option<String> {
+	let mut status = String::new();
+	let mut chars = line.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			// escape sequences like colors are skipped entirely, up to the letter ending them
+			'\x1b' => {
+				if chars.next() == Some('[') {
+					for c in chars.by_ref() {
+						if ('@'..='~').contains(&c) {
+							break;
+						}
+					}
+				}
+			}
+			'\t' => status.push(' '),
+			c if c.is_control() => {}
+			c => status.push(c),
+		}
+	}
+	let status = status.trim();
+	if status.is_empty() {
+		return None;
+	}
+	Some(if status.chars().count() > MAX_LINE_STATUS {
+		let cut: String = status.chars().take(MAX_LINE_STATUS - 1).collect();
+		format!("{cut}…")
+	} else {
+		status.to_string()
+	})
+}
+
+/// progress settings from the config, each overriding the preset when set
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ProgressSettings {
+	/// the indicatif template for remotes which are still running
+	#[serde(default)]
+	pub template: Option<String>,
+	/// the indicatif template for remotes which have finished, where `{spinner}` is a green ✓ or a red ✗
+	#[serde(default)]
+	pub finished_template: Option<String>,
+	/// the characters the spinner cycles through, at least two
+	#[serde(default)]
+	pub tick_chars: Option<String>,
+	/// how often the spinner ticks in milliseconds
+	#[serde(default)]
+	pub tick_millis: Option<u64>,
+	/// what's shown as each remote's status while it's being pupdated, defaults to what apt is doing
+	#[serde(default)]
+	pub messages: Option<ProgressMessages>,
+}
+
+impl ProgressSettings {
+	/// merges other settings on top of these ones, letting the other settings win
+	pub fn merge(&mut self, other: Self) {
+		if other.template.is_some() {
+			self.template = other.template;
+		}
+		if other.finished_template.is_some() {
+			self.finished_template = other.finished_template;
+		}
+		if other.tick_chars.is_some() {
+			self.tick_chars = other.tick_chars;
+		}
+		if other.tick_millis.is_some() {
+			self.tick_millis = other.tick_millis;
+		}
+		if other.messages.is_some() {
+			self.messages = other.messages;
+		}
+	}
+}
+
+/// indicatif's progress bar, which can't exist without the `progress` feature
+#[cfg(not(feature = "progress"))]
+enum ProgressBar {}
+
+/// how progress is laid out in a terminal of a particular width
+#[cfg(feature = "progress")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+	/// the preset's layout, customized by the config
+	Full,
+	/// a short prefix, the spinner, and how long the remote has been running, with finished messages cut to fit
+	Narrow,
+	/// plain printed lines instead of progress bars
+	Lines,
+}
+
+#[cfg(feature = "progress")]
+impl Layout {
+	/// gets the layout which fits a terminal of the given width, or the full layout if the width isn't known
+	fn for_width(width: Option<u16>) -> Self {
+		match width {
+			Some(width) if width < BARS_WIDTH => Self::Lines,
+			Some(width) if width < FULL_LAYOUT_WIDTH => Self::Narrow,
+			_ => Self::Full,
+		}
+	}
+}
+
+/// gets the width of the terminal progress bars are drawn to, if they're drawn to a terminal
+#[cfg(feature = "progress")]
+fn terminal_width() -> Option<u16> {
+	console::Term::stderr()
+		.size_checked()
+		.map(|(_, width)| width)
+}
+
+/// the styles a remote's progress bar goes through in a single layout
+#[cfg(feature = "progress")]
+#[derive(Clone)]
+struct LayoutStyles {
+	/// the style for remotes which are still running
+	running: ProgressStyle,
+	/// the style for remotes which succeeded, with a green ✓
+	success: ProgressStyle,
+	/// the style for remotes which failed, errored, or were cancelled, with a red ✗
+	failure: ProgressStyle,
+}
+
+#[cfg(feature = "progress")]
+impl LayoutStyles {
+	/// creates the styles from the running and finished templates
+	fn new(template: &str, finished_template: &str, tick_chars: &str) -> eyre::Result<Self> {
+		let running = ProgressStyle::with_template(template)
+			.map_err(|e| eyre::eyre!("invalid progress template {template:?}: {e}"))?
+			.tick_chars(tick_chars);
+
+		// a finished spinner shows its last tick, so a single repeated tick works as a status glyph
+		let finished = |color: &str, glyph: &str| -> eyre::Result<ProgressStyle> {
+			let template = finished_template.replace("{spinner}", &format!("{{spinner:.{color}}}"));
+			Ok(ProgressStyle::with_template(&template)
+				.map_err(|e| {
+					eyre::eyre!("invalid progress finished_template {finished_template:?}: {e}")
+				})?
+				.tick_strings(&[glyph, glyph]))
+		};
+
+		Ok(Self {
+			running,
+			success: finished("green", "✓")?,
+			failure: finished("red", "✗")?,
+		})
+	}
+}
+
+/// the styles for remotes' progress bars, which are only checked without the `progress` feature
+#[derive(Clone)]
+pub struct ProgressStyles {
+	/// the styles for terminals wide enough for the full layout
+	#[cfg(feature = "progress")]
+	full: LayoutStyles,
+	/// the styles for narrow terminals
+	#[cfg(feature = "progress")]
+	narrow: LayoutStyles,
+	/// whether the terminal is narrow, shared between every remote's copy of the styles so they all switch together
+	#[cfg(feature = "progress")]
+	narrowed: Arc<AtomicBool>,
+	/// how often running spinners tick
+	#[cfg(feature = "progress")]
+	tick: Duration,
+	/// what's shown as each remote's status while it's being pupdated
+	#[cfg(feature = "progress")]
+	messages: ProgressMessages,
+}
+
+impl ProgressStyles {
+	/// creates the styles for the given preset with the given settings on top, failing if any of them are invalid
+	pub fn new(preset: ProgressPreset, settings: ProgressSettings) -> eyre::Result<Self> {
+		let (template, finished_template, tick_chars, tick_millis) = preset.settings();
+		let template = settings.template.as_deref().unwrap_or(template);
+		let finished_template = settings
+			.finished_template
+			.as_deref()
+			.unwrap_or(finished_template);
+		let tick_chars = settings.tick_chars.as_deref().unwrap_or(tick_chars);
+		let tick_millis = settings.tick_millis.unwrap_or(tick_millis);
+
+		if tick_chars.chars().count() < 2 {
+			eyre::bail!(
+				"invalid progress tick_chars {tick_chars:?}: at least two characters are needed"
+			);
+		}
+		if tick_millis == 0 {
+			eyre::bail!("invalid progress tick_millis: it must be more than 0");
+		}
+		check_template(template)
+			.map_err(|e| eyre::eyre!("invalid progress template {template:?}: {e}"))?;
+		check_template(finished_template).map_err(|e| {
+			eyre::eyre!("invalid progress finished_template {finished_template:?}: {e}")
+		})?;
+		#[cfg(not(feature = "progress"))]
+		return Ok(Self {});
+
+		#[cfg(feature = "progress")]
+		Ok(Self {
+			full: LayoutStyles::new(template, finished_template, tick_chars)?,
+			narrow: LayoutStyles::new(NARROW_TEMPLATE, NARROW_FINISHED_TEMPLATE, tick_chars)?,
+			narrowed: Arc::new(AtomicBool::new(false)),
+			tick: Duration::from_millis(tick_millis),
+			messages: settings.messages.unwrap_or_default(),
+		})
+	}
+
+	/// gets the styles for the layout currently in use
+	#[cfg(feature = "progress")]
+	fn layout(&self) -> &LayoutStyles {
+		if self.narrowed.load(Ordering::Relaxed) {
+			&self.narrow
+		} else {
+			&self.full
+		}
+	}
+
+	/// switches to the layout which fits a terminal of the given width, returning whether it changed
+	#[cfg(feature = "progress")]
+	fn fit(&self, width: Option<u16>) -> bool {
+		let narrow = Layout::for_width(width) != Layout::Full;
+		self.narrowed.swap(narrow, Ordering::Relaxed) != narrow
+	}
+
+	/// starts a remote's progress bar spinning
+	#[cfg(feature = "progress")]
+	fn start(&self, pb: &ProgressBar) {
+		pb.set_style(self.layout().running.clone());
+		pb.enable_steady_tick(self.tick);
+	}
+
+	/// finishes a remote's progress bar with the style for whether it succeeded
+	#[cfg(feature = "progress")]
+	fn finish(&self, pb: &ProgressBar, success: bool, message: impl Into<Cow<'static, str>>) {
+		let layout = self.layout();
+		pb.set_style(if success {
+			layout.success.clone()
+		} else {
+			layout.failure.clone()
+		});
+		pb.finish_with_message(message);
+	}
+}
+
+/// how the progress of remotes is shown
+#[derive(Clone)]
+pub enum Progress {
+	/// a progress bar for each remote and one for the run overall
+	#[cfg(feature = "progress")]
+	Bars {
+		/// the progress bars being drawn
+		multi: MultiProgress,
+		/// the styles for each remote's progress bar
+		styles: Box<ProgressStyles>,
+	},
+	/// a printed line for each change in a remote's status
+	Lines,
+}
+
+impl Progress {
+	/// shows progress bars with the given styles in the layout which fits the terminal, or plain printed lines when the
+	/// terminal is too narrow for progress bars or without the `progress` feature
+	pub fn bars(styles: ProgressStyles) -> Self {
+		#[cfg(feature = "progress")]
+		{
+			let width = terminal_width();
+			if Layout::for_width(width) == Layout::Lines {
+				return Self::Lines;
+			}
+			styles.fit(width);
+			Self::Bars {
+				multi: MultiProgress::new(),
+				styles: Box::new(styles),
+			}
+		}
+		#[cfg(not(feature = "progress"))]
+		{
+			let _ = styles;
+			Self::Lines
+		}
+	}
+
+	/// creates the overall progress bar for the given number of remotes, if progress bars are shown
+	#[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+	fn overall(&self, remotes: usize) -> Option<ProgressBar> {
+		match self {
+			#[cfg(feature = "progress")]
+			Self::Bars { multi, .. } => Some(multi.add(ProgressBar::new(remotes as u64))),
+			Self::Lines => None,
+		}
+	}
+
+	/// starts showing the progress of a remote, inserting its progress bar before the overall one
+	fn remote(&self, remote: &str, overall: Option<&ProgressBar>) -> RemoteProgress {
+		#[cfg(feature = "progress")]
+		let bar = match (self, overall) {
+			(Self::Bars { multi, styles }, Some(overall)) => {
+				let pb = multi.insert_before(overall, ProgressBar::new_spinner());
+				pb.set_prefix(remote.to_string());
+				styles.start(&pb);
+				Some(RemoteBar {
+					pb,
+					overall: overall.clone(),
+					styles: styles.clone(),
+				})
+			}
+			_ => None,
+		};
+		#[cfg(not(feature = "progress"))]
+		let _ = overall;
+		RemoteProgress {
+			remote: remote.to_string(),
+			#[cfg(feature = "progress")]
+			bar,
+		}
+	}
+
+	/// gets what's shown as each remote's status while it's being pupdated, which is only ever the status for printed
+	/// lines since a line for every change would bury everything else
+	fn messages(&self) -> ProgressMessages {
+		match self {
+			#[cfg(feature = "progress")]
+			Self::Bars { styles, .. } => styles.messages,
+			Self::Lines => ProgressMessages::Status,
+		}
+	}
+
+	/// switches remotes' progress bars to the layout which fits the terminal after it's been resized, restyling the ones
+	/// still running. bars can't be swapped for printed lines partway through a run, so the narrow layout is the
+	/// narrowest used after starting
+	#[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+	fn refit(&self, bars: &HashMap<String, RemoteProgress>) {
+		#[cfg(feature = "progress")]
+		if let Self::Bars { styles, .. } = self {
+			if styles.fit(terminal_width()) {
+				for bar in bars.values().filter_map(|progress| progress.bar.as_ref()) {
+					if !bar.pb.is_finished() {
+						bar.pb.set_style(styles.layout().running.clone());
+					}
+				}
+			}
+		}
+	}
+
+	/// hides progress bars until the returned guard is dropped, so a prompt can print and read from the terminal while
+	/// the bars carry on being updated out of sight, which [`Progress::suspend`] would hold up
+	fn hide(&self) -> HiddenBars {
+		match self {
+			#[cfg(feature = "progress")]
+			Self::Bars { multi, .. } => {
+				// clearing can only fail when the terminal's gone, when there's nothing left to hide anyway
+				let _ = multi.clear();
+				multi.set_draw_target(ProgressDrawTarget::hidden());
+				HiddenBars {
+					multi: Some(multi.clone()),
+				}
+			}
+			Self::Lines => HiddenBars {
+				#[cfg(feature = "progress")]
+				multi: None,
+			},
+		}
+	}
+
+	/// runs the given function with progress bars hidden so it can print and read from the terminal
+	fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+		match self {
+			#[cfg(feature = "progress")]
+			Self::Bars { multi, .. } => multi.suspend(f),
+			Self::Lines => f(),
+		}
+	}
+
+	/// shows the progress of a run of remotes until every remote has finished, returning their results
+	pub async fn show_remotes(
+		&self,
+		run: RemotesRun,
+		remotes: usize,
+		verbose: bool,
+	) -> eyre::Result<Vec<(String, TargetSummary)>> {
+		let RemotesRun {
+			mut events,
+			canceller,
+			results,
+			job_limit,
+		} = run;
+		let overall = self.overall(remotes);
+		let in_flight = Arc::new(Mutex::new(Vec::new()));
+
+		// SIGUSR2 lets a remote be cancelled without stopping the whole run
+		#[cfg(unix)]
+		let cancel_listener = {
+			let progress = self.clone();
+			let in_flight = in_flight.clone();
+			let mut signal =
+				tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined2())?;
+			tokio::spawn(async move {
+				while signal.recv().await.is_some() {
+					prompt_cancel(progress.clone(), in_flight.clone(), canceller.clone()).await?;
+				}
+				Ok::<_, eyre::Report>(())
+			})
+		};
+		#[cfg(not(unix))]
+		drop(canceller);
+
+		// SIGUSR1 lets how many remotes are pupdated at once be changed without restarting the run
+		#[cfg(unix)]
+		let jobs_listener = {
+			let progress = self.clone();
+			let mut signal =
+				tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())?;
+			tokio::spawn(async move {
+				while signal.recv().await.is_some() {
+					prompt_jobs(progress.clone(), job_limit.clone()).await?;
+				}
+				Ok::<_, eyre::Report>(())
+			})
+		};
+		#[cfg(not(unix))]
+		drop(job_limit);
+
+		// resizing the terminal switches progress bars to the layout which fits it
+		let (resized_sender, mut resized) = mpsc::unbounded_channel();
+		#[cfg(all(unix, feature = "progress"))]
+		let resize_listener = {
+			let mut signal =
+				tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())?;
+			tokio::spawn(async move {
+				while signal.recv().await.is_some() {
+					if resized_sender.send(()).is_err() {
+						break;
+					}
+				}
+			})
+		};
+		#[cfg(not(all(unix, feature = "progress")))]
+		drop(resized_sender);
+
+		let mut bars: HashMap<String, RemoteProgress> = HashMap::new();
+		let mut warnings = HashMap::new();
+		let mut timed_out = HashMap::new();
+		// which attempt remotes which were retried are on, so their phases can say
+		let mut attempts = HashMap::new();
+		let mut phases: HashMap<String, PhaseTracker> = HashMap::new();
+		let mut line_shown: HashMap<String, Instant> = HashMap::new();
+		loop {
+			let event = tokio::select! {
+				event = events.recv() => match event {
+					Some(event) => event,
+					None => break,
+				},
+				Some(()) = resized.recv() => {
+					self.refit(&bars);
+					continue;
+				}
+			};
+			match event {
+				Event::RemoteQueued {
+					remote,
+					waiting_for,
+				} => {
+					// a remote can wait on more than one limit, so it may already have a bar
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message(format!("waiting for {waiting_for}..."));
+						continue;
+					}
+					let pb = self.remote(&remote, overall.as_ref());
+					pb.set_message(format!("waiting for {waiting_for}..."));
+					in_flight
+						.lock()
+						.expect("in flight remotes aren't poisoned")
+						.push(remote.clone());
+					bars.insert(remote, pb);
+				}
+				Event::RemoteStarted { remote } => {
+					// remotes which had to wait for their turn already have a bar
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message("pupdating...");
+						continue;
+					}
+					let pb = self.remote(&remote, overall.as_ref());
+					pb.set_message("pupdating...");
+					in_flight
+						.lock()
+						.expect("in flight remotes aren't poisoned")
+						.push(remote.clone());
+					bars.insert(remote, pb);
+				}
+				Event::RemoteRetrying {
+					remote,
+					delay,
+					attempt,
+					attempts: total,
+				} => {
+					let attempt = format!("attempt {attempt}/{total}");
+					if let Some(pb) = bars.get(&remote) {
+						if verbose {
+							pb.println(format!(
+								"{remote}: failed, retrying in {:.1} seconds ({attempt})",
+								delay.as_secs_f64()
+							));
+						}
+						pb.set_message(format!("{attempt}, retrying..."));
+					}
+					attempts.insert(remote.clone(), attempt);
+					if let Some(phase) = phases.get_mut(&remote) {
+						phase.reset();
+					}
+				}
+				Event::RemoteTimedOut { remote, timeout } => {
+					timed_out.insert(remote, timeout);
+					continue;
+				}
+				Event::RemoteClockSkewed {
+					remote,
+					skew_seconds,
+				} => {
+					if let Some(pb) = bars.get(&remote) {
+						let warning = format!("warning: {}", describe_skew(skew_seconds));
+						pb.set_message(format!("{warning}, pupdating..."));
+						warnings
+							.entry(remote)
+							.or_insert_with(Vec::new)
+							.push(warning);
+					}
+				}
+				Event::RemoteFlagsOmitted {
+					remote,
+					flags,
+					reason,
+				} => {
+					if let Some(pb) = bars.get(&remote) {
+						let warning =
+							format!("warning: left out {} since {reason}", flags.join(" "));
+						pb.set_message(format!("{warning}, pupdating..."));
+						warnings
+							.entry(remote)
+							.or_insert_with(Vec::new)
+							.push(warning);
+					}
+				}
+				Event::RemoteSkipped { remote, reason } => {
+					if let Some(pb) = bars.remove(&remote) {
+						pb.finish(false, reason);
+					}
+				}
+				Event::RemoteSilencing { remote } => {
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message("silencing monitoring...");
+					}
+				}
+				Event::RemoteSilenceFailed { remote, error } => {
+					if let Some(pb) = bars.get(&remote) {
+						let warning = format!("warning: couldn't silence monitoring: {error}");
+						pb.set_message(warning.clone());
+						warnings
+							.entry(remote)
+							.or_insert_with(Vec::new)
+							.push(warning);
+					}
+				}
+				Event::RemoteUnsilenceFailed { remote, error } => {
+					// like webhook posts, this happens once the remote has finished
+					self.suspend(|| {
+						println!("{remote}: warning: couldn't expire the silence: {error}")
+					});
+					continue;
+				}
+				Event::RemoteFetchingLogs { remote } => {
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message("fetching logs...");
+					}
+				}
+				Event::RemoteCapturingDiagnostics { remote } => {
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message("capturing diagnostics...");
+					}
+				}
+				Event::RemoteCheckingServices { remote } => {
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message("checking services...");
+					}
+				}
+				Event::RemoteRestartingServices { remote, services } => {
+					if let Some(pb) = bars.get(&remote) {
+						pb.set_message(format!("restarting {} services...", services.len()));
+					}
+				}
+				Event::RemoteFinished {
+					remote,
+					success,
+					up_to_date,
+					upgraded,
+					kept_back,
+					failure,
+					duration,
+					output_bytes,
+					logs,
+				} => {
+					if let Some(pb) = bars.remove(&remote) {
+						let outcome = match failure {
+							_ if timed_out.contains_key(&remote) => {
+								format!("timed out after {} seconds", timed_out[&remote].as_secs())
+							}
+							_ if success && up_to_date => "already up to date".to_string(),
+							_ if success => "succeeded".to_string(),
+							Some(failure) => format!("failed ({failure})"),
+							None => "failed".to_string(),
+						};
+						let upgraded = match upgraded {
+							Some(upgraded) if !up_to_date => format!(", {upgraded} upgraded"),
+							_ => String::new(),
+						};
+						let mut message = format!(
+							"finished in {} seconds: {outcome}{upgraded}, {} output",
+							duration.as_secs(),
+							format_size(output_bytes)
+						);
+						// the list goes last so its commas can't be mistaken for the message's
+						if !kept_back.is_empty() {
+							message.push_str(&format!(
+								", {} kept back: {}",
+								kept_back.len(),
+								kept_back.join(", ")
+							));
+						}
+						if let Some(warnings) = warnings.get(&remote) {
+							message.push_str(&format!(" ({})", warnings.join(", ")));
+						}
+						pb.finish(success, message);
+						// failed remotes' logs are usually the next thing looked at
+						if verbose || !success {
+							for file in logs.iter().flat_map(|logs| logs.files()) {
+								pb.println(format!("{remote}: log {}", file.display()));
+							}
+						}
+					}
+				}
+				Event::RemoteErrored { remote, error } => {
+					if let Some(pb) = bars.remove(&remote) {
+						pb.finish(false, format!("failed: {error}"));
+					}
+				}
+				Event::RemoteCancelled { remote } => {
+					if let Some(pb) = bars.remove(&remote) {
+						pb.finish(false, "cancelled");
+					}
+				}
+				Event::RemoteWebhookFailed { remote, error } => {
+					// the remote has usually finished by now, so there's no bar left to print through
+					self.suspend(|| {
+						println!(
+							"{remote}: warning: failed to post the result to the webhook: {error}"
+						)
+					});
+					continue;
+				}
+				Event::RemoteOutputLine {
+					remote,
+					stream,
+					line,
+				} => {
+					let Some(pb) = bars.get(&remote) else {
+						continue;
+					};
+					let status = |status: String| match attempts.get(&remote) {
+						Some(attempt) => format!("{attempt}, {status}"),
+						None => status,
+					};
+					match self.messages() {
+						ProgressMessages::Status => {}
+						ProgressMessages::Phase => {
+							if let Some(phase) =
+								phases.entry(remote.clone()).or_default().line(&line)
+							{
+								pb.set_phase(status(phase));
+							}
+						}
+						ProgressMessages::LastLine if stream == OutputStream::Stdout => {
+							// remotes can write lines far faster than they're worth redrawing
+							let due = line_shown
+								.get(&remote)
+								.is_none_or(|shown| shown.elapsed() >= LINE_STATUS_INTERVAL);
+							if let Some(line) = line_status(&line).filter(|_| due) {
+								pb.set_phase(status(line));
+								line_shown.insert(remote, Instant::now());
+							}
+						}
+						ProgressMessages::LastLine => {}
+					}
+					continue;
+				}
+				Event::LocalStepStarted { .. } | Event::LocalFinished { .. } => continue,
+			}
+			in_flight
+				.lock()
+				.expect("in flight remotes aren't poisoned")
+				.retain(|remote| bars.contains_key(remote));
+		}
+		#[cfg(unix)]
+		{
+			cancel_listener.abort();
+			jobs_listener.abort();
+		}
+		#[cfg(all(unix, feature = "progress"))]
+		resize_listener.abort();
+
+		#[cfg(feature = "progress")]
+		if let Some(overall) = overall {
+			overall.finish_and_clear();
+		}
+		results.await?
+	}
+}
+
+/// progress bars hidden for a prompt, which are shown again once it's dropped
+struct HiddenBars {
+	/// the hidden progress bars, if progress bars are shown
+	#[cfg(feature = "progress")]
+	multi: Option<MultiProgress>,
+}
+
+impl Drop for HiddenBars {
+	fn drop(&mut self) {
+		#[cfg(feature = "progress")]
+		if let Some(multi) = &self.multi {
+			multi.set_draw_target(ProgressDrawTarget::stderr());
+		}
+	}
+}
+
+/// reads a line typed at a prompt. the read is cancelled along with the prompt, so a prompt left unanswered when the
+/// run finishes doesn't hold it up
+async fn read_answer() -> std::io::Result<String> {
+	let mut line = String::new();
+	tokio::io::BufReader::new(tokio::io::stdin())
+		.read_line(&mut line)
+		.await?;
+	Ok(line)
+}
+
+/// prompts for a remote to cancel out of the ones still in flight, cancelling it
+async fn prompt_cancel(
+	progress: Progress,
+	in_flight: Arc<Mutex<Vec<String>>>,
+	canceller: Canceller,
+) -> eyre::Result<()> {
+	let _hidden = progress.hide();
+	let running = in_flight
+		.lock()
+		.expect("in flight remotes aren't poisoned")
+		.clone();
+	if running.is_empty() {
+		println!("no remotes are in flight");
+		return Ok(());
+	}
+	println!("remotes in flight:");
+	for (i, remote) in running.iter().enumerate() {
+		println!("{}: {remote}", i + 1);
+	}
+	print!("remote to cancel (blank to continue): ");
+	std::io::stdout().flush()?;
+	let line = read_answer().await?;
+	let line = line.trim();
+	if line.is_empty() {
+		return Ok(());
+	}
+	match line
+		.parse::<usize>()
+		.ok()
+		.and_then(|i| running.get(i.wrapping_sub(1)))
+		.or_else(|| running.iter().find(|remote| *remote == line))
+	{
+		Some(remote) => {
+			canceller.cancel(remote);
+			println!("cancelling {remote}");
+		}
+		None => println!("no remote in flight matches {line}"),
+	}
+	Ok(())
+}
+
+/// parses a new limit on how many remotes are pupdated at once, either a number or a change to the current limit like
+/// `+2` or `-1`, which can't go below one
+pub fn parse_job_limit(line: &str, current: usize) -> Option<NonZeroUsize> {
+	let line = line.trim();
+	let jobs = if let Some(more) = line.strip_prefix('+') {
+		current.checked_add(more.parse().ok()?)?
+	} else if let Some(fewer) = line.strip_prefix('-') {
+		current.saturating_sub(fewer.parse().ok()?).max(1)
+	} else {
+		line.parse().ok()?
+	};
+	NonZeroUsize::new(jobs)
+}
+
+/// prompts for a new limit on how many remotes are pupdated at once, showing how many are in flight against the
+/// current limit
+async fn prompt_jobs(progress: Progress, job_limit: Option<JobLimit>) -> eyre::Result<()> {
+	let _hidden = progress.hide();
+	let Some(job_limit) = job_limit else {
+		println!("this run has no limit on how many remotes are pupdated at once to change");
+		return Ok(());
+	};
+	let current = job_limit.limit();
+	println!(
+		"{} of at most {current} remotes in flight",
+		job_limit.in_use()
+	);
+	print!("new limit, or +N/-N to change it (blank to continue): ");
+	std::io::stdout().flush()?;
+	let line = read_answer().await?;
+	if line.trim().is_empty() {
+		return Ok(());
+	}
+	match parse_job_limit(&line, current) {
+		Some(jobs) => {
+			job_limit.set_limit(jobs);
+			println!("pupdating at most {jobs} remotes at once");
+		}
+		None => println!("{} isn't a limit", line.trim()),
+	}
+	Ok(())
+}
+
+/// a remote's progress bar along with what's needed to finish it
+#[cfg(feature = "progress")]
+struct RemoteBar {
+	/// the remote's own progress bar
+	pb: ProgressBar,
+	/// the overall progress bar, advanced when the remote finishes
+	overall: ProgressBar,
+	/// the styles to finish the remote's progress bar with
+	styles: Box<ProgressStyles>,
+}
+
+/// the progress of a single remote, which works the same whether or not progress bars are shown
+struct RemoteProgress {
+	/// the remote whose progress is shown
+	remote: String,
+	/// the remote's progress bar, if progress bars are shown
+	#[cfg(feature = "progress")]
+	bar: Option<RemoteBar>,
+}
+
+impl RemoteProgress {
+	/// sets the remote's current status
+	fn set_message(&self, message: impl Into<Cow<'static, str>>) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = &self.bar {
+			return bar.pb.set_message(message);
+		}
+		println!("{}: {}", self.remote, message.into());
+	}
+
+	/// sets the remote's current phase, which is only shown on progress bars since a printed line for every package
+	/// would bury everything else
+	fn set_phase(&self, status: String) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = &self.bar {
+			bar.pb.set_message(status);
+		}
+		#[cfg(not(feature = "progress"))]
+		let _ = status;
+	}
+
+	/// prints a line without disturbing the progress bars
+	fn println(&self, line: impl AsRef<str>) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = &self.bar {
+			return bar.pb.println(line);
+		}
+		println!("{}", line.as_ref());
+	}
+
+	/// finishes the remote with its final status, marked by whether it succeeded
+	fn finish(&self, success: bool, message: impl Into<Cow<'static, str>>) {
+		#[cfg(feature = "progress")]
+		if let Some(bar) = &self.bar {
+			bar.styles.finish(&bar.pb, success, message);
+			bar.overall.inc(1);
+			return;
+		}
+		#[cfg(not(feature = "progress"))]
+		let _ = success;
+		println!("{}: {}", self.remote, message.into());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn presets_are_valid() {
+		for preset in [
+			ProgressPreset::Default,
+			ProgressPreset::Compact,
+			ProgressPreset::Verbose,
+		] {
+			ProgressStyles::new(preset, ProgressSettings::default()).unwrap();
+		}
+	}
+
+	#[test]
+	fn bad_templates_are_rejected() {
+		assert!(check_template("{prefix:.bold.dim} {{literal}} {wide_msg}").is_ok());
+		assert!(check_template("{prefix:.bold.dim {spinner}").is_err());
+		assert!(check_template("{prefix} }").is_err());
+		assert!(check_template("{prefx}").is_err());
+		assert!(check_template("{wide_msg} {wide_bar}").is_err());
+	}
+
+	#[test]
+	fn lines_are_cleaned_up_for_statuses() {
+		assert_eq!(
+			line_status("\x1b[32mUnpacking linux-image-6.8.0 (6.8.0-45.45) ...\x1b[0m\r")
+				.as_deref(),
+			Some("Unpacking linux-image-6.8.0 (6.8.0-45.45) ...")
+		);
+		assert_eq!(line_status(" \t\r"), None);
+		let status = line_status(&"x".repeat(500)).unwrap();
+		assert_eq!(status.chars().count(), MAX_LINE_STATUS);
+		assert!(status.ends_with('…'));
+	}
+
+	#[cfg(feature = "progress")]
+	#[test]
+	fn layouts_fit_the_terminal() {
+		assert_eq!(Layout::for_width(None), Layout::Full);
+		assert_eq!(Layout::for_width(Some(160)), Layout::Full);
+		assert_eq!(Layout::for_width(Some(80)), Layout::Narrow);
+		assert_eq!(Layout::for_width(Some(40)), Layout::Lines);
+	}
+
+	#[test]
+	fn job_limits_are_parsed() {
+		assert_eq!(parse_job_limit("8\n", 4), NonZeroUsize::new(8));
+		assert_eq!(parse_job_limit("+2", 4), NonZeroUsize::new(6));
+		assert_eq!(parse_job_limit("-2", 4), NonZeroUsize::new(2));
+		assert_eq!(parse_job_limit("-10", 4), NonZeroUsize::new(1));
+		assert_eq!(parse_job_limit("0", 4), None);
+		assert_eq!(parse_job_limit("lots", 4), None);
+	}
+}
@@ -0,0 +1,2268 @@
+use std::{
+	collections::BTreeMap,
+	ffi::OsString,
+	future::Future,
+	num::NonZeroUsize,
+	path::PathBuf,
+	process::{ExitStatus, Output, Stdio},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncRead, BufReader},
+	process::Command,
+	sync::{
+		mpsc::{self, UnboundedReceiver, UnboundedSender},
+		Notify, OwnedSemaphorePermit, Semaphore,
+	},
+	task::{AbortHandle, JoinHandle},
+};
+
+use crate::{
+	config::Config,
+	diagnostics::{capture_diagnostics, DiagnosticCommands, DIAGNOSTICS_FILENAME},
+	events::{describe_skew, emit, tee, Event, OutputStream},
+	fetch::fetch_remote_logs,
+	forward::Forwarding,
+	logs::{
+		attempt_marker, sanitize_file_name, LogFormats, LogHeader, LogLayout, LogPathTemplate,
+		LogPaths, LogWriter, StreamLog, REMOTE_LOG_DIR,
+	},
+	offline::{install_offline, OfflinePackages},
+	output::{
+		apt_upgraded, kept_back, mirror_hosts, output_tail, repo_warnings, reported_log_dir,
+		reported_up_to_date, sudo_needs_password, CappedOutput, LimitedOutput, OutputLimits,
+	},
+	retry::RetryPolicy,
+	services::{restart_services, services_needing_restart, ServicesPolicy},
+	shell::{command_line, merge_stderr, quote, RemoteCommand},
+	silence::{SilenceFailurePolicy, Silencer},
+	state::StateFile,
+	summary::{Failure, FailureKind, TargetSummary},
+	verify::{verify_binary, BinaryCheck, VerificationResult},
+	webhook::{RemoteReport, Webhook},
+};
+
+/// the exit code ssh uses when it couldn't connect to or authenticate with a remote
+const SSH_CONNECTION_ERROR: i32 = 255;
+/// what ssh says when the connection through a jump host closed before the remote was reached, since it isn't a socket
+const JUMP_CLOSED_ERROR: &str = "Connection closed by UNKNOWN port 65535";
+/// what ssh says when the jump host was reached but couldn't reach the remote
+const JUMP_FORWARDING_ERRORS: &[&str] = &["stdio forwarding failed", "open failed"];
+/// the longest line of output passed on as an event, so a runaway line can't grow without bound
+const MAX_LINE_BYTES: usize = 4096;
+/// the signal commands which ran longer than their timeout are reported as killed by
+#[cfg(unix)]
+const SIGKILL: i32 = 9;
+/// the environment variables inherited by ssh which change how it connects, recorded with what it ran
+const SSH_ENVIRONMENT: &[&str] = &[
+	"SSH_AUTH_SOCK",
+	"SSH_ASKPASS",
+	"SSH_ASKPASS_REQUIRE",
+	"DISPLAY",
+	"HOME",
+	"USER",
+];
+/// parts of environment variable names whose values are masked when they're recorded
+const SECRET_NAMES: &[&str] = &["PASS", "SECRET", "TOKEN", "KEY", "CREDENTIAL"];
+
+/// exactly what a runner spawns to run a command on a remote, recorded so a command which works by hand but not under
+/// pupdate can be compared against it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invocation {
+	/// how the command reaches the remote, like `subprocess` for a spawned ssh
+	pub transport: String,
+	/// the full argv spawned, starting with the program
+	pub argv: Vec<String>,
+	/// the environment variables which were set or inherited and matter to the transport, with secret values masked,
+	/// and `None` for variables which were cleared
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub env: BTreeMap<String, Option<String>>,
+}
+
+impl Invocation {
+	/// records a command which is about to be spawned, along with the given variables it inherits
+	pub fn of(command: &Command, transport: &str, inherited: &[&str]) -> Self {
+		let command = command.as_std();
+		let argv = std::iter::once(command.get_program())
+			.chain(command.get_args())
+			.map(|arg| arg.to_string_lossy().into_owned())
+			.collect();
+		let mut env: BTreeMap<_, _> = inherited
+			.iter()
+			.filter_map(|name| Some((name.to_string(), Some(std::env::var(name).ok()?))))
+			.collect();
+		for (name, value) in command.get_envs() {
+			let value = value.map(|value| value.to_string_lossy().into_owned());
+			env.insert(name.to_string_lossy().into_owned(), value);
+		}
+		for (name, value) in &mut env {
+			if let Some(value) = value.as_mut() {
+				if SECRET_NAMES
+					.iter()
+					.any(|secret| name.to_uppercase().contains(secret))
+				{
+					*value = "***".to_string();
+				}
+			}
+		}
+		Self {
+			transport: transport.to_string(),
+			argv,
+			env,
+		}
+	}
+
+	/// the lines describing the invocation, for log headers and the terminal
+	pub fn lines(&self) -> Vec<String> {
+		let mut lines = vec![format!("{}: {}", self.transport, command_line(&self.argv))];
+		for (name, value) in &self.env {
+			lines.push(match value {
+				Some(value) => format!("env: {name}={}", quote(value)),
+				None => format!("env: {name} cleared"),
+			});
+		}
+		lines
+	}
+}
+
+/// runs commands on remotes
+pub trait Runner: Send + Sync + 'static {
+	/// runs a shell command on the given remote, returning its output
+	fn run(
+		&self,
+		remote: &str,
+		command: &str,
+	) -> impl Future<Output = std::io::Result<Output>> + Send;
+
+	/// runs a shell command on the given remote like [`Runner::run`], calling `on_line` with each line of output and
+	/// keeping the output within the given limits, which by default happens once the command has finished. the command
+	/// is killed if it runs longer than the timeout, which by default loses its output
+	fn run_streaming(
+		&self,
+		remote: &str,
+		command: &str,
+		limits: OutputLimits,
+		timeout: Option<Duration>,
+		on_line: impl Fn(OutputStream, String) + Send + Sync,
+	) -> impl Future<Output = std::io::Result<LimitedOutput>> + Send {
+		async move {
+			let run = self.run(remote, command);
+			let output = match timeout {
+				Some(timeout) => match tokio::time::timeout(timeout, run).await {
+					Ok(output) => output?,
+					Err(_) => {
+						return Ok(LimitedOutput {
+							output: Output {
+								status: killed_status(),
+								stdout: Vec::new(),
+								stderr: Vec::new(),
+							},
+							truncated: false,
+							limit_exceeded: false,
+							timed_out: true,
+						})
+					}
+				},
+				None => run.await?,
+			};
+			for line in String::from_utf8_lossy(&output.stdout).lines() {
+				on_line(OutputStream::Stdout, line.to_string());
+			}
+			for line in String::from_utf8_lossy(&output.stderr).lines() {
+				on_line(OutputStream::Stderr, line.to_string());
+			}
+			Ok(LimitedOutput::from_output(output, limits))
+		}
+	}
+
+	/// the jump host the given remote is reached through, if it's reached through one
+	fn jump_host(&self, remote: &str) -> Option<String> {
+		let _ = remote;
+		None
+	}
+
+	/// describes what running a shell command on the given remote spawns, if the runner spawns anything
+	fn invocation(&self, remote: &str, command: &str) -> Option<Invocation> {
+		let _ = (remote, command);
+		None
+	}
+
+	/// copies local files into a directory on the given remote, which isn't supported by default
+	fn push(
+		&self,
+		remote: &str,
+		files: &[PathBuf],
+		remote_dir: &str,
+	) -> impl Future<Output = std::io::Result<Output>> + Send {
+		let _ = (remote, files, remote_dir);
+		async {
+			Err(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				"this runner can't copy files to remotes",
+			))
+		}
+	}
+}
+
+/// how ssh verifies the host keys of remotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+	/// only connect to remotes whose host keys are already known
+	Strict,
+	/// add the host keys of new remotes, but refuse changed host keys
+	AcceptNew,
+	/// connect to any remote without verifying or saving its host key
+	Insecure,
+}
+
+impl HostKeyPolicy {
+	/// the ssh options implementing this policy
+	fn ssh_options(self) -> &'static [&'static str] {
+		match self {
+			Self::Strict => &["-o", "StrictHostKeyChecking=yes"],
+			Self::AcceptNew => &["-o", "StrictHostKeyChecking=accept-new"],
+			Self::Insecure => &[
+				"-o",
+				"StrictHostKeyChecking=no",
+				"-o",
+				"UserKnownHostsFile=/dev/null",
+			],
+		}
+	}
+}
+
+/// how to reach a remote over ssh, for remotes given with more than ssh's destination
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SshTarget {
+	/// the host to connect to
+	pub host: String,
+	/// the user to log in as, leaving it to ssh's own config if unset
+	#[serde(default)]
+	pub user: Option<String>,
+	/// the port to connect to, leaving it to ssh's own config if unset
+	#[serde(default)]
+	pub port: Option<u16>,
+	/// the private key to authenticate with, leaving it to ssh's own config if unset
+	#[serde(default)]
+	pub identity_file: Option<PathBuf>,
+	/// the jump host the target is reached through instead of the config's, like `bastion.example.com` or a chain like
+	/// `admin@bastion1,bastion2:2222`, or `none` to connect directly
+	#[serde(default)]
+	pub jump_host: Option<String>,
+}
+
+impl SshTarget {
+	/// merges a later config's target for the same remote on top of this one, with each field it sets winning
+	pub fn merge(&mut self, other: Self) {
+		self.host = other.host;
+		self.user = other.user.or(self.user.take());
+		self.port = other.port.or(self.port);
+		self.identity_file = other.identity_file.or(self.identity_file.take());
+		self.jump_host = other.jump_host.or(self.jump_host.take());
+	}
+
+	/// the ssh options connecting to the target, to go before its host
+	fn ssh_args(&self) -> Vec<OsString> {
+		let mut args = Vec::new();
+		if let Some(user) = &self.user {
+			args.extend(["-l".into(), user.into()]);
+		}
+		if let Some(port) = self.port {
+			args.extend(["-p".into(), port.to_string().into()]);
+		}
+		if let Some(identity_file) = &self.identity_file {
+			args.extend(["-i".into(), identity_file.into()]);
+		}
+		args
+	}
+
+	/// the scp options connecting to the target, which has its port as `-P` and its user in the destination instead
+	fn scp_args(&self) -> Vec<OsString> {
+		let mut args = Vec::new();
+		if let Some(port) = self.port {
+			args.extend(["-P".into(), port.to_string().into()]);
+		}
+		if let Some(identity_file) = &self.identity_file {
+			args.extend(["-i".into(), identity_file.into()]);
+		}
+		args
+	}
+
+	/// the target as scp's destination, with its user if it has one
+	fn scp_destination(&self) -> String {
+		match &self.user {
+			Some(user) => format!("{user}@{}", self.host),
+			None => self.host.clone(),
+		}
+	}
+}
+
+/// the remote standing for the controller itself, pupdated like any other remote by running the remote command on the
+/// controller with `--local-only` instead of over ssh
+pub const LOCAL_REMOTE: &str = "@local";
+
+/// the jump host telling ssh to connect to a remote directly, even if its own config has a jump host for it
+pub const NO_JUMP_HOST: &str = "none";
+
+/// how many seconds best effort remotes are given to connect unless the config says otherwise
+pub const BEST_EFFORT_CONNECT_TIMEOUT: u64 = 10;
+
+/// runs pupdates on remotes through ssh
+/// TODO: build pupdate daemon and pupdate through that instead
+#[derive(Debug)]
+pub struct SshRunner {
+	/// the ssh executable to use
+	pub ssh: PathBuf,
+	/// the scp executable used to copy files to remotes
+	pub scp: PathBuf,
+	/// how host keys are verified, leaving it to ssh's own config if unset
+	pub host_key_policy: Option<HostKeyPolicy>,
+	/// how many seconds ssh waits to connect to particular remotes before giving up, by remote, leaving it to ssh's own
+	/// config for remotes missing here
+	pub connect_timeouts: BTreeMap<String, u64>,
+	/// how to reach particular remotes, by remote, where remotes missing here are given to ssh as they are
+	pub targets: BTreeMap<String, SshTarget>,
+	/// the jump host remotes are reached through unless they have their own, leaving it to ssh's own config if unset
+	pub jump_host: Option<String>,
+}
+
+impl SshRunner {
+	/// sets up a runner from the config, with the given host key policy from the command line taking priority over the
+	/// config's
+	pub fn from_config(config: &Config, host_key_policy: Option<HostKeyPolicy>) -> Self {
+		let timeout = config
+			.best_effort_connect_timeout
+			.unwrap_or(BEST_EFFORT_CONNECT_TIMEOUT);
+		Self {
+			ssh: config
+				.ssh_binary
+				.clone()
+				.unwrap_or_else(|| PathBuf::from("ssh")),
+			scp: config
+				.scp_binary
+				.clone()
+				.unwrap_or_else(|| PathBuf::from("scp")),
+			host_key_policy: host_key_policy.or(config.host_key_policy),
+			// best effort remotes are given up on sooner when they can't be reached
+			connect_timeouts: config
+				.remote_best_effort
+				.iter()
+				.filter(|(_, best_effort)| **best_effort)
+				.map(|(remote, _)| (remote.clone(), timeout))
+				.collect(),
+			targets: config.remote_targets(),
+			jump_host: config.jump_host.clone(),
+		}
+	}
+
+	/// the jump host a remote is reached through, if it's reached through one that pupdate knows of
+	fn jump_host_for(&self, remote: &str) -> Option<&str> {
+		self.jump_host_arg(remote)
+			.filter(|jump_host| *jump_host != NO_JUMP_HOST)
+	}
+
+	/// what ssh and scp are given as a remote's jump host with `-J`, including `none`, which is how they're told to
+	/// ignore a jump host from ssh's own config
+	fn jump_host_arg(&self, remote: &str) -> Option<&str> {
+		self.targets
+			.get(remote)
+			.and_then(|target| target.jump_host.as_deref())
+			.or(self.jump_host.as_deref())
+	}
+
+	/// the ssh options for reaching a remote, to go before its destination
+	pub fn ssh_args(&self, remote: &str) -> Vec<OsString> {
+		let mut args: Vec<OsString> = Vec::new();
+		if let Some(policy) = self.host_key_policy {
+			args.extend(policy.ssh_options().iter().map(Into::into));
+		}
+		if let Some(timeout) = self.connect_timeouts.get(remote) {
+			args.extend(["-o".into(), format!("ConnectTimeout={timeout}").into()]);
+		}
+		if let Some(target) = self.targets.get(remote) {
+			args.extend(target.ssh_args());
+		}
+		if let Some(jump_host) = self.jump_host_arg(remote) {
+			args.extend(["-J".into(), jump_host.into()]);
+		}
+		args
+	}
+
+	/// what ssh is given as the remote's destination
+	pub fn destination<'a>(&'a self, remote: &'a str) -> &'a str {
+		self.targets
+			.get(remote)
+			.map_or(remote, |target| target.host.as_str())
+	}
+
+	/// builds the ssh command for running a shell command on a remote, or the shell command for running it on the
+	/// controller for [`LOCAL_REMOTE`]
+	fn command(&self, remote: &str, command: &str) -> Command {
+		let mut ssh = if remote == LOCAL_REMOTE {
+			let mut sh = Command::new("sh");
+			sh.arg("-c").arg(command);
+			sh
+		} else {
+			// the remote comes after `--` so a remote starting with `-` can't be taken as an option
+			let mut ssh = Command::new(&self.ssh);
+			ssh.args(self.ssh_args(remote))
+				.arg("--")
+				.arg(self.destination(remote))
+				.arg(command);
+			ssh
+		};
+		// stdin is closed for remotes so anything waiting on input (sudo password, debconf prompts) fails fast instead of
+		// hanging forever, while the local system keeps the controller's like it would when pupdated on its own
+		let stdin = if remote == LOCAL_REMOTE {
+			Stdio::inherit()
+		} else {
+			Stdio::null()
+		};
+		ssh.stdin(stdin).kill_on_drop(true);
+		ssh
+	}
+
+	/// builds the scp command for copying files into a directory on a remote
+	fn scp_command(&self, remote: &str, files: &[PathBuf], remote_dir: &str) -> Command {
+		let mut scp = Command::new(&self.scp);
+		if let Some(policy) = self.host_key_policy {
+			scp.args(policy.ssh_options());
+		}
+		let destination = match self.targets.get(remote) {
+			Some(target) => {
+				scp.args(target.scp_args());
+				target.scp_destination()
+			}
+			None => remote.to_string(),
+		};
+		if let Some(jump_host) = self.jump_host_arg(remote) {
+			scp.arg("-J").arg(jump_host);
+		}
+		// scp interprets the remote path with the remote shell too, so it's quoted like any other command
+		scp.arg("-q")
+			.arg("--")
+			.args(files)
+			.arg(format!("{destination}:{}/", quote(remote_dir)))
+			.stdin(Stdio::null())
+			.kill_on_drop(true);
+		scp
+	}
+}
+
+impl Runner for SshRunner {
+	fn run(
+		&self,
+		remote: &str,
+		command: &str,
+	) -> impl Future<Output = std::io::Result<Output>> + Send {
+		self.command(remote, command).output()
+	}
+
+	fn jump_host(&self, remote: &str) -> Option<String> {
+		(remote != LOCAL_REMOTE)
+			.then(|| self.jump_host_for(remote))
+			.flatten()
+			.map(str::to_string)
+	}
+
+	fn invocation(&self, remote: &str, command: &str) -> Option<Invocation> {
+		Some(Invocation::of(
+			&self.command(remote, command),
+			"subprocess",
+			SSH_ENVIRONMENT,
+		))
+	}
+
+	async fn run_streaming(
+		&self,
+		remote: &str,
+		command: &str,
+		limits: OutputLimits,
+		timeout: Option<Duration>,
+		on_line: impl Fn(OutputStream, String) + Send + Sync,
+	) -> std::io::Result<LimitedOutput> {
+		let mut child = self
+			.command(remote, command)
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()?;
+		let stdout = child.stdout.take().expect("stdout is piped");
+		let stderr = child.stderr.take().expect("stderr is piped");
+		let exceeded = Notify::new();
+		let reading = async {
+			tokio::try_join!(
+				read_lines(stdout, OutputStream::Stdout, limits, &exceeded, &on_line),
+				read_lines(stderr, OutputStream::Stderr, limits, &exceeded, &on_line),
+			)
+		};
+		tokio::pin!(reading);
+		// the timeout covers waiting for the command to exit as well as reading its output, since it can close its
+		// output and carry on running
+		let expired = async {
+			match timeout {
+				Some(timeout) => tokio::time::sleep(timeout).await,
+				None => std::future::pending().await,
+			}
+		};
+		tokio::pin!(expired);
+		let mut timed_out = false;
+		let ((stdout, stdout_exceeded), (stderr, stderr_exceeded)) = tokio::select! {
+			result = &mut reading => result?,
+			() = exceeded.notified() => {
+				// killing ssh closes the other stream too, so reading it finishes
+				child.start_kill()?;
+				reading.await?
+			}
+			() = &mut expired => {
+				timed_out = true;
+				child.start_kill()?;
+				reading.await?
+			}
+		};
+		let status = tokio::select! {
+			status = child.wait() => status?,
+			() = &mut expired, if !timed_out => {
+				timed_out = true;
+				child.start_kill()?;
+				child.wait().await?
+			}
+		};
+		let (stdout, stdout_truncated) = stdout.finish();
+		let (stderr, stderr_truncated) = stderr.finish();
+		Ok(LimitedOutput {
+			output: Output {
+				status,
+				stdout,
+				stderr,
+			},
+			truncated: stdout_truncated || stderr_truncated,
+			limit_exceeded: stdout_exceeded || stderr_exceeded,
+			timed_out,
+		})
+	}
+
+	fn push(
+		&self,
+		remote: &str,
+		files: &[PathBuf],
+		remote_dir: &str,
+	) -> impl Future<Output = std::io::Result<Output>> + Send {
+		if remote == LOCAL_REMOTE {
+			let mut cp = Command::new("cp");
+			cp.arg("--")
+				.args(files)
+				.arg(format!("{remote_dir}/"))
+				.stdin(Stdio::null())
+				.kill_on_drop(true);
+			return cp.output();
+		}
+		self.scp_command(remote, files, remote_dir).output()
+	}
+}
+
+/// reads everything from a stream within the limits, calling `on_line` with each line as it arrives, and returns what
+/// was kept along with whether reading stopped early for going over the kill limit, notifying `exceeded` if it did
+async fn read_lines(
+	stream: impl AsyncRead + Unpin,
+	kind: OutputStream,
+	limits: OutputLimits,
+	exceeded: &Notify,
+	on_line: &(impl Fn(OutputStream, String) + Sync),
+) -> std::io::Result<(CappedOutput, bool)> {
+	let emit_line = |line: &[u8]| {
+		let line = String::from_utf8_lossy(line);
+		on_line(kind, line.trim_end_matches(['\r', '\n']).to_string());
+	};
+	let mut reader = BufReader::new(stream);
+	let mut output = CappedOutput::new(limits.limit);
+	let mut line = Vec::new();
+	loop {
+		let buf = reader.fill_buf().await?;
+		if buf.is_empty() {
+			if !line.is_empty() {
+				emit_line(&line);
+			}
+			return Ok((output, false));
+		}
+		output.push(buf);
+		for chunk in buf.split_inclusive(|&b| b == b'\n') {
+			let room = MAX_LINE_BYTES.saturating_sub(line.len()).min(chunk.len());
+			line.extend_from_slice(&chunk[..room]);
+			if chunk.ends_with(b"\n") {
+				emit_line(&line);
+				line.clear();
+			}
+		}
+		let len = buf.len();
+		reader.consume(len);
+		if limits.exceeded(output.total()) {
+			exceeded.notify_one();
+			return Ok((output, true));
+		}
+	}
+}
+
+/// the status reported for a command killed for running longer than its timeout
+fn killed_status() -> ExitStatus {
+	#[cfg(unix)]
+	return std::os::unix::process::ExitStatusExt::from_raw(SIGKILL);
+	#[cfg(windows)]
+	return std::os::windows::process::ExitStatusExt::from_raw(1);
+}
+
+/// classifies how a command run over ssh failed, if it did
+pub fn ssh_failure(status: ExitStatus) -> Option<Failure> {
+	if status.success() {
+		return None;
+	}
+	#[cfg(unix)]
+	let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+	#[cfg(not(unix))]
+	let signal = None;
+	let exit_code = status.code();
+	let kind = match (exit_code, signal) {
+		(_, Some(_)) => FailureKind::Signal,
+		(Some(SSH_CONNECTION_ERROR), _) => FailureKind::Connection,
+		_ => FailureKind::Command,
+	};
+	Some(Failure {
+		kind,
+		exit_code,
+		signal,
+	})
+}
+
+/// whether what ssh wrote to stderr says it couldn't connect to or authenticate with one of the given jump hosts,
+/// rather than the remote behind them
+pub fn jump_host_failed(stderr: &[u8], jump_host: &str) -> bool {
+	let stderr = String::from_utf8_lossy(stderr);
+	// the last jump host got as far as trying the remote itself
+	if JUMP_FORWARDING_ERRORS
+		.iter()
+		.any(|error| stderr.contains(error))
+	{
+		return false;
+	}
+	// jump hosts look like `[user@]host[:port]`, optionally as an `ssh://` url
+	let hosts: Vec<_> = jump_host
+		.split(',')
+		.map(|jump| {
+			let jump = jump.trim().trim_start_matches("ssh://");
+			let host = jump.rsplit_once('@').map_or(jump, |(_, host)| host);
+			host.split_once(':').map_or(host, |(host, _)| host)
+		})
+		.collect();
+	stderr.contains(JUMP_CLOSED_ERROR)
+		|| stderr.split_whitespace().any(|word| {
+			let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+			let word = word.rsplit_once('@').map_or(word, |(_, host)| host);
+			hosts.contains(&word)
+		})
+}
+
+/// what to do with remotes whose clocks are too far off from the controller's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewPolicy {
+	/// warn about the skew and pupdate the remote anyway
+	Warn,
+	/// warn about the skew and skip the remote, since apt will likely reject its release files
+	Skip,
+}
+
+/// how remotes' clocks are checked against the controller's before pupdating them
+#[derive(Debug, Clone, Copy)]
+pub struct SkewCheck {
+	/// what to do with remotes whose clocks are too far off
+	pub policy: SkewPolicy,
+	/// how far off a remote's clock can be before it's considered skewed
+	pub threshold: Duration,
+}
+
+/// measures how many seconds the remote's clock is ahead of the controller's, or behind if negative
+async fn clock_skew<R: Runner>(runner: &R, remote: &str) -> Option<i64> {
+	let before = SystemTime::now();
+	let output = runner.run(remote, "date +%s").await.ok()?;
+	let after = SystemTime::now();
+	if !output.status.success() {
+		return None;
+	}
+	let remote_time: i64 = String::from_utf8_lossy(&output.stdout)
+		.trim()
+		.parse()
+		.ok()?;
+	// the remote read its clock somewhere between sending the command and the output arriving, so the midpoint is the
+	// best guess for the controller's time at that moment
+	let local_time = before + after.duration_since(before).unwrap_or_default() / 2;
+	// `date +%s` truncates to whole seconds, so the controller's time is truncated too
+	let local_time = local_time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+	Some(remote_time - local_time)
+}
+
+/// options shared by every remote pupdate in a run
+#[derive(Debug, Clone)]
+pub struct RemoteOptions {
+	/// the command run on remotes to pupdate them
+	pub command: RemoteCommand,
+	/// the command run instead of `command` to pupdate the controller when it's listed as [`LOCAL_REMOTE`], such as
+	/// this pupdate binary with the configs it was given
+	pub local_command: Option<RemoteCommand>,
+	/// the run directory to log to, if any
+	pub log_dir: Option<PathBuf>,
+	/// how logs are laid out inside the run directory
+	pub log_layout: LogLayout,
+	/// which files remotes' logs are written to and how, unless their output is merged
+	pub log_formats: LogFormats,
+	/// the names particular remotes' logs are written under instead of the remote itself
+	pub log_names: BTreeMap<String, String>,
+	/// where remotes' logs are written instead of the run directory, if anywhere
+	pub log_template: Option<LogPathTemplate>,
+	/// at most how long lines written to the logs wait before they're flushed
+	pub log_flush_interval: Duration,
+	/// the log every target's lines are appended to as they arrive, if there is one
+	pub stream_log: Option<StreamLog>,
+	/// whether to report the package mirrors contacted
+	pub report_sources: bool,
+	/// whether to fetch the remote's own pupdate logs into the local run directory
+	pub fetch_logs: bool,
+	/// whether to have the remote gzip its logs before fetching them
+	pub fetch_compressed: bool,
+	/// whether to keep each remote's stdout in its summary
+	pub capture_stdout: bool,
+	/// how failed pupdates are retried
+	pub retry: RetryPolicy,
+	/// packages to push and install instead of running the command, for remotes without internet access
+	pub offline: Option<OfflinePackages>,
+	/// how remotes' clocks are checked before pupdating them, if they are
+	pub skew_check: Option<SkewCheck>,
+	/// what to do about services which need restarting after a successful pupdate, if they're checked
+	pub services: Option<ServicesPolicy>,
+	/// how much of each remote's output is kept
+	pub output_limits: OutputLimits,
+	/// whether to have remotes write stderr to stdout, logging both to a single file in the order they were written
+	pub merge_output: bool,
+	/// how long each remote's pupdate can run before it's killed, if there's a limit
+	pub timeout: Option<Duration>,
+	/// the hashes remotes' binaries are checked against before they're run, if they're checked
+	pub verify_binary: Option<BinaryCheck>,
+	/// the webhook each remote's result is posted to as it finishes, if any
+	pub webhook: Option<Webhook>,
+	/// silences monitoring for each remote while it's pupdated, if there's a silence command
+	pub silencer: Option<Silencer>,
+	/// the controller's flags forwarded to pupdate on remotes, if any are
+	pub forward: Option<Forwarding>,
+	/// the run's state file, updated as each remote starts and finishes, if the run has one
+	pub state: Option<Arc<StateFile>>,
+	/// the change ticket the run was tagged with, recorded in logs and webhook posts, if any
+	pub change_id: Option<String>,
+	/// how many remotes are pupdated at once, or all of them if unset
+	pub jobs: Option<NonZeroUsize>,
+	/// how many remotes sharing infrastructure are pupdated at once, on top of `jobs`
+	pub concurrency_groups: ConcurrencyGroups,
+	/// whether apt warning about a repository's signature fails the remote instead of only being recorded
+	pub fail_on_repo_warnings: bool,
+	/// whether apt keeping back packages fails the remote instead of only being recorded
+	pub fail_on_kept_back: bool,
+	/// the read-only diagnostic commands run on remotes which failed to pupdate, whose output is logged
+	pub diagnostics: DiagnosticCommands,
+	/// where to send every event of the run as well, if anywhere
+	pub events: Option<UnboundedSender<Event>>,
+}
+
+impl RemoteOptions {
+	/// gets the name a remote's logs are written under
+	pub fn log_name(&self, remote: &str) -> String {
+		sanitize_file_name(self.log_names.get(remote).map_or(remote, String::as_str))
+	}
+}
+
+/// limits on how many remotes in a group are pupdated at once, so remotes sharing infrastructure like a hypervisor or
+/// database cluster don't all go down together
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyGroups {
+	/// the most remotes in each group pupdated at once, by group, where groups missing here are unlimited
+	pub limits: BTreeMap<String, NonZeroUsize>,
+	/// the group each remote is in, by remote
+	pub remotes: BTreeMap<String, String>,
+}
+
+/// pupdates a single remote
+async fn pupdate_remote<R: Runner>(
+	runner: Arc<R>,
+	remote: String,
+	options: Arc<RemoteOptions>,
+	events: UnboundedSender<Event>,
+) -> eyre::Result<TargetSummary> {
+	let start = Instant::now();
+	let mut clock_skew_seconds = None;
+	if let Some(check) = options.skew_check {
+		clock_skew_seconds = clock_skew(runner.as_ref(), &remote).await;
+		if let Some(skew) =
+			clock_skew_seconds.filter(|skew| skew.unsigned_abs() > check.threshold.as_secs())
+		{
+			if check.policy == SkewPolicy::Warn {
+				emit(
+					&events,
+					Event::RemoteClockSkewed {
+						remote: remote.clone(),
+						skew_seconds: skew,
+					},
+				);
+			} else {
+				let reason = format!("skipped, {}", describe_skew(skew));
+				emit(
+					&events,
+					Event::RemoteSkipped {
+						remote,
+						reason: reason.clone(),
+					},
+				);
+				return Ok(TargetSummary {
+					clock_skew_seconds,
+					error: Some(reason),
+					..Default::default()
+				});
+			}
+		}
+	}
+	// offline remotes don't run the binary, so there's nothing to verify
+	let binary = match (&options.verify_binary, &options.offline) {
+		(Some(check), None) => verify_binary(runner.as_ref(), &remote, check).await,
+		_ => None,
+	};
+	if let Some(failed) = binary.as_ref().filter(|binary| !binary.passed()) {
+		let reason = match (failed.result, &failed.error) {
+			(VerificationResult::Mismatch, _) => "binary hash mismatch".to_string(),
+			(_, Some(error)) => format!("couldn't verify binary: {error}"),
+			_ => "couldn't verify binary".to_string(),
+		};
+		emit(
+			&events,
+			Event::RemoteSkipped {
+				remote,
+				reason: format!("failed: {reason}"),
+			},
+		);
+		return Ok(TargetSummary {
+			clock_skew_seconds,
+			binary,
+			error: Some(reason),
+			..Default::default()
+		});
+	}
+	// the local system is pupdated by this same pupdate rather than whichever the remote command finds
+	let local_command = options
+		.local_command
+		.as_ref()
+		.filter(|_| remote == LOCAL_REMOTE && options.offline.is_none());
+	let mut command = local_command.unwrap_or(&options.command).clone();
+	let mut flags_omitted = Vec::new();
+	let mut pupdate_version = None;
+	// offline remotes don't run pupdate, so there's nothing to forward flags to
+	if let (Some(forwarding), None) = (&options.forward, &options.offline) {
+		let forwarded = match local_command {
+			Some(_) => forwarding.to_own_pupdate(),
+			None => forwarding.for_remote(runner.as_ref(), &remote).await,
+		};
+		if let Some(reason) = forwarded.reason {
+			emit(
+				&events,
+				Event::RemoteFlagsOmitted {
+					remote: remote.clone(),
+					flags: forwarded.omitted.clone(),
+					reason,
+				},
+			);
+		}
+		for arg in &forwarded.args {
+			command = command.with_arg(arg);
+		}
+		flags_omitted = forwarded.omitted;
+		pupdate_version = forwarded.version.map(|version| version.to_string());
+	}
+	// pupdate on the controller would otherwise go on to pupdate the remotes in its config too
+	if remote == LOCAL_REMOTE && options.offline.is_none() {
+		command = command.with_arg("--local-only");
+	}
+	let command_line = command.to_command_line();
+	let command = if options.merge_output {
+		merge_stderr(&command_line)
+	} else {
+		command_line.clone()
+	};
+	// with merged output, stderr only has ssh's own errors, which come after anything the remote wrote, so its logs
+	// are always written once it's finished
+	let formats = if options.merge_output {
+		LogFormats::default()
+	} else {
+		options.log_formats
+	};
+	// offline remotes run several commands to install their packages rather than a single one
+	let invocation = options
+		.offline
+		.is_none()
+		.then(|| runner.invocation(&remote, &command))
+		.flatten();
+	let header = LogHeader {
+		command: options.offline.is_none().then_some(command_line.as_str()),
+		change_id: options.change_id.as_deref(),
+		invocation: invocation.as_ref(),
+	};
+	let logs = match (&options.log_template, &options.log_dir) {
+		(Some(template), _) => Some(
+			LogPaths::create_from_template(
+				template,
+				&options.log_name(&remote),
+				options.merge_output,
+				formats,
+			)
+			.await?,
+		),
+		(None, Some(log_dir)) => {
+			let dir = log_dir.join(REMOTE_LOG_DIR);
+			let name = options.log_name(&remote);
+			Some(if options.merge_output {
+				LogPaths::create_merged_in(&dir, &name, options.log_layout).await?
+			} else {
+				LogPaths::create_formatted_in(&dir, &name, options.log_layout, formats).await?
+			})
+		}
+		(None, None) => None,
+	};
+	let writer = match &logs {
+		Some(logs) if formats.streams_lines() => Some(
+			LogWriter::start(
+				logs,
+				formats,
+				header,
+				options.output_limits.limit,
+				options.log_flush_interval,
+			)
+			.await?,
+		),
+		_ => None,
+	};
+	// when the remote last wrote anything, to tell remotes which stalled from ones which were busy until the end
+	let last_output = Mutex::new(None);
+	// when the latest attempt started and first wrote anything, since connecting takes up the time before a remote
+	// writes its first line
+	let mut attempt_start;
+	let first_output = Mutex::new(None);
+	let mut retry = 0;
+	let attempts = options.retry.retries + 1;
+	// what earlier attempts wrote, each headed by its marker, so the logs written once the remote has finished keep
+	// them ahead of the last attempt's output
+	let mut earlier_stdout = Vec::new();
+	let mut earlier_stderr = Vec::new();
+	let output = loop {
+		attempt_start = Instant::now();
+		*first_output.lock().unwrap() = None;
+		// with retries, each attempt is marked in the logs so a retry doesn't hide what the attempts before it wrote
+		if attempts > 1 {
+			if let Some(writer) = &writer {
+				writer.attempt(retry + 1, attempts);
+			}
+			let marker = attempt_marker(retry + 1, attempts);
+			earlier_stdout.extend_from_slice(marker.as_bytes());
+			earlier_stderr.extend_from_slice(marker.as_bytes());
+		}
+		let on_line = |stream, line: String| {
+			let now = Instant::now();
+			*last_output.lock().unwrap() = Some(now);
+			first_output.lock().unwrap().get_or_insert(now);
+			if let Some(writer) = &writer {
+				writer.line(stream, line.clone());
+			}
+			if let Some(log) = &options.stream_log {
+				log.line(&remote, stream, &line);
+			}
+			emit(
+				&events,
+				Event::RemoteOutputLine {
+					remote: remote.clone(),
+					stream,
+					line,
+				},
+			)
+		};
+		let output = match &options.offline {
+			Some(packages) => {
+				install_offline(
+					runner.as_ref(),
+					&remote,
+					packages,
+					options.output_limits,
+					options.timeout,
+					options.merge_output,
+					on_line,
+				)
+				.await?
+			}
+			None => {
+				runner
+					.run_streaming(
+						&remote,
+						&command,
+						options.output_limits,
+						options.timeout,
+						on_line,
+					)
+					.await?
+			}
+		};
+		// only connection errors are retried, along with failures before the remote wrote anything, which are most
+		// likely the connection dropping too, since a failing update command will most likely fail the same way again
+		let failure = ssh_failure(output.output.status);
+		let silent = first_output.lock().unwrap().is_none();
+		let retryable = failure.is_some_and(|failure| {
+			failure.kind == FailureKind::Connection
+				|| (silent && !output.timed_out && !output.limit_exceeded)
+		});
+		if !retryable || retry >= options.retry.retries {
+			break output;
+		}
+		earlier_stdout.extend_from_slice(&output.output.stdout);
+		earlier_stderr.extend_from_slice(&output.output.stderr);
+		let delay = options.retry.delay(retry);
+		emit(
+			&events,
+			Event::RemoteRetrying {
+				remote: remote.clone(),
+				delay,
+				attempt: retry + 2,
+				attempts,
+			},
+		);
+		tokio::time::sleep(delay).await;
+		retry += 1;
+	};
+	let duration = start.elapsed();
+	let silent = last_output
+		.into_inner()
+		.unwrap()
+		.map_or(duration, |last: Instant| last.elapsed());
+	let connect = first_output
+		.into_inner()
+		.unwrap()
+		.map(|first: Instant| first.duration_since(attempt_start));
+	// the command only starts once the remote has connected
+	let command_duration = attempt_start
+		.elapsed()
+		.saturating_sub(connect.unwrap_or_default());
+	let LimitedOutput {
+		output,
+		truncated: output_truncated,
+		limit_exceeded,
+		timed_out,
+	} = output;
+	let (success, failure) = if limit_exceeded {
+		(
+			false,
+			Some(Failure {
+				kind: FailureKind::OutputLimit,
+				exit_code: None,
+				signal: None,
+			}),
+		)
+	} else if timed_out {
+		if let Some(timeout) = options.timeout {
+			emit(
+				&events,
+				Event::RemoteTimedOut {
+					remote: remote.clone(),
+					timeout,
+				},
+			);
+		}
+		(
+			false,
+			Some(Failure {
+				kind: FailureKind::Timeout,
+				exit_code: None,
+				signal: None,
+			}),
+		)
+	} else {
+		(output.status.success(), ssh_failure(output.status))
+	};
+	// the remote command failing because of sudo is common enough to be worth telling apart from other failures, as
+	// is a jump host failing rather than the remote behind it
+	let jump_host = runner.jump_host(&remote);
+	let failure = failure.map(|failure| match failure.kind {
+		FailureKind::Connection
+			if jump_host
+				.as_deref()
+				.is_some_and(|jump_host| jump_host_failed(&output.stderr, jump_host)) =>
+		{
+			Failure {
+				kind: FailureKind::JumpHost,
+				..failure
+			}
+		}
+		FailureKind::Command
+			if sudo_needs_password(&output.stderr) || sudo_needs_password(&output.stdout) =>
+		{
+			Failure {
+				kind: FailureKind::SudoPassword,
+				..failure
+			}
+		}
+		_ => failure,
+	});
+	// apt only warns about repositories it can't verify, so a remote can succeed without them having been updated
+	let repo_warnings = repo_warnings(&[&output.stdout, &output.stderr]);
+	let (success, failure) =
+		if success && options.fail_on_repo_warnings && !repo_warnings.is_empty() {
+			(
+				false,
+				Some(Failure {
+					kind: FailureKind::RepoWarnings,
+					exit_code: output.status.code(),
+					signal: None,
+				}),
+			)
+		} else {
+			(success, failure)
+		};
+	// apt doesn't fail over packages it keeps back either, so a remote can go on without them for good
+	let kept_back = kept_back(&output.stdout);
+	let (success, failure) = if success && options.fail_on_kept_back && !kept_back.is_empty() {
+		(
+			false,
+			Some(Failure {
+				kind: FailureKind::KeptBack,
+				exit_code: output.status.code(),
+				signal: None,
+			}),
+		)
+	} else {
+		(success, failure)
+	};
+	// a remote with packages kept back isn't up to date, even if apt had nothing else to do
+	let up_to_date = success && reported_up_to_date(&output.stdout) && kept_back.is_empty();
+	if let Some(writer) = writer {
+		writer.finish().await?;
+	}
+	if let Some(logs) = &logs {
+		earlier_stdout.extend_from_slice(&output.stdout);
+		earlier_stderr.extend_from_slice(&output.stderr);
+		logs.write(header, &earlier_stdout, &earlier_stderr, formats)
+			.await?;
+	}
+	let (fetched_logs, fetch_error) = match (&options.log_dir, options.fetch_logs) {
+		(Some(log_dir), true) => {
+			emit(
+				&events,
+				Event::RemoteFetchingLogs {
+					remote: remote.clone(),
+				},
+			);
+			let local_dir = options.log_layout.path(
+				&log_dir.join(REMOTE_LOG_DIR),
+				&options.log_name(&remote),
+				"remote",
+			);
+			match reported_log_dir(&output.stdout) {
+				Some(remote_dir) => match fetch_remote_logs(
+					runner.as_ref(),
+					&remote,
+					&remote_dir,
+					&local_dir,
+					options.fetch_compressed,
+				)
+				.await
+				{
+					Ok(fetched) => (Some(fetched), None),
+					Err(e) => (None, Some(e.to_string())),
+				},
+				None => (
+					None,
+					Some("the remote didn't report a log directory".to_string()),
+				),
+			}
+		}
+		_ => (None, None),
+	};
+	// diagnostics are written beside the remote's logs, so there's nowhere for them to go without a log directory
+	let diagnostics = match (&options.log_dir, options.diagnostics.for_remote(&remote)) {
+		(Some(log_dir), commands) if !success && !commands.is_empty() => {
+			emit(
+				&events,
+				Event::RemoteCapturingDiagnostics {
+					remote: remote.clone(),
+				},
+			);
+			let path = options.log_layout.path(
+				&log_dir.join(REMOTE_LOG_DIR),
+				&options.log_name(&remote),
+				DIAGNOSTICS_FILENAME,
+			);
+			Some(capture_diagnostics(runner.as_ref(), &remote, commands, &path).await)
+		}
+		_ => None,
+	};
+	let (services_needing_restart, services_restarted, services_error) = match options.services {
+		Some(policy) if success => {
+			emit(
+				&events,
+				Event::RemoteCheckingServices {
+					remote: remote.clone(),
+				},
+			);
+			match services_needing_restart(runner.as_ref(), &remote).await {
+				Ok(services) if policy == ServicesPolicy::Restart && !services.is_empty() => {
+					emit(
+						&events,
+						Event::RemoteRestartingServices {
+							remote: remote.clone(),
+							services: services.clone(),
+						},
+					);
+					match restart_services(runner.as_ref(), &remote, &services).await {
+						Ok(()) => (Some(services), true, None),
+						Err(e) => (Some(services), false, Some(e.to_string())),
+					}
+				}
+				Ok(services) => (Some(services), false, None),
+				Err(e) => (None, false, Some(e.to_string())),
+			}
+		}
+		_ => (None, false, None),
+	};
+	let (stdout_bytes, stderr_bytes) = (output.stdout.len() as u64, output.stderr.len() as u64);
+	emit(
+		&events,
+		Event::RemoteFinished {
+			remote,
+			success,
+			up_to_date,
+			upgraded: apt_upgraded(&output.stdout),
+			kept_back: kept_back.clone(),
+			failure,
+			duration,
+			output_bytes: stdout_bytes + stderr_bytes,
+			logs: logs.clone(),
+		},
+	);
+	let sources = options.report_sources.then(|| mirror_hosts(&output.stdout));
+	// with merged output, what the remote wrote to stderr is in stdout unless ssh failed before running anything
+	let stderr_tail = (!success).then(|| {
+		output_tail(if options.merge_output && output.stderr.is_empty() {
+			&output.stdout
+		} else {
+			&output.stderr
+		})
+	});
+	Ok(TargetSummary {
+		success,
+		duration_seconds: Some(duration.as_secs_f64()),
+		silent_seconds: Some(silent.as_secs_f64()),
+		connect_seconds: connect.map(|connect| connect.as_secs_f64()),
+		command_seconds: Some(command_duration.as_secs_f64()),
+		logs,
+		sources,
+		stderr_tail,
+		fetched_logs,
+		fetch_error,
+		diagnostics,
+		stdout: options.capture_stdout.then_some(output.stdout),
+		failure,
+		clock_skew_seconds,
+		services_needing_restart,
+		services_restarted,
+		services_error,
+		output_truncated,
+		stdout_bytes: Some(stdout_bytes),
+		stderr_bytes: Some(stderr_bytes),
+		binary,
+		command: options.offline.is_none().then_some(command_line),
+		invocation,
+		up_to_date,
+		flags_omitted,
+		pupdate_version,
+		repo_warnings,
+		kept_back,
+		..Default::default()
+	})
+}
+
+/// silences monitoring for a remote, returning why it couldn't be silenced if it's pupdated anyway, or the remote's
+/// result if it's skipped instead
+async fn silence_remote(
+	silencer: &Silencer,
+	remote: &str,
+	events: &UnboundedSender<Event>,
+) -> Result<Option<String>, TargetSummary> {
+	emit(
+		events,
+		Event::RemoteSilencing {
+			remote: remote.to_string(),
+		},
+	);
+	let Err(e) = silencer.silence(remote).await else {
+		return Ok(None);
+	};
+	emit(
+		events,
+		Event::RemoteSilenceFailed {
+			remote: remote.to_string(),
+			error: e.to_string(),
+		},
+	);
+	match silencer.on_failure {
+		SilenceFailurePolicy::Warn => Ok(Some(e.to_string())),
+		SilenceFailurePolicy::Skip => {
+			let reason = format!("couldn't silence monitoring: {e}");
+			emit(
+				events,
+				Event::RemoteSkipped {
+					remote: remote.to_string(),
+					reason: reason.clone(),
+				},
+			);
+			Err(TargetSummary {
+				skipped: Some(reason),
+				silence_error: Some(e.to_string()),
+				..Default::default()
+			})
+		}
+	}
+}
+
+/// expires a remote's silence if it has one, returning why it couldn't be expired if it couldn't
+async fn unsilence_remote(
+	silencer: &Silencer,
+	remote: &str,
+	events: &UnboundedSender<Event>,
+) -> Option<String> {
+	let e = silencer.unsilence(remote).await.err()?;
+	emit(
+		events,
+		Event::RemoteUnsilenceFailed {
+			remote: remote.to_string(),
+			error: e.to_string(),
+		},
+	);
+	Some(e.to_string())
+}
+
+/// pupdates a single remote, silencing monitoring for it while it's pupdated if there's a silence command, recording it
+/// in the run's state, and posting its result to the webhook once it's done if there is one
+async fn pupdate_and_report<R: Runner>(
+	runner: Arc<R>,
+	remote: String,
+	options: Arc<RemoteOptions>,
+	events: UnboundedSender<Event>,
+	queued: Option<Duration>,
+) -> eyre::Result<TargetSummary> {
+	if let Some(state) = &options.state {
+		state.remote_started(&remote);
+	}
+	let silenced = match &options.silencer {
+		Some(silencer) => silence_remote(silencer, &remote, &events).await,
+		None => Ok(None),
+	};
+	let mut result = match silenced {
+		Ok(mut silence_error) => {
+			let mut result =
+				pupdate_remote(runner, remote.clone(), options.clone(), events.clone()).await;
+			// the silence is expired however the pupdate went
+			if let Some(silencer) = &options.silencer {
+				if let Some(e) = unsilence_remote(silencer, &remote, &events).await {
+					silence_error.get_or_insert(e);
+				}
+			}
+			if let Ok(summary) = &mut result {
+				summary.silence_error = silence_error;
+			}
+			result
+		}
+		Err(skipped) => Ok(skipped),
+	};
+	if let (Ok(summary), Some(queued)) = (&mut result, queued) {
+		summary.queued_seconds = Some(queued.as_secs_f64());
+	}
+	if let Some(webhook) = &options.webhook {
+		// a remote's report goes out as soon as it's done instead of waiting for the remotes before it
+		let change_id = options.change_id.as_deref();
+		let posted = match &result {
+			Ok(summary) => {
+				webhook
+					.post(&RemoteReport::new(&remote, summary, change_id))
+					.await
+			}
+			Err(e) => {
+				webhook
+					.post(&RemoteReport::errored(&remote, &e.to_string(), change_id))
+					.await
+			}
+		};
+		if let Err(e) = posted {
+			emit(
+				&events,
+				Event::RemoteWebhookFailed {
+					remote: remote.clone(),
+					error: e.to_string(),
+				},
+			);
+			if let Ok(summary) = &mut result {
+				summary.webhook_error = Some(e.to_string());
+			}
+		}
+	}
+	if let Some(state) = &options.state {
+		match &result {
+			Ok(summary) => state.remote_finished(&remote, summary),
+			Err(e) => state.remote_finished(&remote, &TargetSummary::errored(e)),
+		}
+	}
+	result
+}
+
+/// cancels remotes in a run of [`pupdate_remotes`] by name
+#[derive(Debug, Clone)]
+pub struct Canceller(UnboundedSender<String>);
+
+impl Canceller {
+	/// cancels the given remote if it's still being pupdated
+	pub fn cancel(&self, remote: &str) {
+		// once the run is over there's nothing left to cancel
+		let _ = self.0.send(remote.to_string());
+	}
+}
+
+/// how many remotes a run of [`pupdate_remotes`] pupdates at once, which can be raised or lowered while the run is in
+/// progress. lowering it below how many remotes are in flight doesn't stop any of them, it just keeps new ones from
+/// starting until enough have finished
+#[derive(Debug, Clone)]
+pub struct JobLimit(Arc<JobLimitState>);
+
+/// the state shared by a [`JobLimit`] and the slots taken from it
+#[derive(Debug)]
+struct JobLimitState {
+	/// the free slots
+	slots: Semaphore,
+	/// the limit and how many slots in use are given up once they're freed, from lowering the limit while they were
+	/// in use
+	limit: Mutex<(usize, usize)>,
+}
+
+/// a slot taken from a [`JobLimit`], freed when it's dropped unless the limit was lowered while it was in use
+#[derive(Debug)]
+struct JobSlot(Arc<JobLimitState>);
+
+impl Drop for JobSlot {
+	fn drop(&mut self) {
+		let mut limit = self.0.limit.lock().unwrap();
+		if limit.1 > 0 {
+			limit.1 -= 1;
+		} else {
+			self.0.slots.add_permits(1);
+		}
+	}
+}
+
+impl JobLimit {
+	/// creates a limit of the given number of remotes at once
+	pub fn new(jobs: NonZeroUsize) -> Self {
+		Self(Arc::new(JobLimitState {
+			slots: Semaphore::new(jobs.get()),
+			limit: Mutex::new((jobs.get(), 0)),
+		}))
+	}
+
+	/// gets the current limit
+	pub fn limit(&self) -> usize {
+		self.0.limit.lock().unwrap().0
+	}
+
+	/// gets how many slots are in use, which can be more than the limit right after lowering it
+	pub fn in_use(&self) -> usize {
+		let (limit, owed) = *self.0.limit.lock().unwrap();
+		(limit + owed).saturating_sub(self.0.slots.available_permits())
+	}
+
+	/// changes the limit, taking effect for remotes which haven't started yet
+	pub fn set_limit(&self, jobs: NonZeroUsize) {
+		let mut limit = self.0.limit.lock().unwrap();
+		let (current, owed) = *limit;
+		let jobs = jobs.get();
+		if jobs > current {
+			// slots still owed from lowering the limit are kept instead of adding new ones
+			let kept = owed.min(jobs - current);
+			self.0.slots.add_permits(jobs - current - kept);
+			*limit = (jobs, owed - kept);
+		} else {
+			let lowered = current - jobs;
+			let forgotten = self.0.slots.forget_permits(lowered);
+			*limit = (jobs, owed + lowered - forgotten);
+		}
+	}
+
+	/// takes a slot, announcing the remote is waiting for one if there isn't one free
+	async fn take(&self, remote: &str, events: &UnboundedSender<Event>) -> JobSlot {
+		if let Ok(permit) = self.0.slots.try_acquire() {
+			permit.forget();
+		} else {
+			emit(
+				events,
+				Event::RemoteQueued {
+					remote: remote.to_string(),
+					waiting_for: "a free slot".to_string(),
+				},
+			);
+			self.0
+				.slots
+				.acquire()
+				.await
+				.expect("remote slots are never closed")
+				.forget();
+		}
+		JobSlot(self.0.clone())
+	}
+}
+
+/// a run of [`pupdate_remotes`] in progress
+#[derive(Debug)]
+pub struct RemotesRun {
+	/// the events of the run, which end once every remote has finished
+	pub events: UnboundedReceiver<Event>,
+	/// cancels remotes while the run is in progress
+	pub canceller: Canceller,
+	/// the run's limit on how many remotes are pupdated at once, if it has one
+	pub job_limit: Option<JobLimit>,
+	/// the result of each remote in the order they were given, once every remote has finished
+	pub results: JoinHandle<eyre::Result<Vec<(String, TargetSummary)>>>,
+}
+
+/// starts pupdating every remote concurrently, returning the run's events as they happen along with each remote's
+/// result in order once they've all finished, even when some of them error
+pub fn pupdate_remotes<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	options: Arc<RemoteOptions>,
+) -> RemotesRun {
+	let (events, events_rx) = mpsc::unbounded_channel();
+	let (cancel, cancel_rx) = mpsc::unbounded_channel();
+	let job_limit = options.jobs.map(JobLimit::new);
+	let events_rx = match &options.events {
+		Some(sender) => tee(events_rx, sender.clone()),
+		None => events_rx,
+	};
+	RemotesRun {
+		events: events_rx,
+		canceller: Canceller(cancel),
+		results: tokio::spawn(run_remotes(
+			runner,
+			remotes,
+			options,
+			job_limit.clone(),
+			events,
+			cancel_rx,
+		)),
+		job_limit,
+	}
+}
+
+/// takes a slot from the given limit, announcing what the remote is waiting for if there isn't one free
+async fn take_slot(
+	remote: &str,
+	slots: Arc<Semaphore>,
+	waiting_for: String,
+	events: &UnboundedSender<Event>,
+) -> OwnedSemaphorePermit {
+	if let Ok(slot) = slots.clone().try_acquire_owned() {
+		return slot;
+	}
+	emit(
+		events,
+		Event::RemoteQueued {
+			remote: remote.to_string(),
+			waiting_for,
+		},
+	);
+	slots
+		.acquire_owned()
+		.await
+		.expect("remote slots are never closed")
+}
+
+/// pupdates every remote concurrently, collecting each remote's result in order
+async fn run_remotes<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	options: Arc<RemoteOptions>,
+	slots: Option<JobLimit>,
+	events: UnboundedSender<Event>,
+	mut cancel: UnboundedReceiver<String>,
+) -> eyre::Result<Vec<(String, TargetSummary)>> {
+	let group_slots: BTreeMap<_, _> = options
+		.concurrency_groups
+		.limits
+		.iter()
+		.map(|(group, limit)| (group.clone(), Arc::new(Semaphore::new(limit.get()))))
+		.collect();
+	let mut tasks = Vec::with_capacity(remotes.len());
+	for remote in remotes {
+		let group = options
+			.concurrency_groups
+			.remotes
+			.get(&remote)
+			.and_then(|group| Some((group.clone(), group_slots.get(group)?.clone())));
+		let task = if slots.is_none() && group.is_none() {
+			// remotes are announced here rather than in their tasks so they're always announced in order
+			emit(
+				&events,
+				Event::RemoteStarted {
+					remote: remote.clone(),
+				},
+			);
+			tokio::spawn(pupdate_and_report(
+				runner.clone(),
+				remote.clone(),
+				options.clone(),
+				events.clone(),
+				None,
+			))
+		} else {
+			let (runner, remote, options, events, slots) = (
+				runner.clone(),
+				remote.clone(),
+				options.clone(),
+				events.clone(),
+				slots.clone(),
+			);
+			// remotes with limits are announced once they get their turn, or as waiting if they don't get it at once
+			tokio::spawn(async move {
+				let start = Instant::now();
+				// the group's slot is taken first so a remote waiting on its group doesn't hold one of the run's
+				let _group_slot = match group {
+					Some((group, slots)) => Some(
+						take_slot(
+							&remote,
+							slots,
+							format!("concurrency group {group}"),
+							&events,
+						)
+						.await,
+					),
+					None => None,
+				};
+				let _slot = match &slots {
+					Some(slots) => Some(slots.take(&remote, &events).await),
+					None => None,
+				};
+				emit(
+					&events,
+					Event::RemoteStarted {
+						remote: remote.clone(),
+					},
+				);
+				pupdate_and_report(runner, remote, options, events, Some(start.elapsed())).await
+			})
+		};
+		tasks.push((remote, task));
+	}
+
+	let in_flight: Vec<(String, AbortHandle)> = tasks
+		.iter()
+		.map(|(remote, task)| (remote.clone(), task.abort_handle()))
+		.collect();
+	let cancel_listener = tokio::spawn(async move {
+		while let Some(remote) = cancel.recv().await {
+			for (_, handle) in in_flight.iter().filter(|(r, _)| *r == remote) {
+				handle.abort();
+			}
+		}
+	});
+
+	let mut results = Vec::with_capacity(tasks.len());
+	for (remote, task) in tasks {
+		let error = match task.await {
+			Ok(Ok(result)) => {
+				results.push((remote, result));
+				continue;
+			}
+			Err(e) if e.is_cancelled() => None,
+			Ok(Err(e)) => Some(e.to_string()),
+			Err(e) => Some(e.to_string()),
+		};
+		// the task never got to report finishing, so it's reported here instead
+		let mut result = if let Some(error) = error {
+			emit(
+				&events,
+				Event::RemoteErrored {
+					remote: remote.clone(),
+					error: error.clone(),
+				},
+			);
+			TargetSummary::errored(error)
+		} else {
+			emit(
+				&events,
+				Event::RemoteCancelled {
+					remote: remote.clone(),
+				},
+			);
+			TargetSummary::cancelled()
+		};
+		// nor to expire its silence
+		if let Some(silencer) = &options.silencer {
+			result.silence_error = unsilence_remote(silencer, &remote, &events).await;
+		}
+		// a cancelled remote's task never got to record finishing
+		if let Some(state) = &options.state {
+			state.remote_finished(&remote, &result);
+		}
+		results.push((remote, result));
+	}
+	cancel_listener.abort();
+
+	// results are written once every remote is done so cancelled and errored remotes get one too
+	if let Some(log_dir) = &options.log_dir {
+		let dir = log_dir.join(REMOTE_LOG_DIR);
+		for (remote, result) in &mut results {
+			if let Err(e) = options
+				.log_layout
+				.write_result(&dir, &options.log_name(remote), result)
+				.await
+			{
+				result.success = false;
+				result.error.get_or_insert(e.to_string());
+			}
+		}
+	}
+	Ok(results)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::os::unix::process::ExitStatusExt;
+
+	use super::*;
+	use crate::logs::DEFAULT_LOG_FLUSH_INTERVAL;
+
+	/// a runner which fails to start for the remote named `broken` and succeeds for every other remote
+	struct MockRunner;
+
+	impl Runner for MockRunner {
+		async fn run(&self, remote: &str, _command: &str) -> std::io::Result<Output> {
+			if remote == "broken" {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::NotFound,
+					"mock spawn failure",
+				));
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			Ok(Output {
+				status: std::process::ExitStatus::from_raw(0),
+				stdout: Vec::new(),
+				stderr: Vec::new(),
+			})
+		}
+	}
+
+	/// a runner which succeeds at everything, noting each command it's given along with its remote
+	#[derive(Default)]
+	struct RecordingRunner(Mutex<Vec<(String, String)>>);
+
+	impl Runner for RecordingRunner {
+		async fn run(&self, remote: &str, command: &str) -> std::io::Result<Output> {
+			self.0
+				.lock()
+				.unwrap()
+				.push((remote.to_string(), command.to_string()));
+			Ok(Output {
+				status: std::process::ExitStatus::from_raw(0),
+				stdout: Vec::new(),
+				stderr: Vec::new(),
+			})
+		}
+	}
+
+	/// a runner where `flaky` drops its connection the first time it's run and `noisy` fails its update every time,
+	/// counting how many times each remote is run
+	#[derive(Default)]
+	struct FlakyRunner(Mutex<BTreeMap<String, u32>>);
+
+	impl Runner for FlakyRunner {
+		async fn run(&self, remote: &str, _command: &str) -> std::io::Result<Output> {
+			let runs = {
+				let mut runs = self.0.lock().unwrap();
+				let runs = runs.entry(remote.to_string()).or_default();
+				*runs += 1;
+				*runs
+			};
+			let (code, stdout) = match remote {
+				"flaky" if runs == 1 => (255, Vec::new()),
+				"noisy" => (
+					100,
+					b"E: Sub-process /usr/bin/dpkg returned an error code (1)\n".to_vec(),
+				),
+				_ => (0, Vec::new()),
+			};
+			Ok(Output {
+				status: std::process::ExitStatus::from_raw(code << 8),
+				stdout,
+				stderr: Vec::new(),
+			})
+		}
+	}
+
+	/// a runner which takes a while to connect, writing its first output well before it finishes
+	struct SlowConnectRunner;
+
+	impl Runner for SlowConnectRunner {
+		async fn run(&self, _remote: &str, _command: &str) -> std::io::Result<Output> {
+			unreachable!("only run_streaming is used")
+		}
+
+		async fn run_streaming(
+			&self,
+			_remote: &str,
+			_command: &str,
+			limits: OutputLimits,
+			_timeout: Option<Duration>,
+			on_line: impl Fn(OutputStream, String) + Send + Sync,
+		) -> std::io::Result<LimitedOutput> {
+			tokio::time::sleep(Duration::from_millis(300)).await;
+			on_line(OutputStream::Stdout, "connected".to_string());
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			let output = Output {
+				status: std::process::ExitStatus::from_raw(0),
+				stdout: b"connected\n".to_vec(),
+				stderr: Vec::new(),
+			};
+			Ok(LimitedOutput::from_output(output, limits))
+		}
+	}
+
+	#[test]
+	fn ssh_failures_are_classified() {
+		assert_eq!(ssh_failure(std::process::ExitStatus::from_raw(0)), None);
+		let kind = |raw| ssh_failure(std::process::ExitStatus::from_raw(raw)).map(|f| f.kind);
+		assert_eq!(kind(255 << 8), Some(FailureKind::Connection));
+		assert_eq!(kind(100 << 8), Some(FailureKind::Command));
+		assert_eq!(kind(9), Some(FailureKind::Signal));
+	}
+
+	#[test]
+	fn invocations_mask_secrets() {
+		let runner = SshRunner {
+			ssh: PathBuf::from("ssh"),
+			scp: PathBuf::from("scp"),
+			host_key_policy: Some(HostKeyPolicy::Strict),
+			connect_timeouts: BTreeMap::new(),
+			targets: BTreeMap::new(),
+			jump_host: None,
+		};
+		let mut command = runner.command("web1", "sudo pupdate");
+		command.env("SSHPASS", "hunter2").env_remove("SSH_ASKPASS");
+		let invocation = Invocation::of(&command, "subprocess", &[]);
+		assert_eq!(
+			invocation.argv,
+			[
+				"ssh",
+				"-o",
+				"StrictHostKeyChecking=yes",
+				"--",
+				"web1",
+				"sudo pupdate"
+			]
+		);
+		assert_eq!(
+			invocation.lines()[1..],
+			["env: SSHPASS='***'", "env: SSH_ASKPASS cleared"]
+		);
+
+		let runner = SshRunner {
+			targets: BTreeMap::from([(
+				"pup1".to_string(),
+				SshTarget {
+					host: "pup1.example.com".to_string(),
+					user: Some("admin".to_string()),
+					port: Some(2222),
+					identity_file: Some(PathBuf::from("~/.ssh/pup")),
+					jump_host: None,
+				},
+			)]),
+			jump_host: Some("bastion1,admin@bastion2:2222".to_string()),
+			..runner
+		};
+		assert_eq!(
+			Invocation::of(&runner.command("pup1", "sudo pupdate"), "subprocess", &[]).argv,
+			[
+				"ssh",
+				"-o",
+				"StrictHostKeyChecking=yes",
+				"-l",
+				"admin",
+				"-p",
+				"2222",
+				"-i",
+				"~/.ssh/pup",
+				"-J",
+				"bastion1,admin@bastion2:2222",
+				"--",
+				"pup1.example.com",
+				"sudo pupdate"
+			]
+		);
+
+		let local = runner.command(LOCAL_REMOTE, "sudo pupdate --local-only");
+		assert_eq!(
+			Invocation::of(&local, "subprocess", &[]).argv,
+			["sh", "-c", "sudo pupdate --local-only"]
+		);
+	}
+
+	#[test]
+	fn copies_skip_the_jump_host_like_commands_do() {
+		let runner = SshRunner {
+			ssh: PathBuf::from("ssh"),
+			scp: PathBuf::from("scp"),
+			host_key_policy: None,
+			connect_timeouts: BTreeMap::new(),
+			targets: BTreeMap::from([(
+				"db1".to_string(),
+				SshTarget {
+					host: "10.0.0.5".to_string(),
+					user: None,
+					port: None,
+					identity_file: None,
+					jump_host: Some(NO_JUMP_HOST.to_string()),
+				},
+			)]),
+			jump_host: Some("bastion1".to_string()),
+		};
+		let scp = runner.scp_command("db1", &[PathBuf::from("a.deb")], "/tmp/pupdate");
+		assert_eq!(
+			Invocation::of(&scp, "subprocess", &[]).argv,
+			[
+				"scp",
+				"-J",
+				"none",
+				"-q",
+				"--",
+				"a.deb",
+				"10.0.0.5:/tmp/pupdate/"
+			]
+		);
+		let ssh = runner.command("db1", "true");
+		assert_eq!(
+			Invocation::of(&ssh, "subprocess", &[]).argv,
+			["ssh", "-J", "none", "--", "10.0.0.5", "true"]
+		);
+		assert_eq!(runner.jump_host("db1"), None);
+	}
+
+	#[tokio::test]
+	async fn timed_out_commands_are_killed() {
+		let runner = SshRunner {
+			ssh: PathBuf::from("ssh"),
+			scp: PathBuf::from("scp"),
+			host_key_policy: None,
+			connect_timeouts: BTreeMap::new(),
+			targets: BTreeMap::new(),
+			jump_host: None,
+		};
+		let start = Instant::now();
+		let output = runner
+			.run_streaming(
+				LOCAL_REMOTE,
+				// sh execs sleep so killing it leaves nothing holding the output open
+				"echo started; exec sleep 10",
+				OutputLimits::default(),
+				Some(Duration::from_millis(200)),
+				|_, _| {},
+			)
+			.await
+			.unwrap();
+		assert!(start.elapsed() < Duration::from_secs(5));
+		assert!(output.timed_out);
+		// what was written before the timeout is kept
+		assert_eq!(output.output.stdout, b"started\n");
+		assert!(!output.output.status.success());
+
+		// commands which close their output and carry on running are killed too
+		let start = Instant::now();
+		let output = runner
+			.run_streaming(
+				LOCAL_REMOTE,
+				"echo started; exec >/dev/null 2>&1; sleep 10",
+				OutputLimits::default(),
+				Some(Duration::from_millis(200)),
+				|_, _| {},
+			)
+			.await
+			.unwrap();
+		assert!(start.elapsed() < Duration::from_secs(5));
+		assert!(output.timed_out);
+		assert_eq!(output.output.stdout, b"started\n");
+	}
+
+	#[test]
+	fn jump_host_failures_are_told_apart() {
+		let jump_host = "bastion1,admin@bastion2.example.com:2222";
+		assert!(jump_host_failed(
+			b"ssh: connect to host bastion2.example.com port 2222: Connection refused\r\n",
+			jump_host
+		));
+		assert!(jump_host_failed(
+			b"admin@bastion2.example.com: Permission denied (publickey).\r\n",
+			jump_host
+		));
+		assert!(jump_host_failed(
+			b"Host key verification failed.\r\nConnection closed by UNKNOWN port 65535\r\n",
+			jump_host
+		));
+		assert!(!jump_host_failed(
+			b"channel 0: open failed: connect failed: No route to host\r\nstdio forwarding failed\r\n\
+			Connection closed by UNKNOWN port 65535\r\n",
+			jump_host
+		));
+		assert!(!jump_host_failed(
+			b"admin@web1: Permission denied (publickey).\r\n",
+			jump_host
+		));
+	}
+
+	/// options for pupdating remotes with the mock runner without logs or retries
+	fn mock_options() -> RemoteOptions {
+		RemoteOptions {
+			command: RemoteCommand::Shell("sudo pupdate".to_string()),
+			local_command: None,
+			log_dir: None,
+			log_layout: LogLayout::default(),
+			log_formats: LogFormats::default(),
+			log_names: BTreeMap::new(),
+			log_template: None,
+			log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+			stream_log: None,
+			report_sources: false,
+			fetch_logs: false,
+			fetch_compressed: false,
+			capture_stdout: false,
+			retry: RetryPolicy {
+				retries: 0,
+				base: Duration::ZERO,
+				max: Duration::ZERO,
+			},
+			offline: None,
+			skew_check: None,
+			services: None,
+			output_limits: OutputLimits::default(),
+			merge_output: false,
+			timeout: None,
+			verify_binary: None,
+			webhook: None,
+			silencer: None,
+			forward: None,
+			state: None,
+			change_id: None,
+			jobs: None,
+			concurrency_groups: ConcurrencyGroups::default(),
+			fail_on_repo_warnings: false,
+			fail_on_kept_back: false,
+			diagnostics: DiagnosticCommands::default(),
+			events: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn errored_remote_does_not_abort_others() {
+		let options = Arc::new(mock_options());
+		let remotes = vec![
+			"first".to_string(),
+			"broken".to_string(),
+			"last".to_string(),
+		];
+		let mut run = pupdate_remotes(Arc::new(MockRunner), remotes, options);
+		let results = run.results.await.unwrap().unwrap();
+
+		let names: Vec<_> = results.iter().map(|(remote, _)| remote.as_str()).collect();
+		assert_eq!(names, ["first", "broken", "last"]);
+		assert!(results[0].1.success);
+		assert!(!results[1].1.success);
+		assert!(results[1]
+			.1
+			.error
+			.as_deref()
+			.is_some_and(|e| e.contains("mock spawn failure")));
+		assert!(results[2].1.success);
+
+		let mut started = Vec::new();
+		let mut errored = Vec::new();
+		let mut finished = Vec::new();
+		while let Some(event) = run.events.recv().await {
+			match event {
+				Event::RemoteStarted { remote } => started.push(remote),
+				Event::RemoteErrored { remote, .. } => errored.push(remote),
+				Event::RemoteFinished { remote, .. } => finished.push(remote),
+				_ => {}
+			}
+		}
+		assert_eq!(started, ["first", "broken", "last"]);
+		assert_eq!(errored, ["broken"]);
+		finished.sort();
+		assert_eq!(finished, ["first", "last"]);
+	}
+
+	#[tokio::test]
+	async fn cancelled_remotes_match_the_schema() {
+		let mut run = pupdate_remotes(
+			Arc::new(SlowConnectRunner),
+			vec!["slow".to_string()],
+			Arc::new(mock_options()),
+		);
+		while let Some(event) = run.events.recv().await {
+			if let Event::RemoteStarted { remote } = event {
+				run.canceller.cancel(&remote);
+			}
+		}
+		let summary = crate::summary::Summary {
+			remotes: run.results.await.unwrap().unwrap().into_iter().collect(),
+			..Default::default()
+		};
+		assert!(summary.remotes["slow"].cancelled);
+		let summary = serde_json::to_value(&summary).unwrap();
+		assert_eq!(
+			crate::schema::validate(&crate::schema::summary_schema(), &summary),
+			Ok(())
+		);
+	}
+
+	#[tokio::test]
+	async fn the_local_system_is_pupdated_with_the_local_command() {
+		let options = Arc::new(RemoteOptions {
+			local_command: Some(RemoteCommand::Argv(vec![
+				"/usr/bin/pupdate".to_string(),
+				"--config".to_string(),
+				"fleet.json".to_string(),
+			])),
+			..mock_options()
+		});
+		let runner = Arc::new(RecordingRunner::default());
+		let remotes = vec![LOCAL_REMOTE.to_string(), "web1".to_string()];
+		let run = pupdate_remotes(runner.clone(), remotes, options);
+		run.results.await.unwrap().unwrap();
+		let mut commands = runner.0.lock().unwrap().clone();
+		commands.sort();
+		assert_eq!(
+			commands,
+			[
+				(
+					LOCAL_REMOTE.to_string(),
+					"/usr/bin/pupdate --config fleet.json --local-only".to_string()
+				),
+				("web1".to_string(), "sudo pupdate".to_string()),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn only_dropped_connections_are_retried() {
+		let options = Arc::new(RemoteOptions {
+			retry: RetryPolicy {
+				retries: 2,
+				base: Duration::ZERO,
+				max: Duration::ZERO,
+			},
+			..mock_options()
+		});
+		let runner = Arc::new(FlakyRunner::default());
+		let remotes = vec!["flaky".to_string(), "noisy".to_string()];
+		let run = pupdate_remotes(runner.clone(), remotes, options);
+		let results: BTreeMap<_, _> = run.results.await.unwrap().unwrap().into_iter().collect();
+		assert!(results["flaky"].success);
+		assert!(!results["noisy"].success);
+		// the failed upgrade wrote output, so it would only fail the same way again
+		let runs = runner.0.lock().unwrap().clone();
+		assert_eq!(
+			runs,
+			BTreeMap::from([("flaky".to_string(), 2), ("noisy".to_string(), 1)])
+		);
+	}
+
+	#[tokio::test]
+	async fn connecting_is_not_counted_as_the_command() {
+		let options = Arc::new(mock_options());
+		let run = pupdate_remotes(
+			Arc::new(SlowConnectRunner),
+			vec!["web1".to_string()],
+			options,
+		);
+		let results = run.results.await.unwrap().unwrap();
+		let result = &results[0].1;
+		assert!(result.success);
+		let connect = result.connect_seconds.unwrap();
+		let command = result.command_seconds.unwrap();
+		assert!(connect >= 0.3);
+		assert!((0.05..0.3).contains(&command), "{command}");
+	}
+
+	#[tokio::test]
+	async fn job_limits_can_be_changed() {
+		let limit = JobLimit::new(NonZeroUsize::new(2).unwrap());
+		let (events, _events) = mpsc::unbounded_channel();
+		let first = limit.take("first", &events).await;
+		let second = limit.take("second", &events).await;
+		assert_eq!(limit.in_use(), 2);
+
+		// lowering the limit below what's in flight gives up slots as they're freed
+		limit.set_limit(NonZeroUsize::MIN);
+		assert_eq!(limit.in_use(), 2);
+		drop(first);
+		assert_eq!(limit.in_use(), 1);
+		assert!(limit.0.slots.try_acquire().is_err());
+		drop(second);
+		assert_eq!(limit.in_use(), 0);
+
+		limit.set_limit(NonZeroUsize::new(3).unwrap());
+		assert_eq!(limit.0.slots.available_permits(), 3);
+		let _third = limit.take("third", &events).await;
+		assert_eq!((limit.in_use(), limit.limit()), (1, 3));
+	}
+
+	#[tokio::test]
+	async fn concurrency_groups_are_limited() {
+		let options = Arc::new(RemoteOptions {
+			concurrency_groups: ConcurrencyGroups {
+				limits: BTreeMap::from([("pg".to_string(), NonZeroUsize::MIN)]),
+				remotes: BTreeMap::from([
+					("db1".to_string(), "pg".to_string()),
+					("db2".to_string(), "pg".to_string()),
+					("web1".to_string(), "unlimited".to_string()),
+				]),
+			},
+			..mock_options()
+		});
+		let remotes = vec!["db1".to_string(), "db2".to_string(), "web1".to_string()];
+		let mut run = pupdate_remotes(Arc::new(MockRunner), remotes, options);
+		let results: BTreeMap<_, _> = run.results.await.unwrap().unwrap().into_iter().collect();
+		assert!(results.values().all(|result| result.success));
+		assert_eq!(results["web1"].queued_seconds, None);
+
+		let mut queued = Vec::new();
+		while let Some(event) = run.events.recv().await {
+			if let Event::RemoteQueued {
+				remote,
+				waiting_for,
+			} = event
+			{
+				queued.push((remote, waiting_for));
+			}
+		}
+		// whichever of the database remotes got the group's slot first, the other waited for it to finish
+		assert_eq!(queued.len(), 1);
+		let (waited, waiting_for) = &queued[0];
+		assert_eq!(waiting_for, "concurrency group pg");
+		assert!(results[waited]
+			.queued_seconds
+			.is_some_and(|queued| queued >= 0.05));
+	}
+}
@@ -0,0 +1,146 @@
+//! checking that remotes' hostnames resolve before pupdating them, so a typo in a hostname is reported up front
+//! instead of by ssh once it's timed out
+//!
+//! remotes can be aliases from ssh's config, so each one is looked up with `ssh -G` first to get the hostname ssh would
+//! actually connect to
+
+use std::{collections::BTreeMap, net::IpAddr, process::Stdio, sync::Arc, time::Duration};
+
+use crate::remote::{SshRunner, LOCAL_REMOTE};
+use serde::{Deserialize, Serialize};
+use tokio::{process::Command, task::JoinSet};
+
+/// how long a remote's hostname has to resolve before it's given up on
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// what to do with remotes whose hostnames don't resolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolvePolicy {
+	/// warn about the remote and pupdate it anyway
+	Warn,
+	/// warn about the remote and skip it
+	Skip,
+	/// stop before pupdating anything
+	Abort,
+}
+
+/// where ssh would connect to for a remote, according to `ssh -G`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Effective {
+	/// the hostname connected to
+	pub hostname: String,
+	/// the port connected to
+	pub port: u16,
+	/// whether the connection goes through a proxy or jump host, which resolves the hostname itself
+	pub proxied: bool,
+}
+
+/// parses the output of `ssh -G`, which prints ssh's config for a remote as lowercase keys followed by their values
+pub fn parse_ssh_config(output: &[u8]) -> Option<Effective> {
+	let mut hostname = None;
+	let mut port = 22;
+	let mut proxied = false;
+	for line in String::from_utf8_lossy(output).lines() {
+		let Some((key, value)) = line.split_once(' ') else {
+			continue;
+		};
+		match key {
+			"hostname" => hostname = Some(value.to_string()),
+			"port" => port = value.parse().ok()?,
+			"proxycommand" | "proxyjump" if value != "none" => proxied = true,
+			_ => {}
+		}
+	}
+	Some(Effective {
+		hostname: hostname?,
+		port,
+		proxied,
+	})
+}
+
+/// checks that a remote's hostname resolves, looking it up with the same options the runner reaches it with,
+/// returning why it doesn't if it doesn't, or `None` if it does or couldn't be checked
+async fn check(runner: &SshRunner, remote: &str) -> Option<String> {
+	let output = Command::new(&runner.ssh)
+		.arg("-G")
+		.args(runner.ssh_args(remote))
+		.arg("--")
+		.arg(runner.destination(remote))
+		.stdin(Stdio::null())
+		.kill_on_drop(true)
+		.output()
+		.await
+		.ok()?;
+	// ssh not understanding the remote will be reported when it's pupdated
+	let effective = parse_ssh_config(&output.stdout).filter(|_| output.status.success())?;
+	if effective.proxied || effective.hostname.parse::<IpAddr>().is_ok() {
+		return None;
+	}
+	let resolved = tokio::net::lookup_host((effective.hostname.as_str(), effective.port))
+		.await
+		.map(|mut addresses| addresses.next().is_some());
+	match resolved {
+		Ok(true) => None,
+		Ok(false) => Some(format!("{} has no addresses", effective.hostname)),
+		Err(e) => Some(format!("{} doesn't resolve: {e}", effective.hostname)),
+	}
+}
+
+/// checks that every remote's hostname resolves at once, returning why each one which doesn't doesn't
+pub async fn unresolved_remotes(
+	runner: Arc<SshRunner>,
+	remotes: &[String],
+) -> BTreeMap<String, String> {
+	let mut checks = JoinSet::new();
+	// the controller itself isn't reached by hostname
+	for remote in remotes.iter().filter(|remote| *remote != LOCAL_REMOTE) {
+		let (runner, remote) = (runner.clone(), remote.clone());
+		checks.spawn(async move {
+			let problem = match tokio::time::timeout(RESOLVE_TIMEOUT, check(&runner, &remote)).await
+			{
+				Ok(problem) => problem,
+				Err(_) => Some(format!(
+					"didn't resolve within {} seconds",
+					RESOLVE_TIMEOUT.as_secs()
+				)),
+			};
+			(remote, problem)
+		});
+	}
+	let mut unresolved = BTreeMap::new();
+	while let Some(checked) = checks.join_next().await {
+		if let Ok((remote, Some(problem))) = checked {
+			unresolved.insert(remote, problem);
+		}
+	}
+	unresolved
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ssh_config_is_parsed() {
+		assert_eq!(
+			parse_ssh_config(
+				b"user deploy\nhostname web1.example.com\nport 2222\nproxycommand none\n"
+			),
+			Some(Effective {
+				hostname: "web1.example.com".to_string(),
+				port: 2222,
+				proxied: false,
+			})
+		);
+		assert_eq!(
+			parse_ssh_config(b"hostname 10.0.0.5\nproxyjump bastion\n"),
+			Some(Effective {
+				hostname: "10.0.0.5".to_string(),
+				port: 22,
+				proxied: true,
+			})
+		);
+		assert_eq!(parse_ssh_config(b"user deploy\n"), None);
+	}
+}
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// how failed pupdates are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	/// how many times to retry after the first attempt fails
+	pub retries: u32,
+	/// the delay before the first retry, doubled for each retry after it
+	pub base: Duration,
+	/// the longest delay between retries
+	pub max: Duration,
+}
+
+impl RetryPolicy {
+	/// gets the delay before the given retry, counting from 0, as `base * 2^retry` capped at the max delay and jittered
+	/// to somewhere between half and all of that so retries across remotes don't line up
+	pub fn delay(&self, retry: u32) -> Duration {
+		let delay = self
+			.base
+			.saturating_mul(2u32.saturating_pow(retry))
+			.min(self.max);
+		delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+	}
+}
@@ -0,0 +1,111 @@
+//! spotting risky updates, like a new kernel or libc, before they're applied so an unattended run can stop and ask
+//!
+//! pending updates are found by simulating an upgrade with `apt-get -s upgrade`, which needs no root and changes
+//! nothing
+
+use serde::{Deserialize, Serialize};
+
+/// the packages whose updates are risky by default: the kernel, libc, openssl, and the init system. a trailing `*`
+/// matches any package starting with what comes before it
+pub const DEFAULT_RISKY_PACKAGES: &[&str] = &[
+	"linux-image-*",
+	"linux-headers-*",
+	"libc6",
+	"libssl*",
+	"openssl",
+	"systemd",
+	"systemd-sysv",
+	"init",
+	"sysvinit-core",
+];
+/// the arguments given to apt-get to simulate an upgrade
+pub const SIMULATE_UPGRADE_ARGS: &[&str] = &["-s", "upgrade"];
+
+/// what to do with targets which have risky updates pending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskyPolicy {
+	/// ask whether to pupdate each target with risky updates pending
+	Prompt,
+	/// skip targets with risky updates pending
+	Skip,
+	/// pupdate targets with risky updates pending anyway, only recording the updates
+	Proceed,
+}
+
+/// checks whether a package matches any of the given risky package patterns
+pub fn is_risky(package: &str, patterns: &[String]) -> bool {
+	patterns
+		.iter()
+		.any(|pattern| match pattern.strip_suffix('*') {
+			Some(prefix) => package.starts_with(prefix),
+			None => package == pattern,
+		})
+}
+
+/// parses the output of `apt-get -s upgrade` into the packages which would be upgraded or installed
+pub fn parse_simulated_upgrade(output: &[u8]) -> Vec<String> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.filter_map(|line| {
+			// lines look like `Inst libc6 [2.36-9+deb12u3] (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])`
+			let package = line.strip_prefix("Inst ")?.split_whitespace().next()?;
+			// multiarch packages can be qualified by their architecture, like `libc6:i386`
+			Some(package.split(':').next().unwrap_or(package).to_string())
+		})
+		.collect()
+}
+
+/// finds the risky packages among the pending updates in the output of `apt-get -s upgrade`
+pub fn risky_updates(output: &[u8], patterns: &[String]) -> Vec<String> {
+	let mut risky: Vec<_> = parse_simulated_upgrade(output)
+		.into_iter()
+		.filter(|package| is_risky(package, patterns))
+		.collect();
+	risky.dedup();
+	risky
+}
+
+/// describes the risky updates pending on a target, for why it was skipped
+pub fn describe_risky(packages: &[String]) -> String {
+	format!("risky updates pending ({})", packages.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn risky_updates_are_found() {
+		let output = b"\
+Reading package lists...
+Building dependency tree...
+Reading state information...
+Calculating upgrade...
+The following packages will be upgraded:
+  curl libc6 libc6:i386 linux-image-6.8.0-45-generic
+4 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.
+Inst libc6 [2.36-9+deb12u3] (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])
+Inst libc6:i386 [2.36-9+deb12u3] (2.36-9+deb12u4 Debian-Security:12/stable-security [i386])
+Inst curl [7.88.1-10+deb12u5] (7.88.1-10+deb12u7 Debian:12.7/stable [amd64])
+Inst linux-image-6.8.0-45-generic (6.8.0-45.45 Ubuntu:24.04/noble-updates [amd64])
+Conf libc6 (2.36-9+deb12u4 Debian-Security:12/stable-security [amd64])
+Conf curl (7.88.1-10+deb12u7 Debian:12.7/stable [amd64])
+";
+		assert_eq!(
+			parse_simulated_upgrade(output),
+			["libc6", "libc6", "curl", "linux-image-6.8.0-45-generic"]
+		);
+		let patterns: Vec<_> = DEFAULT_RISKY_PACKAGES
+			.iter()
+			.map(|pattern| pattern.to_string())
+			.collect();
+		assert_eq!(
+			risky_updates(output, &patterns),
+			["libc6", "linux-image-6.8.0-45-generic"]
+		);
+		assert_eq!(risky_updates(output, &["curl*".to_string()]), ["curl"]);
+		assert!(!is_risky("libc6-dev", &patterns));
+		assert!(is_risky("libssl3", &patterns));
+	}
+}
@@ -0,0 +1,131 @@
+use std::{collections::BTreeMap, io::Write, path::Path, sync::Arc};
+
+use pupdate::{
+	diagnostics::DiagnosticCommands,
+	logs::{LogFormats, LogLayout, DEFAULT_LOG_FLUSH_INTERVAL},
+	output::OutputLimits,
+	progress::Progress,
+	remote::{pupdate_remotes, ConcurrencyGroups, RemoteOptions, Runner},
+	retry::RetryPolicy,
+	risk::{describe_risky, risky_updates, RiskyPolicy, SIMULATE_UPGRADE_ARGS},
+	shell::RemoteCommand,
+};
+
+/// checks every remote for risky updates by simulating an upgrade, returning the risky packages pending on each remote
+/// which has any
+pub async fn check_risky_remotes<R: Runner>(
+	runner: Arc<R>,
+	remotes: Vec<String>,
+	patterns: &[String],
+	retry: RetryPolicy,
+	progress: &Progress,
+	verbose: bool,
+) -> eyre::Result<BTreeMap<String, Vec<String>>> {
+	println!("checking {} remotes for risky updates", remotes.len());
+	let len = remotes.len();
+	let run = pupdate_remotes(
+		runner,
+		remotes,
+		Arc::new(RemoteOptions {
+			command: RemoteCommand::Argv(
+				std::iter::once("apt-get")
+					.chain(SIMULATE_UPGRADE_ARGS.iter().copied())
+					.map(str::to_string)
+					.collect(),
+			),
+			local_command: None,
+			log_dir: None,
+			log_layout: LogLayout::default(),
+			log_formats: LogFormats::default(),
+			log_names: BTreeMap::new(),
+			log_template: None,
+			log_flush_interval: DEFAULT_LOG_FLUSH_INTERVAL,
+			stream_log: None,
+			report_sources: false,
+			fetch_logs: false,
+			fetch_compressed: false,
+			capture_stdout: true,
+			retry,
+			offline: None,
+			skew_check: None,
+			services: None,
+			output_limits: OutputLimits::default(),
+			merge_output: false,
+			timeout: None,
+			verify_binary: None,
+			webhook: None,
+			silencer: None,
+			forward: None,
+			state: None,
+			change_id: None,
+			jobs: None,
+			concurrency_groups: ConcurrencyGroups::default(),
+			fail_on_repo_warnings: false,
+			fail_on_kept_back: false,
+			diagnostics: DiagnosticCommands::default(),
+			events: None,
+		}),
+	);
+	let mut risky = BTreeMap::new();
+	for (remote, result) in progress.show_remotes(run, len, verbose).await? {
+		if !result.success {
+			// a remote which can't be checked likely can't be pupdated either, which its pupdate will report
+			eprintln!("warning: couldn't check {remote} for risky updates, pupdating it anyway");
+			continue;
+		}
+		let packages = risky_updates(result.stdout.as_deref().unwrap_or_default(), patterns);
+		if !packages.is_empty() {
+			risky.insert(remote, packages);
+		}
+	}
+	Ok(risky)
+}
+
+/// checks the local system for risky updates by simulating an upgrade, returning the risky packages pending
+pub async fn check_risky_local(apt: &Path, patterns: &[String]) -> Vec<String> {
+	let output = tokio::process::Command::new(apt)
+		.args(SIMULATE_UPGRADE_ARGS)
+		.stdin(std::process::Stdio::null())
+		.output()
+		.await;
+	match output {
+		Ok(output) if output.status.success() => risky_updates(&output.stdout, patterns),
+		Ok(output) => {
+			eprintln!(
+				"warning: couldn't check the local system for risky updates ({}), pupdating it anyway",
+				output.status
+			);
+			Vec::new()
+		}
+		Err(e) => {
+			eprintln!("warning: couldn't check the local system for risky updates ({e}), pupdating it anyway");
+			Vec::new()
+		}
+	}
+}
+
+/// decides whether to pupdate a target with risky updates pending according to the policy, asking when it's `prompt`
+pub fn allow_risky(policy: RiskyPolicy, target: &str, packages: &[String]) -> eyre::Result<bool> {
+	let risky = describe_risky(packages);
+	match policy {
+		RiskyPolicy::Proceed => {
+			println!("{target} has {risky}, pupdating it anyway");
+			Ok(true)
+		}
+		RiskyPolicy::Skip => {
+			println!("skipping {target}: {risky}");
+			Ok(false)
+		}
+		RiskyPolicy::Prompt => {
+			print!("{target} has {risky}, pupdate it anyway? [y/N] ");
+			std::io::stdout().flush()?;
+			let mut line = String::new();
+			std::io::stdin().read_line(&mut line)?;
+			let allowed = matches!(line.trim().to_lowercase().as_str(), "y" | "yes");
+			if !allowed {
+				println!("skipping {target}");
+			}
+			Ok(allowed)
+		}
+	}
+}
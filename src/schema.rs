@@ -0,0 +1,505 @@
+//! the json schema of `summary.json`, written out by hand from the types in [`crate::summary`] so tools reading the
+//! summary have something stable to check against, along with a validator for the parts of json schema it uses
+//!
+//! any change to the summary which could break a tool reading it, like removing or renaming a field or changing its
+//! type, has to bump [`SUMMARY_SCHEMA_VERSION`]. adding an optional field doesn't
+
+use serde_json::{json, Map, Value};
+
+/// the version of the summary's schema, recorded in every summary as `schema_version`
+pub const SUMMARY_SCHEMA_VERSION: u32 = 1;
+
+/// the schema of a target's log paths
+fn log_paths() -> Value {
+	let paths = |names: &[&str]| {
+		let properties: Map<String, Value> = names
+			.iter()
+			.map(|name| (name.to_string(), json!({ "type": "string" })))
+			.collect();
+		json!({
+			"type": "object",
+			"properties": properties,
+			"required": names,
+			"additionalProperties": false,
+		})
+	};
+	json!({
+		"anyOf": [
+			paths(&["stdout", "stderr", "combined"]),
+			paths(&["stdout", "stderr"]),
+			paths(&["output"]),
+			{ "type": "null" },
+		],
+	})
+}
+
+/// the schema of an object with the given properties, of which the given ones are required
+fn object(properties: Value, required: &[&str]) -> Value {
+	json!({
+		"type": "object",
+		"properties": properties,
+		"required": required,
+		"additionalProperties": false,
+	})
+}
+
+/// the properties of a target's result, shared by remotes and local steps
+fn target_properties() -> Map<String, Value> {
+	let string = json!({ "type": "string" });
+	let strings = json!({ "type": "array", "items": { "type": "string" } });
+	let seconds = json!({ "type": "number", "minimum": 0 });
+	let bytes = json!({ "type": "integer", "minimum": 0 });
+	let flag = json!({ "type": "boolean" });
+	let properties = json!({
+		"success": flag,
+		"duration_seconds": seconds,
+		"repo_warnings": strings,
+		"kept_back": strings,
+		"silent_seconds": seconds,
+		"connect_seconds": seconds,
+		"command_seconds": seconds,
+		"suspicious": string,
+		"offline": flag,
+		"outside_window": string,
+		"declined": flag,
+		"diagnostics": object(json!({ "log": string, "failed": strings }), &[]),
+		"queued_seconds": seconds,
+		"logs": log_paths(),
+		"sources": strings,
+		"stderr_tail": string,
+		"cancelled": flag,
+		"fetched_logs": strings,
+		"fetch_error": string,
+		"skipped": string,
+		"up_to_date": flag,
+		"services_needing_restart": strings,
+		"services_restarted": flag,
+		"services_error": string,
+		"output_truncated": flag,
+		"stdout_bytes": bytes,
+		"stderr_bytes": bytes,
+		"binary": object(
+			json!({
+				"result": { "enum": ["verified", "mismatch", "error", "skipped"] },
+				"expected_sha256": string,
+				"path": string,
+				"sha256": string,
+				"error": string,
+			}),
+			&["result", "expected_sha256"],
+		),
+		"error": string,
+		"command": string,
+		"invocation": object(
+			json!({
+				"transport": string,
+				"argv": strings,
+				"env": {
+					"type": "object",
+					"additionalProperties": { "type": ["string", "null"] },
+				},
+			}),
+			&["transport", "argv"],
+		),
+		"pupdate_version": string,
+		"flags_omitted": strings,
+		"webhook_error": string,
+		"silence_error": string,
+		"approval": object(
+			json!({ "approved": flag, "by": string, "how": string }),
+			&["approved", "by"],
+		),
+		"clock_skew_seconds": { "type": "integer" },
+		"risky_updates": strings,
+		"failure": {
+			"enum": ["connection", "jump_host", "command", "signal", "output_limit", "timeout", "sudo_password", "repo_warnings", "kept_back"],
+		},
+		"exit_code": { "type": "integer" },
+		"signal": { "type": "integer" },
+	});
+	let Value::Object(properties) = properties else {
+		unreachable!("the properties are an object");
+	};
+	properties
+}
+
+/// the json schema of `summary.json`
+pub fn summary_schema() -> Value {
+	let string = json!({ "type": "string" });
+	let strings = json!({ "type": "array", "items": { "type": "string" } });
+	let seconds = json!({ "type": "number", "minimum": 0 });
+	let mut step = target_properties();
+	step.insert("name".to_string(), string.clone());
+	let local = object(
+		json!({
+			"success": { "type": "boolean" },
+			"skipped": string,
+			"risky_updates": strings,
+			"up_to_date": { "type": "boolean" },
+			"sources": strings,
+			"steps": {
+				"type": "array",
+				"items": object(Value::Object(step), &["name", "success", "logs"]),
+			},
+		}),
+		&["success", "steps"],
+	);
+	json!({
+		"$schema": "https://json-schema.org/draft/2020-12/schema",
+		"title": "pupdate summary",
+		"type": "object",
+		"properties": {
+			"schema_version": { "const": SUMMARY_SCHEMA_VERSION },
+			"remotes": {
+				"type": "object",
+				"additionalProperties": { "$ref": "#/$defs/target" },
+			},
+			"local": { "anyOf": [local, { "type": "null" }] },
+			"output_limit_bytes": { "type": "integer", "minimum": 0 },
+			"output_kill_limit_bytes": { "type": "integer", "minimum": 0 },
+			"log_format": {
+				"type": "array",
+				"items": { "enum": ["raw", "timestamped", "combined"] },
+			},
+			"resumed": object(
+				json!({
+					"resumed_at": string,
+					"remotes": strings,
+					"mid_flight": strings,
+					"local": { "type": "boolean" },
+				}),
+				&["resumed_at", "remotes", "local"],
+			),
+			"change_id": string,
+			"phases": {
+				"type": "array",
+				"items": object(
+					json!({
+						"name": string,
+						"remotes": strings,
+						"failed": strings,
+						"duration_seconds": seconds,
+						"skipped": string,
+					}),
+					&["name", "remotes"],
+				),
+			},
+			"parallelism": object(
+				json!({ "sequential_seconds": seconds, "wall_seconds": seconds }),
+				&["sequential_seconds", "wall_seconds"],
+			),
+		},
+		"required": ["schema_version", "remotes", "local"],
+		"additionalProperties": false,
+		"$defs": {
+			"target": object(Value::Object(target_properties()), &["success", "logs"]),
+		},
+	})
+}
+
+/// whether a value has the given json schema type
+fn has_type(value: &Value, ty: &str) -> bool {
+	match ty {
+		"null" => value.is_null(),
+		"boolean" => value.is_boolean(),
+		"integer" => value.is_i64() || value.is_u64(),
+		"number" => value.is_number(),
+		"string" => value.is_string(),
+		"array" => value.is_array(),
+		"object" => value.is_object(),
+		_ => false,
+	}
+}
+
+/// checks a value against a schema, supporting the parts of json schema the summary's schema uses: `$ref` to its own
+/// `$defs`, `type`, `const`, `enum`, `minimum`, `anyOf`, `properties`, `required`, `additionalProperties`, and `items`.
+/// the error names the path to the first value which doesn't match
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+	validate_at(schema, schema, value, "$")
+}
+
+/// checks a value at the given path against part of a schema, resolving references against the whole schema
+fn validate_at(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+	if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+		let name = reference
+			.strip_prefix("#/$defs/")
+			.ok_or_else(|| format!("unsupported reference {reference}"))?;
+		let def = root
+			.get("$defs")
+			.and_then(|defs| defs.get(name))
+			.ok_or_else(|| format!("unknown reference {reference}"))?;
+		return validate_at(root, def, value, path);
+	}
+	if let Some(ty) = schema.get("type") {
+		let matches = match ty {
+			Value::String(ty) => has_type(value, ty),
+			Value::Array(types) => types
+				.iter()
+				.filter_map(Value::as_str)
+				.any(|ty| has_type(value, ty)),
+			_ => false,
+		};
+		if !matches {
+			return Err(format!("{path} should be of type {ty}, got {value}"));
+		}
+	}
+	if let Some(expected) = schema.get("const") {
+		if value != expected {
+			return Err(format!("{path} should be {expected}, got {value}"));
+		}
+	}
+	if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+		if !allowed.contains(value) {
+			return Err(format!("{path} should be one of {allowed:?}, got {value}"));
+		}
+	}
+	if let (Some(minimum), Some(number)) = (
+		schema.get("minimum").and_then(Value::as_f64),
+		value.as_f64(),
+	) {
+		if number < minimum {
+			return Err(format!("{path} should be at least {minimum}, got {number}"));
+		}
+	}
+	if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+		if !options
+			.iter()
+			.any(|option| validate_at(root, option, value, path).is_ok())
+		{
+			return Err(format!("{path} doesn't match any of its allowed shapes"));
+		}
+	}
+	if let Some(object) = value.as_object() {
+		let properties = schema.get("properties").and_then(Value::as_object);
+		for required in schema
+			.get("required")
+			.and_then(Value::as_array)
+			.into_iter()
+			.flatten()
+			.filter_map(Value::as_str)
+		{
+			if !object.contains_key(required) {
+				return Err(format!("{path} is missing {required}"));
+			}
+		}
+		for (key, field) in object {
+			let path = format!("{path}.{key}");
+			match (
+				properties.and_then(|properties| properties.get(key)),
+				schema.get("additionalProperties"),
+			) {
+				(Some(property), _) => validate_at(root, property, field, &path)?,
+				(None, Some(Value::Bool(false))) => {
+					return Err(format!("{path} isn't in the schema"));
+				}
+				(None, Some(additional @ Value::Object(_))) => {
+					validate_at(root, additional, field, &path)?;
+				}
+				(None, _) => {}
+			}
+		}
+	}
+	if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+		for (i, item) in array.iter().enumerate() {
+			validate_at(root, items, item, &format!("{path}[{i}]"))?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::BTreeMap, path::PathBuf};
+
+	use super::*;
+	use crate::{
+		approval::ApprovalDecision,
+		diagnostics::Diagnostics,
+		logs::{LogFormats, LogPaths},
+		output::OutputLimits,
+		remote::Invocation,
+		summary::{
+			Failure, FailureKind, LocalSummary, Parallelism, PhaseSummary, Resumed, StepSummary,
+			Summary, TargetSummary,
+		},
+		verify::{BinaryVerification, VerificationResult},
+	};
+
+	/// checks a summary against the schema
+	fn check(summary: &Summary) -> Result<(), String> {
+		validate(&summary_schema(), &serde_json::to_value(summary).unwrap())
+	}
+
+	/// a remote's result with every field set, so a field missing from the schema is caught
+	fn everything() -> TargetSummary {
+		let some = || Some("something".to_string());
+		TargetSummary {
+			success: false,
+			duration_seconds: Some(42.5),
+			repo_warnings: vec!["W: GPG error".to_string()],
+			kept_back: vec!["systemd".to_string()],
+			silent_seconds: Some(1.0),
+			connect_seconds: Some(0.5),
+			command_seconds: Some(40.0),
+			suspicious: some(),
+			offline: true,
+			outside_window: some(),
+			declined: true,
+			diagnostics: Some(Diagnostics {
+				log: Some(PathBuf::from("/logs/web1/diagnostics.log")),
+				failed: vec!["df -h".to_string()],
+			}),
+			queued_seconds: Some(3.0),
+			logs: Some(LogPaths::SplitAndCombined {
+				stdout: PathBuf::from("stdout.log"),
+				stderr: PathBuf::from("stderr.log"),
+				combined: PathBuf::from("combined.log"),
+			}),
+			sources: Some(vec!["deb.debian.org".to_string()]),
+			stderr_tail: some(),
+			cancelled: true,
+			fetched_logs: Some(vec![PathBuf::from("remote/apt.log")]),
+			fetch_error: some(),
+			skipped: some(),
+			up_to_date: true,
+			services_needing_restart: Some(vec!["nginx".to_string()]),
+			services_restarted: true,
+			services_error: some(),
+			output_truncated: true,
+			stdout_bytes: Some(100),
+			stderr_bytes: Some(0),
+			binary: Some(BinaryVerification {
+				result: VerificationResult::Mismatch,
+				expected_sha256: "abc".to_string(),
+				path: some(),
+				sha256: some(),
+				error: some(),
+			}),
+			error: some(),
+			command: some(),
+			invocation: Some(Invocation {
+				transport: "subprocess".to_string(),
+				argv: vec!["ssh".to_string()],
+				env: BTreeMap::from([
+					("SSH_AUTH_SOCK".to_string(), some()),
+					("SSH_ASKPASS".to_string(), None),
+				]),
+			}),
+			pupdate_version: some(),
+			flags_omitted: vec!["--only-security".to_string()],
+			webhook_error: some(),
+			silence_error: some(),
+			approval: Some(ApprovalDecision {
+				approved: false,
+				by: "pupdate".to_string(),
+				how: some(),
+			}),
+			clock_skew_seconds: Some(-90),
+			risky_updates: vec!["linux-image-amd64".to_string()],
+			failure: Some(Failure {
+				kind: FailureKind::Signal,
+				exit_code: Some(1),
+				signal: Some(9),
+			}),
+			stdout: Some(b"not serialized".to_vec()),
+		}
+	}
+
+	#[test]
+	fn everything_a_summary_records_matches_the_schema() {
+		// a run where every remote finished one way or another, with everything a summary can record
+		let mut summary = Summary {
+			remotes: BTreeMap::from([
+				("everything".to_string(), everything()),
+				("cancelled".to_string(), TargetSummary::cancelled()),
+				(
+					"errored".to_string(),
+					TargetSummary::errored("spawn failed"),
+				),
+				(
+					"failed".to_string(),
+					TargetSummary {
+						failure: Some(Failure {
+							kind: FailureKind::Connection,
+							exit_code: Some(255),
+							signal: None,
+						}),
+						logs: Some(LogPaths::Merged {
+							output: PathBuf::from("output.log"),
+						}),
+						..Default::default()
+					},
+				),
+			]),
+			local: Some(LocalSummary {
+				success: true,
+				sources: Some(Vec::new()),
+				steps: vec![StepSummary {
+					name: "update".to_string(),
+					result: TargetSummary {
+						success: true,
+						logs: Some(LogPaths::Split {
+							stdout: PathBuf::from("stdout.log"),
+							stderr: PathBuf::from("stderr.log"),
+						}),
+						..Default::default()
+					},
+				}],
+				..Default::default()
+			}),
+			output_limits: Some(OutputLimits {
+				limit: 1024,
+				kill_limit: Some(4096),
+			}),
+			log_format: Some(LogFormats::default()),
+			resumed: Some(Resumed {
+				resumed_at: "2025-01-10T14:30:05Z".to_string(),
+				remotes: vec!["failed".to_string()],
+				mid_flight: vec!["cancelled".to_string()],
+				local: false,
+			}),
+			change_id: Some("CHG-1234".to_string()),
+			phases: vec![PhaseSummary {
+				name: "web".to_string(),
+				remotes: vec!["everything".to_string()],
+				failed: vec!["everything".to_string()],
+				duration_seconds: Some(42.5),
+				skipped: Some("an earlier phase failed".to_string()),
+			}],
+			parallelism: Some(Parallelism {
+				sequential_seconds: 85.0,
+				wall_seconds: 42.5,
+			}),
+			..Default::default()
+		};
+		check(&summary).unwrap();
+
+		// a run which skipped the local system and had nothing to pupdate
+		assert_eq!(check(&Summary::default()), Ok(()));
+		summary.local = Some(LocalSummary {
+			skipped: Some("risky updates pending".to_string()),
+			risky_updates: vec!["linux-image-amd64".to_string()],
+			..Default::default()
+		});
+		check(&summary).unwrap();
+	}
+
+	#[test]
+	fn mismatches_are_found() {
+		let schema = summary_schema();
+		let mut summary = serde_json::to_value(Summary::default()).unwrap();
+		assert_eq!(validate(&schema, &summary), Ok(()));
+		summary["remotes"]["web1"] = json!({ "success": true, "logs": null, "surprise": 1 });
+		assert_eq!(
+			validate(&schema, &summary),
+			Err("$.remotes.web1.surprise isn't in the schema".to_string())
+		);
+		summary["remotes"]["web1"] = json!({ "success": "yes", "logs": null });
+		assert!(validate(&schema, &summary).is_err());
+		summary["remotes"]["web1"] = json!({ "success": true, "logs": { "stdout": "a" } });
+		assert!(validate(&schema, &summary).is_err());
+		summary["remotes"] = json!({});
+		summary["schema_version"] = json!(SUMMARY_SCHEMA_VERSION + 1);
+		assert!(validate(&schema, &summary).is_err());
+	}
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use pupdate::{
+	remote::Invocation,
+	shell::{command_line, quote},
+};
+
+/// what a remote pupdate would run, as far as it's known up front
+pub enum ScriptedRemote {
+	/// the remote runs a single command through the given invocation
+	Invocation(Invocation),
+	/// the remote is pupdated offline, by copying packages to it and installing them with several commands
+	Offline,
+	/// the runner doesn't spawn anything which can be rendered
+	Unknown,
+}
+
+/// renders an invocation as a line of shell, with stdin closed like pupdate runs it
+fn invocation_line(invocation: &Invocation) -> String {
+	let cleared: Vec<_> = invocation
+		.env
+		.iter()
+		.filter(|(_, value)| value.is_none())
+		.flat_map(|(name, _)| ["-u".to_string(), name.clone()])
+		.collect();
+	let mut line = if cleared.is_empty() {
+		String::new()
+	} else {
+		format!("env {} ", command_line(&cleared))
+	};
+	line.push_str(&command_line(&invocation.argv));
+	line.push_str(" < /dev/null");
+	line
+}
+
+/// renders everything a run would execute as a posix shell script: each remote's command, one after another where
+/// pupdate runs them concurrently, followed by the local steps, which stop at the first failure like they do in a run
+pub fn render_script(
+	remotes: &[(String, ScriptedRemote)],
+	forwarded: &[String],
+	local: &[Vec<String>],
+) -> String {
+	let mut script = vec![
+		"#!/bin/sh".to_string(),
+		"# the commands a pupdate run would execute, written by --emit-script".to_string(),
+		"# checks pupdate makes around each remote, like clock skew, binary hashes, and services, aren't included"
+			.to_string(),
+	];
+	if !remotes.is_empty() {
+		script.push(String::new());
+		script.push("# remotes, which pupdate runs concurrently".to_string());
+		if !forwarded.is_empty() {
+			script.push(format!(
+				"# {} would also be passed to remotes whose pupdate is new enough for it",
+				forwarded.join(" ")
+			));
+		}
+	}
+	for (remote, scripted) in remotes {
+		match scripted {
+			ScriptedRemote::Invocation(invocation) => script.push(invocation_line(invocation)),
+			ScriptedRemote::Offline => script.push(format!(
+				"# {} is pupdated offline by copying packages to it, which isn't rendered",
+				quote(remote)
+			)),
+			ScriptedRemote::Unknown => {
+				script.push(format!("# {} can't be rendered", quote(remote)))
+			}
+		}
+	}
+	if !local.is_empty() {
+		script.push(String::new());
+		script.push("# the local system".to_string());
+		for command in local {
+			script.push(format!("{} || exit 1", command_line(command)));
+		}
+	}
+	script.push(String::new());
+	script.join("\n")
+}
+
+/// writes the script to the given path, executable on unix
+pub fn write_script(path: &Path, script: &str) -> eyre::Result<()> {
+	std::fs::write(path, script)
+		.map_err(|e| eyre::eyre!("failed to write script {}: {e}", path.display()))?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+			.map_err(|e| eyre::eyre!("failed to make script {} executable: {e}", path.display()))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use super::*;
+
+	#[test]
+	fn scripts_render_every_command() {
+		let invocation = Invocation {
+			transport: "subprocess".to_string(),
+			argv: ["ssh", "--", "web1", "sudo pupdate"]
+				.map(str::to_string)
+				.to_vec(),
+			env: BTreeMap::from([
+				("SSH_ASKPASS".to_string(), None),
+				("SSH_AUTH_SOCK".to_string(), Some("/tmp/agent".to_string())),
+			]),
+		};
+		let script = render_script(
+			&[
+				("web1".to_string(), ScriptedRemote::Invocation(invocation)),
+				("site box".to_string(), ScriptedRemote::Offline),
+			],
+			&[],
+			&[["sudo", "apt-get", "upgrade", "-y"]
+				.map(str::to_string)
+				.to_vec()],
+		);
+		let lines: Vec<_> = script.lines().skip(3).collect();
+		assert_eq!(
+			lines,
+			[
+				"",
+				"# remotes, which pupdate runs concurrently",
+				"env -u SSH_ASKPASS ssh -- web1 'sudo pupdate' < /dev/null",
+				"# 'site box' is pupdated offline by copying packages to it, which isn't rendered",
+				"",
+				"# the local system",
+				"sudo apt-get upgrade -y || exit 1",
+			]
+		);
+	}
+}
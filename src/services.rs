@@ -0,0 +1,84 @@
+//! finding and restarting services still running code replaced by an update, using `needs-restarting` from yum-utils
+//! or dnf-utils
+
+use serde::{Deserialize, Serialize};
+
+use crate::{output::output_tail, remote::Runner, shell::command_line};
+
+/// the exit code shells use when a command can't be found
+const COMMAND_NOT_FOUND: i32 = 127;
+
+/// what to do about services which need restarting after a remote is pupdated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ServicesPolicy {
+	/// report the services which need restarting
+	Report,
+	/// restart the services which need restarting and report them
+	Restart,
+}
+
+/// parses the output of `needs-restarting -s`, which lists one systemd unit per line
+fn parse_needs_restarting(output: &[u8]) -> Vec<String> {
+	String::from_utf8_lossy(output)
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty())
+		.map(str::to_string)
+		.collect()
+}
+
+/// lists the services on a remote which are still running code replaced by an update
+pub async fn services_needing_restart<R: Runner>(
+	runner: &R,
+	remote: &str,
+) -> eyre::Result<Vec<String>> {
+	// needs-restarting only sees the processes of the user running it, so it needs root to see every service
+	let output = runner
+		.run(remote, &command_line(&["sudo", "needs-restarting", "-s"]))
+		.await?;
+	if output.status.code() == Some(COMMAND_NOT_FOUND) {
+		eyre::bail!(
+			"needs-restarting isn't installed on the remote, it comes with yum-utils or dnf-utils"
+		);
+	}
+	if !output.status.success() {
+		eyre::bail!(
+			"failed to check which services need restarting: {}",
+			output_tail(&output.stderr)
+		);
+	}
+	Ok(parse_needs_restarting(&output.stdout))
+}
+
+/// restarts the given services on a remote
+pub async fn restart_services<R: Runner>(
+	runner: &R,
+	remote: &str,
+	services: &[String],
+) -> eyre::Result<()> {
+	let mut restart = vec!["sudo", "systemctl", "restart", "--"];
+	restart.extend(services.iter().map(String::as_str));
+	let output = runner.run(remote, &command_line(&restart)).await?;
+	if !output.status.success() {
+		eyre::bail!(
+			"failed to restart services: {}",
+			output_tail(&output.stderr)
+		);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_needs_restarting() {
+		assert_eq!(
+			parse_needs_restarting(b"nginx.service\n  sshd.service\n\n"),
+			vec!["nginx.service".to_string(), "sshd.service".to_string()]
+		);
+		assert!(parse_needs_restarting(b"").is_empty());
+	}
+}
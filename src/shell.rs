@@ -0,0 +1,184 @@
+//! building command lines for remote shells
+//!
+//! ssh joins its arguments into a single string which the remote shell then interprets, so anything passed to a remote
+//! has to be quoted for it to arrive as the literal string it started as
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// a command to run on a remote
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RemoteCommand {
+	/// a command line passed to the remote shell as is, so it may use pipes, variables, etc
+	Shell(String),
+	/// a program followed by its arguments, each of which is quoted so it arrives on the remote literally
+	Argv(Vec<String>),
+}
+
+impl RemoteCommand {
+	/// renders the command as a line for the remote shell
+	pub fn to_command_line(&self) -> String {
+		match self {
+			Self::Shell(command) => command.clone(),
+			Self::Argv(argv) => command_line(argv),
+		}
+	}
+
+	/// gets the command with an extra argument on the end, quoted if it's a shell command line
+	pub fn with_arg(mut self, arg: &str) -> Self {
+		match &mut self {
+			Self::Shell(command) => {
+				command.push(' ');
+				command.push_str(&quote(arg));
+			}
+			Self::Argv(argv) => argv.push(arg.to_string()),
+		}
+		self
+	}
+
+	/// gets the program the command runs, skipping over `sudo` and its options, which for a shell command line is a best
+	/// guess from its first words
+	pub fn program(&self) -> Option<&str> {
+		let mut words: Box<dyn Iterator<Item = &str>> = match self {
+			Self::Shell(command) => Box::new(command.split_whitespace()),
+			Self::Argv(argv) => Box::new(argv.iter().map(String::as_str)),
+		};
+		let first = words.next()?;
+		if first != "sudo" {
+			return Some(first);
+		}
+		words.find(|word| !word.starts_with('-'))
+	}
+}
+
+impl Default for RemoteCommand {
+	/// runs pupdate on the remote through sudo
+	fn default() -> Self {
+		Self::Argv(vec!["sudo".to_string(), "pupdate".to_string()])
+	}
+}
+
+/// quotes a single argument for a posix shell so it's passed through literally
+pub fn quote(arg: &str) -> Cow<'_, str> {
+	let is_safe = |c: char| {
+		c.is_ascii_alphanumeric()
+			|| matches!(c, '-' | '_' | '.' | '/' | ',' | ':' | '=' | '@' | '+')
+	};
+	if !arg.is_empty() && arg.chars().all(is_safe) {
+		Cow::Borrowed(arg)
+	} else {
+		Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+	}
+}
+
+/// builds a command line from a program and its arguments, quoting each of them
+pub fn command_line<S: AsRef<str>>(argv: &[S]) -> String {
+	argv.iter()
+		.map(|arg| quote(arg.as_ref()))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// wraps a command line so everything it writes to stderr goes to stdout instead, keeping the two in the order they
+/// were written
+pub fn merge_stderr(command: &str) -> String {
+	format!("exec 2>&1; {command}")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn programs_skip_sudo() {
+		let shell = |command: &str| RemoteCommand::Shell(command.to_string());
+		assert_eq!(shell("sudo pupdate").program(), Some("pupdate"));
+		assert_eq!(
+			shell("sudo -n /opt/pupdate -v").program(),
+			Some("/opt/pupdate")
+		);
+		assert_eq!(
+			RemoteCommand::Argv(vec!["pupdate".to_string()]).program(),
+			Some("pupdate")
+		);
+		assert_eq!(shell("sudo").program(), None);
+	}
+
+	/// runs a command line through a local shell, returning each argument it received
+	#[cfg(unix)]
+	fn received_args(line: &str) -> Vec<String> {
+		let output = std::process::Command::new("sh")
+			.arg("-c")
+			.arg(format!(
+				"for arg in {line}; do printf '%s\\0' \"$arg\"; done"
+			))
+			.output()
+			.unwrap();
+		assert!(output.status.success());
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.split_terminator('\0')
+			.map(str::to_string)
+			.collect()
+	}
+
+	#[test]
+	fn extra_args_are_appended() {
+		assert_eq!(
+			RemoteCommand::Shell("sudo pupdate".to_string())
+				.with_arg("--only-security")
+				.to_command_line(),
+			"sudo pupdate --only-security"
+		);
+		assert_eq!(
+			RemoteCommand::Argv(vec!["sudo".to_string(), "pupdate".to_string()])
+				.with_arg("--only-security")
+				.to_command_line(),
+			"sudo pupdate --only-security"
+		);
+	}
+
+	#[test]
+	fn safe_args_are_left_alone() {
+		assert_eq!(quote("pupdate"), "pupdate");
+		assert_eq!(quote("user@host.example.com"), "user@host.example.com");
+		assert_eq!(quote("/var/log/pupdate"), "/var/log/pupdate");
+	}
+
+	#[test]
+	fn unsafe_args_are_quoted() {
+		assert_eq!(quote(""), "''");
+		assert_eq!(quote("two words"), "'two words'");
+		assert_eq!(quote("it's"), "'it'\\''s'");
+		assert_eq!(quote("$HOME"), "'$HOME'");
+	}
+
+	#[test]
+	fn argv_commands_are_quoted_element_by_element() {
+		let command = RemoteCommand::Argv(vec!["sudo".into(), "pupdate".into(), "a b".into()]);
+		assert_eq!(command.to_command_line(), "sudo pupdate 'a b'");
+		let command = RemoteCommand::Shell("sudo pupdate | tee log".into());
+		assert_eq!(command.to_command_line(), "sudo pupdate | tee log");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn args_arrive_literally() {
+		let args = [
+			"plain",
+			"with spaces",
+			"it's \"quoted\"",
+			"$HOME and ${PATH}",
+			"\"$(rm -rf /)\"",
+			"`whoami`",
+			"; echo nope &&",
+			"glob*?[a]",
+			"ünïcödé.hôst",
+			"tab\tand\nnewline",
+			"",
+		];
+		assert_eq!(received_args(&command_line(&args)), args);
+	}
+}
@@ -0,0 +1,295 @@
+//! silencing monitoring for each remote while it's pupdated, so reboots and restarted services don't page anyone
+//!
+//! silences are created and expired by commands run on the controller, like an `amtool` or `curl` invocation, with
+//! placeholders filled in for the remote being pupdated
+
+use std::{
+	collections::BTreeMap,
+	process::Stdio,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{duration::parse_duration, output::output_tail, shell::quote};
+
+/// what's replaced by the remote in a silence command
+pub const REMOTE_PLACEHOLDER: &str = "{remote}";
+/// what's replaced by how long the remote is expected to be down in a silence command, in seconds like `1800s`
+pub const DURATION_PLACEHOLDER: &str = "{duration}";
+/// what's replaced by the run's id in a silence command, which is the name of its run directory
+pub const RUN_ID_PLACEHOLDER: &str = "{run_id}";
+/// what's replaced by what the silence command wrote to stdout in the unsilence command, like the silence's id
+pub const SILENCE_OUTPUT_PLACEHOLDER: &str = "{silence_output}";
+/// how long remotes are expected to be down for when neither they nor the silence settings say
+pub const DEFAULT_EXPECTED_DOWNTIME: Duration = Duration::from_secs(30 * 60);
+/// how long a silence command can take before it's given up on
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// what happens to a remote whose silence couldn't be created
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SilenceFailurePolicy {
+	/// warn and pupdate the remote anyway
+	#[default]
+	Warn,
+	/// skip the remote without pupdating it
+	Skip,
+}
+
+/// the silence settings from the config
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct SilenceSettings {
+	/// the command which silences monitoring for a remote before it's pupdated, run with `sh -c`
+	#[serde(default)]
+	pub command: Option<String>,
+	/// the command which expires the silence once the remote has finished, however it finished, run with `sh -c`
+	#[serde(default)]
+	pub unsilence_command: Option<String>,
+	/// how long remotes are expected to be down for, like `30m`, unless they have a downtime of their own
+	#[serde(default)]
+	pub expected_downtime: Option<String>,
+	/// what happens to a remote whose silence couldn't be created
+	#[serde(default)]
+	pub on_failure: Option<SilenceFailurePolicy>,
+}
+
+impl SilenceSettings {
+	/// merges settings from another config on top of these, with the other config's values winning
+	pub fn merge(&mut self, other: Self) {
+		if other.command.is_some() {
+			self.command = other.command;
+		}
+		if other.unsilence_command.is_some() {
+			self.unsilence_command = other.unsilence_command;
+		}
+		if other.expected_downtime.is_some() {
+			self.expected_downtime = other.expected_downtime;
+		}
+		if other.on_failure.is_some() {
+			self.on_failure = other.on_failure;
+		}
+	}
+}
+
+/// fills in a command template's placeholders with the given values, each quoted for the shell. the template is only
+/// scanned once, so placeholders in the values themselves are left alone, and braces which aren't a known placeholder
+/// are kept as they are
+pub fn fill_placeholders(template: &str, values: &[(&str, &str)]) -> String {
+	let mut filled = String::with_capacity(template.len());
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		filled.push_str(&rest[..start]);
+		rest = &rest[start..];
+		match values
+			.iter()
+			.find(|(placeholder, _)| rest.starts_with(placeholder))
+		{
+			Some((placeholder, value)) => {
+				filled.push_str(&quote(value));
+				rest = &rest[placeholder.len()..];
+			}
+			None => {
+				filled.push('{');
+				rest = &rest[1..];
+			}
+		}
+	}
+	filled.push_str(rest);
+	filled
+}
+
+/// silences monitoring for remotes while they're pupdated
+#[derive(Debug, Clone)]
+pub struct Silencer {
+	/// the command which silences a remote
+	command: String,
+	/// the command which expires a remote's silence, if there is one
+	unsilence_command: Option<String>,
+	/// how long remotes are expected to be down for unless they have a downtime of their own
+	downtime: Duration,
+	/// how long particular remotes are expected to be down for, by remote
+	remote_downtime: BTreeMap<String, Duration>,
+	/// what happens to a remote whose silence couldn't be created
+	pub on_failure: SilenceFailurePolicy,
+	/// the run's id
+	run_id: String,
+	/// what each silenced remote's silence command wrote to stdout, by remote, until it's unsilenced
+	silenced: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl Silencer {
+	/// checks the silence settings for a run with the given id, returning a silencer if there's a silence command
+	pub fn new(
+		settings: SilenceSettings,
+		remote_downtime: &BTreeMap<String, String>,
+		run_id: String,
+	) -> eyre::Result<Option<Self>> {
+		let Some(command) = settings.command else {
+			if settings.unsilence_command.is_some() {
+				eyre::bail!("silence.unsilence_command needs silence.command to be set too");
+			}
+			return Ok(None);
+		};
+		if command.contains(SILENCE_OUTPUT_PLACEHOLDER) {
+			eyre::bail!(
+				"silence.command can't use {SILENCE_OUTPUT_PLACEHOLDER}, which is only known once it's run"
+			);
+		}
+		let parse = |downtime: &str| {
+			parse_duration(downtime)
+				.map_err(|e| eyre::eyre!("invalid expected downtime `{downtime}`: {e}"))
+		};
+		Ok(Some(Self {
+			command,
+			unsilence_command: settings.unsilence_command,
+			downtime: settings
+				.expected_downtime
+				.as_deref()
+				.map(parse)
+				.transpose()?
+				.unwrap_or(DEFAULT_EXPECTED_DOWNTIME),
+			remote_downtime: remote_downtime
+				.iter()
+				.map(|(remote, downtime)| Ok((remote.clone(), parse(downtime)?)))
+				.collect::<eyre::Result<_>>()?,
+			on_failure: settings.on_failure.unwrap_or_default(),
+			run_id,
+			silenced: Arc::default(),
+		}))
+	}
+
+	/// fills in a command's placeholders for the given remote
+	fn command_for(&self, template: &str, remote: &str, silence_output: &str) -> String {
+		let downtime = self.remote_downtime.get(remote).unwrap_or(&self.downtime);
+		fill_placeholders(
+			template,
+			&[
+				(REMOTE_PLACEHOLDER, remote),
+				(DURATION_PLACEHOLDER, &format!("{}s", downtime.as_secs())),
+				(RUN_ID_PLACEHOLDER, &self.run_id),
+				(SILENCE_OUTPUT_PLACEHOLDER, silence_output),
+			],
+		)
+	}
+
+	/// silences monitoring for a remote, keeping what the command wrote to stdout for unsilencing it later
+	pub async fn silence(&self, remote: &str) -> eyre::Result<()> {
+		let output = run(&self.command_for(&self.command, remote, "")).await?;
+		self.silenced
+			.lock()
+			.unwrap()
+			.insert(remote.to_string(), output);
+		Ok(())
+	}
+
+	/// expires a remote's silence if it was silenced and hasn't been unsilenced yet, returning whether it was
+	pub async fn unsilence(&self, remote: &str) -> eyre::Result<bool> {
+		let Some(output) = self.silenced.lock().unwrap().remove(remote) else {
+			return Ok(false);
+		};
+		if let Some(template) = &self.unsilence_command {
+			run(&self.command_for(template, remote, &output)).await?;
+		}
+		Ok(true)
+	}
+}
+
+/// runs a command line with `sh -c`, returning what it wrote to stdout without surrounding whitespace
+async fn run(command: &str) -> eyre::Result<String> {
+	let output = Command::new("sh")
+		.arg("-c")
+		.arg(command)
+		.stdin(Stdio::null())
+		.kill_on_drop(true)
+		.output();
+	let output = tokio::time::timeout(SILENCE_TIMEOUT, output)
+		.await
+		.map_err(|_| {
+			eyre::eyre!(
+				"`{command}` didn't finish within {} seconds",
+				SILENCE_TIMEOUT.as_secs()
+			)
+		})?
+		.map_err(|e| eyre::eyre!("failed to run `{command}`: {e}"))?;
+	if !output.status.success() {
+		let stderr = output_tail(&output.stderr);
+		if stderr.is_empty() {
+			eyre::bail!("`{command}` failed ({})", output.status);
+		}
+		eyre::bail!("`{command}` failed ({}): {stderr}", output.status);
+	}
+	Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn placeholders_are_quoted() {
+		assert_eq!(
+			fill_placeholders(
+				"amtool silence add instance={remote} --duration={duration} --comment=${USER} {x}",
+				&[
+					(REMOTE_PLACEHOLDER, "web1; rm -rf /"),
+					(DURATION_PLACEHOLDER, "1800s")
+				],
+			),
+			"amtool silence add instance='web1; rm -rf /' --duration=1800s --comment=${USER} {x}"
+		);
+		// values aren't scanned for placeholders again
+		assert_eq!(
+			fill_placeholders(
+				"{remote} {run_id}",
+				&[
+					(REMOTE_PLACEHOLDER, "{run_id}"),
+					(RUN_ID_PLACEHOLDER, "run")
+				],
+			),
+			"'{run_id}' run"
+		);
+	}
+
+	#[tokio::test]
+	async fn silences_are_expired_with_their_output() {
+		let dir = std::env::temp_dir().join(format!("pupdate-silence-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let expired = dir.join("expired");
+		let settings = SilenceSettings {
+			command: Some("echo silence-{remote}-{duration}".to_string()),
+			unsilence_command: Some(format!(
+				"echo {{silence_output}} {{run_id}} > {}",
+				expired.display()
+			)),
+			expected_downtime: Some("1h".to_string()),
+			on_failure: None,
+		};
+		let downtime = BTreeMap::from([("db1".to_string(), "5m".to_string())]);
+		let silencer = Silencer::new(settings, &downtime, "run1".to_string())
+			.unwrap()
+			.unwrap();
+		silencer.silence("db1").await.unwrap();
+		assert!(silencer.unsilence("db1").await.unwrap());
+		assert_eq!(
+			std::fs::read_to_string(&expired).unwrap(),
+			"silence-db1-300s run1\n"
+		);
+		// a remote is only unsilenced once
+		assert!(!silencer.unsilence("db1").await.unwrap());
+		assert!(silencer.unsilence("web1").await.is_ok_and(|done| !done));
+
+		let failing = SilenceSettings {
+			command: Some("echo nope >&2; exit 3".to_string()),
+			..Default::default()
+		};
+		let silencer = Silencer::new(failing, &BTreeMap::new(), "run1".to_string())
+			.unwrap()
+			.unwrap();
+		let error = silencer.silence("web1").await.unwrap_err().to_string();
+		assert!(error.contains("nope"), "{error}");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,124 @@
+use std::{
+	collections::BTreeMap,
+	fmt,
+	io::{IsTerminal, Read},
+	path::{Path, PathBuf},
+};
+
+/// what's given instead of a remote or a path to read remotes from stdin
+pub const STDIN: &str = "-";
+
+/// where a remote to act on came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteSource {
+	/// given as an argument
+	Cli,
+	/// read from stdin
+	Stdin,
+	/// read from the given file
+	File(PathBuf),
+	/// printed by `--remotes-from-command`
+	Command,
+	/// configured in the config
+	Config,
+	/// listed in a phase of the playbook
+	Playbook,
+}
+
+impl fmt::Display for RemoteSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Cli => write!(f, "cli"),
+			Self::Stdin => write!(f, "stdin"),
+			Self::File(path) => write!(f, "file {}", path.display()),
+			Self::Command => write!(f, "command"),
+			Self::Config => write!(f, "config"),
+			Self::Playbook => write!(f, "playbook"),
+		}
+	}
+}
+
+impl serde::Serialize for RemoteSource {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+/// parses a list of remotes, one per line, skipping blank lines and comments starting with `#`
+pub fn parse_remote_lines(contents: &str) -> Vec<String> {
+	contents
+		.lines()
+		.map(|line| line.split('#').next().unwrap_or_default().trim())
+		.filter(|line| !line.is_empty())
+		.map(str::to_string)
+		.collect()
+}
+
+/// reads a list of remotes from the given file, or from stdin if it's `-`, erroring if it doesn't list any so a broken
+/// pipeline can't quietly turn into a local-only run
+pub fn read_remotes(path: &Path) -> eyre::Result<(Vec<String>, RemoteSource)> {
+	let (contents, source) = if path == Path::new(STDIN) {
+		// a terminal would otherwise be waited on for a list which was meant to be piped in
+		if std::io::stdin().is_terminal() {
+			eyre::bail!("remotes were to be read from stdin, but nothing was piped to it");
+		}
+		let mut contents = String::new();
+		std::io::stdin()
+			.read_to_string(&mut contents)
+			.map_err(|e| eyre::eyre!("failed to read remotes from stdin: {e}"))?;
+		(contents, RemoteSource::Stdin)
+	} else {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| eyre::eyre!("failed to read remotes from {}: {e}", path.display()))?;
+		(contents, RemoteSource::File(path.to_path_buf()))
+	};
+	let remotes = parse_remote_lines(&contents);
+	if remotes.is_empty() {
+		eyre::bail!("no remotes were read from {source}");
+	}
+	Ok((remotes, source))
+}
+
+/// the remotes to act on in the order they were resolved, each once, along with where each came from
+#[derive(Debug, Default)]
+pub struct ResolvedRemotes {
+	/// the remotes, in the order they were resolved
+	pub remotes: Vec<String>,
+	/// where each remote came from, by remote, the first source it came from when it came from several
+	pub sources: BTreeMap<String, RemoteSource>,
+}
+
+impl ResolvedRemotes {
+	/// adds remotes which came from the given source, leaving out ones which were already added
+	pub fn add(&mut self, remotes: impl IntoIterator<Item = String>, source: RemoteSource) {
+		for remote in remotes {
+			if !self.sources.contains_key(&remote) {
+				self.sources.insert(remote.clone(), source.clone());
+				self.remotes.push(remote);
+			}
+		}
+	}
+
+	/// keeps only the given remotes, in the given order
+	pub fn keep(&mut self, remotes: Vec<String>) {
+		self.sources.retain(|remote, _| remotes.contains(remote));
+		self.remotes = remotes;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn remote_lists_are_parsed_and_merged() {
+		let read = parse_remote_lines("# from consul\nweb1\n\n  web2  # canary\nweb1\n");
+		assert_eq!(read, ["web1", "web2", "web1"]);
+		let mut resolved = ResolvedRemotes::default();
+		resolved.add(read, RemoteSource::Stdin);
+		resolved.add(["web2", "db1"].map(str::to_string), RemoteSource::Config);
+		assert_eq!(resolved.remotes, ["web1", "web2", "db1"]);
+		assert_eq!(resolved.sources["web2"], RemoteSource::Stdin);
+		assert_eq!(resolved.sources["db1"].to_string(), "config");
+	}
+}
@@ -0,0 +1,290 @@
+//! the state of a run as it happens, kept in the run directory so a run which was interrupted can be resumed
+//!
+//! the state is written again each time a remote or the local system starts or finishes, always by replacing the file
+//! at once so a crash can't leave it half written
+
+use std::{
+	collections::BTreeMap,
+	io::Write,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::summary::{LocalSummary, TargetSummary};
+
+/// the name of the state file written to the run directory
+pub const STATE_FILENAME: &str = "state.json";
+
+/// how far a target got in a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+	/// the target hasn't been started yet
+	Pending,
+	/// the target was started and hasn't finished, which for an interrupted run means it may be partly pupdated
+	Running,
+	/// the target finished successfully
+	Succeeded,
+	/// the target finished unsuccessfully, errored, or was cancelled
+	Failed,
+}
+
+impl Status {
+	/// the status of a target which finished
+	pub fn finished(success: bool) -> Self {
+		if success {
+			Self::Succeeded
+		} else {
+			Self::Failed
+		}
+	}
+}
+
+/// how far a target got in a run, along with its result once it's finished
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetState<T> {
+	/// how far the target got
+	pub status: Status,
+	/// the target's result, once it's finished
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub result: Option<T>,
+}
+
+impl<T> TargetState<T> {
+	/// the state of a target which hasn't been started yet
+	fn pending() -> Self {
+		Self {
+			status: Status::Pending,
+			result: None,
+		}
+	}
+}
+
+/// how far each target got in a run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunState {
+	/// the arguments pupdate was run with, so the run can be resumed the same way
+	#[serde(default)]
+	pub arguments: Vec<String>,
+	/// how far each remote got, by remote
+	#[serde(default)]
+	pub remotes: BTreeMap<String, TargetState<TargetSummary>>,
+	/// how far the local system got, if it's being pupdated
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub local: Option<TargetState<LocalSummary>>,
+}
+
+/// a run's state file, written again whenever the state changes
+#[derive(Debug)]
+pub struct StateFile {
+	/// where the state is written
+	path: PathBuf,
+	/// the current state
+	state: Mutex<RunState>,
+	/// the first error writing the state, if there was one
+	error: Mutex<Option<String>>,
+}
+
+impl StateFile {
+	/// starts the state of a run in the given run directory, with every remote pending, along with the local system
+	/// if it's being pupdated
+	pub fn create<'a>(
+		dir: &Path,
+		remotes: impl IntoIterator<Item = &'a String>,
+		local: bool,
+	) -> eyre::Result<Self> {
+		let state = RunState {
+			arguments: std::env::args_os()
+				.map(|arg| arg.to_string_lossy().into_owned())
+				.collect(),
+			remotes: remotes
+				.into_iter()
+				.map(|remote| (remote.clone(), TargetState::pending()))
+				.collect(),
+			local: local.then(TargetState::pending),
+		};
+		let path = dir.join(STATE_FILENAME);
+		write_state(&path, &state)?;
+		Ok(Self {
+			path,
+			state: Mutex::new(state),
+			error: Mutex::new(None),
+		})
+	}
+
+	/// opens the state of an earlier run in the given run directory
+	pub fn open(dir: &Path) -> eyre::Result<Self> {
+		let path = dir.join(STATE_FILENAME);
+		let state = match std::fs::read_to_string(&path) {
+			Ok(contents) => serde_json::from_str(&contents)
+				.map_err(|e| eyre::eyre!("failed to parse run state {}: {e}", path.display()))?,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				eyre::bail!(
+					"{} has no {STATE_FILENAME}, so it can't be resumed",
+					dir.display()
+				)
+			}
+			Err(e) => eyre::bail!("failed to read run state {}: {e}", path.display()),
+		};
+		Ok(Self {
+			path,
+			state: Mutex::new(state),
+			error: Mutex::new(None),
+		})
+	}
+
+	/// gets the current state
+	pub fn state(&self) -> RunState {
+		self.state.lock().expect("run state isn't poisoned").clone()
+	}
+
+	/// gets the first error writing the state, if there was one
+	pub fn error(&self) -> Option<String> {
+		self.error.lock().expect("run state isn't poisoned").clone()
+	}
+
+	/// marks a remote as started
+	pub fn remote_started(&self, remote: &str) {
+		self.update(|state| {
+			state.remotes.insert(
+				remote.to_string(),
+				TargetState {
+					status: Status::Running,
+					result: None,
+				},
+			);
+		});
+	}
+
+	/// marks a remote as finished with the given result
+	pub fn remote_finished(&self, remote: &str, result: &TargetSummary) {
+		self.update(|state| {
+			state.remotes.insert(
+				remote.to_string(),
+				TargetState {
+					status: Status::finished(result.success),
+					result: Some(result.clone()),
+				},
+			);
+		});
+	}
+
+	/// marks the local system as started
+	pub fn local_started(&self) {
+		self.update(|state| {
+			state.local = Some(TargetState {
+				status: Status::Running,
+				result: None,
+			});
+		});
+	}
+
+	/// marks the local system as finished with the given result
+	pub fn local_finished(&self, result: &LocalSummary) {
+		self.update(|state| {
+			state.local = Some(TargetState {
+				status: Status::finished(result.success),
+				result: Some(result.clone()),
+			});
+		});
+	}
+
+	/// changes the state and writes it, keeping the first error rather than failing a remote over its state
+	fn update(&self, change: impl FnOnce(&mut RunState)) {
+		// the lock is held while writing so writes land in the order the changes were made
+		let mut state = self.state.lock().expect("run state isn't poisoned");
+		change(&mut state);
+		if let Err(e) = write_state(&self.path, &state) {
+			self.error
+				.lock()
+				.expect("run state isn't poisoned")
+				.get_or_insert(e.to_string());
+		}
+	}
+}
+
+/// writes a run's state to the given path, replacing the file at once so a crash can't leave it half written
+fn write_state(path: &Path, state: &RunState) -> eyre::Result<()> {
+	let temp = path.with_extension("json.tmp");
+	let json = serde_json::to_string_pretty(state)?;
+	replace_file(path, &temp, json.as_bytes())
+		.map_err(|e| eyre::eyre!("failed to write run state {}: {e}", path.display()))
+}
+
+/// replaces the file at `path` with the given contents by way of `temp`, syncing both the new file and the directory
+/// holding it so the state is on disk, not just in the page cache, when a power loss follows
+fn replace_file(path: &Path, temp: &Path, contents: &[u8]) -> std::io::Result<()> {
+	let mut file = std::fs::File::create(temp)?;
+	file.write_all(contents)?;
+	file.sync_all()?;
+	drop(file);
+	std::fs::rename(temp, path)?;
+	// directories can only be opened to sync them on unix
+	#[cfg(unix)]
+	if let Some(dir) = path.parent() {
+		let dir = if dir.as_os_str().is_empty() {
+			Path::new(".")
+		} else {
+			dir
+		};
+		std::fs::File::open(dir)?.sync_all()?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn state_survives_a_round_trip() {
+		let mut state = RunState {
+			arguments: vec!["pupdate".to_string(), "--skip-local".to_string()],
+			remotes: ["web1", "web2", "web3"]
+				.into_iter()
+				.map(|remote| (remote.to_string(), TargetState::pending()))
+				.collect(),
+			local: Some(TargetState::pending()),
+		};
+		state.remotes.insert(
+			"web1".to_string(),
+			TargetState {
+				status: Status::Succeeded,
+				result: Some(TargetSummary {
+					success: true,
+					duration_seconds: Some(12.5),
+					..Default::default()
+				}),
+			},
+		);
+		state.remotes.get_mut("web2").unwrap().status = Status::Running;
+
+		let state: RunState =
+			serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+		let statuses: Vec<_> = state
+			.remotes
+			.iter()
+			.map(|(remote, target)| (remote.as_str(), target.status))
+			.collect();
+		assert_eq!(
+			statuses,
+			[
+				("web1", Status::Succeeded),
+				("web2", Status::Running),
+				("web3", Status::Pending),
+			]
+		);
+		assert_eq!(
+			state.remotes["web1"]
+				.result
+				.as_ref()
+				.unwrap()
+				.duration_seconds,
+			Some(12.5)
+		);
+		assert_eq!(state.arguments, ["pupdate", "--skip-local"]);
+		assert_eq!(state.local.unwrap().status, Status::Pending);
+	}
+}
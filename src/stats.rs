@@ -0,0 +1,196 @@
+use std::{path::Path, time::Duration};
+
+use pupdate::{duration::format_age, history::History};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// a remote's history as printed by `pupdate history --json`
+#[derive(Debug, Serialize)]
+struct RemoteEntry<'a> {
+	/// the remote
+	remote: &'a str,
+	/// how many seconds ago the remote was last pupdated successfully, if it ever was
+	#[serde(skip_serializing_if = "Option::is_none")]
+	since_last_success_seconds: Option<u64>,
+	/// how many runs in a row have failed to pupdate the remote
+	consecutive_failures: u32,
+	/// the change ticket the latest run to pupdate the remote was tagged with
+	#[serde(skip_serializing_if = "Option::is_none")]
+	last_change_id: Option<&'a str>,
+}
+
+/// prints what's remembered about each remote, or with `stats` the remembered runs added up by week, either as a
+/// table or as json
+pub fn print_history(path: &Path, stats: bool, json: bool) -> eyre::Result<()> {
+	let history = History::load(path)?;
+	if stats {
+		let weeks = history.weekly_stats();
+		if json {
+			println!("{}", serde_json::to_string_pretty(&weeks)?);
+		} else if weeks.is_empty() {
+			println!("no runs are recorded in {}", path.display());
+		} else {
+			println!(
+				"{:<8}  {:>4}  {:>7}  {:>12}  {:>8}  {:>7}",
+				"week", "runs", "remotes", "per remote", "failures", "speedup"
+			);
+			for week in weeks {
+				println!(
+					"{:<8}  {:>4}  {:>7.1}  {:>12}  {:>7.1}%  {:>6.1}x",
+					week.week,
+					week.runs,
+					week.average_remotes,
+					format_age(Duration::from_secs_f64(week.average_remote_seconds)),
+					week.failure_rate * 100.0,
+					week.speedup
+				);
+			}
+		}
+		return Ok(());
+	}
+
+	let now = OffsetDateTime::now_utc();
+	let entries: Vec<_> = history
+		.remotes
+		.iter()
+		.map(|(remote, entry)| RemoteEntry {
+			remote,
+			since_last_success_seconds: history
+				.since_last_success(remote, now)
+				.map(|age| age.as_secs()),
+			consecutive_failures: entry.consecutive_failures,
+			last_change_id: entry.last_change_id.as_deref(),
+		})
+		.collect();
+	if json {
+		println!("{}", serde_json::to_string_pretty(&entries)?);
+	} else if entries.is_empty() {
+		println!("no remotes are recorded in {}", path.display());
+	} else {
+		for entry in entries {
+			let last_success = match entry.since_last_success_seconds {
+				Some(age) => format!("last pupdated {} ago", format_age(Duration::from_secs(age))),
+				None => "never pupdated successfully".to_string(),
+			};
+			let mut line = format!("{}: {last_success}", entry.remote);
+			if entry.consecutive_failures > 0 {
+				line.push_str(&format!(
+					", {} failures in a row",
+					entry.consecutive_failures
+				));
+			}
+			if let Some(change_id) = entry.last_change_id {
+				line.push_str(&format!(", last tagged {change_id}"));
+			}
+			println!("{line}");
+		}
+	}
+	Ok(())
+}
+
+/// how many runs in a row a remote has to have had packages kept back to be listed by `pupdate history --kept-back`,
+/// since a package kept back once is often just waiting on a phased rollout
+const KEPT_BACK_RUNS: u32 = 2;
+
+/// a remote which has had packages kept back run after run, as printed by `pupdate history --kept-back --json`
+#[derive(Debug, Serialize)]
+struct KeptBackEntry<'a> {
+	/// the remote
+	remote: &'a str,
+	/// how many runs in a row have had packages kept back on the remote
+	consecutive_runs: u32,
+	/// the packages kept back in the latest run
+	kept_back: &'a [String],
+}
+
+/// prints the remotes which have had packages kept back in more than one run in a row, which won't get those packages
+/// upgraded without someone looking into why
+pub fn print_kept_back(path: &Path, json: bool) -> eyre::Result<()> {
+	let history = History::load(path)?;
+	let entries: Vec<_> = history
+		.remotes
+		.iter()
+		.filter(|(_, entry)| entry.consecutive_kept_back >= KEPT_BACK_RUNS)
+		.map(|(remote, entry)| KeptBackEntry {
+			remote,
+			consecutive_runs: entry.consecutive_kept_back,
+			kept_back: &entry.kept_back,
+		})
+		.collect();
+	if json {
+		println!("{}", serde_json::to_string_pretty(&entries)?);
+	} else if entries.is_empty() {
+		println!("no remotes have had packages kept back in {KEPT_BACK_RUNS} runs in a row");
+	} else {
+		println!("remotes with packages kept back run after run:");
+		for entry in entries {
+			println!(
+				"{}: {} runs in a row, {}",
+				entry.remote,
+				entry.consecutive_runs,
+				entry.kept_back.join(", ")
+			);
+		}
+	}
+	Ok(())
+}
+
+/// a best effort remote which hasn't been pupdated successfully for too long, as printed by
+/// `pupdate history --stale --json`
+#[derive(Debug, Serialize)]
+struct StaleEntry<'a> {
+	/// the remote
+	remote: &'a str,
+	/// how many seconds ago the remote was last pupdated successfully, or none if it never was
+	since_last_success_seconds: Option<u64>,
+}
+
+/// prints the given best effort remotes which haven't been pupdated successfully within `stale`, including ones which
+/// never were, so remotes which are rarely online don't go without updates unnoticed
+pub fn print_stale(
+	path: &Path,
+	best_effort: &[&str],
+	stale: Duration,
+	json: bool,
+) -> eyre::Result<()> {
+	let history = History::load(path)?;
+	let now = OffsetDateTime::now_utc();
+	let entries: Vec<_> = best_effort
+		.iter()
+		.map(|remote| StaleEntry {
+			remote,
+			since_last_success_seconds: history
+				.since_last_success(remote, now)
+				.map(|age| age.as_secs()),
+		})
+		.filter(|entry| {
+			entry
+				.since_last_success_seconds
+				.is_none_or(|age| age >= stale.as_secs())
+		})
+		.collect();
+	if json {
+		println!("{}", serde_json::to_string_pretty(&entries)?);
+	} else if entries.is_empty() {
+		println!(
+			"every best effort remote was pupdated successfully in the last {}",
+			format_age(stale)
+		);
+	} else {
+		println!(
+			"best effort remotes not pupdated successfully in the last {}:",
+			format_age(stale)
+		);
+		for entry in entries {
+			match entry.since_last_success_seconds {
+				Some(age) => println!(
+					"{}: last pupdated {} ago",
+					entry.remote,
+					format_age(Duration::from_secs(age))
+				),
+				None => println!("{}: never pupdated successfully", entry.remote),
+			}
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,413 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	approval::ApprovalDecision,
+	diagnostics::Diagnostics,
+	logs::{LogFormats, LogPaths},
+	output::{OutputLimits, SUDO_PASSWORD_REQUIRED},
+	remote::Invocation,
+	schema::SUMMARY_SCHEMA_VERSION,
+	verify::BinaryVerification,
+};
+
+/// the name of the summary file written to the run directory
+pub const SUMMARY_FILENAME: &str = "summary.json";
+
+/// the result of pupdating a single target
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetSummary {
+	/// whether the pupdate succeeded
+	pub success: bool,
+	/// how long the pupdate took in seconds, including retries, if it ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration_seconds: Option<f64>,
+	/// the lines in which apt warned about a repository's signature, like a missing or expired key
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub repo_warnings: Vec<String>,
+	/// the packages apt kept back instead of upgrading
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub kept_back: Vec<String>,
+	/// how long the remote went without writing any output before it finished in seconds, if it ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub silent_seconds: Option<f64>,
+	/// how long the remote took to write its first output in seconds, from the start of its last attempt, which is
+	/// mostly the time spent connecting, if it wrote anything
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub connect_seconds: Option<f64>,
+	/// how long the remote's last attempt took from connecting to finishing in seconds, leaving out checks made before
+	/// it and earlier attempts, if it ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub command_seconds: Option<f64>,
+	/// why the remote's output makes it look like it stalled, if it was checked with `--max-output-age` and does
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub suspicious: Option<String>,
+	/// whether the remote is best effort and couldn't be reached, so it isn't counted as failed
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub offline: bool,
+	/// the remote's maintenance window, if it was skipped for being outside it, so it isn't counted as failed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub outside_window: Option<String>,
+	/// whether the remote was declined at the prompt or not approved, so it isn't counted as failed
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub declined: bool,
+	/// the diagnostics captured once the remote failed to pupdate, if any were configured
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub diagnostics: Option<Diagnostics>,
+	/// how long the remote waited for its turn under the run's concurrency limits in seconds, if any applied to it
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub queued_seconds: Option<f64>,
+	/// the logs written for the target, if any
+	pub logs: Option<LogPaths>,
+	/// the mirror hostnames contacted, if sources were being reported
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sources: Option<Vec<String>>,
+	/// the tail of stderr, kept for failed targets
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stderr_tail: Option<String>,
+	/// whether the pupdate was cancelled before it finished
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub cancelled: bool,
+	/// the remote's own logs fetched into the local run directory, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fetched_logs: Option<Vec<PathBuf>>,
+	/// why fetching the remote's own logs failed, if it did
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fetch_error: Option<String>,
+	/// why the target was skipped without being pupdated, if it was
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub skipped: Option<String>,
+	/// whether the pupdate succeeded without anything needing an upgrade
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub up_to_date: bool,
+	/// the services still running code replaced by the update, if they were checked
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub services_needing_restart: Option<Vec<String>>,
+	/// whether the services needing a restart were restarted
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub services_restarted: bool,
+	/// why checking or restarting services failed, if it did
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub services_error: Option<String>,
+	/// whether some of the output was dropped for going over the output limit, leaving only the start and the tail
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub output_truncated: bool,
+	/// how many bytes of stdout were kept, if the pupdate ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stdout_bytes: Option<u64>,
+	/// how many bytes of stderr were kept, if the pupdate ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stderr_bytes: Option<u64>,
+	/// the result of checking the remote's binary before running it, if it was checked
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub binary: Option<BinaryVerification>,
+	/// the error which stopped the pupdate from running to completion, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+	/// the command line run on the remote, including forwarded flags, if a command was run
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub command: Option<String>,
+	/// exactly what was spawned to run the command on the remote, with secret environment values masked, if it's known
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub invocation: Option<Invocation>,
+	/// the version of pupdate on the remote, if it was needed to forward flags and could be found
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub pupdate_version: Option<String>,
+	/// the flags which weren't forwarded because the remote's pupdate wouldn't know them
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub flags_omitted: Vec<String>,
+	/// why the result couldn't be posted to the per-remote webhook, if it couldn't
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub webhook_error: Option<String>,
+	/// why monitoring couldn't be silenced for the remote or its silence couldn't be expired, if either failed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub silence_error: Option<String>,
+	/// whether the remote was approved and by whom, if it needed approving
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub approval: Option<ApprovalDecision>,
+	/// how many seconds the remote's clock was ahead of the controller's, or behind if negative, if it was checked
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub clock_skew_seconds: Option<i64>,
+	/// the risky packages with updates pending, if risky updates were checked for and any were found
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub risky_updates: Vec<String>,
+	/// how the pupdate failed, if it ran to completion and failed
+	#[serde(flatten)]
+	pub failure: Option<Failure>,
+	/// everything written to stdout, kept when the caller needs to inspect it
+	#[serde(skip)]
+	pub stdout: Option<Vec<u8>>,
+}
+
+impl TargetSummary {
+	/// the summary of a target whose pupdate was cancelled
+	pub fn cancelled() -> Self {
+		Self {
+			cancelled: true,
+			..Default::default()
+		}
+	}
+
+	/// the summary of a target whose pupdate errored before it could finish
+	pub fn errored(error: impl ToString) -> Self {
+		Self {
+			error: Some(error.to_string()),
+			..Default::default()
+		}
+	}
+
+	/// whether the target counts as failed, leaving out remotes which weren't expected to be pupdated: best effort
+	/// ones which were offline, ones outside their maintenance window, and ones someone declined
+	pub fn failed(&self) -> bool {
+		!self.success && !self.offline && self.outside_window.is_none() && !self.declined
+	}
+
+	/// how many bytes of output were kept in all, if the pupdate ran to completion
+	pub fn output_bytes(&self) -> Option<u64> {
+		Some(self.stdout_bytes? + self.stderr_bytes?)
+	}
+
+	/// checks whether the target's output makes it look like it stalled: that it wrote nothing at all, or went without
+	/// writing anything for longer than the given age before it finished
+	pub fn stalled(&self, max_output_age: Duration) -> Option<String> {
+		if self.output_bytes()? == 0 {
+			return Some("wrote no output".to_string());
+		}
+		let silent = self.silent_seconds?;
+		(silent > max_output_age.as_secs_f64()).then(|| {
+			format!(
+				"wrote nothing for the last {} seconds before finishing",
+				silent.round()
+			)
+		})
+	}
+}
+
+/// what kind of failure a pupdate ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+	/// ssh couldn't connect to or authenticate with the remote
+	Connection,
+	/// ssh couldn't connect to or authenticate with the jump host the remote is reached through
+	JumpHost,
+	/// the pupdate command ran and exited unsuccessfully
+	Command,
+	/// the pupdate was killed by a signal
+	Signal,
+	/// the pupdate was killed for writing more output than the kill limit
+	OutputLimit,
+	/// the pupdate was killed for running longer than the timeout
+	Timeout,
+	/// sudo needed a password but had no way to ask for one
+	SudoPassword,
+	/// the pupdate succeeded, but apt warned about a repository's signature and repository warnings are failures
+	RepoWarnings,
+	/// the pupdate succeeded, but apt kept back packages and kept back packages are failures
+	KeptBack,
+}
+
+/// how a pupdate which ran to completion failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+	/// what kind of failure it was
+	#[serde(rename = "failure")]
+	pub kind: FailureKind,
+	/// the exit code, if the pupdate exited on its own
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub exit_code: Option<i32>,
+	/// the signal which killed the pupdate, if one did
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub signal: Option<i32>,
+}
+
+impl fmt::Display for Failure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match (self.kind, self.exit_code, self.signal) {
+			(FailureKind::Connection, _, _) => write!(f, "connection/authentication error"),
+			(FailureKind::JumpHost, _, _) => {
+				write!(f, "connection/authentication error at the jump host")
+			}
+			(FailureKind::Signal, _, Some(signal)) => write!(f, "killed by signal {signal}"),
+			(FailureKind::OutputLimit, _, _) => write!(f, "output limit exceeded"),
+			(FailureKind::Timeout, _, _) => write!(f, "timed out"),
+			(FailureKind::RepoWarnings, _, _) => {
+				write!(f, "apt warned about repository signatures")
+			}
+			(FailureKind::KeptBack, _, _) => write!(f, "apt kept back packages"),
+			// this starts with sudo's own message so a controller can spot it in a remote pupdate's output too
+			(FailureKind::SudoPassword, _, _) => {
+				write!(
+					f,
+					"{SUDO_PASSWORD_REQUIRED}, this host needs passwordless sudo"
+				)
+			}
+			(_, Some(code), _) => write!(f, "update command failed with exit code {code}"),
+			_ => write!(f, "update command failed"),
+		}
+	}
+}
+
+/// the result of running a single local step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepSummary {
+	/// the name of the step
+	pub name: String,
+	/// the step's result
+	#[serde(flatten)]
+	pub result: TargetSummary,
+}
+
+/// the result of pupdating the local system
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LocalSummary {
+	/// whether every step succeeded
+	pub success: bool,
+	/// why the local system was skipped without being pupdated, if it was
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub skipped: Option<String>,
+	/// the risky packages with updates pending, if risky updates were checked for and any were found
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub risky_updates: Vec<String>,
+	/// whether every step succeeded without apt upgrading, installing, or removing anything
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub up_to_date: bool,
+	/// the mirror hostnames contacted across all steps, if sources were being reported
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sources: Option<Vec<String>>,
+	/// results for each step that was run, in order
+	pub steps: Vec<StepSummary>,
+}
+
+/// the version of the schema a summary follows, which is always the current one for summaries written by this build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+impl Default for SchemaVersion {
+	fn default() -> Self {
+		Self(SUMMARY_SCHEMA_VERSION)
+	}
+}
+
+/// the summary of a run, written to the log directory
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+	/// the version of the summary's schema, printed by `pupdate schema summary`
+	pub schema_version: SchemaVersion,
+	/// results for each remote, by remote name
+	pub remotes: BTreeMap<String, TargetSummary>,
+	/// results for the local system, if it was pupdated
+	pub local: Option<LocalSummary>,
+	/// how much of each remote's output was kept, if any remotes were pupdated
+	#[serde(flatten, skip_serializing_if = "Option::is_none")]
+	pub output_limits: Option<OutputLimits>,
+	/// the formats the run's logs were written in, unless its output was merged
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub log_format: Option<LogFormats>,
+	/// when and how the run was last resumed after being interrupted, if it was
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub resumed: Option<Resumed>,
+	/// the change ticket the run was tagged with, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change_id: Option<String>,
+	/// how each phase of the playbook went, in order, if the run followed one
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub phases: Vec<PhaseSummary>,
+	/// how much pupdating remotes concurrently saved, if any remotes were pupdated
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub parallelism: Option<Parallelism>,
+}
+
+/// how long pupdating remotes took from start to finish, against how long it would have taken one after another
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Parallelism {
+	/// how long pupdating every remote took added together
+	pub sequential_seconds: f64,
+	/// how long pupdating the remotes took from start to finish
+	pub wall_seconds: f64,
+}
+
+impl Parallelism {
+	/// how many times faster pupdating the remotes concurrently was than pupdating them one after another would have
+	/// been, if it took any time at all
+	pub fn speedup(&self) -> Option<f64> {
+		(self.wall_seconds > 0.0).then(|| self.sequential_seconds / self.wall_seconds)
+	}
+}
+
+/// how a phase of a playbook went
+#[derive(Debug, Serialize)]
+pub struct PhaseSummary {
+	/// the phase's name
+	pub name: String,
+	/// the phase's remotes which were pupdated or skipped with it
+	pub remotes: Vec<String>,
+	/// the phase's remotes which failed to pupdate
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub failed: Vec<String>,
+	/// how long the phase took, unless it was skipped
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration_seconds: Option<f64>,
+	/// why the phase was skipped, if it was
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub skipped: Option<String>,
+}
+
+/// how an interrupted run was resumed
+#[derive(Debug, Serialize)]
+pub struct Resumed {
+	/// when the run was resumed, in rfc3339
+	pub resumed_at: String,
+	/// the remotes pupdated again, because they hadn't finished successfully
+	pub remotes: Vec<String>,
+	/// the remotes which were still running when the run was interrupted, so they may have been partly pupdated
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub mid_flight: Vec<String>,
+	/// whether the local system was pupdated again
+	pub local: bool,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn declined_targets_arent_failed() {
+		assert!(TargetSummary::default().failed());
+		let declined = TargetSummary {
+			skipped: Some("declined at the prompt".to_string()),
+			declined: true,
+			..Default::default()
+		};
+		assert!(!declined.failed());
+		let outside_window = TargetSummary {
+			outside_window: Some("02:00-04:00".to_string()),
+			..Default::default()
+		};
+		assert!(!outside_window.failed());
+	}
+
+	#[test]
+	fn stalled_targets_are_found() {
+		let target = |bytes, silent_seconds| TargetSummary {
+			stdout_bytes: Some(bytes),
+			stderr_bytes: Some(0),
+			silent_seconds: Some(silent_seconds),
+			..Default::default()
+		};
+		let age = Duration::from_secs(60);
+		assert_eq!(
+			target(0, 5.0).stalled(age).as_deref(),
+			Some("wrote no output")
+		);
+		assert_eq!(
+			target(100, 300.0).stalled(age).as_deref(),
+			Some("wrote nothing for the last 300 seconds before finishing")
+		);
+		assert_eq!(target(100, 5.0).stalled(age), None);
+		assert_eq!(TargetSummary::cancelled().stalled(age), None);
+	}
+}
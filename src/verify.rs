@@ -0,0 +1,186 @@
+//! checking that the binary a remote is about to run is the one that was deployed
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{output::output_tail, remote::Runner, shell::command_line};
+
+/// the sha256 hashes remotes' binaries are expected to have
+#[derive(Debug, Clone)]
+pub struct BinaryCheck {
+	/// the program to check, as the remote command runs it
+	pub program: String,
+	/// the hash every remote's binary is expected to have, unless it has its own
+	pub expected_sha256: Option<String>,
+	/// the hashes particular remotes' binaries are expected to have, by remote
+	pub remote_sha256: BTreeMap<String, String>,
+	/// whether to skip the check for this run, only recording that it was skipped
+	pub skip: bool,
+}
+
+impl BinaryCheck {
+	/// gets the hash the given remote's binary is expected to have, if there is one
+	pub fn expected_for(&self, remote: &str) -> Option<&str> {
+		self.remote_sha256
+			.get(remote)
+			.or(self.expected_sha256.as_ref())
+			.map(String::as_str)
+	}
+}
+
+/// how checking a remote's binary turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationResult {
+	/// the binary had the expected hash
+	Verified,
+	/// the binary had another hash, so it wasn't run
+	Mismatch,
+	/// the binary couldn't be found or hashed, so it wasn't run
+	Error,
+	/// the check was skipped with `--no-verify`
+	Skipped,
+}
+
+/// the result of checking a remote's binary, recorded in the summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryVerification {
+	/// how the check turned out
+	pub result: VerificationResult,
+	/// the hash the binary was expected to have
+	pub expected_sha256: String,
+	/// the path the binary resolved to on the remote, if it was found
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub path: Option<String>,
+	/// the binary's actual hash, if it was hashed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sha256: Option<String>,
+	/// why the binary couldn't be found or hashed, if it couldn't
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl BinaryVerification {
+	/// whether the remote's binary can be run
+	pub fn passed(&self) -> bool {
+		matches!(
+			self.result,
+			VerificationResult::Verified | VerificationResult::Skipped
+		)
+	}
+}
+
+/// parses a sha256 hash given in hex, in either case
+pub fn parse_sha256(hash: &str) -> Result<String, String> {
+	let hash = hash.trim();
+	if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+		Ok(hash.to_ascii_lowercase())
+	} else {
+		Err(format!(
+			"`{hash}` isn't a sha256 hash, expected 64 hex digits"
+		))
+	}
+}
+
+/// parses the hash out of `sha256sum`'s output, which looks like `<hash>  <path>`
+fn parse_sha256sum(output: &[u8]) -> Option<String> {
+	let output = String::from_utf8_lossy(output);
+	parse_sha256(output.split_whitespace().next()?).ok()
+}
+
+/// resolves the program on the remote and hashes it, returning its path and hash
+async fn hash_binary<R: Runner>(
+	runner: &R,
+	remote: &str,
+	program: &str,
+) -> eyre::Result<(String, String)> {
+	let path = runner
+		.run(remote, &format!("command -v {}", command_line(&[program])))
+		.await?;
+	let resolved = String::from_utf8_lossy(&path.stdout).trim().to_string();
+	if !path.status.success() || resolved.is_empty() {
+		eyre::bail!("{program} wasn't found on the remote");
+	}
+	let hash = runner
+		.run(remote, &command_line(&["sha256sum", "--", &resolved]))
+		.await?;
+	if !hash.status.success() {
+		eyre::bail!("failed to hash {resolved}: {}", output_tail(&hash.stderr));
+	}
+	let hash = parse_sha256sum(&hash.stdout).ok_or_else(|| {
+		eyre::eyre!("sha256sum printed something other than a hash for {resolved}")
+	})?;
+	Ok((resolved, hash))
+}
+
+/// checks a remote's binary against the hash it's expected to have, if it's expected to have one
+pub async fn verify_binary<R: Runner>(
+	runner: &R,
+	remote: &str,
+	check: &BinaryCheck,
+) -> Option<BinaryVerification> {
+	let expected_sha256 = check.expected_for(remote)?.trim().to_ascii_lowercase();
+	if check.skip {
+		return Some(BinaryVerification {
+			result: VerificationResult::Skipped,
+			expected_sha256,
+			path: None,
+			sha256: None,
+			error: None,
+		});
+	}
+	Some(match hash_binary(runner, remote, &check.program).await {
+		Ok((path, sha256)) => BinaryVerification {
+			result: if sha256 == expected_sha256 {
+				VerificationResult::Verified
+			} else {
+				VerificationResult::Mismatch
+			},
+			expected_sha256,
+			path: Some(path),
+			sha256: Some(sha256),
+			error: None,
+		},
+		Err(e) => BinaryVerification {
+			result: VerificationResult::Error,
+			expected_sha256,
+			path: None,
+			sha256: None,
+			error: Some(e.to_string()),
+		},
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_sha256sum() {
+		let hash = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+		assert_eq!(
+			parse_sha256sum(format!("{hash}  /usr/bin/pupdate\n").as_bytes()),
+			Some(hash.to_ascii_lowercase())
+		);
+		assert_eq!(
+			parse_sha256sum(b"sha256sum: /usr/bin/pupdate: No such file"),
+			None
+		);
+		assert_eq!(parse_sha256sum(b""), None);
+		assert!(parse_sha256(hash).is_ok());
+		assert!(parse_sha256("9f86d081").is_err());
+	}
+
+	#[test]
+	fn remotes_can_expect_their_own_hash() {
+		let check = BinaryCheck {
+			program: "pupdate".to_string(),
+			expected_sha256: Some("a".to_string()),
+			remote_sha256: BTreeMap::from([("web1".to_string(), "b".to_string())]),
+			skip: false,
+		};
+		assert_eq!(check.expected_for("web1"), Some("b"));
+		assert_eq!(check.expected_for("web2"), Some("a"));
+	}
+}
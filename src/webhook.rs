@@ -0,0 +1,132 @@
+//! posting each remote's result to a webhook as soon as it finishes, so dashboards can follow a run as it happens
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+	output::output_tail,
+	retry::RetryPolicy,
+	summary::{Failure, TargetSummary},
+};
+
+/// how posts which fail are retried
+const WEBHOOK_RETRY: RetryPolicy = RetryPolicy {
+	retries: 2,
+	base: Duration::from_secs(1),
+	max: Duration::from_secs(4),
+};
+/// how many seconds a single post can take before it's given up on
+const WEBHOOK_TIMEOUT_SECS: &str = "10";
+
+/// a remote's result as it's posted to the webhook
+#[derive(Debug, Serialize)]
+pub struct RemoteReport<'a> {
+	/// the remote
+	pub remote: &'a str,
+	/// whether the pupdate succeeded
+	pub success: bool,
+	/// whether the pupdate succeeded without anything needing an upgrade
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pub up_to_date: bool,
+	/// how long the pupdate took in seconds, if it ran to completion
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration_seconds: Option<f64>,
+	/// how the pupdate failed, if it ran to completion and failed
+	#[serde(flatten)]
+	pub failure: Option<Failure>,
+	/// the error which stopped the pupdate from running to completion, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<&'a str>,
+	/// the change ticket the run was tagged with, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change_id: Option<&'a str>,
+}
+
+impl<'a> RemoteReport<'a> {
+	/// reports a remote's result from a run tagged with the given change ticket, if any
+	pub fn new(remote: &'a str, result: &'a TargetSummary, change_id: Option<&'a str>) -> Self {
+		Self {
+			remote,
+			success: result.success,
+			up_to_date: result.up_to_date,
+			duration_seconds: result.duration_seconds,
+			failure: result.failure,
+			error: result.error.as_deref(),
+			change_id,
+		}
+	}
+
+	/// reports a remote whose pupdate errored before it could finish, from a run tagged with the given change ticket, if
+	/// any
+	pub fn errored(remote: &'a str, error: &'a str, change_id: Option<&'a str>) -> Self {
+		Self {
+			remote,
+			success: false,
+			up_to_date: false,
+			duration_seconds: None,
+			failure: None,
+			error: Some(error),
+			change_id,
+		}
+	}
+}
+
+/// a webhook each remote's result is posted to with curl
+#[derive(Debug, Clone)]
+pub struct Webhook {
+	/// the curl executable to post with
+	pub curl: PathBuf,
+	/// the url to post to
+	pub url: String,
+}
+
+impl Webhook {
+	/// posts a remote's result as json, retrying a couple of times before giving up
+	pub async fn post(&self, report: &RemoteReport<'_>) -> eyre::Result<()> {
+		let body = serde_json::to_vec(report)?;
+		let mut retry = 0;
+		loop {
+			match self.post_once(&body).await {
+				Ok(()) => return Ok(()),
+				Err(e) if retry >= WEBHOOK_RETRY.retries => return Err(e),
+				Err(_) => {
+					tokio::time::sleep(WEBHOOK_RETRY.delay(retry)).await;
+					retry += 1;
+				}
+			}
+		}
+	}
+
+	/// posts the body once
+	async fn post_once(&self, body: &[u8]) -> eyre::Result<()> {
+		let mut child = Command::new(&self.curl)
+			.args([
+				"--silent",
+				"--show-error",
+				"--fail",
+				"--max-time",
+				WEBHOOK_TIMEOUT_SECS,
+				"--header",
+				"Content-Type: application/json",
+				"--data-binary",
+				"@-",
+				"--url",
+				&self.url,
+			])
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| eyre::eyre!("failed to run curl: {e}"))?;
+		let mut stdin = child.stdin.take().expect("stdin is piped");
+		stdin.write_all(body).await?;
+		drop(stdin);
+		let output = child.wait_with_output().await?;
+		if !output.status.success() {
+			eyre::bail!("curl failed: {}", output_tail(&output.stderr));
+		}
+		Ok(())
+	}
+}
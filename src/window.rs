@@ -0,0 +1,286 @@
+//! maintenance windows, the hours of the day pupdate is allowed to pupdate in
+//!
+//! windows can be given in a named time zone like `Europe/Berlin`, which follows the zone's daylight saving time, or
+//! a fixed utc offset
+
+use std::{collections::BTreeMap, fmt};
+
+use time::{OffsetDateTime, Time, UtcOffset};
+use time_tz::{timezones, OffsetDateTimeExt, TimeZone, Tz};
+
+/// the hours of the day pupdate is allowed to pupdate in, wrapping past midnight when it ends before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+	/// when the window opens
+	pub start: Time,
+	/// when the window closes
+	pub end: Time,
+	/// the time zone the window's times are in, or `None` for the local time zone
+	pub zone: Option<WindowZone>,
+}
+
+/// the time zone a window's times are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowZone {
+	/// a fixed utc offset, like `+01:00`
+	Offset(UtcOffset),
+	/// a named time zone from the tz database, like `Europe/Berlin`
+	Named(&'static Tz),
+}
+
+/// parses a time of day like `22:00`
+fn parse_time(s: &str) -> Option<Time> {
+	let (hour, minute) = s.split_once(':')?;
+	if minute.len() != 2 {
+		return None;
+	}
+	Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+/// parses a utc offset like `+01:00`, `-05:30`, or `utc`
+fn parse_offset(s: &str) -> Option<UtcOffset> {
+	if s.eq_ignore_ascii_case("utc") || s == "Z" {
+		return Some(UtcOffset::UTC);
+	}
+	let (sign, rest) = match s.split_at_checked(1)? {
+		("+", rest) => (1, rest),
+		("-", rest) => (-1, rest),
+		_ => return None,
+	};
+	let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "00"));
+	let (hours, minutes): (i8, i8) = (hours.parse().ok()?, minutes.parse().ok()?);
+	UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// formats a time of day like `22:00`
+pub fn format_time(time: Time) -> String {
+	format!("{:02}:{:02}", time.hour(), time.minute())
+}
+
+/// parses a window's time zone, either a utc offset or a named time zone like `Europe/Berlin`
+fn parse_zone(s: &str) -> Option<WindowZone> {
+	parse_offset(s)
+		.map(WindowZone::Offset)
+		.or_else(|| timezones::get_by_name(s).map(WindowZone::Named))
+}
+
+/// formats a window's time zone to follow its times, like ` +01:00`, ` utc`, or ` Europe/Berlin`, or nothing for the
+/// local time zone
+fn format_zone(zone: Option<WindowZone>) -> String {
+	match zone {
+		Some(WindowZone::Offset(offset)) if offset.is_utc() => " utc".to_string(),
+		Some(WindowZone::Offset(offset)) => {
+			let (hours, minutes, _) = offset.as_hms();
+			let sign = if offset.is_negative() { '-' } else { '+' };
+			format!(" {sign}{:02}:{:02}", hours.abs(), minutes.abs())
+		}
+		Some(WindowZone::Named(zone)) => format!(" {}", zone.name()),
+		None => String::new(),
+	}
+}
+
+impl MaintenanceWindow {
+	/// parses a window like `22:00-04:00`, optionally followed by the time zone its times are in, like
+	/// `22:00-04:00 Europe/Berlin`, `22:00-04:00 +01:00`, or `22:00-04:00 utc`
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let expected = || format!("expected a window like 22:00-04:00, got `{s}`");
+		let (times, zone) = match s.trim().split_once(char::is_whitespace) {
+			Some((times, zone)) => (times, Some(zone.trim())),
+			None => (s.trim(), None),
+		};
+		let (start, end) = times.split_once('-').ok_or_else(expected)?;
+		let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) else {
+			return Err(expected());
+		};
+		if start == end {
+			return Err(format!("`{s}` starts and ends at the same time"));
+		}
+		let zone = zone
+			.map(|zone| {
+				parse_zone(zone).ok_or_else(|| {
+					format!(
+						"unknown time zone `{zone}` in `{s}`, expected one like Europe/Berlin, +01:00, or utc"
+					)
+				})
+			})
+			.transpose()?;
+		Ok(Self { start, end, zone })
+	}
+
+	/// whether the given time of day, in the window's time zone, is inside the window
+	pub fn contains(&self, time: Time) -> bool {
+		if self.start < self.end {
+			self.start <= time && time < self.end
+		} else {
+			time >= self.start || time < self.end
+		}
+	}
+
+	/// the time of day it is at the given moment in the window's time zone, or in the local offset for windows without
+	/// one, unless the local offset isn't known
+	pub fn time_at(&self, now: OffsetDateTime, local_offset: Option<UtcOffset>) -> Option<Time> {
+		match self.zone {
+			Some(WindowZone::Offset(offset)) => Some(now.to_offset(offset).time()),
+			Some(WindowZone::Named(zone)) => Some(now.to_timezone(zone).time()),
+			None => Some(now.to_offset(local_offset?).time()),
+		}
+	}
+
+	/// describes when the window opens, like `opens 02:00 +01:00`
+	pub fn describe_opening(&self) -> String {
+		format!(
+			"opens {}{}",
+			format_time(self.start),
+			format_zone(self.zone)
+		)
+	}
+}
+
+/// finds which of the given remotes are outside their own maintenance windows at the given moment, by remote. windows
+/// in the local time zone can't be checked when the local offset isn't known, which is an error
+pub fn outside_windows<'a>(
+	remotes: impl IntoIterator<Item = &'a String>,
+	windows: &BTreeMap<String, String>,
+	now: OffsetDateTime,
+	local_offset: Option<UtcOffset>,
+) -> Result<BTreeMap<String, MaintenanceWindow>, String> {
+	let mut outside = BTreeMap::new();
+	for remote in remotes {
+		let Some(window) = windows.get(remote) else {
+			continue;
+		};
+		let window = MaintenanceWindow::parse(window)
+			.map_err(|e| format!("invalid maintenance window for {remote}: {e}"))?;
+		let time = window.time_at(now, local_offset).ok_or_else(|| {
+			format!(
+				"couldn't determine the local time zone to check {remote}'s maintenance window {window} against, give \
+				 it a time zone like `{window} Europe/Berlin`"
+			)
+		})?;
+		if !window.contains(time) {
+			outside.insert(remote.clone(), window);
+		}
+	}
+	Ok(outside)
+}
+
+impl fmt::Display for MaintenanceWindow {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}-{}{}",
+			format_time(self.start),
+			format_time(self.end),
+			format_zone(self.zone)
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::{datetime, time};
+
+	use super::*;
+
+	#[test]
+	fn windows_are_parsed() {
+		assert_eq!(
+			MaintenanceWindow::parse("22:00-04:00"),
+			Ok(MaintenanceWindow {
+				start: time!(22:00),
+				end: time!(04:00),
+				zone: None,
+			})
+		);
+		let window = MaintenanceWindow::parse("01:30-05:00 -05:30").unwrap();
+		assert_eq!(
+			window.zone,
+			UtcOffset::from_hms(-5, -30, 0).ok().map(WindowZone::Offset)
+		);
+		assert_eq!(window.to_string(), "01:30-05:00 -05:30");
+		assert_eq!(
+			MaintenanceWindow::parse("9:00-17:00 UTC")
+				.unwrap()
+				.to_string(),
+			"09:00-17:00 utc"
+		);
+		assert!(MaintenanceWindow::parse("22:00").is_err());
+		assert!(MaintenanceWindow::parse("22:00-25:00").is_err());
+		assert!(MaintenanceWindow::parse("22:00-22:00").is_err());
+		assert_eq!(
+			MaintenanceWindow::parse("22:00-04:00 Europe/Berlin")
+				.unwrap()
+				.to_string(),
+			"22:00-04:00 Europe/Berlin"
+		);
+		assert!(MaintenanceWindow::parse("22:00-04:00 Europe/Atlantis").is_err());
+	}
+
+	#[test]
+	fn windows_wrap_past_midnight() {
+		let night = MaintenanceWindow::parse("22:00-04:00").unwrap();
+		assert!(night.contains(time!(23:59)));
+		assert!(night.contains(time!(00:00)));
+		assert!(night.contains(time!(22:00)));
+		assert!(!night.contains(time!(04:00)));
+		assert!(!night.contains(time!(12:00)));
+
+		let day = MaintenanceWindow::parse("09:00-17:00").unwrap();
+		assert!(day.contains(time!(12:00)));
+		assert!(!day.contains(time!(17:00)));
+		assert!(!day.contains(time!(23:00)));
+	}
+
+	#[test]
+	fn windows_are_checked_in_their_offset() {
+		let berlin = MaintenanceWindow::parse("02:00-05:00 +01:00").unwrap();
+		let now = datetime!(2025-01-10 02:30 utc);
+		assert_eq!(berlin.time_at(now, None), Some(time!(03:30)));
+		assert_eq!(berlin.describe_opening(), "opens 02:00 +01:00");
+
+		let local = MaintenanceWindow::parse("02:00-05:00").unwrap();
+		assert_eq!(local.time_at(now, None), None);
+		assert_eq!(
+			local.time_at(now, UtcOffset::from_hms(-5, 0, 0).ok()),
+			Some(time!(21:30))
+		);
+		assert_eq!(local.describe_opening(), "opens 02:00");
+	}
+
+	#[test]
+	fn named_zones_follow_daylight_saving_time() {
+		let berlin = MaintenanceWindow::parse("02:00-05:00 Europe/Berlin").unwrap();
+		// berlin is at +01:00 in winter and +02:00 once the clocks go forward on 2025-03-30
+		assert_eq!(
+			berlin.time_at(datetime!(2025-03-29 01:30 utc), None),
+			Some(time!(02:30))
+		);
+		assert_eq!(
+			berlin.time_at(datetime!(2025-03-30 01:30 utc), None),
+			Some(time!(03:30))
+		);
+		assert_eq!(
+			berlin.time_at(datetime!(2025-03-30 03:30 utc), None),
+			Some(time!(05:30))
+		);
+		assert_eq!(berlin.describe_opening(), "opens 02:00 Europe/Berlin");
+	}
+
+	#[test]
+	fn remotes_outside_their_windows_are_found() {
+		let windows = BTreeMap::from([
+			("pos1".to_string(), "02:00-05:00 +01:00".to_string()),
+			("pos2".to_string(), "01:00-03:00 utc".to_string()),
+		]);
+		let remotes = ["pos1", "pos2", "web1"].map(str::to_string);
+		let outside =
+			outside_windows(&remotes, &windows, datetime!(2025-01-10 12:00 utc), None).unwrap();
+		assert_eq!(outside.keys().collect::<Vec<_>>(), ["pos1", "pos2"]);
+		let outside =
+			outside_windows(&remotes, &windows, datetime!(2025-01-10 02:30 utc), None).unwrap();
+		assert!(outside.is_empty());
+
+		let local = BTreeMap::from([("pos1".to_string(), "02:00-05:00".to_string())]);
+		assert!(outside_windows(&remotes, &local, datetime!(2025-01-10 12:00 utc), None).is_err());
+	}
+}